@@ -0,0 +1,262 @@
+use crate::api::*;
+use crate::auth::constant_time_eq;
+use chrono::Utc;
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    Body, Method, StatusCode,
+};
+use serde_json::{json, Value};
+
+/// Maintenance tasks every server supports besides the layer tasks.
+const BUILTIN_TASKS: &[&str] = &["flush", "lifecycle"];
+
+impl<API: ApiLayer> S3Server<API> {
+    /// Serve the admin endpoints, these are not part of the S3 API
+    /// and should only be exposed to operators.
+    ///
+    /// Anyone that reaches them can read the layer configuration and delete
+    /// buckets with all their objects. With an admin token every request but
+    /// the health probes needs `Authorization: Bearer <token>`, and without one
+    /// the server only binds admin listeners to loopback addresses.
+    ///
+    /// - `GET /healthz` - the process is up
+    /// - `GET /readyz` - the layers are ready to serve, 503 otherwise
+    /// - `GET /metrics` - prometheus metrics of the server and layers
+    /// - `GET /admin/layers` - the layer stack and its configuration
+    /// - `GET /admin/buckets` - all buckets with object counts and bytes
+    /// - `DELETE /admin/buckets/{name}?force=true` - delete a bucket with its objects
+    /// - `GET /admin/tasks` - the maintenance tasks
+    /// - `POST /admin/tasks/{name}` - run a maintenance task
+    pub async fn admin_handler(&self, req: HttpRequest) -> HttpResult {
+        let method = req.method();
+        let path = req.uri().path();
+        let probe = matches!((method, path), (&Method::GET, "/healthz" | "/readyz"));
+        if !probe && !self.admin_authorized(&req) {
+            let mut res = text_response(StatusCode::UNAUTHORIZED, "text/plain", "unauthorized\n");
+            res.headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return Ok(res);
+        }
+        let res = match (method, path) {
+            (&Method::GET, "/healthz") => text_response(StatusCode::OK, "text/plain", "ok\n"),
+            (&Method::GET, "/readyz") => self.admin_ready().await,
+            (&Method::GET, "/metrics") => {
+                let mut stats = self.api().stats().await;
+                if let Some(notifier) = &self.notifier {
+                    stats.extend(notifier.stats());
+                }
+                text_response(
+                    StatusCode::OK,
+                    "text/plain; version=0.0.4",
+                    self.metrics().render(&stats),
+                )
+            }
+            (&Method::GET, "/admin/layers") => {
+                json_response(StatusCode::OK, layer_json(&self.api().describe()))
+            }
+            (&Method::GET, "/admin/buckets") => self.admin_buckets().await,
+            (&Method::DELETE, p) if p.starts_with("/admin/buckets/") => {
+                let force = req.uri().query().unwrap_or("");
+                self.admin_delete_bucket(&p["/admin/buckets/".len()..], force)
+                    .await
+            }
+            (&Method::GET, "/admin/tasks") => {
+                json_response(StatusCode::OK, json!({ "tasks": self.admin_tasks() }))
+            }
+            (&Method::POST, p) if p.starts_with("/admin/tasks/") => {
+                self.admin_run_task(&p["/admin/tasks/".len()..]).await
+            }
+            (
+                _,
+                "/healthz" | "/readyz" | "/metrics" | "/admin/layers" | "/admin/buckets"
+                | "/admin/tasks",
+            ) => text_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "text/plain",
+                "method not allowed\n",
+            ),
+            _ => text_response(StatusCode::NOT_FOUND, "text/plain", "not found\n"),
+        };
+        Ok(res)
+    }
+
+    /// Requests carry the admin token when there is one.
+    fn admin_authorized(&self, req: &HttpRequest) -> bool {
+        let token = match &self.admin_token {
+            Some(token) => token,
+            None => return true,
+        };
+        req.headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|v| constant_time_eq(v.trim().as_bytes(), token.as_bytes()))
+    }
+
+    async fn admin_ready(&self) -> HttpResponse {
+        let layer = self.api().describe().name;
+        match self.api().probe().await {
+            Ok(()) => json_response(StatusCode::OK, json!({ "ready": true, "layer": layer })),
+            Err(err) => {
+                tracing::warn!(%err, layer = layer.as_str(), "readiness probe failed");
+                json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "ready": false, "layer": layer, "error": err.to_string() }),
+                )
+            }
+        }
+    }
+
+    async fn admin_buckets(&self) -> HttpResponse {
+        match self.api().usage().await {
+            Ok(usage) => {
+                let buckets: Vec<Value> = usage
+                    .iter()
+                    .map(|u| {
+                        json!({
+                            "name": u.info.name,
+                            "owner": u.info.owner.id,
+                            "region": u.info.region,
+                            "objects": u.objects,
+                            "bytes": u.bytes,
+                        })
+                    })
+                    .collect();
+                json_response(StatusCode::OK, json!({ "buckets": buckets }))
+            }
+            Err(err) => error_json(err.info().status_code, err.to_string()),
+        }
+    }
+
+    /// Buckets are deleted like S3 does unless force=true is given,
+    /// which deletes the objects too and is meant for test environments.
+    async fn admin_delete_bucket(&self, bucket: &str, query: &str) -> HttpResponse {
+        let force = match QueryStr::parse(query) {
+            Ok(qs) => qs.get("force") == "true",
+            Err(err) => return error_json(err.info().status_code, err.to_string()),
+        };
+        let bucket = match decode_path(bucket) {
+            Ok(bucket) => bucket,
+            Err(err) => return error_json(err.info().status_code, err.to_string()),
+        };
+        tracing::info!(bucket = bucket.as_str(), force, "admin delete bucket");
+        let req = delete_bucket::Req::new(delete_bucket::Params {
+            bucket: bucket.to_owned(),
+            force,
+        });
+        match self.api().delete_bucket(req).await {
+            Ok(_) => json_response(StatusCode::OK, json!({ "deleted": bucket, "force": force })),
+            Err(err) => error_json(err.info().status_code, err.to_string()),
+        }
+    }
+
+    fn admin_tasks(&self) -> Vec<&'static str> {
+        let mut tasks = BUILTIN_TASKS.to_vec();
+        tasks.extend(self.api().tasks());
+        tasks
+    }
+
+    async fn admin_run_task(&self, name: &str) -> HttpResponse {
+        if !self.admin_tasks().contains(&name) {
+            return error_json(StatusCode::NOT_FOUND, format!("unknown task {}", name));
+        }
+        tracing::info!(task = name, "running maintenance task");
+        let res = match name {
+            "flush" => {
+                self.api().flush().await;
+                Ok("flushed".to_string())
+            }
+            "lifecycle" => match apply_lifecycle(self.api(), Utc::now()).await {
+                Ok(stats) => Ok(format!(
+                    "{} buckets, {} expired, {} errors",
+                    stats.buckets, stats.expired, stats.errors
+                )),
+                Err(err) => Err(err.into()),
+            },
+            _ => self.api().run_task(name).await,
+        };
+        match res {
+            Ok(result) => json_response(StatusCode::OK, json!({ "task": name, "result": result })),
+            Err(err) => {
+                tracing::warn!(task = name, %err, "maintenance task failed");
+                error_json(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+        }
+    }
+}
+
+fn layer_json(info: &LayerInfo) -> Value {
+    let config: serde_json::Map<String, Value> = info
+        .config
+        .iter()
+        .map(|(k, v)| (k.to_string(), Value::from(v.as_str())))
+        .collect();
+    json!({
+        "name": info.name,
+        "config": config,
+        "inner": info.inner.iter().map(layer_json).collect::<Vec<_>>(),
+    })
+}
+
+fn error_json(status: StatusCode, error: String) -> HttpResponse {
+    json_response(status, json!({ "error": error }))
+}
+
+fn json_response(status: StatusCode, value: Value) -> HttpResponse {
+    text_response(status, "application/json", format!("{}\n", value))
+}
+
+fn text_response<T: Into<Body>>(
+    status: StatusCode,
+    content_type: &'static str,
+    body: T,
+) -> HttpResponse {
+    let mut r = HttpResponse::new(body.into());
+    *r.status_mut() = status;
+    r.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::S3dServer;
+    use hyper::{header::AUTHORIZATION, Body, Client, Request, StatusCode};
+
+    async fn get(url: &str, token: Option<&str>) -> StatusCode {
+        let mut req = Request::builder().uri(url);
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = req.body(Body::empty()).unwrap();
+        Client::new().request(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_need_the_token() {
+        let server = S3dServer::builder()
+            .bind_ephemeral()
+            .admin(([127, 0, 0, 1], 0).into())
+            .admin_token("secret-token")
+            .start()
+            .await
+            .unwrap();
+        let url = server.admin_url().to_string();
+        let metrics = format!("{}/metrics", url);
+        assert_eq!(get(&format!("{}/healthz", url), None).await, StatusCode::OK);
+        assert_eq!(get(&metrics, None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(&metrics, Some("wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(&metrics, Some("secret-token")).await, StatusCode::OK);
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn admin_listeners_off_loopback_need_a_token() {
+        let server = S3dServer::builder()
+            .bind_ephemeral()
+            .admin(([0, 0, 0, 0], 0).into())
+            .start()
+            .await;
+        assert!(server.is_err());
+    }
+}
@@ -8,18 +8,261 @@ pub trait ApiLayer
 where
     Self: Send + Sync,
 {
-    fn new() -> Self;
+    fn new() -> Self
+    where
+        Self: Sized;
 
     async fn list_buckets(&self, req: list_buckets::Req) -> list_buckets::Ret;
     async fn get_bucket(&self, req: get_bucket::Req) -> get_bucket::Ret;
     async fn put_bucket(&self, req: put_bucket::Req) -> put_bucket::Ret;
     async fn delete_bucket(&self, req: delete_bucket::Req) -> delete_bucket::Ret;
 
+    /// The region of the bucket, by default the one get_bucket reports.
+    async fn get_bucket_location(&self, req: get_bucket_location::Req) -> get_bucket_location::Ret {
+        let (parts, params) = req.into_parts();
+        let req = get_bucket::Req::from_parts(
+            parts,
+            get_bucket::Params {
+                bucket: params.bucket,
+            },
+        );
+        let info = self.get_bucket(req).await?.into_body().info;
+        Ok(get_bucket_location::Res::new(get_bucket_location::Reply {
+            region: info.region,
+        }))
+    }
+
     async fn list_objects(&self, req: list_objects::Req) -> list_objects::Ret;
     async fn get_object(&self, req: get_object::Req) -> get_object::Ret;
     async fn put_object(&self, req: put_object::Req) -> put_object::Ret;
     async fn delete_object(&self, req: delete_object::Req) -> delete_object::Ret;
 
+    // bucket policy - layers that don't store policies leave these unimplemented
+    async fn get_bucket_policy(&self, _req: get_bucket_policy::Req) -> get_bucket_policy::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_policy(&self, _req: put_bucket_policy::Req) -> put_bucket_policy::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_bucket_policy(
+        &self,
+        _req: delete_bucket_policy::Req,
+    ) -> delete_bucket_policy::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // access control lists
+    async fn get_bucket_acl(&self, _req: get_bucket_acl::Req) -> get_bucket_acl::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_acl(&self, _req: put_bucket_acl::Req) -> put_bucket_acl::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn get_object_acl(&self, _req: get_object_acl::Req) -> get_object_acl::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_object_acl(&self, _req: put_object_acl::Req) -> put_object_acl::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // tagging - layers that don't store tags leave these unimplemented
+    async fn get_bucket_tagging(&self, _req: get_bucket_tagging::Req) -> get_bucket_tagging::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_tagging(&self, _req: put_bucket_tagging::Req) -> put_bucket_tagging::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_bucket_tagging(
+        &self,
+        _req: delete_bucket_tagging::Req,
+    ) -> delete_bucket_tagging::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn get_object_tagging(&self, _req: get_object_tagging::Req) -> get_object_tagging::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_object_tagging(&self, _req: put_object_tagging::Req) -> put_object_tagging::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_object_tagging(
+        &self,
+        _req: delete_object_tagging::Req,
+    ) -> delete_object_tagging::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // lifecycle - the configuration is stored by the layer and applied by apply_lifecycle
+    async fn get_bucket_lifecycle(
+        &self,
+        _req: get_bucket_lifecycle::Req,
+    ) -> get_bucket_lifecycle::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_lifecycle(
+        &self,
+        _req: put_bucket_lifecycle::Req,
+    ) -> put_bucket_lifecycle::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_bucket_lifecycle(
+        &self,
+        _req: delete_bucket_lifecycle::Req,
+    ) -> delete_bucket_lifecycle::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // cors - the configuration is stored by the layer and evaluated by the server
+    async fn get_bucket_cors(&self, _req: get_bucket_cors::Req) -> get_bucket_cors::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_cors(&self, _req: put_bucket_cors::Req) -> put_bucket_cors::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_bucket_cors(&self, _req: delete_bucket_cors::Req) -> delete_bucket_cors::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // website - the configuration is stored by the layer and served by the website listeners
+    async fn get_bucket_website(&self, _req: get_bucket_website::Req) -> get_bucket_website::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_website(&self, _req: put_bucket_website::Req) -> put_bucket_website::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_bucket_website(
+        &self,
+        _req: delete_bucket_website::Req,
+    ) -> delete_bucket_website::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // encryption - the default encryption is stored by the layer and applied by the EncryptionLayer
+    async fn get_bucket_encryption(
+        &self,
+        _req: get_bucket_encryption::Req,
+    ) -> get_bucket_encryption::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_encryption(
+        &self,
+        _req: put_bucket_encryption::Req,
+    ) -> put_bucket_encryption::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_bucket_encryption(
+        &self,
+        _req: delete_bucket_encryption::Req,
+    ) -> delete_bucket_encryption::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // object lock - enabled when the bucket is created, layers that keep it refuse
+    // deletes and overwrites of protected objects
+    async fn get_object_lock_configuration(
+        &self,
+        _req: get_object_lock_configuration::Req,
+    ) -> get_object_lock_configuration::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_object_lock_configuration(
+        &self,
+        _req: put_object_lock_configuration::Req,
+    ) -> put_object_lock_configuration::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn get_object_retention(
+        &self,
+        _req: get_object_retention::Req,
+    ) -> get_object_retention::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_object_retention(
+        &self,
+        _req: put_object_retention::Req,
+    ) -> put_object_retention::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn get_object_legal_hold(
+        &self,
+        _req: get_object_legal_hold::Req,
+    ) -> get_object_legal_hold::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_object_legal_hold(
+        &self,
+        _req: put_object_legal_hold::Req,
+    ) -> put_object_legal_hold::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // notification - stored by the layer, the server sends the events to its webhooks
+    async fn get_bucket_notification(
+        &self,
+        _req: get_bucket_notification::Req,
+    ) -> get_bucket_notification::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_notification(
+        &self,
+        _req: put_bucket_notification::Req,
+    ) -> put_bucket_notification::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    // replication - stored by the layer, the ReplicationLayer replays writes to its destinations
+    async fn get_bucket_replication(
+        &self,
+        _req: get_bucket_replication::Req,
+    ) -> get_bucket_replication::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn put_bucket_replication(
+        &self,
+        _req: put_bucket_replication::Req,
+    ) -> put_bucket_replication::Ret {
+        Err(S3Error::NotImplemented)
+    }
+    async fn delete_bucket_replication(
+        &self,
+        _req: delete_bucket_replication::Req,
+    ) -> delete_bucket_replication::Ret {
+        Err(S3Error::NotImplemented)
+    }
+
+    /// Flush buffered state (e.g. write-back caches, temp files) before the process exits.
+    async fn flush(&self) {}
+
+    /// Describe the layer and the layers it wraps, shown by the admin API.
+    fn describe(&self) -> LayerInfo {
+        LayerInfo::new(std::any::type_name::<Self>())
+    }
+
+    /// Check that the layer can serve requests (e.g. upstream reachable, storage writable),
+    /// probed by the admin readiness endpoint.
+    async fn probe(&self) -> Result<(), SyncError> {
+        Ok(())
+    }
+
+    /// Object count and bytes per bucket of all owners, for the admin API.
+    async fn usage(&self) -> Result<Vec<BucketUsage>, S3Error> {
+        Err(S3Error::NotImplemented)
+    }
+
+    /// Names of the maintenance tasks the admin API can trigger with run_task().
+    fn tasks(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Run a maintenance task, returning a short report.
+    async fn run_task(&self, name: &str) -> Result<String, SyncError> {
+        Err(format!("unknown task {}", name).into())
+    }
+
+    /// Stats exported on the metrics endpoint, e.g. object counts or cache hits and misses.
+    async fn stats(&self) -> Vec<LayerStat> {
+        Vec::new()
+    }
+
     // TODO: multipart upload
     // async fn initiate_multipart_upload(&self, req: InitiateMultipartUpload::Req) -> InitiateMultipartUpload::Res;
     // async fn complete_multipart_upload(&self, req: CompleteMultipartUpload::Req) -> CompleteMultipartUpload::Res;
@@ -35,10 +278,14 @@ where
     // async fn list_object_versions(&self, req: ListObjects::Req) -> ListObjects::Res;
 }
 
+/// The region of servers that don't configure one, like S3.
+pub const DEFAULT_REGION: &str = "us-east-1";
+
 #[derive(Debug, Clone)]
 pub struct BucketInfo {
     pub name: String,
     pub class: String,
+    /// empty when the layer does not keep it, which means the server region
     pub region: String,
     pub owner: UserInfo,
 }
@@ -53,6 +300,24 @@ pub struct ObjectInfo {
     pub etag: String,
     pub storage_class: String,
     pub owner: UserInfo,
+    /// returned as x-amz-tagging-count by get_object
+    pub tag_count: usize,
+    /// returned as x-amz-website-redirect-location, website listeners redirect to it
+    pub website_redirect_location: String,
+    /// the key MD5 of objects stored with SSE-C, returned with the algorithm
+    pub sse_customer_key_md5: String,
+    /// AES256 or aws:kms for objects encrypted with server managed keys, see EncryptionLayer
+    pub sse_algorithm: String,
+    /// the KMS key of aws:kms objects
+    pub sse_kms_key_id: String,
+    /// retention and legal hold, returned as x-amz-object-lock-*
+    pub object_lock: ObjectLock,
+    /// PENDING, COMPLETED or FAILED for objects replicated by the ReplicationLayer,
+    /// returned as x-amz-replication-status
+    pub replication_status: String,
+    /// the data size of objects encrypted by the EncryptionLayer, whose size
+    /// in the layers under it is of the stored object
+    pub plaintext_size: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -61,8 +326,181 @@ pub struct UserInfo {
     pub display_name: String,
 }
 
+/// ObjectRange is the byte range of a read, start..=end like the HTTP Range header.
+/// Without a start it is the suffix of the last end bytes, and without both
+/// it is the whole object.
 #[derive(Debug, Clone)]
 pub struct ObjectRange {
     pub start: Option<u64>,
     pub end: Option<u64>,
 }
+
+impl ObjectRange {
+    /// Parse a `Range: bytes=start-end` header. Like S3, other units and multiple
+    /// ranges are ignored and read the whole object.
+    pub fn parse(value: Option<&str>) -> Self {
+        let full = ObjectRange {
+            start: None,
+            end: None,
+        };
+        let spec = match value.and_then(|v| v.trim().strip_prefix("bytes=")) {
+            Some(spec) if !spec.contains(',') => spec,
+            _ => return full,
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => return full,
+        };
+        let parse = |s: &str| match s {
+            "" => Ok(None),
+            s => s.parse::<u64>().map(Some),
+        };
+        match (parse(start), parse(end)) {
+            (Ok(Some(start)), Ok(Some(end))) if start <= end => ObjectRange {
+                start: Some(start),
+                end: Some(end),
+            },
+            (Ok(Some(start)), Ok(None)) => ObjectRange {
+                start: Some(start),
+                end: None,
+            },
+            (Ok(None), Ok(Some(end))) => ObjectRange {
+                start: None,
+                end: Some(end),
+            },
+            _ => full,
+        }
+    }
+
+    /// The bytes start..end the range selects of an object of the given size,
+    /// None for the whole object, and InvalidRange when it selects no byte.
+    pub fn resolve(&self, size: u64) -> Result<Option<(u64, u64)>, S3Error> {
+        match (self.start, self.end) {
+            (None, None) => Ok(None),
+            (Some(start), _) if start >= size => Err(S3Error::InvalidRange),
+            (Some(start), end) => Ok(Some((
+                start,
+                end.map_or(size, |end| end.saturating_add(1).min(size)),
+            ))),
+            (None, Some(suffix)) if suffix == 0 || size == 0 => Err(S3Error::InvalidRange),
+            (None, Some(suffix)) => Ok(Some((size.saturating_sub(suffix), size))),
+        }
+    }
+}
+
+/// LayerInfo describes a layer in the stack, layers that wrap others list them as inner.
+#[derive(Debug, Clone)]
+pub struct LayerInfo {
+    pub name: String,
+    pub config: Vec<(&'static str, String)>,
+    pub inner: Vec<LayerInfo>,
+}
+
+impl LayerInfo {
+    /// The name is the last path segment of a type name without generics,
+    /// e.g. `s3d::layers::MemLayer` is MemLayer.
+    pub fn new(name: &str) -> Self {
+        let name = name.split('<').next().unwrap_or(name);
+        LayerInfo {
+            name: name.rsplit("::").next().unwrap_or(name).to_string(),
+            config: Vec::new(),
+            inner: Vec::new(),
+        }
+    }
+
+    pub fn config(mut self, name: &'static str, value: &str) -> Self {
+        self.config.push((name, value.to_string()));
+        self
+    }
+
+    pub fn inner(mut self, info: LayerInfo) -> Self {
+        self.inner.push(info);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BucketUsage {
+    pub info: BucketInfo,
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// LayerStat is a value a layer exports as a prometheus metric.
+/// Stats with the same name are exported as one metric with different labels.
+#[derive(Debug, Clone)]
+pub struct LayerStat {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: StatKind,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatKind {
+    Counter,
+    Gauge,
+}
+
+impl LayerStat {
+    pub fn gauge(name: &'static str, help: &'static str, value: f64) -> Self {
+        LayerStat {
+            name,
+            help,
+            kind: StatKind::Gauge,
+            labels: Vec::new(),
+            value,
+        }
+    }
+
+    pub fn counter(name: &'static str, help: &'static str, value: f64) -> Self {
+        LayerStat {
+            kind: StatKind::Counter,
+            ..Self::gauge(name, help, value)
+        }
+    }
+
+    pub fn label(mut self, name: &'static str, value: &str) -> Self {
+        self.labels.push((name, value.to_string()));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(header: &str, size: u64) -> Result<Option<(u64, u64)>, S3Error> {
+        ObjectRange::parse(Some(header)).resolve(size)
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(resolve("bytes=0-9", 100).unwrap(), Some((0, 10)));
+        assert_eq!(resolve("bytes=90-200", 100).unwrap(), Some((90, 100)));
+        assert_eq!(resolve("bytes=10-", 100).unwrap(), Some((10, 100)));
+        assert_eq!(resolve("bytes=-10", 100).unwrap(), Some((90, 100)));
+        assert_eq!(resolve("bytes=-200", 100).unwrap(), Some((0, 100)));
+        assert!(matches!(
+            resolve("bytes=100-", 100),
+            Err(S3Error::InvalidRange)
+        ));
+        assert!(matches!(
+            resolve("bytes=-0", 100),
+            Err(S3Error::InvalidRange)
+        ));
+        assert!(matches!(resolve("bytes=0-", 0), Err(S3Error::InvalidRange)));
+        // what S3 ignores reads the whole object
+        for header in [
+            "bytes=0-1,5-6",
+            "items=0-1",
+            "bytes=9-1",
+            "bytes=x-1",
+            "bytes=-",
+        ] {
+            assert_eq!(resolve(header, 100).unwrap(), None, "{}", header);
+        }
+        assert_eq!(ObjectRange::parse(None).resolve(0).unwrap(), None);
+    }
+}
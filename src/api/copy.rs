@@ -0,0 +1,246 @@
+use crate::api::*;
+use crate::auth::*;
+use hyper::Body;
+
+/// Run a CopyObject request as a read of the source and a write of the copy,
+/// so every layer sees a copy like any other upload.
+///
+/// SSE-C sources are read with the copy source key, and the copy is encrypted
+/// with the key of the request, if any. The tags of the source are copied unless
+/// the request replaces them.
+pub async fn copy_object<API: ApiLayer + ?Sized>(
+    api: &API,
+    req: copy_object::Req,
+) -> copy_object::Ret {
+    let (parts, params) = req.into_parts();
+    let source = params.source;
+    // like S3 an object is only copied over itself to change its attributes
+    if source.bucket == params.bucket
+        && source.key == params.key
+        && params.sse_customer.is_none()
+        && params.copy_source_sse_customer.is_none()
+        && params.sse.is_none()
+        && params.tagging.is_none()
+        && params.website_redirect_location.is_empty()
+    {
+        return Err(S3Error::InvalidRequest);
+    }
+    // the reads of the source are made by the requester too
+    let principal = parts
+        .extensions
+        .get::<Principal>()
+        .cloned()
+        .unwrap_or(Principal::Anonymous);
+    let mut get = get_object::Req::new(get_object::Params {
+        bucket: source.bucket.to_owned(),
+        key: source.key.to_owned(),
+        version_id: source.version_id.to_owned(),
+        head_only: false,
+        range: ObjectRange {
+            start: None,
+            end: None,
+        },
+        sse_customer: params.copy_source_sse_customer,
+    });
+    get.extensions_mut().insert(principal.to_owned());
+    let object = api.get_object(get).await?.into_body();
+    let tagging = match params.tagging {
+        Some(tagging) => tagging,
+        None => {
+            let mut req = get_object_tagging::Req::new(get_object_tagging::Params {
+                bucket: source.bucket,
+                key: source.key,
+                version_id: source.version_id,
+            });
+            req.extensions_mut().insert(principal.to_owned());
+            match api.get_object_tagging(req).await {
+                Ok(res) => res.into_body().tagging,
+                // layers without tags have none to copy
+                Err(S3Error::NotImplemented) => Tagging::default(),
+                Err(err) => return Err(err),
+            }
+        }
+    };
+    let put = put_object::Req::from_parts(
+        parts,
+        put_object::Params {
+            bucket: params.bucket,
+            key: params.key,
+            acl: params.acl,
+            tagging,
+            website_redirect_location: params.website_redirect_location,
+            sse_customer: params.sse_customer,
+            sse: params.sse,
+            object_lock: params.object_lock,
+            plaintext_size: None,
+            body: Some(object.body.unwrap_or_else(Body::empty)),
+        },
+    );
+    let object = api.put_object(put).await?.into_body().object;
+    Ok(copy_object::Res::new(copy_object::Reply { object }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::MemLayer;
+    use hyper::{body::Bytes, HeaderMap};
+
+    fn alice() -> Principal {
+        Principal::User(UserInfo {
+            id: String::from("alice"),
+            display_name: String::from("alice"),
+        })
+    }
+
+    async fn layer() -> MemLayer {
+        let layer = MemLayer::new();
+        let mut req = put_bucket::Req::new(put_bucket::Params {
+            bucket: String::from("bucket"),
+            class: String::new(),
+            region: String::new(),
+            acl: AclHeaders::default(),
+            object_lock_enabled: false,
+            body: None,
+        });
+        req.extensions_mut().insert(alice());
+        layer.put_bucket(req).await.unwrap();
+        let mut req = put_object::Req::new(put_object::Params {
+            bucket: String::from("bucket"),
+            key: String::from("plain"),
+            acl: AclHeaders::default(),
+            tagging: Tagging::from_header("a=1").unwrap(),
+            website_redirect_location: String::new(),
+            sse_customer: None,
+            sse: None,
+            object_lock: ObjectLock::default(),
+            plaintext_size: None,
+            body: Some(Body::from("plain data")),
+        });
+        req.extensions_mut().insert(alice());
+        layer.put_object(req).await.unwrap();
+        layer
+    }
+
+    fn copy(
+        source: &str,
+        key: &str,
+        copy_source_sse_customer: Option<SseCustomerKey>,
+        sse_customer: Option<SseCustomerKey>,
+    ) -> copy_object::Req {
+        let mut req = copy_object::Req::new(copy_object::Params {
+            bucket: String::from("bucket"),
+            key: key.to_string(),
+            source: copy_object::CopySource {
+                bucket: String::from("bucket"),
+                key: source.to_string(),
+                version_id: String::new(),
+            },
+            acl: AclHeaders::default(),
+            tagging: None,
+            website_redirect_location: String::new(),
+            sse_customer,
+            copy_source_sse_customer,
+            sse: None,
+            object_lock: ObjectLock::default(),
+        });
+        req.extensions_mut().insert(alice());
+        req
+    }
+
+    async fn get(layer: &MemLayer, key: &str, sse_customer: Option<SseCustomerKey>) -> Bytes {
+        let req = get_object::Req::new(get_object::Params {
+            bucket: String::from("bucket"),
+            key: key.to_string(),
+            version_id: String::new(),
+            head_only: false,
+            range: ObjectRange {
+                start: None,
+                end: None,
+            },
+            sse_customer,
+        });
+        let body = layer.get_object(req).await.unwrap().into_body().body;
+        read_body(body).await.unwrap()
+    }
+
+    fn key(byte: u8) -> Option<SseCustomerKey> {
+        Some(SseCustomerKey::from_bytes([byte; 32]))
+    }
+
+    #[tokio::test]
+    async fn copies_data_and_tags() {
+        let layer = layer().await;
+        let res = copy_object(&layer, copy("plain", "copy", None, None)).await;
+        assert_eq!(res.unwrap().into_body().object.size, 10);
+        assert_eq!(get(&layer, "copy", None).await, "plain data");
+        let req = get_object_tagging::Req::new(get_object_tagging::Params {
+            bucket: String::from("bucket"),
+            key: String::from("copy"),
+            version_id: String::new(),
+        });
+        let tagging = layer.get_object_tagging(req).await.unwrap();
+        assert_eq!(tagging.into_body().tagging.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn copies_between_customer_keys() {
+        let layer = layer().await;
+        copy_object(&layer, copy("plain", "secret", None, key(1)))
+            .await
+            .unwrap();
+        assert_eq!(get(&layer, "secret", key(1)).await, "plain data");
+        // the source key is required to read the source
+        assert!(matches!(
+            copy_object(&layer, copy("secret", "other", None, key(2))).await,
+            Err(S3Error::InvalidRequest)
+        ));
+        assert!(matches!(
+            copy_object(&layer, copy("secret", "other", key(2), key(2))).await,
+            Err(S3Error::AccessDenied)
+        ));
+        copy_object(&layer, copy("secret", "secret", key(1), key(2)))
+            .await
+            .unwrap();
+        assert_eq!(get(&layer, "secret", key(2)).await, "plain data");
+    }
+
+    #[tokio::test]
+    async fn copy_over_itself_needs_a_change() {
+        let layer = layer().await;
+        assert!(matches!(
+            copy_object(&layer, copy("plain", "plain", None, None)).await,
+            Err(S3Error::InvalidRequest)
+        ));
+    }
+
+    #[test]
+    fn parses_copy_source() {
+        let source = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-amz-copy-source", value.parse().unwrap());
+            copy_object::CopySource::from_headers(&headers)
+        };
+        let parsed = source("/bucket/a%20b/c?versionId=v1").unwrap();
+        assert_eq!(
+            (parsed.bucket.as_str(), parsed.key.as_str()),
+            ("bucket", "a b/c")
+        );
+        assert_eq!(parsed.version_id, "v1");
+        assert_eq!(source("bucket/key").unwrap().key, "key");
+        assert!(source("bucket").is_err());
+        assert!(source("/bucket/").is_err());
+    }
+
+    #[test]
+    fn customer_keys_need_https() {
+        let mut headers = HeaderMap::new();
+        assert!(check_sse_c_transport(&headers, false).is_ok());
+        headers.insert(SSE_C_COPY_SOURCE_KEY_HEADER, "key".parse().unwrap());
+        assert!(matches!(
+            check_sse_c_transport(&headers, false),
+            Err(S3Error::InvalidRequest)
+        ));
+        assert!(check_sse_c_transport(&headers, true).is_ok());
+    }
+}
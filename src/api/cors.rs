@@ -0,0 +1,341 @@
+use crate::api::*;
+use crate::auth::wildcard_match;
+use hyper::{Body, HeaderMap, Method};
+use serde::{Deserialize, Serialize};
+
+/// Limits of the CORS configuration, like S3.
+pub const MAX_CORS_RULES: usize = 100;
+pub const MAX_CORS_ID_LEN: usize = 255;
+
+/// The methods a CORS rule can allow.
+const CORS_METHODS: &[&str] = &["GET", "PUT", "HEAD", "POST", "DELETE"];
+
+/// CorsConfiguration is the `?cors` document of a bucket.
+///
+/// The server evaluates it for `OPTIONS` preflight requests and adds the
+/// `Access-Control-*` headers to responses of requests with a matching `Origin`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorsConfiguration {
+    #[serde(rename = "CORSRule", default)]
+    pub rules: Vec<CorsRule>,
+}
+
+/// Origins and allowed headers may have one `*` wildcard, e.g. `https://*.example.com`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorsRule {
+    #[serde(rename = "ID", default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(rename = "AllowedHeader", default)]
+    pub allowed_headers: Vec<String>,
+    #[serde(rename = "AllowedMethod", default)]
+    pub allowed_methods: Vec<String>,
+    #[serde(rename = "AllowedOrigin", default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(rename = "ExposeHeader", default)]
+    pub expose_headers: Vec<String>,
+    #[serde(
+        rename = "MaxAgeSeconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_age_seconds: Option<u32>,
+}
+
+/// CorsRequest is what a rule is matched against, from the request headers.
+#[derive(Debug, Clone, Default)]
+pub struct CorsRequest {
+    pub origin: String,
+    pub method: String,
+    /// the lowercase names of Access-Control-Request-Headers, only for preflight
+    pub headers: Vec<String>,
+}
+
+impl CorsConfiguration {
+    /// Read and validate a CORSConfiguration document from a request body.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let config: CorsConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("CORSConfiguration", self)
+    }
+
+    /// Check the rules like S3 does, documents that don't follow the schema
+    /// are MalformedXML and unsupported values are InvalidRequest.
+    pub fn validate(&self) -> Result<(), S3Error> {
+        if self.rules.is_empty() || self.rules.len() > MAX_CORS_RULES {
+            return Err(S3Error::MalformedXML);
+        }
+        for rule in self.rules.iter() {
+            if rule.id.chars().count() > MAX_CORS_ID_LEN {
+                return Err(S3Error::InvalidArgument);
+            }
+            if rule.allowed_methods.is_empty() || rule.allowed_origins.is_empty() {
+                return Err(S3Error::MalformedXML);
+            }
+            if rule
+                .allowed_methods
+                .iter()
+                .any(|m| !CORS_METHODS.contains(&m.as_str()))
+            {
+                return Err(S3Error::InvalidRequest);
+            }
+            let wildcards = |v: &[String]| v.iter().any(|s| s.matches('*').count() > 1);
+            if wildcards(&rule.allowed_origins) || wildcards(&rule.allowed_headers) {
+                return Err(S3Error::InvalidRequest);
+            }
+        }
+        Ok(())
+    }
+
+    /// The first rule that allows the request, like S3 evaluates them in order.
+    pub fn find_rule(&self, req: &CorsRequest) -> Option<&CorsRule> {
+        self.rules.iter().find(|r| r.allows(req))
+    }
+}
+
+impl CorsRule {
+    pub fn allows(&self, req: &CorsRequest) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|o| wildcard_match(o, &req.origin, false))
+            && self.allowed_methods.contains(&req.method)
+            && req.headers.iter().all(|h| {
+                self.allowed_headers
+                    .iter()
+                    .any(|a| wildcard_match(a, h, true))
+            })
+    }
+
+    /// Set the Access-Control headers of the rule on the response,
+    /// preflight responses also allow the requested headers and carry the max age.
+    pub fn write_headers(
+        &self,
+        res: &mut HttpResponse,
+        req: &CorsRequest,
+        preflight: bool,
+    ) -> Result<(), S3Error> {
+        // a wildcard origin is answered as is and never with credentials
+        if self.allowed_origins.iter().any(|o| o == "*") {
+            set_header(res, "Access-Control-Allow-Origin", "*")?;
+        } else {
+            set_header(res, "Access-Control-Allow-Origin", &req.origin)?;
+            set_header(res, "Access-Control-Allow-Credentials", "true")?;
+        }
+        set_header(
+            res,
+            "Access-Control-Allow-Methods",
+            &self.allowed_methods.join(", "),
+        )?;
+        if !self.expose_headers.is_empty() {
+            set_header(
+                res,
+                "Access-Control-Expose-Headers",
+                &self.expose_headers.join(", "),
+            )?;
+        }
+        if preflight {
+            if !req.headers.is_empty() {
+                set_header(res, "Access-Control-Allow-Headers", &req.headers.join(", "))?;
+            }
+            if let Some(max_age) = self.max_age_seconds {
+                set_header(res, "Access-Control-Max-Age", &max_age.to_string())?;
+            }
+        }
+        set_header(
+            res,
+            "Vary",
+            "Origin, Access-Control-Request-Headers, Access-Control-Request-Method",
+        )
+    }
+}
+
+impl CorsRequest {
+    /// The CORS request of an actual (not preflight) request, None without an Origin.
+    pub fn from_headers(method: &Method, headers: &HeaderMap) -> Option<Self> {
+        let origin = headers.get("Origin")?.to_str().ok()?;
+        Some(CorsRequest {
+            origin: origin.to_string(),
+            method: method.to_string(),
+            headers: Vec::new(),
+        })
+    }
+
+    /// The CORS request of a preflight, which must name the origin and the method.
+    pub fn preflight(headers: &HeaderMap) -> Result<Self, S3Error> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| v.to_str().map_err(|_| S3Error::BadRequest))
+                .transpose()
+        };
+        let origin = header("Origin")?.ok_or(S3Error::BadRequest)?;
+        let method = header("Access-Control-Request-Method")?.ok_or(S3Error::BadRequest)?;
+        let headers = header("Access-Control-Request-Headers")?
+            .unwrap_or("")
+            .split(',')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        Ok(CorsRequest {
+            origin: origin.to_string(),
+            method: method.to_string(),
+            headers,
+        })
+    }
+}
+
+/// The CORS configuration of the bucket, None when the bucket has none
+/// or the layer does not keep it.
+pub async fn bucket_cors<API: ApiLayer + ?Sized>(
+    api: &API,
+    bucket: &str,
+) -> Option<CorsConfiguration> {
+    let req = get_bucket_cors::Req::new(get_bucket_cors::Params {
+        bucket: bucket.to_string(),
+    });
+    Some(api.get_bucket_cors(req).await.ok()?.into_body().config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn rule(origins: &[&str], methods: &[&str], headers: &[&str]) -> CorsRule {
+        CorsRule {
+            allowed_origins: strings(origins),
+            allowed_methods: strings(methods),
+            allowed_headers: strings(headers),
+            ..CorsRule::default()
+        }
+    }
+
+    fn preflight(origin: &str, method: &str, headers: &str) -> CorsRequest {
+        let mut map = HeaderMap::new();
+        map.insert("Origin", HeaderValue::from_str(origin).unwrap());
+        map.insert(
+            "Access-Control-Request-Method",
+            HeaderValue::from_str(method).unwrap(),
+        );
+        if !headers.is_empty() {
+            map.insert(
+                "Access-Control-Request-Headers",
+                HeaderValue::from_str(headers).unwrap(),
+            );
+        }
+        CorsRequest::preflight(&map).unwrap()
+    }
+
+    fn header<'a>(res: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        res.headers().get(name).map(|v| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn rules_are_validated() {
+        let config = |rules: Vec<CorsRule>| CorsConfiguration { rules }.validate();
+        assert!(config(vec![rule(&["*"], &["GET"], &[])]).is_ok());
+        assert_eq!(config(vec![]), Err(S3Error::MalformedXML));
+        assert_eq!(
+            config(vec![rule(&[], &["GET"], &[])]),
+            Err(S3Error::MalformedXML)
+        );
+        assert_eq!(
+            config(vec![rule(&["*"], &["PATCH"], &[])]),
+            Err(S3Error::InvalidRequest)
+        );
+        assert_eq!(
+            config(vec![rule(&["https://*.*.com"], &["GET"], &[])]),
+            Err(S3Error::InvalidRequest)
+        );
+        let mut long_id = rule(&["*"], &["GET"], &[]);
+        long_id.id = "i".repeat(MAX_CORS_ID_LEN + 1);
+        assert_eq!(config(vec![long_id]), Err(S3Error::InvalidArgument));
+        let rules = vec![rule(&["*"], &["GET"], &[]); MAX_CORS_RULES + 1];
+        assert_eq!(config(rules), Err(S3Error::MalformedXML));
+    }
+
+    #[test]
+    fn preflights_match_the_first_allowing_rule() {
+        let config = CorsConfiguration {
+            rules: vec![
+                rule(
+                    &["https://*.example.com"],
+                    &["PUT"],
+                    &["x-amz-*", "content-type"],
+                ),
+                rule(&["*"], &["GET", "HEAD"], &[]),
+            ],
+        };
+        let find = |req: &CorsRequest| config.find_rule(req).map(|r| r.allowed_methods[0].as_str());
+        let req = preflight("https://app.example.com", "PUT", "X-Amz-Date, Content-Type");
+        assert_eq!(req.headers, ["x-amz-date", "content-type"]);
+        assert_eq!(find(&req), Some("PUT"));
+        // a header no rule allows
+        let req = preflight("https://app.example.com", "PUT", "authorization");
+        assert_eq!(find(&req), None);
+        let req = preflight("https://other.com", "PUT", "");
+        assert_eq!(find(&req), None);
+        let req = preflight("https://other.com", "GET", "");
+        assert_eq!(find(&req), Some("GET"));
+        assert_eq!(find(&preflight("https://other.com", "DELETE", "")), None);
+        // methods match case sensitively like S3
+        assert_eq!(find(&preflight("https://other.com", "get", "")), None);
+        assert_eq!(
+            CorsRequest::preflight(&HeaderMap::new()).err(),
+            Some(S3Error::BadRequest)
+        );
+    }
+
+    #[test]
+    fn headers_of_the_rule() {
+        let mut specific = rule(&["https://app.example.com"], &["GET", "PUT"], &["*"]);
+        specific.expose_headers = strings(&["ETag"]);
+        specific.max_age_seconds = Some(600);
+        let req = preflight("https://app.example.com", "PUT", "x-amz-date");
+        let mut res = HttpResponse::new(Body::empty());
+        specific.write_headers(&mut res, &req, true).unwrap();
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(header(&res, "Access-Control-Expose-Headers"), Some("ETag"));
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Headers"),
+            Some("x-amz-date")
+        );
+        assert_eq!(header(&res, "Access-Control-Max-Age"), Some("600"));
+        assert!(header(&res, "Vary").is_some());
+
+        // actual requests get no preflight headers, and wildcard origins no credentials
+        let any = rule(&["*"], &["GET"], &[]);
+        let req = CorsRequest::from_headers(&Method::GET, &{
+            let mut map = HeaderMap::new();
+            map.insert("Origin", HeaderValue::from_static("https://other.com"));
+            map
+        })
+        .unwrap();
+        let mut res = HttpResponse::new(Body::empty());
+        any.write_headers(&mut res, &req, false).unwrap();
+        assert_eq!(header(&res, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(header(&res, "Access-Control-Allow-Credentials"), None);
+        assert_eq!(header(&res, "Access-Control-Allow-Headers"), None);
+        assert_eq!(header(&res, "Access-Control-Max-Age"), None);
+        assert!(CorsRequest::from_headers(&Method::GET, &HeaderMap::new()).is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::api::*;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+
+/// ServerSideEncryptionConfiguration is the `?encryption` document of a bucket,
+/// the default encryption of objects uploaded without `x-amz-server-side-encryption`.
+///
+/// Layers keep it and the EncryptionLayer applies it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerSideEncryptionConfiguration {
+    #[serde(rename = "Rule", default)]
+    pub rules: Vec<ServerSideEncryptionRule>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServerSideEncryptionRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apply_server_side_encryption_by_default: Option<ServerSideEncryptionByDefault>,
+    /// kept for clients, every object has its own data key anyway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket_key_enabled: Option<bool>,
+}
+
+/// The KMS key id is only valid with aws:kms, empty for the default KMS key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerSideEncryptionByDefault {
+    #[serde(rename = "SSEAlgorithm")]
+    pub sse_algorithm: String,
+    #[serde(
+        rename = "KMSMasterKeyID",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub kms_master_key_id: String,
+}
+
+impl ServerSideEncryptionConfiguration {
+    /// Read and validate a ServerSideEncryptionConfiguration document from a request body.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let config: ServerSideEncryptionConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("ServerSideEncryptionConfiguration", self)
+    }
+
+    /// Check the configuration like S3 does, it has a single rule
+    /// with a known algorithm.
+    pub fn validate(&self) -> Result<(), S3Error> {
+        if self.rules.len() != 1 {
+            return Err(S3Error::MalformedXML);
+        }
+        if let Some(default) = &self.rules[0].apply_server_side_encryption_by_default {
+            let algorithm = SseAlgorithm::parse(&default.sse_algorithm)?;
+            if algorithm != SseAlgorithm::AwsKms && !default.kms_master_key_id.is_empty() {
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        Ok(())
+    }
+
+    /// The encryption of objects uploaded without one.
+    pub fn default_encryption(&self) -> Option<ServerSideEncryption> {
+        let default = self
+            .rules
+            .iter()
+            .find_map(|r| r.apply_server_side_encryption_by_default.as_ref())?;
+        Some(ServerSideEncryption {
+            algorithm: SseAlgorithm::parse(&default.sse_algorithm).ok()?,
+            kms_key_id: default.kms_master_key_id.to_owned(),
+        })
+    }
+}
+
+/// The default encryption of the bucket, None when the bucket has none
+/// or the layer does not keep it.
+pub async fn bucket_encryption<API: ApiLayer + ?Sized>(
+    api: &API,
+    bucket: &str,
+) -> Option<ServerSideEncryptionConfiguration> {
+    let req = get_bucket_encryption::Req::new(get_bucket_encryption::Params {
+        bucket: bucket.to_string(),
+    });
+    Some(
+        api.get_bucket_encryption(req)
+            .await
+            .ok()?
+            .into_body()
+            .config,
+    )
+}
@@ -1,63 +1,390 @@
 use crate::api::*;
+use hyper::{Body, StatusCode};
 
-#[derive(Debug, Clone)]
+/// S3Error codes, see https://docs.aws.amazon.com/AmazonS3/latest/API/ErrorResponses.html#ErrorCodeList
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum S3Error {
+    AccessDenied,
+    AuthorizationHeaderMalformed,
+    AuthorizationQueryParametersError,
+    BadDigest,
     BadRequest,
     BucketAlreadyExists,
+    BucketAlreadyOwnedByYou,
+    BucketNotEmpty,
+    CastFailed,
+    CSVParsingError,
+    EntityTooLarge,
+    ExpressionTooLong,
+    IllegalLocationConstraintException,
+    IncompleteBody,
+    InternalError,
+    InvalidAccessKeyId,
+    InvalidArgument,
+    InvalidBucketName,
+    InvalidBucketState,
+    InvalidCompressionFormat,
+    InvalidDigest,
+    InvalidEncryptionAlgorithmError,
+    InvalidExpressionType,
+    InvalidObjectState,
+    InvalidRange,
+    InvalidRedirectLocation,
+    InvalidRequest,
+    InvalidTag,
+    InvalidURI,
+    JSONParsingError,
+    KeyTooLongError,
+    MalformedACLError,
+    MalformedPolicy,
+    MalformedXML,
+    MaxMessageLengthExceeded,
+    MethodNotAllowed,
+    MissingContentLength,
     NoSuchBucket,
+    NoSuchBucketPolicy,
+    NoSuchCORSConfiguration,
     NoSuchKey,
-    _InternalError,
+    NoSuchLifecycleConfiguration,
+    NoSuchObjectLockConfiguration,
+    NoSuchTagSet,
+    NoSuchWebsiteConfiguration,
+    NotImplemented,
+    ObjectLockConfigurationNotFoundError,
+    OverMaxRecordSize,
+    ParseUnexpectedToken,
+    PreconditionFailed,
+    ReplicationConfigurationNotFoundError,
+    RequestTimeout,
+    RequestTimeTooSkewed,
+    ServerSideEncryptionConfigurationNotFoundError,
+    ServiceUnavailable,
+    SignatureDoesNotMatch,
+    SlowDown,
+    UnsupportedSyntax,
 }
 
 #[derive(Debug, Clone)]
 pub struct S3ErrorInfo {
-    pub status_code: hyper::StatusCode,
+    pub status_code: StatusCode,
     pub code: String,
-    // pub msg: String,
-    // pub resource: String,
-    // pub request_id: String,
+    pub msg: String,
+    pub resource: String,
+    pub request_id: String,
+}
+
+/// ErrorContext describes the failed request and is filled in by the server.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorContext {
+    pub resource: String,
+    pub request_id: String,
+    pub bucket: String,
+    pub key: String,
+    pub method: String,
+    /// HEAD responses carry only the status code, like S3 does
+    pub head_only: bool,
 }
 
 impl S3Error {
-    fn info(&self) -> S3ErrorInfo {
-        match self {
-            Self::BadRequest => S3ErrorInfo {
-                status_code: hyper::StatusCode::BAD_REQUEST,
-                code: "BadRequest".to_string(),
-            },
-            Self::BucketAlreadyExists => S3ErrorInfo {
-                status_code: hyper::StatusCode::CONFLICT,
-                code: "BucketAlreadyExists".to_string(),
-            },
-            Self::NoSuchBucket => S3ErrorInfo {
-                status_code: hyper::StatusCode::NOT_FOUND,
-                code: "NoSuchBucket".to_string(),
-            },
-            Self::NoSuchKey => S3ErrorInfo {
-                status_code: hyper::StatusCode::NOT_FOUND,
-                code: "NoSuchKey".to_string(),
-            },
-            _ => S3ErrorInfo {
-                status_code: hyper::StatusCode::INTERNAL_SERVER_ERROR,
-                code: "InternalError".to_string(),
-            },
+    pub fn info(&self) -> S3ErrorInfo {
+        let (status_code, msg) = match self {
+            Self::AccessDenied => (StatusCode::FORBIDDEN, "Access Denied"),
+            Self::AuthorizationHeaderMalformed => (
+                StatusCode::BAD_REQUEST,
+                "The authorization header you provided is invalid.",
+            ),
+            Self::AuthorizationQueryParametersError => (
+                StatusCode::BAD_REQUEST,
+                "The authorization query parameters you provided are invalid.",
+            ),
+            Self::BadDigest => (
+                StatusCode::BAD_REQUEST,
+                "The Content-MD5 you specified did not match what we received.",
+            ),
+            Self::BadRequest => (StatusCode::BAD_REQUEST, "Bad Request"),
+            Self::BucketAlreadyExists => (
+                StatusCode::CONFLICT,
+                "The requested bucket name is not available. The bucket namespace is shared by all users of the system. Please select a different name and try again.",
+            ),
+            Self::BucketAlreadyOwnedByYou => (
+                StatusCode::CONFLICT,
+                "Your previous request to create the named bucket succeeded and you already own it.",
+            ),
+            Self::BucketNotEmpty => (
+                StatusCode::CONFLICT,
+                "The bucket you tried to delete is not empty",
+            ),
+            Self::CastFailed => (
+                StatusCode::BAD_REQUEST,
+                "Attempt to convert from one data type to another using CAST failed in the SQL expression.",
+            ),
+            Self::CSVParsingError => (
+                StatusCode::BAD_REQUEST,
+                "Encountered an error parsing the CSV file.",
+            ),
+            Self::EntityTooLarge => (
+                StatusCode::BAD_REQUEST,
+                "Your proposed upload exceeds the maximum allowed object size.",
+            ),
+            Self::ExpressionTooLong => (
+                StatusCode::BAD_REQUEST,
+                "The SQL expression is too long: The maximum byte-length for the SQL expression is 256 KB.",
+            ),
+            Self::IllegalLocationConstraintException => (
+                StatusCode::BAD_REQUEST,
+                "The specified location constraint is incompatible with the region of this endpoint.",
+            ),
+            Self::IncompleteBody => (
+                StatusCode::BAD_REQUEST,
+                "You did not provide the number of bytes specified by the Content-Length HTTP header.",
+            ),
+            Self::InternalError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We encountered an internal error. Please try again.",
+            ),
+            Self::InvalidAccessKeyId => (
+                StatusCode::FORBIDDEN,
+                "The AWS access key Id you provided does not exist in our records.",
+            ),
+            Self::InvalidArgument => (StatusCode::BAD_REQUEST, "Invalid Argument"),
+            Self::InvalidBucketName => (
+                StatusCode::BAD_REQUEST,
+                "The specified bucket is not valid.",
+            ),
+            Self::InvalidBucketState => (
+                StatusCode::CONFLICT,
+                "The request is not valid with the current state of the bucket.",
+            ),
+            Self::InvalidCompressionFormat => (
+                StatusCode::BAD_REQUEST,
+                "The file is not in a supported compression format. Only GZIP and BZIP2 are supported.",
+            ),
+            Self::InvalidDigest => (
+                StatusCode::BAD_REQUEST,
+                "The Content-MD5 you specified is not valid.",
+            ),
+            Self::InvalidEncryptionAlgorithmError => (
+                StatusCode::BAD_REQUEST,
+                "The encryption request you specified is not valid. The valid value is AES256.",
+            ),
+            Self::InvalidExpressionType => (
+                StatusCode::BAD_REQUEST,
+                "The ExpressionType is invalid. Only SQL expressions are supported.",
+            ),
+            Self::InvalidObjectState => (
+                StatusCode::FORBIDDEN,
+                "The operation is not valid for the current state of the object.",
+            ),
+            Self::InvalidRange => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "The requested range is not satisfiable",
+            ),
+            Self::InvalidRedirectLocation => (
+                StatusCode::BAD_REQUEST,
+                "The website redirect location must have a prefix of 'http://' or 'https://' or '/'.",
+            ),
+            Self::InvalidRequest => (StatusCode::BAD_REQUEST, "Invalid Request"),
+            Self::InvalidTag => (
+                StatusCode::BAD_REQUEST,
+                "The tag provided was not a valid tag.",
+            ),
+            Self::InvalidURI => (
+                StatusCode::BAD_REQUEST,
+                "Couldn't parse the specified URI.",
+            ),
+            Self::JSONParsingError => (
+                StatusCode::BAD_REQUEST,
+                "Encountered an error parsing the JSON file.",
+            ),
+            Self::KeyTooLongError => (StatusCode::BAD_REQUEST, "Your key is too long"),
+            Self::MalformedACLError => (
+                StatusCode::BAD_REQUEST,
+                "The XML you provided was not well-formed or did not validate against our published schema",
+            ),
+            Self::MalformedPolicy => (
+                StatusCode::BAD_REQUEST,
+                "Policies must be valid JSON and follow the policy grammar",
+            ),
+            Self::MalformedXML => (
+                StatusCode::BAD_REQUEST,
+                "The XML you provided was not well-formed or did not validate against our published schema",
+            ),
+            Self::MaxMessageLengthExceeded => (StatusCode::BAD_REQUEST, "Your request was too big."),
+            Self::MethodNotAllowed => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "The specified method is not allowed against this resource.",
+            ),
+            Self::MissingContentLength => (
+                StatusCode::LENGTH_REQUIRED,
+                "You must provide the Content-Length HTTP header.",
+            ),
+            Self::NoSuchBucket => (
+                StatusCode::NOT_FOUND,
+                "The specified bucket does not exist",
+            ),
+            Self::NoSuchBucketPolicy => (
+                StatusCode::NOT_FOUND,
+                "The bucket policy does not exist",
+            ),
+            Self::NoSuchCORSConfiguration => (
+                StatusCode::NOT_FOUND,
+                "The CORS configuration does not exist",
+            ),
+            Self::NoSuchKey => (StatusCode::NOT_FOUND, "The specified key does not exist."),
+            Self::NoSuchLifecycleConfiguration => (
+                StatusCode::NOT_FOUND,
+                "The lifecycle configuration does not exist",
+            ),
+            Self::NoSuchObjectLockConfiguration => (
+                StatusCode::NOT_FOUND,
+                "The specified object does not have a ObjectLock configuration",
+            ),
+            Self::NoSuchTagSet => (StatusCode::NOT_FOUND, "The TagSet does not exist"),
+            Self::NoSuchWebsiteConfiguration => (
+                StatusCode::NOT_FOUND,
+                "The specified bucket does not have a website configuration",
+            ),
+            Self::NotImplemented => (
+                StatusCode::NOT_IMPLEMENTED,
+                "A header or query you provided implies functionality that is not implemented",
+            ),
+            Self::ObjectLockConfigurationNotFoundError => (
+                StatusCode::NOT_FOUND,
+                "Object Lock configuration does not exist for this bucket",
+            ),
+            Self::OverMaxRecordSize => (
+                StatusCode::BAD_REQUEST,
+                "The length of a record in the input or result is greater than maxCharsPerRecord of 1 MB.",
+            ),
+            Self::ParseUnexpectedToken => (
+                StatusCode::BAD_REQUEST,
+                "The SQL expression contains an unexpected token.",
+            ),
+            Self::PreconditionFailed => (
+                StatusCode::PRECONDITION_FAILED,
+                "At least one of the pre-conditions you specified did not hold",
+            ),
+            Self::ReplicationConfigurationNotFoundError => (
+                StatusCode::NOT_FOUND,
+                "The replication configuration was not found",
+            ),
+            Self::RequestTimeout => (
+                StatusCode::BAD_REQUEST,
+                "Your socket connection to the server was not read from or written to within the timeout period.",
+            ),
+            Self::RequestTimeTooSkewed => (
+                StatusCode::FORBIDDEN,
+                "The difference between the request time and the server's time is too large.",
+            ),
+            Self::ServerSideEncryptionConfigurationNotFoundError => (
+                StatusCode::NOT_FOUND,
+                "The server side encryption configuration was not found",
+            ),
+            Self::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service is unable to handle request.",
+            ),
+            Self::SignatureDoesNotMatch => (
+                StatusCode::FORBIDDEN,
+                "The request signature we calculated does not match the signature you provided. Check your key and signing method.",
+            ),
+            Self::SlowDown => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Please reduce your request rate.",
+            ),
+            Self::UnsupportedSyntax => (
+                StatusCode::BAD_REQUEST,
+                "The SQL expression contains unsupported syntax.",
+            ),
+        };
+        S3ErrorInfo {
+            status_code,
+            code: format!("{:?}", self),
+            msg: msg.to_string(),
+            resource: String::new(),
+            request_id: String::new(),
         }
     }
-}
 
-impl ResWriter for S3Error {
-    fn write(self) -> HttpResponse {
-        let mut w = BodyWriter::new_xml();
-        let info = self.info();
-        w.append("<Error>");
-        w.append_xml("Code", info.code.as_str());
-        // w.append_xml("Message", info.msg.as_str());
-        // w.append_xml("Resource", info.resource.as_str());
-        // w.append_xml("RequestId", info.request_id.as_str());
-        w.append("</Error>");
-
-        let mut r = HttpResponse::new(w.body());
+    /// Write the error response with the request context.
+    /// Errors that refer to a bucket or key include it like S3 does.
+    pub fn write_with(self, ctx: &ErrorContext) -> HttpResponse {
+        let info = S3ErrorInfo {
+            resource: ctx.resource.to_owned(),
+            request_id: ctx.request_id.to_owned(),
+            ..self.info()
+        };
+        let body = if ctx.head_only {
+            Body::empty()
+        } else {
+            let mut w = BodyWriter::new_xml();
+            w.append("<Error>");
+            w.append_xml("Code", info.code.as_str());
+            w.append_xml("Message", info.msg.as_str());
+            match self {
+                Self::NoSuchBucket
+                | Self::NoSuchBucketPolicy
+                | Self::NoSuchCORSConfiguration
+                | Self::NoSuchTagSet
+                | Self::NoSuchLifecycleConfiguration
+                | Self::NoSuchWebsiteConfiguration
+                | Self::ServerSideEncryptionConfigurationNotFoundError
+                | Self::ObjectLockConfigurationNotFoundError
+                | Self::ReplicationConfigurationNotFoundError
+                | Self::InvalidBucketState
+                | Self::BucketAlreadyExists
+                | Self::BucketAlreadyOwnedByYou
+                | Self::BucketNotEmpty
+                | Self::InvalidBucketName => {
+                    w.append_xml("BucketName", ctx.bucket.as_str());
+                }
+                Self::NoSuchKey | Self::NoSuchObjectLockConfiguration | Self::KeyTooLongError => {
+                    w.append_xml("Key", ctx.key.as_str());
+                }
+                Self::MethodNotAllowed => {
+                    w.append_xml("Method", ctx.method.as_str());
+                    w.append_xml(
+                        "ResourceType",
+                        if ctx.key.is_empty() {
+                            "BUCKET"
+                        } else {
+                            "OBJECT"
+                        },
+                    );
+                }
+                _ => {}
+            }
+            w.append_xml("Resource", info.resource.as_str());
+            w.append_xml("RequestId", info.request_id.as_str());
+            w.append("</Error>");
+            w.body()
+        };
+        let mut r = HttpResponse::new(body);
         *r.status_mut() = info.status_code;
+        if !ctx.head_only {
+            r.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/xml"),
+            );
+        }
+        r.extensions_mut().insert(self);
         r
     }
 }
+
+impl std::fmt::Display for S3Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let info = self.info();
+        write!(f, "{}: {}", info.code, info.msg)
+    }
+}
+
+impl std::error::Error for S3Error {}
+
+impl S3Error {
+    /// Write without request context, the server rewrites error responses
+    /// with the context using write_with() which finds the error in the extensions.
+    pub fn write(self) -> HttpResponse {
+        self.write_with(&ErrorContext::default())
+    }
+}
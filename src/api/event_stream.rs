@@ -0,0 +1,73 @@
+use crate::api::*;
+use hyper::body::Bytes;
+
+/// Header values of the event stream are all strings.
+const STRING_HEADER: u8 = 7;
+
+/// Encode a message of the AWS event stream format, used by SelectObjectContent:
+///
+/// ```text
+/// total length (u32) | headers length (u32) | prelude crc32 (u32)
+/// headers | payload | message crc32 (u32)
+/// ```
+///
+/// Each header is a name length (u8), the name, the value type (7 for string),
+/// the value length (u16) and the value. Integers are big endian.
+pub fn encode_message(headers: &[(&str, &str)], payload: &[u8]) -> Bytes {
+    let mut hbuf = Vec::new();
+    for (name, value) in headers {
+        hbuf.push(name.len() as u8);
+        hbuf.extend_from_slice(name.as_bytes());
+        hbuf.push(STRING_HEADER);
+        hbuf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        hbuf.extend_from_slice(value.as_bytes());
+    }
+    let total = 12 + hbuf.len() + payload.len() + 4;
+    let mut buf = Vec::with_capacity(total);
+    buf.extend_from_slice(&(total as u32).to_be_bytes());
+    buf.extend_from_slice(&(hbuf.len() as u32).to_be_bytes());
+    let prelude_crc = crc32fast::hash(&buf);
+    buf.extend_from_slice(&prelude_crc.to_be_bytes());
+    buf.extend_from_slice(&hbuf);
+    buf.extend_from_slice(payload);
+    let message_crc = crc32fast::hash(&buf);
+    buf.extend_from_slice(&message_crc.to_be_bytes());
+    Bytes::from(buf)
+}
+
+/// An event message, e.g. Records, Stats, Progress or End.
+/// Events without a payload have no content type.
+pub fn event_message(event_type: &str, payload: &[u8]) -> Bytes {
+    let content_type = match event_type {
+        "Records" => "application/octet-stream",
+        _ => "text/xml",
+    };
+    match payload.is_empty() {
+        true => encode_message(
+            &[(":message-type", "event"), (":event-type", event_type)],
+            payload,
+        ),
+        false => encode_message(
+            &[
+                (":message-type", "event"),
+                (":event-type", event_type),
+                (":content-type", content_type),
+            ],
+            payload,
+        ),
+    }
+}
+
+/// An error message, which ends the stream when the request fails
+/// after the response started.
+pub fn error_message(err: &S3Error) -> Bytes {
+    let info = err.info();
+    encode_message(
+        &[
+            (":message-type", "error"),
+            (":error-code", &info.code),
+            (":error-message", &info.msg),
+        ],
+        &[],
+    )
+}
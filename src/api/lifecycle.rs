@@ -0,0 +1,450 @@
+use crate::api::*;
+use chrono::{DateTime, Duration as Days, NaiveTime, Timelike, Utc};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+/// Limits of the lifecycle configuration, like S3.
+pub const MAX_LIFECYCLE_RULES: usize = 1000;
+pub const MAX_RULE_ID_LEN: usize = 255;
+
+/// LifecycleConfiguration is the `?lifecycle` document of a bucket.
+///
+/// Expiration is applied by the lifecycle worker to any layer. Noncurrent version
+/// expiration and aborting incomplete multipart uploads are validated and kept
+/// for layers that keep versions and uploads, the worker does not apply them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LifecycleConfiguration {
+    #[serde(rename = "Rule", default)]
+    pub rules: Vec<LifecycleRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleRule {
+    #[serde(rename = "ID", default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    /// Enabled or Disabled
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<LifecycleFilter>,
+    /// the deprecated rule level prefix, instead of a filter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<LifecycleExpiration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noncurrent_version_expiration: Option<NoncurrentVersionExpiration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abort_incomplete_multipart_upload: Option<AbortIncompleteMultipartUpload>,
+}
+
+/// The filter has at most one of prefix, tag or and.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<Tag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub and: Option<LifecycleAnd>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleAnd {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(rename = "Tag", default)]
+    pub tags: Vec<Tag>,
+}
+
+/// The expiration has exactly one of days, date or expired object delete marker.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleExpiration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days: Option<u32>,
+    /// ISO 8601 at midnight UTC, e.g. 2030-01-01T00:00:00.000Z
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expired_object_delete_marker: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NoncurrentVersionExpiration {
+    pub noncurrent_days: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newer_noncurrent_versions: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AbortIncompleteMultipartUpload {
+    pub days_after_initiation: u32,
+}
+
+/// LifecycleStats counts what a lifecycle run did.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LifecycleStats {
+    pub buckets: u64,
+    pub expired: u64,
+    pub errors: u64,
+}
+
+impl LifecycleConfiguration {
+    /// Read and validate a LifecycleConfiguration document from a request body.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let config: LifecycleConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("LifecycleConfiguration", self)
+    }
+
+    /// Check the rules like S3 does, documents that don't follow the schema
+    /// are MalformedXML and values out of range are InvalidArgument.
+    pub fn validate(&self) -> Result<(), S3Error> {
+        if self.rules.is_empty() || self.rules.len() > MAX_LIFECYCLE_RULES {
+            return Err(S3Error::MalformedXML);
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.id.chars().count() > MAX_RULE_ID_LEN {
+                return Err(S3Error::InvalidArgument);
+            }
+            if !rule.id.is_empty() && self.rules[..i].iter().any(|r| r.id == rule.id) {
+                return Err(S3Error::InvalidArgument);
+            }
+            rule.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Whether matching needs the object tags, to skip fetching them otherwise.
+    pub fn needs_tags(&self) -> bool {
+        self.rules.iter().any(|r| !r.filter_tags().is_empty())
+    }
+
+    /// The earliest expiration of the enabled rules that match the object,
+    /// with the id of the rule.
+    pub fn expiration(
+        &self,
+        key: &str,
+        tags: &[Tag],
+        last_modified: DateTime<Utc>,
+    ) -> Option<(DateTime<Utc>, &str)> {
+        self.rules
+            .iter()
+            .filter(|r| r.matches(key, tags))
+            .filter_map(|r| r.expiry(last_modified).map(|t| (t, r.id.as_str())))
+            .min_by_key(|(t, _)| *t)
+    }
+}
+
+impl LifecycleRule {
+    pub fn is_enabled(&self) -> bool {
+        self.status == "Enabled"
+    }
+
+    fn validate(&self) -> Result<(), S3Error> {
+        if self.status != "Enabled" && self.status != "Disabled" {
+            return Err(S3Error::MalformedXML);
+        }
+        if self.prefix.is_some() && self.filter.is_some() {
+            return Err(S3Error::MalformedXML);
+        }
+        if let Some(filter) = &self.filter {
+            let set = [
+                filter.prefix.is_some(),
+                filter.tag.is_some(),
+                filter.and.is_some(),
+            ];
+            if set.iter().filter(|s| **s).count() > 1 {
+                return Err(S3Error::MalformedXML);
+            }
+        }
+        if self.expiration.is_none()
+            && self.noncurrent_version_expiration.is_none()
+            && self.abort_incomplete_multipart_upload.is_none()
+        {
+            return Err(S3Error::InvalidRequest);
+        }
+        if let Some(exp) = &self.expiration {
+            let set = [
+                exp.days.is_some(),
+                exp.date.is_some(),
+                exp.expired_object_delete_marker.is_some(),
+            ];
+            if set.iter().filter(|s| **s).count() != 1 {
+                return Err(S3Error::MalformedXML);
+            }
+            if exp.days == Some(0) {
+                return Err(S3Error::InvalidArgument);
+            }
+            if let Some(date) = &exp.date {
+                parse_midnight(date)?;
+            }
+        }
+        if let Some(nve) = &self.noncurrent_version_expiration {
+            if nve.noncurrent_days == 0 {
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        if let Some(abort) = &self.abort_incomplete_multipart_upload {
+            // uploads have no tags to filter on
+            if abort.days_after_initiation == 0 || !self.filter_tags().is_empty() {
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        Ok(())
+    }
+
+    fn filter_prefix(&self) -> &str {
+        let filter = self.filter.as_ref();
+        self.prefix
+            .as_deref()
+            .or_else(|| filter.and_then(|f| f.prefix.as_deref()))
+            .or_else(|| filter.and_then(|f| f.and.as_ref()?.prefix.as_deref()))
+            .unwrap_or("")
+    }
+
+    fn filter_tags(&self) -> Vec<&Tag> {
+        match &self.filter {
+            Some(LifecycleFilter { tag: Some(tag), .. }) => vec![tag],
+            Some(LifecycleFilter { and: Some(and), .. }) => and.tags.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Enabled rules match keys with the prefix that have all the filter tags.
+    pub fn matches(&self, key: &str, tags: &[Tag]) -> bool {
+        self.is_enabled()
+            && key.starts_with(self.filter_prefix())
+            && self.filter_tags().iter().all(|t| tags.contains(t))
+    }
+
+    /// Objects expire at the date, or the midnight UTC that follows
+    /// the given days after the last modification, like S3.
+    /// Days past the dates chrono can represent never expire.
+    pub fn expiry(&self, last_modified: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let exp = self.expiration.as_ref()?;
+        if let Some(date) = &exp.date {
+            return parse_midnight(date).ok();
+        }
+        let t = last_modified.checked_add_signed(Days::days(exp.days? as i64))?;
+        let midnight = t.date_naive().and_time(NaiveTime::MIN).and_utc();
+        match t == midnight {
+            true => Some(midnight),
+            false => midnight.checked_add_signed(Days::days(1)),
+        }
+    }
+}
+
+/// Lifecycle dates must be at midnight UTC.
+fn parse_midnight(date: &str) -> Result<DateTime<Utc>, S3Error> {
+    let t = DateTime::parse_from_rfc3339(date)
+        .map_err(|_| S3Error::InvalidArgument)?
+        .with_timezone(&Utc);
+    if t.num_seconds_from_midnight() != 0 || t.nanosecond() != 0 {
+        return Err(S3Error::InvalidArgument);
+    }
+    Ok(t)
+}
+
+/// The x-amz-expiration header of objects that a rule expires.
+pub fn expiration_header(expiry: DateTime<Utc>, rule_id: &str) -> String {
+    format!(
+        "expiry-date=\"{}\", rule-id=\"{}\"",
+        expiry.format("%a, %d %b %Y %H:%M:%S GMT"),
+        rule_id
+    )
+}
+
+/// The x-amz-expiration header of an object that a lifecycle rule of its bucket
+/// expires, None when no rule matches or the layer keeps no lifecycle.
+pub async fn object_expiration<API: ApiLayer + ?Sized>(
+    api: &API,
+    bucket: &str,
+    object: &ObjectInfo,
+) -> Option<String> {
+    let req = get_bucket_lifecycle::Req::new(get_bucket_lifecycle::Params {
+        bucket: bucket.to_string(),
+    });
+    let config = api.get_bucket_lifecycle(req).await.ok()?.into_body().config;
+    let last_modified = DateTime::parse_from_rfc3339(&object.last_modified)
+        .ok()?
+        .with_timezone(&Utc);
+    let tags = match config.needs_tags() && object.tag_count > 0 {
+        true => object_tags(api, bucket, &object.key).await,
+        false => Vec::new(),
+    };
+    let (expiry, rule_id) = config.expiration(&object.key, &tags, last_modified)?;
+    Some(expiration_header(expiry, rule_id))
+}
+
+/// Expire the objects of every bucket that has a lifecycle configuration.
+/// Buckets are enumerated with `usage` which covers all owners, layers that
+/// don't implement it fall back to `list_buckets`. Failures are logged
+/// and counted, the run goes on with the next object or bucket.
+pub async fn apply_lifecycle<API: ApiLayer + ?Sized>(
+    api: &API,
+    now: DateTime<Utc>,
+) -> Result<LifecycleStats, S3Error> {
+    let buckets = match api.usage().await {
+        Ok(usage) => usage.into_iter().map(|u| u.info).collect(),
+        Err(S3Error::NotImplemented) => {
+            let req = list_buckets::Req::new(list_buckets::Params {});
+            api.list_buckets(req).await?.into_body().buckets
+        }
+        Err(err) => return Err(err),
+    };
+    let mut stats = LifecycleStats::default();
+    for bucket in buckets {
+        let req = get_bucket_lifecycle::Req::new(get_bucket_lifecycle::Params {
+            bucket: bucket.name.to_owned(),
+        });
+        let config = match api.get_bucket_lifecycle(req).await {
+            Ok(res) => res.into_body().config,
+            Err(S3Error::NoSuchLifecycleConfiguration | S3Error::NotImplemented) => continue,
+            Err(err) => {
+                tracing::warn!(bucket = bucket.name.as_str(), %err, "lifecycle get config failed");
+                stats.errors += 1;
+                continue;
+            }
+        };
+        stats.buckets += 1;
+        if let Err(err) = expire_bucket(api, &bucket.name, &config, now, &mut stats).await {
+            tracing::warn!(bucket = bucket.name.as_str(), %err, "lifecycle list failed");
+            stats.errors += 1;
+        }
+    }
+    Ok(stats)
+}
+
+async fn expire_bucket<API: ApiLayer + ?Sized>(
+    api: &API,
+    bucket: &str,
+    config: &LifecycleConfiguration,
+    now: DateTime<Utc>,
+    stats: &mut LifecycleStats,
+) -> Result<(), S3Error> {
+    let mut marker = String::new();
+    loop {
+        let req = list_objects::Req::new(list_objects::Params {
+            bucket: bucket.to_string(),
+            prefix: String::new(),
+            delimiter: String::new(),
+            marker: marker.to_owned(),
+            max_keys: list_objects::DEFAULT_MAX_KEYS,
+            encoding_type: String::new(),
+            v2: false,
+            start_after: String::new(),
+            continuation_token: String::new(),
+            fetch_owner: false,
+        });
+        let page = api.list_objects(req).await?.into_body();
+        for obj in page.objects.iter() {
+            let last_modified = match DateTime::parse_from_rfc3339(&obj.last_modified) {
+                Ok(t) => t.with_timezone(&Utc),
+                Err(_) => continue,
+            };
+            let tags = match config.needs_tags() {
+                true => object_tags(api, bucket, &obj.key).await,
+                false => Vec::new(),
+            };
+            let expiry = match config.expiration(&obj.key, &tags, last_modified) {
+                Some((expiry, _)) if expiry <= now => expiry,
+                _ => continue,
+            };
+            let req = delete_object::Req::new(delete_object::Params {
+                bucket: bucket.to_string(),
+                key: obj.key.to_owned(),
+                version_id: String::new(),
+                bypass_governance: false,
+            });
+            match api.delete_object(req).await {
+                Ok(_) => {
+                    tracing::info!(bucket, key = obj.key.as_str(), %expiry, "lifecycle expired object");
+                    stats.expired += 1;
+                }
+                Err(err) => {
+                    tracing::warn!(bucket, key = obj.key.as_str(), %err, "lifecycle delete failed");
+                    stats.errors += 1;
+                }
+            }
+        }
+        if !page.is_truncated {
+            return Ok(());
+        }
+        marker = match page.next_marker.is_empty() {
+            false => page.next_marker,
+            true => match page.objects.last() {
+                Some(obj) => obj.key.to_owned(),
+                None => return Ok(()),
+            },
+        };
+    }
+}
+
+/// Apply the lifecycle rules every interval until shutdown.
+pub async fn lifecycle_worker<API: ApiLayer>(
+    srv: Arc<S3Server<API>>,
+    interval: Duration,
+    mut shutdown: Shutdown,
+) -> Result<(), SyncError> {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.recv() => return Ok(()),
+        }
+        match apply_lifecycle(srv.api(), Utc::now()).await {
+            Ok(stats) => tracing::debug!(?stats, "lifecycle run done"),
+            Err(err) => tracing::warn!(%err, "lifecycle run failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(days: u32) -> LifecycleRule {
+        LifecycleRule {
+            id: String::new(),
+            status: String::from("Enabled"),
+            filter: None,
+            prefix: None,
+            expiration: Some(LifecycleExpiration {
+                days: Some(days),
+                ..LifecycleExpiration::default()
+            }),
+            noncurrent_version_expiration: None,
+            abort_incomplete_multipart_upload: None,
+        }
+    }
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn expiry_rounds_up_to_midnight() {
+        let expiry = rule(1).expiry(time("2024-03-01T10:30:00Z"));
+        assert_eq!(expiry, Some(time("2024-03-03T00:00:00Z")));
+        let expiry = rule(1).expiry(time("2024-03-01T00:00:00Z"));
+        assert_eq!(expiry, Some(time("2024-03-02T00:00:00Z")));
+    }
+
+    #[test]
+    fn expiry_past_the_representable_dates_never_expires() {
+        assert_eq!(rule(u32::MAX).expiry(Utc::now()), None);
+        let last = DateTime::<Utc>::MAX_UTC - Days::days(1);
+        assert_eq!(rule(1).expiry(last), None);
+    }
+}
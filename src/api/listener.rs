@@ -0,0 +1,341 @@
+use crate::api::*;
+use hyper::{server::conn::Http, service::service_fn};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    sync::{mpsc, watch},
+};
+
+/// How long a client has to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Listener is an address the server accepts connections on.
+#[derive(Clone)]
+pub enum Listener {
+    Http(SocketAddr),
+    Https(SocketAddr, TlsReloader),
+    Unix(PathBuf),
+    /// serves the admin endpoints (e.g. /metrics) instead of the S3 API
+    Admin(SocketAddr),
+    /// serves the buckets as static websites instead of the S3 API
+    Website(SocketAddr),
+}
+
+/// BoundListener is a listener that was bound and is ready to accept.
+pub enum BoundListener {
+    Tcp(TcpListener, Option<TlsReloader>),
+    Unix(UnixListener, PathBuf),
+    Admin(TcpListener),
+    Website(TcpListener),
+}
+
+/// Service is what the connections of a listener are served by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Service {
+    S3,
+    Admin,
+    Website,
+}
+
+/// Shutdown is held by every listener and connection task.
+/// Listeners stop accepting when it fires, and connections finish their
+/// in-flight requests and close. The handle knows all tasks are done
+/// once every clone was dropped.
+#[derive(Clone)]
+pub struct Shutdown {
+    signal: watch::Receiver<bool>,
+    _guard: mpsc::Sender<()>,
+}
+
+pub struct ShutdownHandle {
+    trigger: watch::Sender<bool>,
+    drained: mpsc::Receiver<()>,
+    template: Shutdown,
+}
+
+impl Listener {
+    /// Listeners from S3D_HTTP_ADDR (default 127.0.0.1:3000), S3D_HTTPS_ADDR
+    /// (default 127.0.0.1:3443, only with TLS configured), S3D_UNIX_SOCKET,
+    /// S3D_ADMIN_ADDR and S3D_WEBSITE_ADDR. Each is a comma separated list, empty to disable.
+    pub fn from_env() -> Result<Vec<Listener>, SyncError> {
+        let list = |name: &str, default: &str| {
+            std::env::var(name)
+                .unwrap_or_else(|_| default.to_string())
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let mut listeners = Vec::new();
+        for addr in list("S3D_HTTP_ADDR", "127.0.0.1:3000") {
+            listeners.push(Listener::Http(addr.parse()?));
+        }
+        if let Some(tls_config) = TlsConfig::from_env() {
+            let tls = TlsReloader::new(tls_config)?;
+            tls.watch()?;
+            for addr in list("S3D_HTTPS_ADDR", "127.0.0.1:3443") {
+                listeners.push(Listener::Https(addr.parse()?, tls.clone()));
+            }
+        }
+        for path in list("S3D_UNIX_SOCKET", "") {
+            listeners.push(Listener::Unix(PathBuf::from(path)));
+        }
+        for addr in list("S3D_ADMIN_ADDR", "") {
+            listeners.push(Listener::Admin(addr.parse()?));
+        }
+        for addr in list("S3D_WEBSITE_ADDR", "") {
+            listeners.push(Listener::Website(addr.parse()?));
+        }
+        Ok(listeners)
+    }
+
+    pub async fn bind(self) -> Result<BoundListener, SyncError> {
+        match self {
+            Listener::Http(addr) => Ok(BoundListener::Tcp(TcpListener::bind(addr).await?, None)),
+            Listener::Https(addr, tls) => Ok(BoundListener::Tcp(
+                TcpListener::bind(addr).await?,
+                Some(tls),
+            )),
+            Listener::Unix(path) => {
+                // a stale socket file from a previous run would fail the bind
+                if path.exists() {
+                    std::fs::remove_file(&path)?;
+                }
+                Ok(BoundListener::Unix(UnixListener::bind(&path)?, path))
+            }
+            Listener::Admin(addr) => Ok(BoundListener::Admin(TcpListener::bind(addr).await?)),
+            Listener::Website(addr) => Ok(BoundListener::Website(TcpListener::bind(addr).await?)),
+        }
+    }
+}
+
+impl BoundListener {
+    pub fn is_admin(&self) -> bool {
+        matches!(self, BoundListener::Admin(_))
+    }
+
+    pub fn is_website(&self) -> bool {
+        matches!(self, BoundListener::Website(_))
+    }
+
+    /// The URL clients should use to reach this listener.
+    pub fn url(&self) -> String {
+        match self {
+            BoundListener::Tcp(l, tls) => {
+                let scheme = if tls.is_some() { "https" } else { "http" };
+                match l.local_addr() {
+                    Ok(addr) => format!("{}://{}", scheme, addr),
+                    Err(_) => format!("{}://", scheme),
+                }
+            }
+            BoundListener::Admin(l) | BoundListener::Website(l) => match l.local_addr() {
+                Ok(addr) => format!("http://{}", addr),
+                Err(_) => "http://".to_string(),
+            },
+            BoundListener::Unix(_, path) => format!("unix://{}", path.display()),
+        }
+    }
+
+    /// Accept connections until shutdown, accept errors are logged and retried.
+    pub async fn run<API: ApiLayer + 'static>(
+        self,
+        srv: Arc<S3Server<API>>,
+        mut shutdown: Shutdown,
+    ) -> Result<(), SyncError> {
+        tracing::info!(url = %self.url(), "listening");
+        let service = match &self {
+            BoundListener::Admin(_) => Service::Admin,
+            BoundListener::Website(_) => Service::Website,
+            _ => Service::S3,
+        };
+        match self {
+            BoundListener::Tcp(listener, tls) => loop {
+                let (stream, remote_addr) = tokio::select! {
+                    _ = shutdown.recv() => return Ok(()),
+                    res = listener.accept() => match res {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            tracing::warn!(%err, "accept error");
+                            continue;
+                        }
+                    },
+                };
+                let conn = ConnInfo {
+                    remote_addr: Some(remote_addr),
+                    secure: tls.is_some(),
+                };
+                let srv = Arc::clone(&srv);
+                let shutdown = shutdown.clone();
+                match &tls {
+                    None => {
+                        tokio::spawn(serve_connection(srv, stream, conn, shutdown, Service::S3));
+                    }
+                    Some(tls) => {
+                        let acceptor = tls.acceptor();
+                        let mut shutdown = shutdown;
+                        tokio::spawn(async move {
+                            // clients that never finish the handshake don't hold the connection
+                            let handshake = tokio::time::timeout(
+                                TLS_HANDSHAKE_TIMEOUT,
+                                acceptor.accept(stream),
+                            );
+                            let stream = tokio::select! {
+                                _ = shutdown.recv() => return,
+                                res = handshake => match res {
+                                    Ok(Ok(stream)) => stream,
+                                    Ok(Err(err)) => {
+                                        tracing::debug!(%remote_addr, %err, "TLS handshake failed");
+                                        return;
+                                    }
+                                    Err(_) => {
+                                        tracing::debug!(%remote_addr, "TLS handshake timed out");
+                                        return;
+                                    }
+                                },
+                            };
+                            serve_connection(srv, stream, conn, shutdown, Service::S3).await
+                        });
+                    }
+                }
+            },
+            BoundListener::Unix(listener, path) => {
+                loop {
+                    let stream = tokio::select! {
+                        _ = shutdown.recv() => break,
+                        res = listener.accept() => match res {
+                            Ok((stream, _)) => stream,
+                            Err(err) => {
+                                tracing::warn!(%err, "accept error");
+                                continue;
+                            }
+                        },
+                    };
+                    let conn = ConnInfo {
+                        remote_addr: None,
+                        secure: false,
+                    };
+                    tokio::spawn(serve_connection(
+                        Arc::clone(&srv),
+                        stream,
+                        conn,
+                        shutdown.clone(),
+                        Service::S3,
+                    ));
+                }
+                std::fs::remove_file(&path)?;
+                Ok(())
+            }
+            BoundListener::Admin(listener) | BoundListener::Website(listener) => loop {
+                let (stream, remote_addr) = tokio::select! {
+                    _ = shutdown.recv() => return Ok(()),
+                    res = listener.accept() => match res {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            tracing::warn!(%err, "accept error");
+                            continue;
+                        }
+                    },
+                };
+                let conn = ConnInfo {
+                    remote_addr: Some(remote_addr),
+                    secure: false,
+                };
+                tokio::spawn(serve_connection(
+                    Arc::clone(&srv),
+                    stream,
+                    conn,
+                    shutdown.clone(),
+                    service,
+                ));
+            },
+        }
+    }
+}
+
+/// Serve http requests on the connection, on shutdown the connection
+/// completes the in-flight requests and closes.
+/// Admin and website connections are served by their handlers instead of the S3 API.
+async fn serve_connection<API, S>(
+    srv: Arc<S3Server<API>>,
+    stream: S,
+    conn: ConnInfo,
+    mut shutdown: Shutdown,
+    service: Service,
+) where
+    API: ApiLayer + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |mut req: HttpRequest| {
+        let srv = Arc::clone(&srv);
+        req.extensions_mut().insert(conn);
+        async move {
+            match service {
+                Service::S3 => srv.handler(req).await,
+                Service::Admin => srv.admin_handler(req).await,
+                Service::Website => srv.website_handler(req).await,
+            }
+        }
+    });
+    let connection = Http::new().serve_connection(stream, service);
+    tokio::pin!(connection);
+    let res = tokio::select! {
+        res = connection.as_mut() => res,
+        _ = shutdown.recv() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(err) = res {
+        tracing::debug!(remote_addr = ?conn.remote_addr, %err, "connection error");
+    }
+}
+
+impl Shutdown {
+    /// Wait for the shutdown signal.
+    pub async fn recv(&mut self) {
+        while !*self.signal.borrow() {
+            if self.signal.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        let (trigger, signal) = watch::channel(false);
+        let (guard, drained) = mpsc::channel(1);
+        ShutdownHandle {
+            trigger,
+            drained,
+            template: Shutdown {
+                signal,
+                _guard: guard,
+            },
+        }
+    }
+
+    pub fn subscribe(&self) -> Shutdown {
+        self.template.clone()
+    }
+
+    /// Signal all listeners and connections and wait for them to drain.
+    /// Returns false if the timeout expired before all connections closed.
+    pub async fn shutdown(self, timeout: Duration) -> bool {
+        let ShutdownHandle {
+            trigger,
+            mut drained,
+            template,
+        } = self;
+        drop(template);
+        let _ = trigger.send(true);
+        tokio::time::timeout(timeout, drained.recv()).await.is_ok()
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
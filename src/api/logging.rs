@@ -0,0 +1,195 @@
+use crate::api::*;
+use chrono::{DateTime, Utc};
+use hyper::{header::HeaderMap, Uri};
+use std::{
+    fs::{File, OpenOptions},
+    io::{LineWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Headers that carry credentials or keys, their values never reach the logs.
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "x-amz-security-token",
+    "x-amz-server-side-encryption-customer-key",
+    "x-amz-copy-source-server-side-encryption-customer-key",
+];
+
+/// Query params of presigned urls that carry credentials.
+const REDACTED_PARAMS: &[&str] = &["signature", "x-amz-signature", "x-amz-security-token"];
+
+const REDACTED: &str = "REDACTED";
+
+/// The request headers for logging, with credentials redacted.
+pub fn redact_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match REDACTED_HEADERS.contains(&name.as_str()) {
+                true => REDACTED.to_string(),
+                false => String::from_utf8_lossy(value.as_bytes()).to_string(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// The request path and query for logging, with presigned url signatures redacted.
+pub fn redact_uri(uri: &Uri) -> String {
+    let query = match uri.query() {
+        None => return uri.path().to_string(),
+        Some(q) => q,
+    };
+    let params: Vec<String> = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if REDACTED_PARAMS.contains(&name.to_lowercase().as_str()) => {
+                format!("{}={}", name, REDACTED)
+            }
+            _ => param.to_string(),
+        })
+        .collect();
+    format!("{}?{}", uri.path(), params.join("&"))
+}
+
+/// The signature version and auth type fields of the access log.
+pub fn auth_type(headers: &HeaderMap, qs: &QueryStr) -> (&'static str, &'static str) {
+    let auth = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if auth.starts_with("AWS4-HMAC-SHA256 ") {
+        ("SigV4", "AuthHeader")
+    } else if auth.starts_with("AWS ") {
+        ("SigV2", "AuthHeader")
+    } else if qs.has("X-Amz-Signature") {
+        ("SigV4", "QueryString")
+    } else if qs.has("Signature") {
+        ("SigV2", "QueryString")
+    } else {
+        ("", "")
+    }
+}
+
+/// AccessLogRecord is one line of the access log.
+/// Empty fields are written as `-` like S3 does.
+#[derive(Debug, Clone, Default)]
+pub struct AccessLogRecord {
+    pub time: DateTime<Utc>,
+    pub bucket_owner: String,
+    pub bucket: String,
+    pub remote_ip: String,
+    pub requester: String,
+    pub request_id: String,
+    /// e.g. REST.GET.OBJECT
+    pub operation: String,
+    pub key: String,
+    /// e.g. GET /bucket/key HTTP/1.1
+    pub request_uri: String,
+    pub status: u16,
+    pub error_code: String,
+    pub bytes_sent: Option<u64>,
+    pub object_size: Option<u64>,
+    pub total_time_ms: u128,
+    pub referer: String,
+    pub user_agent: String,
+    pub version_id: String,
+    pub host_id: String,
+    pub signature_version: String,
+    pub auth_type: String,
+    pub host: String,
+    pub secure: bool,
+}
+
+impl AccessLogRecord {
+    /// Format the record in the S3 server access log format, see
+    /// https://docs.aws.amazon.com/AmazonS3/latest/userguide/LogFormat.html
+    pub fn to_line(&self) -> String {
+        let field = |s: &str| match s.is_empty() {
+            true => "-".to_string(),
+            false => s.replace(' ', "%20"),
+        };
+        let quoted = |s: &str| match s.is_empty() {
+            true => "\"-\"".to_string(),
+            false => format!("\"{}\"", s.replace('"', "\\\"")),
+        };
+        let num = |n: Option<u64>| n.map_or("-".to_string(), |n| n.to_string());
+        format!(
+            "{} {} [{}] {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} - -",
+            field(&self.bucket_owner),
+            field(&self.bucket),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            field(&self.remote_ip),
+            field(&self.requester),
+            field(&self.request_id),
+            field(&self.operation),
+            field(&self.key),
+            quoted(&self.request_uri),
+            self.status,
+            field(&self.error_code),
+            num(self.bytes_sent),
+            num(self.object_size),
+            self.total_time_ms,
+            self.total_time_ms,
+            quoted(&self.referer),
+            quoted(&self.user_agent),
+            field(&self.version_id),
+            field(&self.host_id),
+            field(&self.signature_version),
+            // the negotiated cipher is not known at this level
+            "-",
+            field(&self.auth_type),
+            field(&self.host),
+            if self.secure { "TLS" } else { "-" },
+        )
+    }
+}
+
+/// The S3 operation name used in access logs, e.g. REST.PUT.OBJECT or REST.GET.ACL.
+pub fn log_operation(method: &str, op: Option<Op>) -> String {
+    let resource = op.map_or("UNKNOWN", |op| op.log_resource());
+    format!("REST.{}.{}", method, resource)
+}
+
+/// AccessLog appends a line for every request to a file.
+#[derive(Debug)]
+pub struct AccessLog {
+    path: PathBuf,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl AccessLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AccessLog, SyncError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+        Ok(AccessLog {
+            path: path.as_ref().to_path_buf(),
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append the record, failures are logged and do not fail the request.
+    pub fn write(&self, record: &AccessLogRecord) {
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(err) = writeln!(file, "{}", record.to_line()) {
+            tracing::warn!(path = ?self.path, %err, "access log write failed");
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::api::*;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Upper bounds of the request latency histogram buckets in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Metrics collects the request stats of a server and renders them
+/// in the prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    ops: Mutex<BTreeMap<&'static str, OpMetrics>>,
+    in_flight: Arc<AtomicI64>,
+}
+
+#[derive(Debug, Default)]
+struct OpMetrics {
+    /// by status code
+    requests: BTreeMap<u16, u64>,
+    /// by S3Error code
+    errors: BTreeMap<String, u64>,
+    /// cumulative like prometheus buckets, one per LATENCY_BUCKETS
+    latency_buckets: Vec<u64>,
+    latency_sum: f64,
+    latency_count: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+/// RequestMetrics describes one completed request.
+#[derive(Debug, Clone)]
+pub struct RequestMetrics<'a> {
+    pub op: &'static str,
+    pub status: u16,
+    pub error: Option<&'a S3Error>,
+    pub latency: Duration,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// InFlight counts a request as in flight until dropped,
+/// which also covers requests whose connection closed mid-way.
+pub struct InFlight(Arc<AtomicI64>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start_request(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(Arc::clone(&self.in_flight))
+    }
+
+    pub fn record(&self, req: &RequestMetrics) {
+        let mut ops = match self.ops.lock() {
+            Ok(ops) => ops,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let m = ops.entry(req.op).or_default();
+        *m.requests.entry(req.status).or_default() += 1;
+        if let Some(err) = req.error {
+            *m.errors.entry(format!("{:?}", err)).or_default() += 1;
+        }
+        if m.latency_buckets.is_empty() {
+            m.latency_buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        let secs = req.latency.as_secs_f64();
+        for (i, le) in LATENCY_BUCKETS.iter().enumerate() {
+            if secs <= *le {
+                m.latency_buckets[i] += 1;
+            }
+        }
+        m.latency_sum += secs;
+        m.latency_count += 1;
+        m.bytes_in += req.bytes_in;
+        m.bytes_out += req.bytes_out;
+    }
+
+    /// Render the request metrics followed by the layer stats.
+    pub fn render(&self, layer_stats: &[LayerStat]) -> String {
+        let mut out = String::new();
+        let ops = match self.ops.lock() {
+            Ok(ops) => ops,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        header(
+            &mut out,
+            "s3d_requests_total",
+            "Requests by op and status code.",
+            "counter",
+        );
+        for (op, m) in ops.iter() {
+            for (status, n) in &m.requests {
+                let _ = writeln!(
+                    out,
+                    "s3d_requests_total{{op=\"{}\",status=\"{}\"}} {}",
+                    op, status, n
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "s3d_errors_total",
+            "Error responses by op and S3 error code.",
+            "counter",
+        );
+        for (op, m) in ops.iter() {
+            for (code, n) in &m.errors {
+                let _ = writeln!(
+                    out,
+                    "s3d_errors_total{{op=\"{}\",code=\"{}\"}} {}",
+                    op, code, n
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "s3d_request_duration_seconds",
+            "Time to produce the response headers by op.",
+            "histogram",
+        );
+        for (op, m) in ops.iter() {
+            for (le, n) in LATENCY_BUCKETS.iter().zip(&m.latency_buckets) {
+                let _ = writeln!(
+                    out,
+                    "s3d_request_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    op, le, n
+                );
+            }
+            let _ = writeln!(
+                out,
+                "s3d_request_duration_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}",
+                op, m.latency_count
+            );
+            let _ = writeln!(
+                out,
+                "s3d_request_duration_seconds_sum{{op=\"{}\"}} {}",
+                op, m.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "s3d_request_duration_seconds_count{{op=\"{}\"}} {}",
+                op, m.latency_count
+            );
+        }
+
+        header(
+            &mut out,
+            "s3d_received_bytes_total",
+            "Request body bytes by op.",
+            "counter",
+        );
+        for (op, m) in ops.iter() {
+            let _ = writeln!(
+                out,
+                "s3d_received_bytes_total{{op=\"{}\"}} {}",
+                op, m.bytes_in
+            );
+        }
+
+        header(
+            &mut out,
+            "s3d_sent_bytes_total",
+            "Response body bytes by op.",
+            "counter",
+        );
+        for (op, m) in ops.iter() {
+            let _ = writeln!(out, "s3d_sent_bytes_total{{op=\"{}\"}} {}", op, m.bytes_out);
+        }
+
+        header(
+            &mut out,
+            "s3d_requests_in_flight",
+            "Requests being served.",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "s3d_requests_in_flight {}",
+            self.in_flight.load(Ordering::Relaxed)
+        );
+
+        let mut last_name = "";
+        for stat in layer_stats {
+            if stat.name != last_name {
+                let kind = match stat.kind {
+                    StatKind::Counter => "counter",
+                    StatKind::Gauge => "gauge",
+                };
+                header(&mut out, stat.name, stat.help, kind);
+                last_name = stat.name;
+            }
+            let labels: Vec<String> = stat
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect();
+            match labels.is_empty() {
+                true => {
+                    let _ = writeln!(out, "{} {}", stat.name, stat.value);
+                }
+                false => {
+                    let _ = writeln!(out, "{}{{{}}} {}", stat.name, labels.join(","), stat.value);
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
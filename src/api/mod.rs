@@ -1,11 +1,53 @@
-pub mod ops;
+pub mod admin;
+#[allow(clippy::module_inception)]
 pub mod api;
+pub mod copy;
+pub mod cors;
+pub mod encryption;
 pub mod errors;
+pub mod event_stream;
+pub mod lifecycle;
+pub mod listener;
+pub mod logging;
+pub mod metrics;
+pub mod notification;
+pub mod notifier;
+pub mod object_lock;
+pub mod ops;
+pub mod replication;
+pub mod router;
+pub mod select;
 pub mod server;
+pub mod sql;
+pub mod sse;
+pub mod tagging;
+pub mod tls;
 pub mod util;
+pub mod website;
+pub mod xml;
 
-pub use self::ops::*;
 pub use self::api::*;
+pub use self::copy::*;
+pub use self::cors::*;
+pub use self::encryption::*;
 pub use self::errors::*;
+pub use self::event_stream::*;
+pub use self::lifecycle::*;
+pub use self::listener::*;
+pub use self::logging::*;
+pub use self::metrics::*;
+pub use self::notification::*;
+pub use self::notifier::*;
+pub use self::object_lock::*;
+pub use self::ops::*;
+pub use self::replication::*;
+pub use self::router::*;
+pub use self::select::*;
 pub use self::server::*;
+pub use self::sql::*;
+pub use self::sse::*;
+pub use self::tagging::*;
+pub use self::tls::*;
 pub use self::util::*;
+pub use self::website::*;
+pub use self::xml::*;
@@ -0,0 +1,300 @@
+use crate::api::*;
+use chrono::{SecondsFormat, Utc};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The events that can be configured, wildcards match every event of the kind.
+pub const NOTIFICATION_EVENTS: &[&str] = &[
+    "s3:ObjectCreated:*",
+    "s3:ObjectCreated:Put",
+    "s3:ObjectCreated:Post",
+    "s3:ObjectCreated:Copy",
+    "s3:ObjectCreated:CompleteMultipartUpload",
+    "s3:ObjectRemoved:*",
+    "s3:ObjectRemoved:Delete",
+    "s3:ObjectRemoved:DeleteMarkerCreated",
+];
+
+/// Limits of the notification configuration, like S3.
+pub const MAX_NOTIFICATION_RULES: usize = 100;
+pub const MAX_FILTER_VALUE_LEN: usize = 1024;
+
+/// The ARN of a webhook target is `arn:s3d:webhook::{name}`.
+pub const WEBHOOK_ARN_PREFIX: &str = "arn:s3d:webhook::";
+
+/// NotificationConfiguration is the `?notification` document of a bucket.
+///
+/// Layers keep it and the server sends the matching events to its webhook targets,
+/// so every destination ARN must name a webhook the server is configured with.
+/// An empty configuration turns off the notifications of the bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationConfiguration {
+    #[serde(rename = "TopicConfiguration", default)]
+    pub topics: Vec<TopicConfiguration>,
+    #[serde(rename = "QueueConfiguration", default)]
+    pub queues: Vec<QueueConfiguration>,
+    #[serde(rename = "CloudFunctionConfiguration", default)]
+    pub cloud_functions: Vec<CloudFunctionConfiguration>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TopicConfiguration {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub topic: String,
+    #[serde(rename = "Event", default)]
+    pub events: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<NotificationFilter>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QueueConfiguration {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub queue: String,
+    #[serde(rename = "Event", default)]
+    pub events: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<NotificationFilter>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CloudFunctionConfiguration {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub cloud_function: String,
+    #[serde(rename = "Event", default)]
+    pub events: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<NotificationFilter>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NotificationFilter {
+    #[serde(rename = "S3Key", default)]
+    pub key: KeyFilter,
+}
+
+/// The key filter has at most one prefix and one suffix rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyFilter {
+    #[serde(rename = "FilterRule", default)]
+    pub rules: Vec<FilterRule>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FilterRule {
+    /// prefix or suffix, in any case
+    pub name: String,
+    pub value: String,
+}
+
+/// NotificationRule is a destination of any kind, see NotificationConfiguration::rules().
+#[derive(Debug, Clone, Copy)]
+pub struct NotificationRule<'a> {
+    pub id: &'a str,
+    pub arn: &'a str,
+    pub events: &'a [String],
+    pub filter: Option<&'a NotificationFilter>,
+}
+
+/// EventRequest is what the event records tell about the request that caused them.
+#[derive(Debug, Clone, Default)]
+pub struct EventRequest {
+    pub request_id: String,
+    pub host_id: String,
+    pub principal_id: String,
+    pub source_ip: String,
+}
+
+impl NotificationConfiguration {
+    /// Read and validate a NotificationConfiguration document from a request body,
+    /// an empty body is an empty configuration.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let config: NotificationConfiguration = match buf.is_empty() {
+            true => NotificationConfiguration::default(),
+            false => from_xml(&buf)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("NotificationConfiguration", self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty() && self.queues.is_empty() && self.cloud_functions.is_empty()
+    }
+
+    /// Check the rules like S3 does, every rule has known events and a valid filter.
+    /// Destinations are checked by the server, see Notifier::check_config().
+    pub fn validate(&self) -> Result<(), S3Error> {
+        let rules = self.rules();
+        if rules.len() > MAX_NOTIFICATION_RULES {
+            return Err(S3Error::InvalidArgument);
+        }
+        for (i, rule) in rules.iter().enumerate() {
+            if !rule.id.is_empty() && rules[..i].iter().any(|r| r.id == rule.id) {
+                return Err(S3Error::InvalidArgument);
+            }
+            if rule.events.is_empty() {
+                return Err(S3Error::MalformedXML);
+            }
+            if rule
+                .events
+                .iter()
+                .any(|e| !NOTIFICATION_EVENTS.contains(&e.as_str()))
+            {
+                return Err(S3Error::InvalidArgument);
+            }
+            if let Some(filter) = rule.filter {
+                filter.validate()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The rules of all destination kinds.
+    pub fn rules(&self) -> Vec<NotificationRule<'_>> {
+        let topics = self.topics.iter().map(|c| NotificationRule {
+            id: &c.id,
+            arn: &c.topic,
+            events: &c.events,
+            filter: c.filter.as_ref(),
+        });
+        let queues = self.queues.iter().map(|c| NotificationRule {
+            id: &c.id,
+            arn: &c.queue,
+            events: &c.events,
+            filter: c.filter.as_ref(),
+        });
+        let cloud_functions = self.cloud_functions.iter().map(|c| NotificationRule {
+            id: &c.id,
+            arn: &c.cloud_function,
+            events: &c.events,
+            filter: c.filter.as_ref(),
+        });
+        topics.chain(queues).chain(cloud_functions).collect()
+    }
+}
+
+impl NotificationRule<'_> {
+    /// The rule matches events it names or their wildcard, of keys that pass the filter.
+    /// Event names are given without the `s3:` prefix, e.g. ObjectCreated:Put.
+    pub fn matches(&self, event_name: &str, key: &str) -> bool {
+        let kind = event_name.split(':').next().unwrap_or("");
+        let event = self.events.iter().any(|e| match e.strip_prefix("s3:") {
+            Some(e) => e == event_name || (e.ends_with(":*") && e[..e.len() - 2] == *kind),
+            None => false,
+        });
+        event && self.filter.is_none_or(|f| f.matches(key))
+    }
+}
+
+impl NotificationFilter {
+    fn validate(&self) -> Result<(), S3Error> {
+        let mut prefix = false;
+        let mut suffix = false;
+        for rule in self.key.rules.iter() {
+            let seen = match rule.name.to_lowercase().as_str() {
+                "prefix" => &mut prefix,
+                "suffix" => &mut suffix,
+                _ => return Err(S3Error::InvalidArgument),
+            };
+            if *seen || rule.value.len() > MAX_FILTER_VALUE_LEN {
+                return Err(S3Error::InvalidArgument);
+            }
+            *seen = true;
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.key
+            .rules
+            .iter()
+            .all(|rule| match rule.name.to_lowercase().as_str() {
+                "prefix" => key.starts_with(&rule.value),
+                "suffix" => key.ends_with(&rule.value),
+                _ => false,
+            })
+    }
+}
+
+/// The S3 event record of an object event, see
+/// https://docs.aws.amazon.com/AmazonS3/latest/userguide/notification-content-structure.html
+///
+/// Keys are URL encoded like S3 does, and the sequencer orders the events of a key.
+pub fn event_record(
+    event_name: &str,
+    configuration_id: &str,
+    region: &str,
+    object: &ObjectInfo,
+    request: &EventRequest,
+    sequencer: &str,
+) -> Value {
+    let mut obj = json!({
+        "key": encode_url(&object.key),
+        "sequencer": sequencer,
+    });
+    if event_name.starts_with("ObjectCreated:") {
+        obj["size"] = json!(object.size);
+        obj["eTag"] = json!(object.etag.trim_matches('"'));
+    }
+    if !object.version_id.is_empty() {
+        obj["versionId"] = json!(object.version_id);
+    }
+    json!({
+        "eventVersion": "2.1",
+        "eventSource": "aws:s3",
+        "awsRegion": region,
+        "eventTime": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "eventName": event_name,
+        "userIdentity": { "principalId": request.principal_id },
+        "requestParameters": { "sourceIPAddress": request.source_ip },
+        "responseElements": {
+            "x-amz-request-id": request.request_id,
+            "x-amz-id-2": request.host_id,
+        },
+        "s3": {
+            "s3SchemaVersion": "1.0",
+            "configurationId": configuration_id,
+            "bucket": {
+                "name": object.bucket,
+                "ownerIdentity": { "principalId": object.owner.id },
+                "arn": format!("arn:aws:s3:::{}", object.bucket),
+            },
+            "object": obj,
+        },
+    })
+}
+
+/// The notification configuration of the bucket, None when the bucket has none
+/// or the layer does not keep it.
+pub async fn bucket_notification<API: ApiLayer + ?Sized>(
+    api: &API,
+    bucket: &str,
+) -> Option<NotificationConfiguration> {
+    let req = get_bucket_notification::Req::new(get_bucket_notification::Params {
+        bucket: bucket.to_string(),
+    });
+    let config = api
+        .get_bucket_notification(req)
+        .await
+        .ok()?
+        .into_body()
+        .config;
+    match config.is_empty() {
+        true => None,
+        false => Some(config),
+    }
+}
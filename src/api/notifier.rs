@@ -0,0 +1,505 @@
+use crate::api::*;
+use hyper::{client::HttpConnector, header::CONTENT_TYPE, Body, Client, Method, Request, Uri};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Notify;
+
+/// Events beyond this many waiting for a webhook are dropped, and logged.
+pub const MAX_QUEUED_EVENTS: usize = 10000;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Notifier sends the bucket events to the webhook targets of the server.
+///
+/// Every webhook has its own queue, delivered in order by notification_worker(),
+/// with a POST of `{"Records": [...]}` that must answer 2xx. Failed deliveries
+/// are retried with backoff until they succeed. With a queue directory every
+/// queued event is also kept in a file until delivered, so events survive
+/// restarts and receivers that are down.
+#[derive(Debug)]
+pub struct Notifier {
+    targets: BTreeMap<String, Arc<Webhook>>,
+    /// the last sequencer, see next_sequencer()
+    sequencer: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Webhook {
+    name: String,
+    url: Uri,
+    /// None keeps the queue only in memory
+    dir: Option<PathBuf>,
+    queue: Mutex<VecDeque<QueuedEvent>>,
+    wake: Notify,
+    delivered: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+struct QueuedEvent {
+    /// the file name in the queue directory, ordered like the queue
+    id: String,
+    body: String,
+}
+
+impl Notifier {
+    /// Create the webhook targets from (name, url) pairs. With a queue directory
+    /// each webhook keeps its queue in a sub directory by its name, and the events
+    /// left there by a previous run are queued again.
+    pub async fn open(
+        webhooks: &[(String, String)],
+        queue_dir: Option<&Path>,
+    ) -> Result<Self, SyncError> {
+        let mut targets = BTreeMap::new();
+        for (name, url) in webhooks {
+            if !valid_target_name(name) {
+                return Err(format!("invalid webhook name {:?}", name).into());
+            }
+            let url: Uri = url.parse()?;
+            if url.scheme_str() != Some("http") {
+                return Err(format!("webhook {} must be an http:// url", name).into());
+            }
+            let dir = queue_dir.map(|d| d.join(name));
+            let queue = match &dir {
+                Some(dir) => load_queue(dir).await?,
+                None => VecDeque::new(),
+            };
+            if !queue.is_empty() {
+                tracing::info!(
+                    webhook = name.as_str(),
+                    events = queue.len(),
+                    "resuming queued events"
+                );
+            }
+            let webhook = Webhook {
+                name: name.to_owned(),
+                url,
+                dir,
+                queue: Mutex::new(queue),
+                wake: Notify::new(),
+                delivered: AtomicU64::new(0),
+                failures: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+            };
+            if targets.insert(name.to_owned(), Arc::new(webhook)).is_some() {
+                return Err(format!("duplicate webhook {}", name).into());
+            }
+        }
+        Ok(Notifier {
+            targets,
+            sequencer: AtomicU64::new(0),
+        })
+    }
+
+    /// The ARN that names the webhook in notification configurations.
+    pub fn arn(name: &str) -> String {
+        format!("{}{}", WEBHOOK_ARN_PREFIX, name)
+    }
+
+    pub fn webhooks(&self) -> impl Iterator<Item = &str> {
+        self.targets.keys().map(|k| k.as_str())
+    }
+
+    /// Check that every destination of the configuration is a webhook of the server,
+    /// like S3 validates the destinations when the configuration is put.
+    pub fn check_config(&self, config: &NotificationConfiguration) -> Result<(), S3Error> {
+        for rule in config.rules() {
+            if self.target(rule.arn).is_none() {
+                tracing::debug!(arn = rule.arn, "notification destination is not a webhook");
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        Ok(())
+    }
+
+    fn target(&self, arn: &str) -> Option<&Arc<Webhook>> {
+        self.targets.get(arn.strip_prefix(WEBHOOK_ARN_PREFIX)?)
+    }
+
+    /// Queue an event of the object for every rule of the bucket that matches it.
+    /// Event names are given without the `s3:` prefix, e.g. ObjectCreated:Put.
+    pub async fn notify<API: ApiLayer + ?Sized>(
+        &self,
+        api: &API,
+        region: &str,
+        event_name: &str,
+        object: &ObjectInfo,
+        request: &EventRequest,
+    ) {
+        let config = match bucket_notification(api, &object.bucket).await {
+            Some(config) => config,
+            None => return,
+        };
+        let sequencer = self.next_sequencer();
+        for (i, rule) in config.rules().iter().enumerate() {
+            if !rule.matches(event_name, &object.key) {
+                continue;
+            }
+            let webhook = match self.target(rule.arn) {
+                Some(webhook) => webhook,
+                None => {
+                    tracing::warn!(
+                        bucket = object.bucket.as_str(),
+                        arn = rule.arn,
+                        "notification destination is not a webhook"
+                    );
+                    continue;
+                }
+            };
+            let record = event_record(
+                event_name,
+                rule.id,
+                region,
+                object,
+                request,
+                &format!("{:016X}", sequencer),
+            );
+            let body = json!({ "Records": [record] }).to_string();
+            webhook
+                .enqueue(format!("{:016X}-{:03}", sequencer, i), body)
+                .await;
+        }
+    }
+
+    /// Sequencers increase with every event, and across restarts unless the clock goes back.
+    fn next_sequencer(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let prev = self
+            .sequencer
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or(0);
+        now.max(prev + 1)
+    }
+
+    pub fn stats(&self) -> Vec<LayerStat> {
+        let mut stats = Vec::new();
+        for webhook in self.targets.values() {
+            let queued = webhook.queue.lock().unwrap().len();
+            stats.push(
+                LayerStat::gauge(
+                    "s3d_notification_queued",
+                    "Events waiting for delivery to the webhook.",
+                    queued as f64,
+                )
+                .label("webhook", &webhook.name),
+            );
+        }
+        for webhook in self.targets.values() {
+            stats.push(
+                LayerStat::counter(
+                    "s3d_notification_delivered_total",
+                    "Events delivered to the webhook.",
+                    webhook.delivered.load(Ordering::Relaxed) as f64,
+                )
+                .label("webhook", &webhook.name),
+            );
+        }
+        for webhook in self.targets.values() {
+            stats.push(
+                LayerStat::counter(
+                    "s3d_notification_failures_total",
+                    "Failed deliveries to the webhook, each is retried, and events not written to the queue directory.",
+                    webhook.failures.load(Ordering::Relaxed) as f64,
+                )
+                .label("webhook", &webhook.name),
+            );
+        }
+        for webhook in self.targets.values() {
+            stats.push(
+                LayerStat::counter(
+                    "s3d_notification_dropped_total",
+                    "Events dropped because the webhook queue was full.",
+                    webhook.dropped.load(Ordering::Relaxed) as f64,
+                )
+                .label("webhook", &webhook.name),
+            );
+        }
+        stats
+    }
+}
+
+impl Webhook {
+    /// The file is written before the event is queued, so the worker never removes
+    /// a file that is written after. The queue is checked for room when the event
+    /// is pushed, and the file of a dropped event is removed.
+    async fn enqueue(&self, id: String, body: String) {
+        let path = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", id)));
+        let mut written = false;
+        if let Some(path) = &path {
+            match tokio::fs::write(path, &body).await {
+                Ok(()) => written = true,
+                Err(err) => {
+                    // still delivered unless the process stops first
+                    self.failures.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(webhook = self.name.as_str(), ?path, %err, "notification queue write failed");
+                }
+            }
+        }
+        let queued = {
+            let mut queue = self.queue.lock().unwrap();
+            let room = queue.len() < MAX_QUEUED_EVENTS;
+            if room {
+                queue.push_back(QueuedEvent { id, body });
+            }
+            room
+        };
+        if queued {
+            self.wake.notify_one();
+            return;
+        }
+        tracing::warn!(
+            webhook = self.name.as_str(),
+            "notification queue full, event dropped"
+        );
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        if let (Some(path), true) = (&path, written) {
+            if let Err(err) = tokio::fs::remove_file(path).await {
+                tracing::warn!(webhook = self.name.as_str(), ?path, %err, "notification queue remove failed");
+            }
+        }
+    }
+
+    /// Deliver the queue in order until shutdown, the event at the front
+    /// is retried with backoff until the webhook accepts it.
+    async fn run(&self, client: &Client<HttpConnector>, mut shutdown: Shutdown) {
+        let mut delay = MIN_RETRY_DELAY;
+        loop {
+            let next = self.queue.lock().unwrap().front().cloned();
+            let event = match next {
+                Some(event) => event,
+                None => {
+                    tokio::select! {
+                        _ = self.wake.notified() => continue,
+                        _ = shutdown.recv() => break,
+                    }
+                }
+            };
+            let result = tokio::select! {
+                result = self.deliver(client, &event) => result,
+                _ = shutdown.recv() => break,
+            };
+            match result {
+                Ok(()) => {
+                    self.queue.lock().unwrap().pop_front();
+                    self.delivered.fetch_add(1, Ordering::Relaxed);
+                    delay = MIN_RETRY_DELAY;
+                    if let Some(dir) = &self.dir {
+                        let path = dir.join(format!("{}.json", event.id));
+                        if let Err(err) = tokio::fs::remove_file(&path).await {
+                            tracing::warn!(webhook = self.name.as_str(), ?path, %err, "notification queue remove failed");
+                        }
+                    }
+                }
+                Err(err) => {
+                    self.failures.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(webhook = self.name.as_str(), %err, retry_in = ?delay, "notification delivery failed");
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = shutdown.recv() => break,
+                    }
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+        let queued = self.queue.lock().unwrap().len();
+        if queued > 0 && self.dir.is_none() {
+            tracing::warn!(
+                webhook = self.name.as_str(),
+                queued,
+                "undelivered events are lost"
+            );
+        }
+    }
+
+    async fn deliver(
+        &self,
+        client: &Client<HttpConnector>,
+        event: &QueuedEvent,
+    ) -> Result<(), SyncError> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(event.body.to_owned()))?;
+        let res = tokio::time::timeout(DELIVERY_TIMEOUT, client.request(req)).await??;
+        if !res.status().is_success() {
+            return Err(format!("webhook answered {}", res.status()).into());
+        }
+        tracing::debug!(
+            webhook = self.name.as_str(),
+            id = event.id.as_str(),
+            "notification delivered"
+        );
+        Ok(())
+    }
+}
+
+/// Load the events left in the queue directory, creating it when missing.
+/// Files that are not valid JSON were cut short by a crash and are removed.
+async fn load_queue(dir: &Path) -> Result<VecDeque<QueuedEvent>, SyncError> {
+    tokio::fs::create_dir_all(dir).await?;
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(id) = name.strip_suffix(".json") {
+            files.push(id.to_string());
+        }
+    }
+    files.sort();
+    let mut queue = VecDeque::new();
+    for id in files {
+        let path = dir.join(format!("{}.json", id));
+        let body = tokio::fs::read_to_string(&path).await?;
+        if serde_json::from_str::<Value>(&body).is_err() {
+            tracing::warn!(?path, "removing partial queued event");
+            tokio::fs::remove_file(&path).await?;
+            continue;
+        }
+        queue.push_back(QueuedEvent { id, body });
+    }
+    Ok(queue)
+}
+
+/// Deliver the queued events of every webhook until shutdown.
+pub async fn notification_worker(
+    notifier: Arc<Notifier>,
+    shutdown: Shutdown,
+) -> Result<(), SyncError> {
+    let client = Client::new();
+    let runs = notifier
+        .targets
+        .values()
+        .map(|webhook| webhook.run(&client, shutdown.clone()));
+    futures::future::join_all(runs).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server,
+    };
+    use std::net::SocketAddr;
+
+    async fn free_addr() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    /// A receiver on the address that collects the bodies it is sent.
+    fn receiver(addr: SocketAddr, bodies: Arc<Mutex<Vec<String>>>) {
+        let make = make_service_fn(move |_| {
+            let bodies = Arc::clone(&bodies);
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                    let bodies = Arc::clone(&bodies);
+                    async move {
+                        let body = hyper::body::to_bytes(req.into_body()).await?;
+                        let body = String::from_utf8_lossy(&body).to_string();
+                        bodies.lock().unwrap().push(body);
+                        Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        tokio::spawn(Server::bind(&addr).serve(make));
+    }
+
+    fn queued_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir).unwrap().count()
+    }
+
+    #[tokio::test]
+    async fn queued_events_survive_restarts() {
+        let root = std::env::temp_dir().join(format!("s3d-notifier-{}", new_request_id()));
+        let addr = free_addr().await;
+        let webhooks = [(String::from("hook"), format!("http://{}/", addr))];
+        let notifier = Arc::new(Notifier::open(&webhooks, Some(&root)).await.unwrap());
+        let webhook = notifier.target(&Notifier::arn("hook")).unwrap();
+        for i in 0..3 {
+            let body = json!({ "event": i }).to_string();
+            webhook.enqueue(format!("{:016X}-000", i), body).await;
+        }
+        assert_eq!(queued_files(&root.join("hook")), 3);
+
+        // the receiver is down, so the worker keeps the events
+        let handle = ShutdownHandle::new();
+        let worker = tokio::spawn(notification_worker(
+            Arc::clone(&notifier),
+            handle.subscribe(),
+        ));
+        while webhook.failures.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(handle.shutdown(Duration::from_secs(5)).await);
+        worker.await.unwrap().unwrap();
+        assert_eq!(webhook.queue.lock().unwrap().len(), 3);
+        assert_eq!(queued_files(&root.join("hook")), 3);
+        drop(notifier);
+
+        // a restart queues them again and delivers them in order once the receiver is up
+        let notifier = Arc::new(Notifier::open(&webhooks, Some(&root)).await.unwrap());
+        let webhook = notifier.target(&Notifier::arn("hook")).unwrap();
+        assert_eq!(webhook.queue.lock().unwrap().len(), 3);
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        receiver(addr, Arc::clone(&bodies));
+        let handle = ShutdownHandle::new();
+        let worker = tokio::spawn(notification_worker(
+            Arc::clone(&notifier),
+            handle.subscribe(),
+        ));
+        while webhook.delivered.load(Ordering::Relaxed) < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(handle.shutdown(Duration::from_secs(5)).await);
+        worker.await.unwrap().unwrap();
+        let expected: Vec<_> = (0..3).map(|i| json!({ "event": i }).to_string()).collect();
+        assert_eq!(*bodies.lock().unwrap(), expected);
+        assert_eq!(queued_files(&root.join("hook")), 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_queues_drop_events() {
+        let root = std::env::temp_dir().join(format!("s3d-notifier-{}", new_request_id()));
+        let webhooks = [(String::from("hook"), String::from("http://127.0.0.1:1/"))];
+        let notifier = Notifier::open(&webhooks, Some(&root)).await.unwrap();
+        let webhook = notifier.target(&Notifier::arn("hook")).unwrap();
+        {
+            let mut queue = webhook.queue.lock().unwrap();
+            for i in 0..MAX_QUEUED_EVENTS {
+                queue.push_back(QueuedEvent {
+                    id: format!("{:016X}-000", i),
+                    body: String::new(),
+                });
+            }
+        }
+        webhook
+            .enqueue(String::from("dropped"), String::from("{}"))
+            .await;
+        assert_eq!(webhook.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(webhook.queue.lock().unwrap().len(), MAX_QUEUED_EVENTS);
+        assert_eq!(queued_files(&root.join("hook")), 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,550 @@
+use crate::api::*;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use hyper::{Body, HeaderMap};
+use serde::{Deserialize, Serialize};
+
+pub const OBJECT_LOCK_ENABLED_HEADER: &str = "x-amz-bucket-object-lock-enabled";
+pub const OBJECT_LOCK_MODE_HEADER: &str = "x-amz-object-lock-mode";
+pub const OBJECT_LOCK_RETAIN_UNTIL_HEADER: &str = "x-amz-object-lock-retain-until-date";
+pub const OBJECT_LOCK_LEGAL_HOLD_HEADER: &str = "x-amz-object-lock-legal-hold";
+pub const BYPASS_GOVERNANCE_HEADER: &str = "x-amz-bypass-governance-retention";
+
+/// The action that allows x-amz-bypass-governance-retention, checked besides the op action.
+pub const BYPASS_GOVERNANCE_ACTION: &str = "s3:BypassGovernanceRetention";
+
+/// Limit of the default retention period, like S3.
+pub const MAX_RETENTION_DAYS: u32 = 36500;
+
+/// RetentionMode of a locked object, GOVERNANCE can be lifted by users allowed
+/// to bypass it, COMPLIANCE can't be shortened or removed by anyone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RetentionMode {
+    Governance,
+    Compliance,
+}
+
+/// ObjectLockConfiguration is the `?object-lock` document of a bucket.
+/// Object Lock is enabled when the bucket is created and can't be disabled,
+/// the rule sets the default retention of new objects.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectLockConfiguration {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub object_lock_enabled: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<ObjectLockRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectLockRule {
+    pub default_retention: DefaultRetention,
+}
+
+/// The period is either days or years.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DefaultRetention {
+    pub mode: RetentionMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub years: Option<u32>,
+}
+
+/// ObjectRetention is the `?retention` document of an object.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectRetention {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<RetentionMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retain_until_date: Option<String>,
+}
+
+/// LegalHold is the `?legal-hold` document of an object, with status ON or OFF.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LegalHold {
+    pub status: String,
+}
+
+/// ObjectLock is the retention and legal hold of an object.
+/// A legal hold protects the object until it is removed, regardless of retention.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectLock {
+    pub mode: Option<RetentionMode>,
+    pub retain_until: Option<DateTime<Utc>>,
+    pub legal_hold: bool,
+}
+
+impl ObjectLockConfiguration {
+    /// The configuration of a bucket created with Object Lock enabled.
+    pub fn enabled() -> Self {
+        ObjectLockConfiguration {
+            object_lock_enabled: "Enabled".to_string(),
+            rule: None,
+        }
+    }
+
+    /// Read and validate an ObjectLockConfiguration document from a request body.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let config: ObjectLockConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("ObjectLockConfiguration", self)
+    }
+
+    /// Check the configuration like S3 does, the default retention
+    /// has a positive period of either days or years.
+    pub fn validate(&self) -> Result<(), S3Error> {
+        if self.object_lock_enabled != "Enabled" {
+            return Err(S3Error::MalformedXML);
+        }
+        if let Some(rule) = &self.rule {
+            let days = match (rule.default_retention.days, rule.default_retention.years) {
+                (Some(days), None) => days,
+                (None, Some(years)) => years.saturating_mul(365),
+                _ => return Err(S3Error::MalformedXML),
+            };
+            if days == 0 || days > MAX_RETENTION_DAYS {
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the default retention on an object uploaded without one.
+    pub fn apply_default(&self, lock: &mut ObjectLock, now: DateTime<Utc>) {
+        let retention = match (&self.rule, lock.mode) {
+            (Some(rule), None) => &rule.default_retention,
+            _ => return,
+        };
+        let period = match (retention.days, retention.years) {
+            (Some(days), _) => Duration::days(days as i64),
+            (None, Some(years)) => Duration::days(years as i64 * 365),
+            (None, None) => return,
+        };
+        lock.mode = Some(retention.mode);
+        lock.retain_until = Some(now + period);
+    }
+}
+
+impl ObjectRetention {
+    /// Read an ObjectRetention document, the mode and date are set together.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let retention: ObjectRetention = from_xml(&buf)?;
+        if retention.mode.is_some() != retention.retain_until_date.is_some() {
+            return Err(S3Error::MalformedXML);
+        }
+        Ok(retention)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("Retention", self)
+    }
+}
+
+impl LegalHold {
+    pub fn new(on: bool) -> Self {
+        LegalHold {
+            status: if on { "ON" } else { "OFF" }.to_string(),
+        }
+    }
+
+    /// Read a LegalHold document, returning whether the hold is on.
+    pub async fn read(body: Option<Body>) -> Result<bool, S3Error> {
+        let buf = read_document(body).await?;
+        let hold: LegalHold = from_xml(&buf)?;
+        parse_legal_hold(&hold.status).ok_or(S3Error::MalformedXML)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("LegalHold", self)
+    }
+}
+
+impl ObjectLock {
+    /// The lock requested by the x-amz-object-lock-* headers of an upload,
+    /// the mode and retain until date are set together and the date is in the future.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, S3Error> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| v.to_str().map_err(|_| S3Error::InvalidArgument))
+                .transpose()
+        };
+        let mut lock = ObjectLock::default();
+        match (
+            header(OBJECT_LOCK_MODE_HEADER)?,
+            header(OBJECT_LOCK_RETAIN_UNTIL_HEADER)?,
+        ) {
+            (None, None) => {}
+            (Some(mode), Some(until)) => {
+                lock.set_retention(&ObjectRetention {
+                    mode: Some(parse_mode(mode)?),
+                    retain_until_date: Some(until.to_string()),
+                })?;
+                if !lock.is_retained(Utc::now()) {
+                    return Err(S3Error::InvalidArgument);
+                }
+            }
+            _ => return Err(S3Error::InvalidArgument),
+        }
+        if let Some(hold) = header(OBJECT_LOCK_LEGAL_HOLD_HEADER)? {
+            lock.legal_hold = parse_legal_hold(hold).ok_or(S3Error::InvalidArgument)?;
+        }
+        Ok(lock)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && !self.legal_hold
+    }
+
+    /// The retention is in effect until the retain until date.
+    pub fn is_retained(&self, now: DateTime<Utc>) -> bool {
+        matches!((self.mode, self.retain_until), (Some(_), Some(until)) if until > now)
+    }
+
+    /// Check that the object can be deleted or overwritten, legal holds and
+    /// COMPLIANCE retention protect it from anyone, GOVERNANCE retention only
+    /// from requests that don't bypass it.
+    pub fn check_delete(&self, bypass_governance: bool, now: DateTime<Utc>) -> Result<(), S3Error> {
+        if self.legal_hold {
+            return Err(S3Error::AccessDenied);
+        }
+        if !self.is_retained(now) {
+            return Ok(());
+        }
+        match self.mode {
+            Some(RetentionMode::Governance) if bypass_governance => Ok(()),
+            _ => Err(S3Error::AccessDenied),
+        }
+    }
+
+    /// Check a change of the retention, COMPLIANCE retention can only be extended
+    /// and shortening GOVERNANCE retention requires bypassing it.
+    pub fn check_retention_change(
+        &self,
+        to: &ObjectLock,
+        bypass_governance: bool,
+        now: DateTime<Utc>,
+    ) -> Result<(), S3Error> {
+        if !self.is_retained(now) {
+            return Ok(());
+        }
+        let extends = to.retain_until >= self.retain_until;
+        match (self.mode, to.mode) {
+            (Some(RetentionMode::Compliance), Some(RetentionMode::Compliance)) if extends => Ok(()),
+            (Some(RetentionMode::Compliance), _) => Err(S3Error::AccessDenied),
+            (Some(RetentionMode::Governance), Some(_)) if extends => Ok(()),
+            _ if bypass_governance => Ok(()),
+            _ => Err(S3Error::AccessDenied),
+        }
+    }
+
+    /// Set the mode and date of the retention, both unset removes it.
+    pub fn set_retention(&mut self, retention: &ObjectRetention) -> Result<(), S3Error> {
+        self.mode = retention.mode;
+        self.retain_until = match &retention.retain_until_date {
+            None => None,
+            Some(date) => Some(
+                DateTime::parse_from_rfc3339(date)
+                    .map_err(|_| S3Error::InvalidArgument)?
+                    .with_timezone(&Utc),
+            ),
+        };
+        Ok(())
+    }
+
+    pub fn retention(&self) -> ObjectRetention {
+        ObjectRetention {
+            mode: self.mode,
+            retain_until_date: self
+                .retain_until
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        }
+    }
+
+    /// Set the x-amz-object-lock-* headers of the object.
+    pub fn write_headers(&self, res: &mut HttpResponse) -> Result<(), S3Error> {
+        if let Some(mode) = self.mode {
+            set_header(res, OBJECT_LOCK_MODE_HEADER, mode_str(mode))?;
+        }
+        if let Some(until) = self.retain_until {
+            set_header(
+                res,
+                OBJECT_LOCK_RETAIN_UNTIL_HEADER,
+                &until.to_rfc3339_opts(SecondsFormat::Millis, true),
+            )?;
+        }
+        if self.legal_hold {
+            set_header(res, OBJECT_LOCK_LEGAL_HOLD_HEADER, "ON")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the request asks to bypass GOVERNANCE retention.
+pub fn bypass_governance(headers: &HeaderMap) -> bool {
+    headers
+        .get(BYPASS_GOVERNANCE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+fn parse_mode(s: &str) -> Result<RetentionMode, S3Error> {
+    match s {
+        "GOVERNANCE" => Ok(RetentionMode::Governance),
+        "COMPLIANCE" => Ok(RetentionMode::Compliance),
+        _ => Err(S3Error::InvalidArgument),
+    }
+}
+
+fn mode_str(mode: RetentionMode) -> &'static str {
+    match mode {
+        RetentionMode::Governance => "GOVERNANCE",
+        RetentionMode::Compliance => "COMPLIANCE",
+    }
+}
+
+fn parse_legal_hold(s: &str) -> Option<bool> {
+    match s {
+        "ON" => Some(true),
+        "OFF" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AclHeaders;
+    use crate::layers::MemLayer;
+    use hyper::header::HeaderValue;
+
+    fn lock(mode: Option<RetentionMode>, days: i64, legal_hold: bool) -> ObjectLock {
+        ObjectLock {
+            mode,
+            retain_until: mode.map(|_| Utc::now() + Duration::days(days)),
+            legal_hold,
+        }
+    }
+
+    #[test]
+    fn deletes_respect_retention_and_legal_holds() {
+        let now = Utc::now();
+        let governance = Some(RetentionMode::Governance);
+        let compliance = Some(RetentionMode::Compliance);
+        assert!(lock(None, 0, false).check_delete(false, now).is_ok());
+        // GOVERNANCE retention only stops requests that don't bypass it
+        assert_eq!(
+            lock(governance, 1, false).check_delete(false, now),
+            Err(S3Error::AccessDenied)
+        );
+        assert!(lock(governance, 1, false).check_delete(true, now).is_ok());
+        // COMPLIANCE retention and legal holds stop everyone
+        assert_eq!(
+            lock(compliance, 1, false).check_delete(true, now),
+            Err(S3Error::AccessDenied)
+        );
+        assert_eq!(
+            lock(None, 0, true).check_delete(true, now),
+            Err(S3Error::AccessDenied)
+        );
+        assert_eq!(
+            lock(governance, -1, true).check_delete(true, now),
+            Err(S3Error::AccessDenied)
+        );
+        // expired retention protects nothing
+        assert!(lock(compliance, -1, false).check_delete(false, now).is_ok());
+        assert!(!lock(compliance, -1, false).is_retained(now));
+    }
+
+    #[test]
+    fn retention_changes_are_checked() {
+        let now = Utc::now();
+        let governance = Some(RetentionMode::Governance);
+        let compliance = Some(RetentionMode::Compliance);
+        let check = |from: &ObjectLock, to: &ObjectLock, bypass: bool| {
+            from.check_retention_change(to, bypass, now)
+        };
+        let denied = Err(S3Error::AccessDenied);
+
+        let from = lock(compliance, 2, false);
+        assert!(check(&from, &lock(compliance, 3, false), false).is_ok());
+        assert_eq!(check(&from, &lock(compliance, 1, false), true), denied);
+        assert_eq!(check(&from, &lock(governance, 3, false), true), denied);
+        assert_eq!(check(&from, &lock(None, 0, false), true), denied);
+
+        let from = lock(governance, 2, false);
+        assert!(check(&from, &lock(governance, 3, false), false).is_ok());
+        assert!(check(&from, &lock(compliance, 3, false), false).is_ok());
+        assert_eq!(check(&from, &lock(governance, 1, false), false), denied);
+        assert!(check(&from, &lock(governance, 1, false), true).is_ok());
+        assert_eq!(check(&from, &lock(None, 0, false), false), denied);
+        assert!(check(&from, &lock(None, 0, false), true).is_ok());
+
+        assert!(check(&lock(compliance, -1, false), &lock(None, 0, false), false).is_ok());
+    }
+
+    #[test]
+    fn default_retention_applies_to_new_objects() {
+        let now = Utc::now();
+        let config = |days, years| ObjectLockConfiguration {
+            rule: Some(ObjectLockRule {
+                default_retention: DefaultRetention {
+                    mode: RetentionMode::Governance,
+                    days,
+                    years,
+                },
+            }),
+            ..ObjectLockConfiguration::enabled()
+        };
+        assert!(ObjectLockConfiguration::enabled().validate().is_ok());
+        assert!(ObjectLockConfiguration::default().validate().is_err());
+        assert!(config(Some(1), None).validate().is_ok());
+        assert!(config(None, Some(1)).validate().is_ok());
+        assert!(config(Some(1), Some(1)).validate().is_err());
+        assert!(config(None, None).validate().is_err());
+        assert!(config(Some(0), None).validate().is_err());
+        assert!(config(None, Some(101)).validate().is_err());
+
+        let mut object = ObjectLock::default();
+        config(Some(2), None).apply_default(&mut object, now);
+        assert_eq!(object.mode, Some(RetentionMode::Governance));
+        assert_eq!(object.retain_until, Some(now + Duration::days(2)));
+        // an explicit retention wins over the default
+        let mut object = lock(Some(RetentionMode::Compliance), 1, false);
+        let expected = object.clone();
+        config(Some(2), None).apply_default(&mut object, now);
+        assert_eq!(object, expected);
+    }
+
+    #[test]
+    fn upload_headers_are_parsed() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+        let future = "2999-01-01T00:00:00Z";
+        let object = ObjectLock::from_headers(&headers(&[
+            (OBJECT_LOCK_MODE_HEADER, "COMPLIANCE"),
+            (OBJECT_LOCK_RETAIN_UNTIL_HEADER, future),
+            (OBJECT_LOCK_LEGAL_HOLD_HEADER, "ON"),
+        ]))
+        .unwrap();
+        assert_eq!(object.mode, Some(RetentionMode::Compliance));
+        assert!(object.legal_hold);
+        assert_eq!(
+            object.retention().retain_until_date.as_deref(),
+            Some("2999-01-01T00:00:00.000Z")
+        );
+        let invalid: [&[(&'static str, &'static str)]; 4] = [
+            &[(OBJECT_LOCK_MODE_HEADER, "GOVERNANCE")],
+            &[
+                (OBJECT_LOCK_MODE_HEADER, "GOVERNANCE"),
+                (OBJECT_LOCK_RETAIN_UNTIL_HEADER, "2000-01-01T00:00:00Z"),
+            ],
+            &[
+                (OBJECT_LOCK_MODE_HEADER, "governance"),
+                (OBJECT_LOCK_RETAIN_UNTIL_HEADER, future),
+            ],
+            &[(OBJECT_LOCK_LEGAL_HOLD_HEADER, "yes")],
+        ];
+        for pairs in invalid {
+            assert!(ObjectLock::from_headers(&headers(pairs)).is_err());
+        }
+        assert!(bypass_governance(&headers(&[(
+            BYPASS_GOVERNANCE_HEADER,
+            "True"
+        )])));
+        assert!(!bypass_governance(&headers(&[])));
+    }
+
+    fn upload(key: &str, object_lock: ObjectLock) -> put_object::Req {
+        put_object::Req::new(put_object::Params {
+            bucket: String::from("locked"),
+            key: key.to_string(),
+            acl: AclHeaders::default(),
+            tagging: Tagging::default(),
+            website_redirect_location: String::new(),
+            sse_customer: None,
+            sse: None,
+            object_lock,
+            plaintext_size: None,
+            body: Some(Body::from("data")),
+        })
+    }
+
+    fn delete(key: &str, bypass_governance: bool) -> delete_object::Req {
+        delete_object::Req::new(delete_object::Params {
+            bucket: String::from("locked"),
+            key: key.to_string(),
+            version_id: String::new(),
+            bypass_governance,
+        })
+    }
+
+    #[tokio::test]
+    async fn locked_objects_are_protected() {
+        let layer = MemLayer::new();
+        let req = put_bucket::Req::new(put_bucket::Params {
+            bucket: String::from("locked"),
+            class: String::new(),
+            region: String::new(),
+            acl: AclHeaders::default(),
+            object_lock_enabled: true,
+            body: None,
+        });
+        layer.put_bucket(req).await.unwrap();
+        let governance = lock(Some(RetentionMode::Governance), 1, false);
+        let compliance = lock(Some(RetentionMode::Compliance), 1, false);
+        layer.put_object(upload("gov", governance)).await.unwrap();
+        layer.put_object(upload("comp", compliance)).await.unwrap();
+        layer
+            .put_object(upload("held", lock(None, 0, true)))
+            .await
+            .unwrap();
+
+        for key in ["gov", "comp", "held"] {
+            let err = layer.put_object(upload(key, ObjectLock::default())).await;
+            assert_eq!(err.err(), Some(S3Error::AccessDenied), "{}", key);
+            let err = layer.delete_object(delete(key, false)).await;
+            assert_eq!(err.err(), Some(S3Error::AccessDenied), "{}", key);
+        }
+        let err = layer.delete_object(delete("comp", true)).await;
+        assert_eq!(err.err(), Some(S3Error::AccessDenied));
+        let err = layer.delete_object(delete("held", true)).await;
+        assert_eq!(err.err(), Some(S3Error::AccessDenied));
+        // not even a forced bucket delete removes protected objects
+        let req = delete_bucket::Req::new(delete_bucket::Params {
+            bucket: String::from("locked"),
+            force: true,
+        });
+        assert_eq!(
+            layer.delete_bucket(req).await.err(),
+            Some(S3Error::AccessDenied)
+        );
+        layer.delete_object(delete("gov", true)).await.unwrap();
+
+        // lifting the legal hold allows the delete
+        let req = put_object_legal_hold::Req::new(put_object_legal_hold::Params {
+            bucket: String::from("locked"),
+            key: String::from("held"),
+            version_id: String::new(),
+            body: Some(Body::from(LegalHold::new(false).to_xml().unwrap())),
+        });
+        layer.put_object_legal_hold(req).await.unwrap();
+        layer.delete_object(delete("held", false)).await.unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use crate::api::*;
+use crate::auth::*;
+use hyper::{HeaderMap, Request, Response};
+use serde::Serialize;
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    /// from x-amz-copy-source
+    pub source: CopySource,
+    pub acl: AclHeaders,
+    /// from x-amz-tagging with x-amz-tagging-directive REPLACE,
+    /// None copies the tags of the source
+    pub tagging: Option<Tagging>,
+    /// from x-amz-website-redirect-location, empty for none
+    pub website_redirect_location: String,
+    /// from x-amz-server-side-encryption-customer-*, the copy is encrypted with it
+    pub sse_customer: Option<SseCustomerKey>,
+    /// from x-amz-copy-source-server-side-encryption-customer-*,
+    /// required to read SSE-C sources
+    pub copy_source_sse_customer: Option<SseCustomerKey>,
+    /// from x-amz-server-side-encryption, applied by the EncryptionLayer
+    pub sse: Option<ServerSideEncryption>,
+    /// from x-amz-object-lock-*, only valid in buckets with Object Lock
+    pub object_lock: ObjectLock,
+}
+
+/// CopySource is the object a CopyObject request reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopySource {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub object: ObjectInfo,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CopyObjectResult<'a> {
+    #[serde(rename = "ETag")]
+    etag: String,
+    last_modified: &'a str,
+}
+
+impl CopySource {
+    /// Parse `x-amz-copy-source`, the URL encoded `bucket/key` of the source
+    /// with an optional leading slash and `?versionId=`.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, S3Error> {
+        let value = headers
+            .get("x-amz-copy-source")
+            .ok_or(S3Error::InvalidArgument)?
+            .to_str()
+            .map_err(|_| S3Error::InvalidArgument)?;
+        let (path, version_id) = match value.split_once("?versionId=") {
+            Some((path, version_id)) => (path, decode_query(version_id)?),
+            None => (value, String::new()),
+        };
+        let path = path.strip_prefix('/').unwrap_or(path);
+        let (bucket, key) = parse_path(&format!("/{}", path))?;
+        if bucket.is_empty() || key.is_empty() {
+            return Err(S3Error::InvalidArgument);
+        }
+        Ok(CopySource {
+            bucket,
+            key,
+            version_id,
+        })
+    }
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// PUT /Key+ HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-acl: ACL
+    /// Cache-Control: CacheControl
+    /// Content-Disposition: ContentDisposition
+    /// Content-Encoding: ContentEncoding
+    /// Content-Language: ContentLanguage
+    /// Content-Type: ContentType
+    /// x-amz-copy-source: CopySource
+    /// x-amz-copy-source-if-match: CopySourceIfMatch
+    /// x-amz-copy-source-if-modified-since: CopySourceIfModifiedSince
+    /// x-amz-copy-source-if-none-match: CopySourceIfNoneMatch
+    /// x-amz-copy-source-if-unmodified-since: CopySourceIfUnmodifiedSince
+    /// Expires: Expires
+    /// x-amz-grant-full-control: GrantFullControl
+    /// x-amz-grant-read: GrantRead
+    /// x-amz-grant-read-acp: GrantReadACP
+    /// x-amz-grant-write-acp: GrantWriteACP
+    /// x-amz-metadata-directive: MetadataDirective
+    /// x-amz-tagging-directive: TaggingDirective
+    /// x-amz-server-side-encryption: ServerSideEncryption
+    /// x-amz-storage-class: StorageClass
+    /// x-amz-website-redirect-location: WebsiteRedirectLocation
+    /// x-amz-server-side-encryption-customer-algorithm: SSECustomerAlgorithm
+    /// x-amz-server-side-encryption-customer-key: SSECustomerKey
+    /// x-amz-server-side-encryption-customer-key-MD5: SSECustomerKeyMD5
+    /// x-amz-server-side-encryption-aws-kms-key-id: SSEKMSKeyId
+    /// x-amz-server-side-encryption-context: SSEKMSEncryptionContext
+    /// x-amz-server-side-encryption-bucket-key-enabled: BucketKeyEnabled
+    /// x-amz-copy-source-server-side-encryption-customer-algorithm: CopySourceSSECustomerAlgorithm
+    /// x-amz-copy-source-server-side-encryption-customer-key: CopySourceSSECustomerKey
+    /// x-amz-copy-source-server-side-encryption-customer-key-MD5: CopySourceSSECustomerKeyMD5
+    /// x-amz-request-payer: RequestPayer
+    /// x-amz-tagging: Tagging
+    /// x-amz-object-lock-mode: ObjectLockMode
+    /// x-amz-object-lock-retain-until-date: ObjectLockRetainUntilDate
+    /// x-amz-object-lock-legal-hold: ObjectLockLegalHoldStatus
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// x-amz-source-expected-bucket-owner: ExpectedSourceBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .map(|v| v.to_str().map_err(|_| S3Error::InvalidArgument))
+                .transpose()
+        };
+        let tagging = match header("x-amz-tagging-directive")? {
+            None | Some("COPY") => None,
+            Some("REPLACE") => Some(match parts.headers.get("x-amz-tagging") {
+                None => Tagging::default(),
+                Some(v) => Tagging::from_header(v.to_str().map_err(|_| S3Error::InvalidTag)?)?,
+            }),
+            Some(_) => return Err(S3Error::InvalidArgument),
+        };
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            source: CopySource::from_headers(&parts.headers)?,
+            acl: AclHeaders::from_headers(&parts.headers)?,
+            tagging,
+            website_redirect_location: match parts.headers.get("x-amz-website-redirect-location") {
+                None => String::new(),
+                Some(v) => {
+                    let location = v.to_str().map_err(|_| S3Error::InvalidRedirectLocation)?;
+                    validate_redirect_location(location)?;
+                    location.to_string()
+                }
+            },
+            sse_customer: SseCustomerKey::from_headers(&parts.headers)?,
+            copy_source_sse_customer: SseCustomerKey::from_copy_source_headers(&parts.headers)?,
+            sse: ServerSideEncryption::from_headers(&parts.headers)?,
+            object_lock: ObjectLock::from_headers(&parts.headers)?,
+        };
+        // an object is encrypted with either customer or server managed keys
+        if params.sse_customer.is_some() && params.sse.is_some() {
+            return Err(S3Error::InvalidArgument);
+        }
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// x-amz-expiration: Expiration
+    /// x-amz-copy-source-version-id: CopySourceVersionId
+    /// x-amz-version-id: VersionId
+    /// x-amz-server-side-encryption: ServerSideEncryption
+    /// x-amz-server-side-encryption-customer-algorithm: SSECustomerAlgorithm
+    /// x-amz-server-side-encryption-customer-key-MD5: SSECustomerKeyMD5
+    /// x-amz-server-side-encryption-aws-kms-key-id: SSEKMSKeyId
+    /// x-amz-server-side-encryption-context: SSEKMSEncryptionContext
+    /// x-amz-server-side-encryption-bucket-key-enabled: BucketKeyEnabled
+    /// x-amz-request-charged: RequestCharged
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <CopyObjectResult>
+    ///    <ETag>string</ETag>
+    ///    <LastModified>timestamp</LastModified>
+    ///    <ChecksumCRC32>string</ChecksumCRC32>
+    ///    <ChecksumCRC32C>string</ChecksumCRC32C>
+    ///    <ChecksumSHA1>string</ChecksumSHA1>
+    ///    <ChecksumSHA256>string</ChecksumSHA256>
+    /// </CopyObjectResult>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let body = to_xml(
+            "CopyObjectResult",
+            &CopyObjectResult {
+                etag: format!("\"{}\"", r.object.etag),
+                last_modified: &r.object.last_modified,
+            },
+        )?;
+        let mut res = Response::from_parts(parts, body.into());
+        set_header(&mut res, "x-amz-version-id", &r.object.version_id)?;
+        set_sse_headers(&mut res, &r.object)?;
+        Ok(res)
+    }
+}
@@ -8,6 +8,9 @@ pub type Ret = Result<Res, S3Error>;
 #[derive(Debug, Clone)]
 pub struct Params {
     pub bucket: String,
+    /// delete the objects too instead of failing with BucketNotEmpty,
+    /// only set by the admin API and never by S3 requests.
+    pub force: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -17,29 +20,30 @@ pub struct Reply {
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     /// DELETE / HTTP/1.1
     /// Host: Bucket.s3.amazonaws.com
     /// x-amz-expected-bucket-owner: ExpectedBucketOwner
     /// ```
-    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Self {
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
         let (parts, _) = req.into_parts();
         let params = Params {
             bucket: bucket.to_string(),
+            force: false,
         };
-        Request::from_parts(parts, params)
+        Ok(Request::from_parts(parts, params))
     }
 }
 
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     /// HTTP/1.1 204
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, _) = self.into_parts();
         let mut res = Response::from_parts(parts, Body::empty());
         *res.status_mut() = hyper::StatusCode::NO_CONTENT; // 204
-        res
+        Ok(res)
     }
 }
@@ -10,6 +10,8 @@ pub struct Params {
     pub bucket: String,
     pub key: String,
     pub version_id: String,
+    /// from x-amz-bypass-governance-retention, allows deleting objects under GOVERNANCE retention
+    pub bypass_governance: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -19,7 +21,7 @@ pub struct Reply {
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     /// DELETE /Key+?versionId=VersionId HTTP/1.1
     /// Host: Bucket.s3.amazonaws.com
     /// x-amz-mfa: MFA
@@ -27,32 +29,32 @@ impl ReqParser for Req {
     /// x-amz-bypass-governance-retention: BypassGovernanceRetention
     /// x-amz-expected-bucket-owner: ExpectedBucketOwner
     /// ```
-    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Self {
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
         let (parts, _) = req.into_parts();
         let qs = QueryStr::from_parts(&parts);
         let params = Params {
             bucket: bucket.to_string(),
             key: key.to_string(),
             version_id: qs.get("versionId"),
+            bypass_governance: bypass_governance(&parts.headers),
         };
-        Request::from_parts(parts, params)
+        Ok(Request::from_parts(parts, params))
     }
 }
 
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     /// HTTP/1.1 204
     /// x-amz-delete-marker: DeleteMarker
     /// x-amz-version-id: VersionId
     /// x-amz-request-charged: RequestCharged
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, r) = self.into_parts();
         let mut res = Response::from_parts(parts, Body::empty());
         *res.status_mut() = hyper::StatusCode::NO_CONTENT; // 204
-        res.headers_mut()
-            .insert("x-amz-version-id", r.object.version_id.parse().unwrap());
-        res
+        set_header(&mut res, "x-amz-version-id", &r.object.version_id)?;
+        Ok(res)
     }
 }
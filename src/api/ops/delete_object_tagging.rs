@@ -0,0 +1,52 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub version_id: String,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// DELETE /{Key+}?tagging&versionId=VersionId HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let qs = QueryStr::from_parts(&parts);
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            version_id: qs.get("versionId"),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 204
+    /// x-amz-version-id: VersionId
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let mut res = Response::from_parts(parts, Body::empty());
+        *res.status_mut() = hyper::StatusCode::NO_CONTENT; // 204
+        set_header(&mut res, "x-amz-version-id", &r.version_id)?;
+        Ok(res)
+    }
+}
@@ -17,30 +17,29 @@ pub struct Reply {
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     /// HEAD / HTTP/1.1
     /// Host: Bucket.s3.amazonaws.com
     /// x-amz-expected-bucket-owner: ExpectedBucketOwner
     /// ```
-    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Self {
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
         let (parts, _) = req.into_parts();
         let params = Params {
             bucket: bucket.to_string(),
         };
-        Request::from_parts(parts, params)
+        Ok(Request::from_parts(parts, params))
     }
 }
 
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     /// HTTP/1.1 200
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, r) = self.into_parts();
         let mut res = Response::from_parts(parts, Body::empty());
-        res.headers_mut()
-            .insert("x-amz-bucket-region", r.info.region.parse().unwrap());
-        res
+        set_header(&mut res, "x-amz-bucket-region", &r.info.region)?;
+        Ok(res)
     }
 }
@@ -0,0 +1,65 @@
+use crate::api::*;
+use crate::auth::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub acl: Acl,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /?acl HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <AccessControlPolicy>
+    ///    <Owner>
+    ///       <DisplayName>string</DisplayName>
+    ///       <ID>string</ID>
+    ///    </Owner>
+    ///    <AccessControlList>
+    ///       <Grant>
+    ///          <Grantee>
+    ///             <DisplayName>string</DisplayName>
+    ///             <EmailAddress>string</EmailAddress>
+    ///             <ID>string</ID>
+    ///             <xsi:type>string</xsi:type>
+    ///             <URI>string</URI>
+    ///          </Grantee>
+    ///          <Permission>string</Permission>
+    ///       </Grant>
+    ///    </AccessControlList>
+    /// </AccessControlPolicy>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let mut w = BodyWriter::new_xml();
+        r.acl.write_xml(&mut w);
+        Ok(Response::from_parts(parts, w.body()))
+    }
+}
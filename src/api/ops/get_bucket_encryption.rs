@@ -0,0 +1,53 @@
+use crate::api::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub config: ServerSideEncryptionConfiguration,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /?encryption HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <ServerSideEncryptionConfiguration>
+    ///    <Rule>
+    ///       <ApplyServerSideEncryptionByDefault>
+    ///          <KMSMasterKeyID>string</KMSMasterKeyID>
+    ///          <SSEAlgorithm>string</SSEAlgorithm>
+    ///       </ApplyServerSideEncryptionByDefault>
+    ///       <BucketKeyEnabled>boolean</BucketKeyEnabled>
+    ///    </Rule>
+    /// </ServerSideEncryptionConfiguration>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        Ok(Response::from_parts(parts, r.config.to_xml()?.into()))
+    }
+}
@@ -0,0 +1,77 @@
+use crate::api::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub config: LifecycleConfiguration,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /?lifecycle HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <LifecycleConfiguration>
+    ///    <Rule>
+    ///       <ID>string</ID>
+    ///       <Filter>
+    ///          <Prefix>string</Prefix>
+    ///          <Tag>
+    ///             <Key>string</Key>
+    ///             <Value>string</Value>
+    ///          </Tag>
+    ///          <And>
+    ///             <Prefix>string</Prefix>
+    ///             <Tag>
+    ///                <Key>string</Key>
+    ///                <Value>string</Value>
+    ///             </Tag>
+    ///             ...
+    ///          </And>
+    ///       </Filter>
+    ///       <Status>string</Status>
+    ///       <Expiration>
+    ///          <Date>timestamp</Date>
+    ///          <Days>integer</Days>
+    ///          <ExpiredObjectDeleteMarker>boolean</ExpiredObjectDeleteMarker>
+    ///       </Expiration>
+    ///       <NoncurrentVersionExpiration>
+    ///          <NoncurrentDays>integer</NoncurrentDays>
+    ///       </NoncurrentVersionExpiration>
+    ///       <AbortIncompleteMultipartUpload>
+    ///          <DaysAfterInitiation>integer</DaysAfterInitiation>
+    ///       </AbortIncompleteMultipartUpload>
+    ///    </Rule>
+    ///    ...
+    /// </LifecycleConfiguration>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        Ok(Response::from_parts(parts, r.config.to_xml()?.into()))
+    }
+}
@@ -0,0 +1,54 @@
+use crate::api::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    /// empty means the server region
+    pub region: String,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /?location HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <LocationConstraint>
+    ///    <LocationConstraint>string</LocationConstraint>
+    /// </LocationConstraint>
+    /// ```
+    /// Like S3 the constraint of us-east-1 buckets is empty.
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let region = match r.region.as_str() {
+            DEFAULT_REGION => "",
+            region => region,
+        };
+        let body = to_xml("LocationConstraint", &region)?;
+        Ok(Response::from_parts(parts, body.into()))
+    }
+}
@@ -0,0 +1,73 @@
+use crate::api::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    /// empty when the bucket has no notifications
+    pub config: NotificationConfiguration,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /?notification HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <NotificationConfiguration>
+    ///    <TopicConfiguration>
+    ///       <Event>string</Event>
+    ///       ...
+    ///       <Filter>
+    ///          <S3Key>
+    ///             <FilterRule>
+    ///                <Name>string</Name>
+    ///                <Value>string</Value>
+    ///             </FilterRule>
+    ///             ...
+    ///          </S3Key>
+    ///       </Filter>
+    ///       <Id>string</Id>
+    ///       <Topic>string</Topic>
+    ///    </TopicConfiguration>
+    ///    ...
+    ///    <QueueConfiguration>
+    ///       ...
+    ///       <Queue>string</Queue>
+    ///    </QueueConfiguration>
+    ///    ...
+    ///    <CloudFunctionConfiguration>
+    ///       ...
+    ///       <CloudFunction>string</CloudFunction>
+    ///    </CloudFunctionConfiguration>
+    ///    ...
+    /// </NotificationConfiguration>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        Ok(Response::from_parts(parts, r.config.to_xml()?.into()))
+    }
+}
@@ -1,5 +1,6 @@
 use crate::api::*;
-use hyper::{Body, Method, Request, Response};
+use chrono::{DateTime, Utc};
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode};
 
 pub type Req = Request<Params>;
 pub type Res = Response<Reply>;
@@ -14,22 +15,32 @@ pub struct Params {
     // partial reads
     pub head_only: bool, // = HTTP HEAD method - no body should be returned
     pub range: ObjectRange,
-    // TODO: conditional reads (e.g. if-match, if-none-match) ?
-    // pub if_modified_since: Option<Time>,
-    // pub if_unmodified_since: Option<Time>,
-    // pub if_match: Option<String>,
-    // pub if_none_match: Option<String>,
+    /// from x-amz-server-side-encryption-customer-*, required for SSE-C objects
+    pub sse_customer: Option<SseCustomerKey>,
+}
+
+/// Conditions are the If-* headers of a read, checked by the server against
+/// the object the layers return, see RFC 7232.
+#[derive(Debug, Default)]
+pub struct Conditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+    if_unmodified_since: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug)]
 pub struct Reply {
     pub object: ObjectInfo,
+    /// the bytes start..end of the object in the body for range reads,
+    /// see ObjectRange::resolve
+    pub range: Option<(u64, u64)>,
     pub body: Option<Body>,
 }
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     /// GET /Key+
     ///          ?partNumber=PartNumber
     ///          &response-cache-control=ResponseCacheControl
@@ -51,29 +62,101 @@ impl ReqParser for Req {
     /// x-amz-request-payer: RequestPayer
     /// x-amz-expected-bucket-owner: ExpectedBucketOwner
     /// ```
-    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Self {
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
         let (parts, _) = req.into_parts();
         let qs = QueryStr::from_parts(&parts);
         let head_only = parts.method == Method::HEAD;
-        // let range = ObjectRange::parse(qs.get("range"));
-        let range = ObjectRange {
-            start: Some(0),
-            end: Some(0),
-        };
+        let range = ObjectRange::parse(
+            parts
+                .headers
+                .get(hyper::header::RANGE)
+                .and_then(|v| v.to_str().ok()),
+        );
         let params = Params {
             bucket: bucket.to_string(),
             key: key.to_string(),
             version_id: qs.get("versionId"),
             head_only,
             range,
+            sse_customer: SseCustomerKey::from_headers(&parts.headers)?,
         };
-        Request::from_parts(parts, params)
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl Conditions {
+    /// The conditions of the request, dates that don't parse are ignored like RFC 7232 says.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let date = |name: &str| header(name).and_then(|v| parse_date(&v));
+        Conditions {
+            if_match: header("if-match"),
+            if_none_match: header("if-none-match"),
+            if_modified_since: date("if-modified-since"),
+            if_unmodified_since: date("if-unmodified-since"),
+        }
+    }
+
+    /// Check the object against the conditions, a failed If-Match or If-Unmodified-Since
+    /// is PreconditionFailed, and false is for reads that are not modified.
+    /// If-Match replaces If-Unmodified-Since and If-None-Match replaces If-Modified-Since.
+    pub fn check(&self, object: &ObjectInfo) -> Result<bool, S3Error> {
+        // HTTP dates have no fractions of seconds
+        let last_modified = parse_date(&object.last_modified).map(|t| t.timestamp());
+        let after = |since: &Option<DateTime<Utc>>| match (since, last_modified) {
+            (Some(since), Some(t)) => Some(t > since.timestamp()),
+            _ => None,
+        };
+        match &self.if_match {
+            Some(etags) if !etag_matches(etags, &object.etag) => {
+                return Err(S3Error::PreconditionFailed)
+            }
+            Some(_) => {}
+            None if after(&self.if_unmodified_since) == Some(true) => {
+                return Err(S3Error::PreconditionFailed)
+            }
+            None => {}
+        }
+        Ok(match &self.if_none_match {
+            Some(etags) => !etag_matches(etags, &object.etag),
+            None => after(&self.if_modified_since) != Some(false),
+        })
+    }
+
+    /// The 304 response of a read that is not modified.
+    pub fn not_modified(object: &ObjectInfo) -> Result<HttpResponse, S3Error> {
+        let mut res = HttpResponse::new(Body::empty());
+        *res.status_mut() = StatusCode::NOT_MODIFIED;
+        set_header(&mut res, "Last-Modified", &object.last_modified)?;
+        set_header(&mut res, "ETag", &format!("\"{}\"", object.etag))?;
+        Ok(res)
     }
 }
 
+/// A list of quoted etags, or `*` for any, matches the etag of an object.
+fn etag_matches(etags: &str, etag: &str) -> bool {
+    etags.split(',').any(|e| {
+        let e = e.trim();
+        e == "*" || e.trim_start_matches("W/").trim_matches('"') == etag
+    })
+}
+
+/// HTTP dates, or the RFC 3339 dates layers keep as last modified.
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     /// HTTP/1.1 200
     /// Last-Modified: LastModified
     /// Content-Length: ContentLength
@@ -108,18 +191,114 @@ impl ResWriter for Res {
     ///
     /// Body
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, r) = self.into_parts();
         let body = r.body.unwrap_or(Body::empty());
         let mut res = Response::from_parts(parts, body);
 
-        res.headers_mut()
-            .insert("Last-Modified", r.object.last_modified.parse().unwrap());
-        res.headers_mut()
-            .insert("Content-Length", r.object.size.to_string().parse().unwrap());
-        res.headers_mut()
-            .insert("ETag", format!("\"{}\"", r.object.etag).parse().unwrap());
+        set_header(&mut res, "Last-Modified", &r.object.last_modified)?;
+        set_header(&mut res, "Accept-Ranges", "bytes")?;
+        match r.range {
+            Some((start, end)) => {
+                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                set_header(&mut res, "Content-Length", &(end - start).to_string())?;
+                let range = format!("bytes {}-{}/{}", start, end - 1, r.object.size);
+                set_header(&mut res, "Content-Range", &range)?;
+            }
+            None => set_header(&mut res, "Content-Length", &r.object.size.to_string())?,
+        }
+        set_header(&mut res, "ETag", &format!("\"{}\"", r.object.etag))?;
+        if r.object.tag_count > 0 {
+            set_header(
+                &mut res,
+                "x-amz-tagging-count",
+                &r.object.tag_count.to_string(),
+            )?;
+        }
+        set_sse_headers(&mut res, &r.object)?;
+        r.object.object_lock.write_headers(&mut res)?;
+        if !r.object.website_redirect_location.is_empty() {
+            set_header(
+                &mut res,
+                "x-amz-website-redirect-location",
+                &r.object.website_redirect_location,
+            )?;
+        }
+        if !r.object.replication_status.is_empty() {
+            set_header(
+                &mut res,
+                "x-amz-replication-status",
+                &r.object.replication_status,
+            )?;
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        res
+    fn conditions(headers: &[(&'static str, &'static str)]) -> Conditions {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, hyper::header::HeaderValue::from_static(value));
+        }
+        Conditions::from_headers(&map)
+    }
+
+    #[test]
+    fn conditions_match_etags_and_dates() {
+        let object = ObjectInfo {
+            bucket: String::from("bucket"),
+            key: String::from("key"),
+            version_id: String::new(),
+            size: 0,
+            last_modified: String::from("2024-05-01T10:00:00.500Z"),
+            etag: String::from("abc"),
+            storage_class: String::new(),
+            owner: UserInfo {
+                id: String::new(),
+                display_name: String::new(),
+            },
+            tag_count: 0,
+            website_redirect_location: String::new(),
+            sse_customer_key_md5: String::new(),
+            sse_algorithm: String::new(),
+            sse_kms_key_id: String::new(),
+            object_lock: ObjectLock::default(),
+            replication_status: String::new(),
+            plaintext_size: None,
+        };
+        let check = |headers: &[(&'static str, &'static str)]| conditions(headers).check(&object);
+        assert_eq!(check(&[]), Ok(true));
+        assert_eq!(check(&[("if-match", "\"abc\"")]), Ok(true));
+        assert_eq!(check(&[("if-match", "\"x\", *")]), Ok(true));
+        assert_eq!(
+            check(&[("if-match", "\"x\"")]),
+            Err(S3Error::PreconditionFailed)
+        );
+        assert_eq!(check(&[("if-none-match", "\"abc\"")]), Ok(false));
+        assert_eq!(check(&[("if-none-match", "\"x\"")]), Ok(true));
+        let since = "Wed, 01 May 2024 10:00:00 GMT";
+        assert_eq!(check(&[("if-modified-since", since)]), Ok(false));
+        assert_eq!(check(&[("if-unmodified-since", since)]), Ok(true));
+        let before = "Wed, 01 May 2024 09:59:59 GMT";
+        assert_eq!(check(&[("if-modified-since", before)]), Ok(true));
+        assert_eq!(
+            check(&[("if-unmodified-since", before)]),
+            Err(S3Error::PreconditionFailed)
+        );
+        // the etag conditions replace the date ones
+        assert_eq!(
+            check(&[("if-match", "\"abc\""), ("if-unmodified-since", before)]),
+            Ok(true)
+        );
+        assert_eq!(
+            check(&[("if-none-match", "\"x\""), ("if-modified-since", since)]),
+            Ok(true)
+        );
+        assert_eq!(check(&[("if-modified-since", "yesterday")]), Ok(true));
     }
 }
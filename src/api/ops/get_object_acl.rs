@@ -0,0 +1,71 @@
+use crate::api::*;
+use crate::auth::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub acl: Acl,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /Key+?acl&versionId=VersionId HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-request-payer: RequestPayer
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let qs = QueryStr::from_parts(&parts);
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            version_id: qs.get("versionId"),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <AccessControlPolicy>
+    ///    <Owner>
+    ///       <DisplayName>string</DisplayName>
+    ///       <ID>string</ID>
+    ///    </Owner>
+    ///    <AccessControlList>
+    ///       <Grant>
+    ///          <Grantee>
+    ///             <DisplayName>string</DisplayName>
+    ///             <EmailAddress>string</EmailAddress>
+    ///             <ID>string</ID>
+    ///             <xsi:type>string</xsi:type>
+    ///             <URI>string</URI>
+    ///          </Grantee>
+    ///          <Permission>string</Permission>
+    ///       </Grant>
+    ///    </AccessControlList>
+    /// </AccessControlPolicy>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let mut w = BodyWriter::new_xml();
+        r.acl.write_xml(&mut w);
+        Ok(Response::from_parts(parts, w.body()))
+    }
+}
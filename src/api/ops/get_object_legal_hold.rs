@@ -0,0 +1,53 @@
+use crate::api::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub legal_hold: LegalHold,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /{Key+}?legal-hold&versionId=VersionId HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-request-payer: RequestPayer
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let qs = QueryStr::from_parts(&parts);
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            version_id: qs.get("versionId"),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <LegalHold>
+    ///    <Status>string</Status>
+    /// </LegalHold>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        Ok(Response::from_parts(parts, r.legal_hold.to_xml()?.into()))
+    }
+}
@@ -0,0 +1,62 @@
+use crate::api::*;
+use hyper::{Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub version_id: String,
+    pub tagging: Tagging,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// GET /{Key+}?tagging&versionId=VersionId HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// x-amz-request-payer: RequestPayer
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let qs = QueryStr::from_parts(&parts);
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            version_id: qs.get("versionId"),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// x-amz-version-id: VersionId
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <Tagging>
+    ///    <TagSet>
+    ///       <Tag>
+    ///          <Key>string</Key>
+    ///          <Value>string</Value>
+    ///       </Tag>
+    ///    </TagSet>
+    /// </Tagging>
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let mut res = Response::from_parts(parts, r.tagging.to_xml()?.into());
+        set_header(&mut res, "x-amz-version-id", &r.version_id)?;
+        Ok(res)
+    }
+}
@@ -1,5 +1,6 @@
 use crate::api::*;
 use hyper::{Request, Response};
+use serde::Serialize;
 
 pub type Req = Request<Params>;
 pub type Res = Response<Reply>;
@@ -20,19 +21,19 @@ pub struct Reply {
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     /// GET / HTTP/1.1
     /// ```
-    fn parse(req: HttpRequest, _bucket: &str, _key: &str) -> Self {
+    fn parse(req: HttpRequest, _bucket: &str, _key: &str) -> Result<Self, S3Error> {
         let (parts, _) = req.into_parts();
         let params = Params {};
-        Request::from_parts(parts, params)
+        Ok(Request::from_parts(parts, params))
     }
 }
 
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     /// HTTP/1.1 200
     /// <?xml version="1.0" encoding="UTF-8"?>
     /// <ListAllMyBucketsResult>
@@ -48,27 +49,42 @@ impl ResWriter for Res {
     ///    </Owner>
     /// </ListAllMyBucketsResult>
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, r) = self.into_parts();
-        let mut w = BodyWriter::new_xml();
-
-        w.append("<ListAllMyBucketsResult>");
+        let doc = ListAllMyBucketsResult {
+            buckets: Buckets {
+                bucket: r
+                    .buckets
+                    .iter()
+                    .map(|b| Bucket {
+                        name: &b.name,
+                        creation_date: "2021-09-19T00:00:00.000Z",
+                    })
+                    .collect(),
+            },
+            owner: Owner::from(&r.owner),
+        };
+        let body = to_xml("ListAllMyBucketsResult", &doc)?;
+        Ok(Response::from_parts(parts, body.into()))
+    }
+}
 
-        w.append("<Buckets>");
-        for b in r.buckets {
-            w.append("<Bucket>");
-            w.append_xml("Name", b.name.as_str());
-            w.append_xml("CreationDate", "2021-09-19T00:00:00.000Z");
-            w.append("</Bucket>");
-        }
-        w.append("</Buckets>");
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListAllMyBucketsResult<'a> {
+    buckets: Buckets<'a>,
+    owner: Owner<'a>,
+}
 
-        w.append("<Owner>");
-        w.append_xml("ID", r.owner.id.as_str());
-        w.append_xml("DisplayName", r.owner.display_name.as_str());
-        w.append("</Owner>");
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Buckets<'a> {
+    bucket: Vec<Bucket<'a>>,
+}
 
-        w.append("</ListAllMyBucketsResult>");
-        Response::from_parts(parts, w.body())
-    }
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Bucket<'a> {
+    name: &'a str,
+    creation_date: &'a str,
 }
@@ -1,10 +1,14 @@
 use crate::api::*;
 use hyper::{Request, Response};
+use serde::Serialize;
 
 pub type Req = Request<Params>;
 pub type Res = Response<Reply>;
 pub type Ret = Result<Res, S3Error>;
 
+/// The max-keys of requests that don't specify it, like S3.
+pub const DEFAULT_MAX_KEYS: i32 = 1000;
+
 #[derive(Debug, Clone)]
 pub struct Params {
     pub bucket: String,
@@ -13,8 +17,12 @@ pub struct Params {
     pub marker: String,
     pub max_keys: i32,
     pub encoding_type: String,
-    // TODO: list objects v2
-    // pub start_after: String,
+    /// list-type=2 selects ListObjectsV2, where the continuation token
+    /// or start-after take the place of the marker.
+    pub v2: bool,
+    pub start_after: String,
+    pub continuation_token: String,
+    pub fetch_owner: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -25,9 +33,11 @@ pub struct Reply {
     pub marker: String,
     pub max_keys: i32,
     pub encoding_type: String,
+    pub v2: bool,
+    pub start_after: String,
+    pub continuation_token: String,
+    pub fetch_owner: bool,
 
-    // TODO: list objects v2
-    // pub start_after: String,
     pub is_truncated: bool,
     pub next_marker: String,
 
@@ -37,7 +47,7 @@ pub struct Reply {
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     /// GET /
     ///     ?delimiter=Delimiter
     ///     &encoding-type=EncodingType
@@ -49,25 +59,63 @@ impl ReqParser for Req {
     /// x-amz-request-payer: RequestPayer
     /// x-amz-expected-bucket-owner: ExpectedBucketOwner
     /// ```
-    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Self {
+    ///
+    /// ListObjectsV2 Request Syntax:
+    /// ```text
+    /// GET /?list-type=2
+    ///     &continuation-token=ContinuationToken
+    ///     &delimiter=Delimiter
+    ///     &encoding-type=EncodingType
+    ///     &fetch-owner=FetchOwner
+    ///     &max-keys=MaxKeys
+    ///     &prefix=Prefix
+    ///     &start-after=StartAfter
+    ///     HTTP/1.1
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
         let (parts, _) = req.into_parts();
         let qs = QueryStr::from_parts(&parts);
+        let max_keys = qs.get_i32("max-keys")?.unwrap_or(DEFAULT_MAX_KEYS);
+        if max_keys < 0 {
+            return Err(S3Error::InvalidArgument);
+        }
+        let v2 = match qs.value("list-type") {
+            None | Some("1") => false,
+            Some("2") => true,
+            Some(_) => return Err(S3Error::InvalidArgument),
+        };
+        let start_after = qs.get("start-after");
+        let continuation_token = qs.get("continuation-token");
+        // the continuation token is the last key of the previous page,
+        // so both v2 params map to the v1 marker for the layers
+        let marker = match v2 {
+            false => qs.get("marker"),
+            true if !continuation_token.is_empty() => continuation_token.clone(),
+            true => start_after.clone(),
+        };
+        let encoding_type = qs.get("encoding-type");
+        if !encoding_type.is_empty() && encoding_type != "url" {
+            return Err(S3Error::InvalidArgument);
+        }
         let params = Params {
             bucket: bucket.to_string(),
             prefix: qs.get("prefix"),
             delimiter: qs.get("delimiter"),
-            marker: qs.get("marker"),
-            max_keys: qs.get_i32("max-keys"),
-            encoding_type: qs.get("encoding-type"),
-            // start_after: qs.get("start-after"),
+            marker,
+            max_keys,
+            encoding_type,
+            v2,
+            start_after,
+            continuation_token,
+            fetch_owner: qs.get("fetch-owner") == "true",
         };
-        Request::from_parts(parts, params)
+        Ok(Request::from_parts(parts, params))
     }
 }
 
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     /// HTTP/1.1 200
     /// <?xml version="1.0" encoding="UTF-8"?>
     /// <ListBucketResult>
@@ -97,45 +145,122 @@ impl ResWriter for Res {
     ///    <EncodingType>string</EncodingType>
     /// </ListBucketResult>    
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, r) = self.into_parts();
-        let mut w = BodyWriter::new_xml();
-
-        w.append("<ListBucketResult>");
-
-        w.append_xml("IsTruncated", r.is_truncated.to_string().as_str());
-        w.append_xml("NextMarker", r.next_marker.as_str());
-
-        w.append_xml("Name", r.bucket.as_str());
-        w.append_xml("Prefix", r.prefix.as_str());
-        w.append_xml("Marker", r.marker.as_str());
-        w.append_xml("Delimiter", r.delimiter.as_str());
-        w.append_xml("MaxKeys", r.max_keys.to_string().as_str());
-        w.append_xml("EncodingType", r.encoding_type.as_str());
+        let url = r.encoding_type == "url";
+        let enc = |s: &str| match url {
+            true => encode_url(s),
+            false => s.to_string(),
+        };
+        let contents = r
+            .objects
+            .iter()
+            .map(|obj| Contents {
+                key: enc(&obj.key),
+                last_modified: &obj.last_modified,
+                etag: &obj.etag,
+                size: obj.size,
+                storage_class: &obj.storage_class,
+                // v2 only lists the owner on request
+                owner: match !r.v2 || r.fetch_owner {
+                    true => Some(Owner::from(&obj.owner)),
+                    false => None,
+                },
+            })
+            .collect();
+        let common_prefixes = r
+            .common_prefixes
+            .iter()
+            .map(|p| CommonPrefix { prefix: enc(p) })
+            .collect();
+        let (v1, v2) = match r.v2 {
+            false => (
+                Some(V1 {
+                    marker: enc(&r.marker),
+                    next_marker: enc(&r.next_marker),
+                }),
+                None,
+            ),
+            true => (
+                None,
+                Some(V2 {
+                    key_count: r.objects.len() + r.common_prefixes.len(),
+                    continuation_token: &r.continuation_token,
+                    next_continuation_token: &r.next_marker,
+                    start_after: enc(&r.start_after),
+                }),
+            ),
+        };
+        let doc = ListBucketResult {
+            is_truncated: r.is_truncated,
+            name: &r.bucket,
+            prefix: enc(&r.prefix),
+            delimiter: enc(&r.delimiter),
+            max_keys: r.max_keys,
+            encoding_type: &r.encoding_type,
+            v1,
+            v2,
+            contents,
+            common_prefixes,
+        };
+        let body = to_xml("ListBucketResult", &doc)?;
+        Ok(Response::from_parts(parts, body.into()))
+    }
+}
 
-        for obj in r.objects {
-            w.append("<Contents>");
-            w.append_xml("Key", obj.key.to_string().as_str());
-            w.append_xml("LastModified", obj.last_modified.to_string().as_str());
-            w.append_xml("ETag", obj.etag.to_string().as_str());
-            w.append_xml("Size", obj.size.to_string().as_str());
-            w.append_xml("StorageClass", obj.storage_class.to_string().as_str());
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult<'a> {
+    is_truncated: bool,
+    name: &'a str,
+    prefix: String,
+    delimiter: String,
+    max_keys: i32,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    encoding_type: &'a str,
+    #[serde(flatten)]
+    v1: Option<V1>,
+    #[serde(flatten)]
+    v2: Option<V2<'a>>,
+    contents: Vec<Contents<'a>>,
+    common_prefixes: Vec<CommonPrefix>,
+}
 
-            w.append("<Owner>");
-            w.append_xml("ID", obj.owner.id.as_str());
-            w.append_xml("DisplayName", obj.owner.display_name.as_str());
-            w.append("</Owner>");
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct V1 {
+    marker: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    next_marker: String,
+}
 
-            w.append("</Contents>");
-        }
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct V2<'a> {
+    key_count: usize,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    continuation_token: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    next_continuation_token: &'a str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    start_after: String,
+}
 
-        for prefix in r.common_prefixes {
-            w.append("<CommonPrefixes>");
-            w.append_xml("Prefix", prefix.to_string().as_str());
-            w.append("</CommonPrefixes>");
-        }
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Contents<'a> {
+    key: String,
+    last_modified: &'a str,
+    #[serde(rename = "ETag")]
+    etag: &'a str,
+    size: u64,
+    storage_class: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<Owner<'a>>,
+}
 
-        w.append("</ListBucketResult>");
-        Response::from_parts(parts, w.body())
-    }
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CommonPrefix {
+    prefix: String,
 }
@@ -1,8 +1,48 @@
-pub mod list_buckets;
-pub mod list_objects;
+pub mod copy_object;
+pub mod delete_bucket;
+pub mod delete_bucket_cors;
+pub mod delete_bucket_encryption;
+pub mod delete_bucket_lifecycle;
+pub mod delete_bucket_policy;
+pub mod delete_bucket_replication;
+pub mod delete_bucket_tagging;
+pub mod delete_bucket_website;
+pub mod delete_object;
+pub mod delete_object_tagging;
 pub mod get_bucket;
+pub mod get_bucket_acl;
+pub mod get_bucket_cors;
+pub mod get_bucket_encryption;
+pub mod get_bucket_lifecycle;
+pub mod get_bucket_location;
+pub mod get_bucket_notification;
+pub mod get_bucket_policy;
+pub mod get_bucket_replication;
+pub mod get_bucket_tagging;
+pub mod get_bucket_website;
 pub mod get_object;
+pub mod get_object_acl;
+pub mod get_object_legal_hold;
+pub mod get_object_lock_configuration;
+pub mod get_object_retention;
+pub mod get_object_tagging;
+pub mod list_buckets;
+pub mod list_objects;
+pub mod options_object;
 pub mod put_bucket;
+pub mod put_bucket_acl;
+pub mod put_bucket_cors;
+pub mod put_bucket_encryption;
+pub mod put_bucket_lifecycle;
+pub mod put_bucket_notification;
+pub mod put_bucket_policy;
+pub mod put_bucket_replication;
+pub mod put_bucket_tagging;
+pub mod put_bucket_website;
 pub mod put_object;
-pub mod delete_bucket;
-pub mod delete_object;
+pub mod put_object_acl;
+pub mod put_object_legal_hold;
+pub mod put_object_lock_configuration;
+pub mod put_object_retention;
+pub mod put_object_tagging;
+pub mod select_object_content;
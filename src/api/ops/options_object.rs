@@ -0,0 +1,58 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    pub cors: CorsRequest,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    /// the rule of the bucket CORS configuration that allows the request
+    pub rule: CorsRule,
+    pub cors: CorsRequest,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// OPTIONS /ObjectName HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// Origin: Origin
+    /// Access-Control-Request-Method: HTTPMethod
+    /// Access-Control-Request-Headers: RequestHeader
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, _) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            cors: CorsRequest::preflight(&parts.headers)?,
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// Access-Control-Allow-Origin: Origin
+    /// Access-Control-Max-Age: MaxAgeSeconds
+    /// Access-Control-Allow-Methods: HTTPMethod
+    /// Access-Control-Allow-Headers: RequestHeader
+    /// Access-Control-Expose-Headers: ExposeHeader
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let mut res = Response::from_parts(parts, Body::empty());
+        r.rule.write_headers(&mut res, &r.cors, true)?;
+        Ok(res)
+    }
+}
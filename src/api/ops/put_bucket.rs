@@ -1,14 +1,30 @@
 use crate::api::*;
+use crate::auth::*;
 use hyper::{Body, Request, Response};
+use serde::Deserialize;
 
 pub type Req = Request<Params>;
 pub type Res = Response<Reply>;
 pub type Ret = Result<Res, S3Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Params {
     pub bucket: String,
     pub class: String,
+    /// the server region, set by read_location before the layer is called
+    pub region: String,
+    pub acl: AclHeaders,
+    /// from x-amz-bucket-object-lock-enabled, can't be changed later
+    pub object_lock_enabled: bool,
+    /// the CreateBucketConfiguration, consumed by read_location
+    pub body: Option<Body>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CreateBucketConfiguration {
+    #[serde(default)]
+    location_constraint: String,
 }
 
 #[derive(Debug, Clone)]
@@ -18,7 +34,7 @@ pub struct Reply {
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     /// PUT / HTTP/1.1
     /// Host: Bucket.s3.amazonaws.com
     /// x-amz-acl: ACL
@@ -33,28 +49,57 @@ impl ReqParser for Req {
     ///    <LocationConstraint>string</LocationConstraint>
     /// </CreateBucketConfiguration>
     /// ```    
-    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Self {
-        let (parts, _) = req.into_parts();
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        validate_bucket_name(bucket)?;
+        let (parts, body) = req.into_parts();
         let qs = QueryStr::from_parts(&parts);
         let params = Params {
             bucket: bucket.to_string(),
             class: qs.get("bucket-class"),
+            region: String::new(),
+            acl: AclHeaders::from_headers(&parts.headers)?,
+            object_lock_enabled: match parts.headers.get(OBJECT_LOCK_ENABLED_HEADER) {
+                None => false,
+                Some(v) => v
+                    .to_str()
+                    .map_err(|_| S3Error::InvalidArgument)?
+                    .eq_ignore_ascii_case("true"),
+            },
+            body: Some(body),
         };
-        Request::from_parts(parts, params)
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl Params {
+    /// Read the optional CreateBucketConfiguration body and set the bucket region
+    /// to the server region. A LocationConstraint of another region fails with
+    /// IllegalLocationConstraintException since a server serves a single region.
+    pub async fn read_location(&mut self, server_region: &str) -> Result<(), S3Error> {
+        let buf = read_document(self.body.take()).await?;
+        let config: CreateBucketConfiguration = match buf.is_empty() {
+            true => CreateBucketConfiguration::default(),
+            false => from_xml(&buf)?,
+        };
+        let constraint = config.location_constraint;
+        if !constraint.is_empty() && constraint != server_region {
+            return Err(S3Error::IllegalLocationConstraintException);
+        }
+        self.region = server_region.to_string();
+        Ok(())
     }
 }
 
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     /// HTTP/1.1 200
     /// Location: Location
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, r) = self.into_parts();
         let mut res = Response::from_parts(parts, Body::empty());
-        res.headers_mut()
-            .insert("Location", format!("/{}", r.info.name).parse().unwrap());
-        res
+        set_header(&mut res, "Location", &format!("/{}", r.info.name))?;
+        Ok(res)
     }
 }
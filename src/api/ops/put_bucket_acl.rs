@@ -0,0 +1,74 @@
+use crate::api::*;
+use crate::auth::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    pub acl: AclHeaders,
+    pub body: Option<Body>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub acl: Acl,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// PUT /?acl HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-acl: ACL
+    /// Content-MD5: ContentMD5
+    /// x-amz-grant-full-control: GrantFullControl
+    /// x-amz-grant-read: GrantRead
+    /// x-amz-grant-read-acp: GrantReadACP
+    /// x-amz-grant-write: GrantWrite
+    /// x-amz-grant-write-acp: GrantWriteACP
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <AccessControlPolicy xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    ///    <AccessControlList>
+    ///       <Grant>
+    ///          <Grantee>
+    ///             <DisplayName>string</DisplayName>
+    ///             <EmailAddress>string</EmailAddress>
+    ///             <ID>string</ID>
+    ///             <xsi:type>string</xsi:type>
+    ///             <URI>string</URI>
+    ///          </Grantee>
+    ///          <Permission>string</Permission>
+    ///       </Grant>
+    ///    </AccessControlList>
+    ///    <Owner>
+    ///       <DisplayName>string</DisplayName>
+    ///       <ID>string</ID>
+    ///    </Owner>
+    /// </AccessControlPolicy>
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, body) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+            acl: AclHeaders::from_headers(&parts.headers)?,
+            body: Some(body),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, _) = self.into_parts();
+        Ok(Response::from_parts(parts, Body::empty()))
+    }
+}
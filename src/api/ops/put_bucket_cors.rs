@@ -0,0 +1,61 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    /// the CORSConfiguration document, see CorsConfiguration::read
+    pub body: Option<Body>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// PUT /?cors HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// Content-MD5: ContentMD5
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <CORSConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    ///    <CORSRule>
+    ///       <AllowedHeader>string</AllowedHeader>
+    ///       ...
+    ///       <AllowedMethod>string</AllowedMethod>
+    ///       ...
+    ///       <AllowedOrigin>string</AllowedOrigin>
+    ///       ...
+    ///       <ExposeHeader>string</ExposeHeader>
+    ///       ...
+    ///       <ID>string</ID>
+    ///       <MaxAgeSeconds>integer</MaxAgeSeconds>
+    ///    </CORSRule>
+    ///    ...
+    /// </CORSConfiguration>
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, body) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+            body: Some(body),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, _) = self.into_parts();
+        Ok(Response::from_parts(parts, Body::empty()))
+    }
+}
@@ -0,0 +1,79 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    /// the LifecycleConfiguration document, see LifecycleConfiguration::read
+    pub body: Option<Body>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// PUT /?lifecycle HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// Content-MD5: ContentMD5
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <LifecycleConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    ///    <Rule>
+    ///       <ID>string</ID>
+    ///       <Filter>
+    ///          <Prefix>string</Prefix>
+    ///          <Tag>
+    ///             <Key>string</Key>
+    ///             <Value>string</Value>
+    ///          </Tag>
+    ///          <And>
+    ///             <Prefix>string</Prefix>
+    ///             <Tag>
+    ///                <Key>string</Key>
+    ///                <Value>string</Value>
+    ///             </Tag>
+    ///             ...
+    ///          </And>
+    ///       </Filter>
+    ///       <Status>string</Status>
+    ///       <Expiration>
+    ///          <Date>timestamp</Date>
+    ///          <Days>integer</Days>
+    ///          <ExpiredObjectDeleteMarker>boolean</ExpiredObjectDeleteMarker>
+    ///       </Expiration>
+    ///       <NoncurrentVersionExpiration>
+    ///          <NoncurrentDays>integer</NoncurrentDays>
+    ///       </NoncurrentVersionExpiration>
+    ///       <AbortIncompleteMultipartUpload>
+    ///          <DaysAfterInitiation>integer</DaysAfterInitiation>
+    ///       </AbortIncompleteMultipartUpload>
+    ///    </Rule>
+    ///    ...
+    /// </LifecycleConfiguration>
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, body) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+            body: Some(body),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, _) = self.into_parts();
+        Ok(Response::from_parts(parts, Body::empty()))
+    }
+}
@@ -0,0 +1,86 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    /// set by read_config before the layer is called, empty removes the notifications
+    pub config: NotificationConfiguration,
+    /// the NotificationConfiguration document, consumed by read_config
+    pub body: Option<Body>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// PUT /?notification HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// x-amz-skip-destination-validation: SkipDestinationValidation
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <NotificationConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    ///    <TopicConfiguration>
+    ///       <Event>string</Event>
+    ///       ...
+    ///       <Filter>
+    ///          <S3Key>
+    ///             <FilterRule>
+    ///                <Name>string</Name>
+    ///                <Value>string</Value>
+    ///             </FilterRule>
+    ///             ...
+    ///          </S3Key>
+    ///       </Filter>
+    ///       <Id>string</Id>
+    ///       <Topic>string</Topic>
+    ///    </TopicConfiguration>
+    ///    ...
+    ///    <QueueConfiguration>
+    ///       ...
+    ///       <Queue>string</Queue>
+    ///    </QueueConfiguration>
+    ///    ...
+    ///    <CloudFunctionConfiguration>
+    ///       ...
+    ///       <CloudFunction>string</CloudFunction>
+    ///    </CloudFunctionConfiguration>
+    ///    ...
+    /// </NotificationConfiguration>
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, body) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+            config: NotificationConfiguration::default(),
+            body: Some(body),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl Params {
+    /// Read and validate the NotificationConfiguration body,
+    /// the server checks the destinations before the layer keeps it.
+    pub async fn read_config(&mut self) -> Result<&NotificationConfiguration, S3Error> {
+        self.config = NotificationConfiguration::read(self.body.take()).await?;
+        Ok(&self.config)
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, _) = self.into_parts();
+        Ok(Response::from_parts(parts, Body::empty()))
+    }
+}
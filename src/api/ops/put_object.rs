@@ -1,4 +1,5 @@
 use crate::api::*;
+use crate::auth::*;
 use hyper::{Body, Request, Response};
 
 pub type Req = Request<Params>;
@@ -9,6 +10,19 @@ pub type Ret = Result<Res, S3Error>;
 pub struct Params {
     pub bucket: String,
     pub key: String,
+    pub acl: AclHeaders,
+    /// from x-amz-tagging
+    pub tagging: Tagging,
+    /// from x-amz-website-redirect-location, empty for none
+    pub website_redirect_location: String,
+    /// from x-amz-server-side-encryption-customer-*, the layer encrypts with it
+    pub sse_customer: Option<SseCustomerKey>,
+    /// from x-amz-server-side-encryption, applied by the EncryptionLayer
+    pub sse: Option<ServerSideEncryption>,
+    /// from x-amz-object-lock-*, only valid in buckets with Object Lock
+    pub object_lock: ObjectLock,
+    /// set by the EncryptionLayer, the layers under it keep it with the object
+    pub plaintext_size: Option<u64>,
     pub body: Option<Body>,
     // TODO partial updates
     // pub head_only: bool, // put only headers but keep content
@@ -28,7 +42,7 @@ pub struct Reply {
 
 impl ReqParser for Req {
     /// Request Syntax:
-    /// ```
+    /// ```text
     // PUT /Key+ HTTP/1.1
     // Host: Bucket.s3.amazonaws.com
     // Cache-Control: CacheControl
@@ -62,20 +76,57 @@ impl ReqParser for Req {
     //
     // Body
     /// ```
-    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Self {
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
         let (parts, body) = req.into_parts();
+        // like S3 the size is declared up front, unless the body is chunked
+        let chunked = parts.headers.contains_key(hyper::header::TRANSFER_ENCODING);
+        match parts.headers.get(hyper::header::CONTENT_LENGTH) {
+            None if !chunked => return Err(S3Error::MissingContentLength),
+            None => {}
+            Some(v) => {
+                let len: u64 = v
+                    .to_str()
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or(S3Error::BadRequest)?;
+                if len > MAX_OBJECT_SIZE {
+                    return Err(S3Error::EntityTooLarge);
+                }
+            }
+        }
         let params = Params {
             bucket: bucket.to_string(),
             key: key.to_string(),
+            acl: AclHeaders::from_headers(&parts.headers)?,
+            tagging: match parts.headers.get("x-amz-tagging") {
+                None => Tagging::default(),
+                Some(v) => Tagging::from_header(v.to_str().map_err(|_| S3Error::InvalidTag)?)?,
+            },
+            website_redirect_location: match parts.headers.get("x-amz-website-redirect-location") {
+                None => String::new(),
+                Some(v) => {
+                    let location = v.to_str().map_err(|_| S3Error::InvalidRedirectLocation)?;
+                    validate_redirect_location(location)?;
+                    location.to_string()
+                }
+            },
+            sse_customer: SseCustomerKey::from_headers(&parts.headers)?,
+            sse: ServerSideEncryption::from_headers(&parts.headers)?,
+            object_lock: ObjectLock::from_headers(&parts.headers)?,
+            plaintext_size: None,
             body: Some(body),
         };
-        Request::from_parts(parts, params)
+        // an object is encrypted with either customer or server managed keys
+        if params.sse_customer.is_some() && params.sse.is_some() {
+            return Err(S3Error::InvalidArgument);
+        }
+        Ok(Request::from_parts(parts, params))
     }
 }
 
 impl ResWriter for Res {
     /// Response Syntax:
-    /// ```
+    /// ```text
     // HTTP/1.1 200
     // ETag: ETag
     // x-amz-version-id: VersionId
@@ -88,13 +139,12 @@ impl ResWriter for Res {
     // x-amz-server-side-encryption-context: SSEKMSEncryptionContext
     // x-amz-server-side-encryption-bucket-key-enabled: BucketKeyEnabled
     /// ```
-    fn write(self) -> HttpResponse {
+    fn write(self) -> Result<HttpResponse, S3Error> {
         let (parts, r) = self.into_parts();
         let mut res = Response::from_parts(parts, Body::empty());
-        res.headers_mut()
-            .insert("ETag", r.object.etag.parse().unwrap());
-        res.headers_mut()
-            .insert("x-amz-version-id", r.object.version_id.parse().unwrap());
-        res
+        set_header(&mut res, "ETag", &r.object.etag)?;
+        set_header(&mut res, "x-amz-version-id", &r.object.version_id)?;
+        set_sse_headers(&mut res, &r.object)?;
+        Ok(res)
     }
 }
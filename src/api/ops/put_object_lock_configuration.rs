@@ -0,0 +1,59 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    /// the ObjectLockConfiguration document, see ObjectLockConfiguration::read
+    pub body: Option<Body>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// PUT /?object-lock HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-request-payer: RequestPayer
+    /// x-amz-bucket-object-lock-token: Token
+    /// Content-MD5: ContentMD5
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <ObjectLockConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    ///    <ObjectLockEnabled>string</ObjectLockEnabled>
+    ///    <Rule>
+    ///       <DefaultRetention>
+    ///          <Days>integer</Days>
+    ///          <Mode>string</Mode>
+    ///          <Years>integer</Years>
+    ///       </DefaultRetention>
+    ///    </Rule>
+    /// </ObjectLockConfiguration>
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, _key: &str) -> Result<Self, S3Error> {
+        let (parts, body) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+            body: Some(body),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// x-amz-request-charged: RequestCharged
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, _) = self.into_parts();
+        Ok(Response::from_parts(parts, Body::empty()))
+    }
+}
@@ -0,0 +1,65 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: String,
+    /// the Tagging document, see Tagging::read
+    pub body: Option<Body>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub version_id: String,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// PUT /{Key+}?tagging&versionId=VersionId HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// Content-MD5: ContentMD5
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// x-amz-request-payer: RequestPayer
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <Tagging xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    ///    <TagSet>
+    ///       <Tag>
+    ///          <Key>string</Key>
+    ///          <Value>string</Value>
+    ///       </Tag>
+    ///    </TagSet>
+    /// </Tagging>
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, body) = req.into_parts();
+        let qs = QueryStr::from_parts(&parts);
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            version_id: qs.get("versionId"),
+            body: Some(body),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// x-amz-version-id: VersionId
+    /// ```
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        let mut res = Response::from_parts(parts, Body::empty());
+        set_header(&mut res, "x-amz-version-id", &r.version_id)?;
+        Ok(res)
+    }
+}
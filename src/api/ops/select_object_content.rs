@@ -0,0 +1,121 @@
+use crate::api::*;
+use hyper::{Body, Request, Response};
+
+pub type Req = Request<Params>;
+pub type Res = Response<Reply>;
+pub type Ret = Result<Res, S3Error>;
+
+#[derive(Debug)]
+pub struct Params {
+    pub bucket: String,
+    pub key: String,
+    /// from x-amz-server-side-encryption-customer-*, required for SSE-C objects
+    pub sse_customer: Option<SseCustomerKey>,
+    /// the SelectObjectContentRequest document, see SelectRequest::read
+    pub body: Option<Body>,
+}
+
+#[derive(Debug)]
+pub struct Reply {
+    /// the event stream of the records, see select_object_content
+    pub body: Body,
+}
+
+impl ReqParser for Req {
+    /// Request Syntax:
+    /// ```text
+    /// POST /Key+?select&select-type=2 HTTP/1.1
+    /// Host: Bucket.s3.amazonaws.com
+    /// x-amz-server-side-encryption-customer-algorithm: SSECustomerAlgorithm
+    /// x-amz-server-side-encryption-customer-key: SSECustomerKey
+    /// x-amz-server-side-encryption-customer-key-MD5: SSECustomerKeyMD5
+    /// x-amz-expected-bucket-owner: ExpectedBucketOwner
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <SelectObjectContentRequest xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    ///    <Expression>string</Expression>
+    ///    <ExpressionType>string</ExpressionType>
+    ///    <RequestProgress>
+    ///       <Enabled>boolean</Enabled>
+    ///    </RequestProgress>
+    ///    <InputSerialization>
+    ///       <CompressionType>string</CompressionType>
+    ///       <CSV>
+    ///          <AllowQuotedRecordDelimiter>boolean</AllowQuotedRecordDelimiter>
+    ///          <Comments>string</Comments>
+    ///          <FieldDelimiter>string</FieldDelimiter>
+    ///          <FileHeaderInfo>string</FileHeaderInfo>
+    ///          <QuoteCharacter>string</QuoteCharacter>
+    ///          <QuoteEscapeCharacter>string</QuoteEscapeCharacter>
+    ///          <RecordDelimiter>string</RecordDelimiter>
+    ///       </CSV>
+    ///       <JSON>
+    ///          <Type>string</Type>
+    ///       </JSON>
+    ///       <Parquet>
+    ///       </Parquet>
+    ///    </InputSerialization>
+    ///    <OutputSerialization>
+    ///       <CSV>
+    ///          <FieldDelimiter>string</FieldDelimiter>
+    ///          <QuoteCharacter>string</QuoteCharacter>
+    ///          <QuoteEscapeCharacter>string</QuoteEscapeCharacter>
+    ///          <QuoteFields>string</QuoteFields>
+    ///          <RecordDelimiter>string</RecordDelimiter>
+    ///       </CSV>
+    ///       <JSON>
+    ///          <RecordDelimiter>string</RecordDelimiter>
+    ///       </JSON>
+    ///    </OutputSerialization>
+    ///    <ScanRange>
+    ///       <End>long</End>
+    ///       <Start>long</Start>
+    ///    </ScanRange>
+    /// </SelectObjectContentRequest>
+    /// ```
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error> {
+        let (parts, body) = req.into_parts();
+        let params = Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            sse_customer: SseCustomerKey::from_headers(&parts.headers)?,
+            body: Some(body),
+        };
+        Ok(Request::from_parts(parts, params))
+    }
+}
+
+impl ResWriter for Res {
+    /// Response Syntax:
+    /// ```text
+    /// HTTP/1.1 200
+    /// <?xml version="1.0" encoding="UTF-8"?>
+    /// <Payload>
+    ///    <Records>
+    ///       <Payload>blob</Payload>
+    ///    </Records>
+    ///    <Stats>
+    ///       <Details>
+    ///          <BytesProcessed>long</BytesProcessed>
+    ///          <BytesReturned>long</BytesReturned>
+    ///          <BytesScanned>long</BytesScanned>
+    ///       </Details>
+    ///    </Stats>
+    ///    <Progress>
+    ///       <Details>
+    ///          <BytesProcessed>long</BytesProcessed>
+    ///          <BytesReturned>long</BytesReturned>
+    ///          <BytesScanned>long</BytesScanned>
+    ///       </Details>
+    ///    </Progress>
+    ///    <Cont>
+    ///    </Cont>
+    ///    <End>
+    ///    </End>
+    /// </Payload>
+    /// ```
+    /// The payload is sent as messages of the event stream format.
+    fn write(self) -> Result<HttpResponse, S3Error> {
+        let (parts, r) = self.into_parts();
+        Ok(Response::from_parts(parts, r.body))
+    }
+}
@@ -0,0 +1,228 @@
+use crate::api::*;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+
+/// Limits of the replication configuration, like S3.
+pub const MAX_REPLICATION_RULES: usize = 1000;
+pub const MAX_REPLICATION_RULE_ID_LEN: usize = 255;
+
+/// The ARN of a destination bucket on a replication destination of the server
+/// is `arn:s3d:replica::{destination}:{bucket}`. The S3 form `arn:aws:s3:::{bucket}`
+/// is also accepted when the server has a single destination.
+pub const REPLICA_ARN_PREFIX: &str = "arn:s3d:replica::";
+pub const BUCKET_ARN_PREFIX: &str = "arn:aws:s3:::";
+
+/// ReplicationConfiguration is the `?replication` document of a bucket.
+///
+/// Layers keep it and the ReplicationLayer replays the writes and deletes of the
+/// matching objects to the destination buckets. The role is kept as given,
+/// destinations are accessed with the credentials of the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationConfiguration {
+    #[serde(rename = "Role", default)]
+    pub role: String,
+    #[serde(rename = "Rule", default)]
+    pub rules: Vec<ReplicationRule>,
+}
+
+/// The rule has the deprecated prefix or a filter, not both.
+/// Filters have the same shape as the lifecycle rule filters.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReplicationRule {
+    #[serde(rename = "ID", default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<LifecycleFilter>,
+    pub status: String,
+    pub destination: ReplicationDestination,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_marker_replication: Option<DeleteMarkerReplication>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReplicationDestination {
+    /// the bucket ARN, see REPLICA_ARN_PREFIX
+    pub bucket: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeleteMarkerReplication {
+    pub status: String,
+}
+
+/// The x-amz-replication-status of objects in the source bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationStatus {
+    Pending,
+    Completed,
+    Failed,
+}
+
+impl ReplicationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReplicationStatus::Pending => "PENDING",
+            ReplicationStatus::Completed => "COMPLETED",
+            ReplicationStatus::Failed => "FAILED",
+        }
+    }
+}
+
+impl ReplicationConfiguration {
+    /// Read and validate a ReplicationConfiguration document from a request body.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let config: ReplicationConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("ReplicationConfiguration", self)
+    }
+
+    /// Check the rules like S3 does. Destinations are checked by the
+    /// ReplicationLayer, which knows the destinations of the server.
+    pub fn validate(&self) -> Result<(), S3Error> {
+        if self.rules.is_empty() || self.rules.len() > MAX_REPLICATION_RULES {
+            return Err(S3Error::MalformedXML);
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.id.len() > MAX_REPLICATION_RULE_ID_LEN {
+                return Err(S3Error::InvalidArgument);
+            }
+            if !rule.id.is_empty() && self.rules[..i].iter().any(|r| r.id == rule.id) {
+                return Err(S3Error::InvalidArgument);
+            }
+            rule.validate()?;
+        }
+        Ok(())
+    }
+
+    /// The enabled rules that replicate the key, at most one per destination bucket,
+    /// which is the rule with the highest priority, or the first one.
+    pub fn rules_for(&self, key: &str, tags: &[Tag]) -> Vec<&ReplicationRule> {
+        let mut rules: Vec<&ReplicationRule> = Vec::new();
+        for rule in self.rules.iter().filter(|r| r.matches(key, tags)) {
+            match rules
+                .iter_mut()
+                .find(|r| r.destination.bucket == rule.destination.bucket)
+            {
+                Some(r) if rule.priority.unwrap_or(0) > r.priority.unwrap_or(0) => *r = rule,
+                Some(_) => {}
+                None => rules.push(rule),
+            }
+        }
+        rules
+    }
+
+    /// Rules that filter on tags need the tags of the object to match it.
+    pub fn has_tag_filters(&self) -> bool {
+        self.rules.iter().any(|r| !r.filter_tags().is_empty())
+    }
+}
+
+impl ReplicationRule {
+    pub fn is_enabled(&self) -> bool {
+        self.status == "Enabled"
+    }
+
+    /// Deletes are only replicated by rules that enable delete marker replication.
+    pub fn replicates_deletes(&self) -> bool {
+        self.delete_marker_replication
+            .as_ref()
+            .is_some_and(|d| d.status == "Enabled")
+    }
+
+    fn validate(&self) -> Result<(), S3Error> {
+        if self.status != "Enabled" && self.status != "Disabled" {
+            return Err(S3Error::MalformedXML);
+        }
+        if let Some(dmr) = &self.delete_marker_replication {
+            if dmr.status != "Enabled" && dmr.status != "Disabled" {
+                return Err(S3Error::MalformedXML);
+            }
+        }
+        if self.prefix.is_some() && self.filter.is_some() {
+            return Err(S3Error::MalformedXML);
+        }
+        if let Some(filter) = &self.filter {
+            let set = [
+                filter.prefix.is_some(),
+                filter.tag.is_some(),
+                filter.and.is_some(),
+            ];
+            if set.iter().filter(|s| **s).count() > 1 {
+                return Err(S3Error::MalformedXML);
+            }
+        }
+        if self.destination.bucket.is_empty() {
+            return Err(S3Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    fn filter_prefix(&self) -> &str {
+        let filter = self.filter.as_ref();
+        self.prefix
+            .as_deref()
+            .or_else(|| filter.and_then(|f| f.prefix.as_deref()))
+            .or_else(|| filter.and_then(|f| f.and.as_ref()?.prefix.as_deref()))
+            .unwrap_or("")
+    }
+
+    fn filter_tags(&self) -> Vec<&Tag> {
+        match &self.filter {
+            Some(LifecycleFilter { tag: Some(tag), .. }) => vec![tag],
+            Some(LifecycleFilter { and: Some(and), .. }) => and.tags.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Enabled rules match keys with the prefix that have all the filter tags.
+    pub fn matches(&self, key: &str, tags: &[Tag]) -> bool {
+        self.is_enabled()
+            && key.starts_with(self.filter_prefix())
+            && self.filter_tags().iter().all(|t| tags.contains(t))
+    }
+}
+
+impl ReplicationDestination {
+    /// The destination name and bucket of the ARN, the name is None
+    /// for the S3 form of bucket ARNs.
+    pub fn target(&self) -> Option<(Option<&str>, &str)> {
+        if let Some(rest) = self.bucket.strip_prefix(REPLICA_ARN_PREFIX) {
+            let (name, bucket) = rest.split_once(':')?;
+            if name.is_empty() || bucket.is_empty() {
+                return None;
+            }
+            return Some((Some(name), bucket));
+        }
+        match self.bucket.strip_prefix(BUCKET_ARN_PREFIX) {
+            Some(bucket) if !bucket.is_empty() => Some((None, bucket)),
+            _ => None,
+        }
+    }
+}
+
+/// The replication configuration of the bucket, None when the bucket has none
+/// or the layer does not keep it.
+pub async fn bucket_replication<API: ApiLayer + ?Sized>(
+    api: &API,
+    bucket: &str,
+) -> Option<ReplicationConfiguration> {
+    let req = get_bucket_replication::Req::new(get_bucket_replication::Params {
+        bucket: bucket.to_string(),
+    });
+    let res = api.get_bucket_replication(req).await.ok()?;
+    Some(res.into_body().config)
+}
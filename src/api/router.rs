@@ -0,0 +1,528 @@
+use hyper::{HeaderMap, Method};
+
+/// Target is the resource a request addresses, by the presence of a bucket and key in the path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Service,
+    Bucket,
+    Object,
+}
+
+/// Route matches a request to an op by method, target, sub-resource query params
+/// and headers. All the params and the header must be present for the route to match.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub method: Method,
+    pub target: Target,
+    pub query: &'static [&'static str],
+    pub header: Option<&'static str>,
+    pub op: Op,
+}
+
+macro_rules! ops {
+    ($($op:ident => $action:literal, $log:literal;)*) => {
+        /// Op is an S3 API operation, see https://docs.aws.amazon.com/AmazonS3/latest/API/API_Operations_Amazon_Simple_Storage_Service.html
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Op {
+            $($op,)*
+        }
+
+        impl Op {
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Op::$op => stringify!($op),)*
+                }
+            }
+
+            /// The IAM action name used for policy evaluation.
+            pub fn action(&self) -> &'static str {
+                match self {
+                    $(Op::$op => $action,)*
+                }
+            }
+
+            /// The resource part of the access log operation, e.g. OBJECT in REST.GET.OBJECT.
+            pub fn log_resource(&self) -> &'static str {
+                match self {
+                    $(Op::$op => $log,)*
+                }
+            }
+        }
+    };
+}
+
+ops! {
+    ListBuckets => "s3:ListAllMyBuckets", "SERVICE";
+    ListObjects => "s3:ListBucket", "BUCKET";
+    ListObjectsV2 => "s3:ListBucket", "BUCKET";
+    ListObjectVersions => "s3:ListBucketVersions", "BUCKET";
+    ListMultipartUploads => "s3:ListBucketMultipartUploads", "UPLOADS";
+    HeadBucket => "s3:ListBucket", "BUCKET";
+    CreateBucket => "s3:CreateBucket", "BUCKET";
+    DeleteBucket => "s3:DeleteBucket", "BUCKET";
+    DeleteObjects => "s3:DeleteObject", "MULTI_OBJECT_DELETE";
+    PostObject => "s3:PutObject", "OBJECT";
+
+    GetBucketPolicy => "s3:GetBucketPolicy", "BUCKETPOLICY";
+    PutBucketPolicy => "s3:PutBucketPolicy", "BUCKETPOLICY";
+    DeleteBucketPolicy => "s3:DeleteBucketPolicy", "BUCKETPOLICY";
+    GetBucketPolicyStatus => "s3:GetBucketPolicyStatus", "BUCKETPOLICYSTATUS";
+    GetBucketAcl => "s3:GetBucketAcl", "ACL";
+    PutBucketAcl => "s3:PutBucketAcl", "ACL";
+    GetBucketLocation => "s3:GetBucketLocation", "LOCATION";
+    GetBucketVersioning => "s3:GetBucketVersioning", "VERSIONING";
+    PutBucketVersioning => "s3:PutBucketVersioning", "VERSIONING";
+    GetBucketTagging => "s3:GetBucketTagging", "TAGGING";
+    PutBucketTagging => "s3:PutBucketTagging", "TAGGING";
+    DeleteBucketTagging => "s3:PutBucketTagging", "TAGGING";
+    GetBucketLifecycle => "s3:GetLifecycleConfiguration", "LIFECYCLE";
+    PutBucketLifecycle => "s3:PutLifecycleConfiguration", "LIFECYCLE";
+    DeleteBucketLifecycle => "s3:PutLifecycleConfiguration", "LIFECYCLE";
+    GetBucketCors => "s3:GetBucketCORS", "CORS";
+    PutBucketCors => "s3:PutBucketCORS", "CORS";
+    DeleteBucketCors => "s3:PutBucketCORS", "CORS";
+    GetBucketWebsite => "s3:GetBucketWebsite", "WEBSITE";
+    PutBucketWebsite => "s3:PutBucketWebsite", "WEBSITE";
+    DeleteBucketWebsite => "s3:DeleteBucketWebsite", "WEBSITE";
+    GetBucketEncryption => "s3:GetEncryptionConfiguration", "ENCRYPTION";
+    PutBucketEncryption => "s3:PutEncryptionConfiguration", "ENCRYPTION";
+    DeleteBucketEncryption => "s3:PutEncryptionConfiguration", "ENCRYPTION";
+    GetBucketNotification => "s3:GetBucketNotification", "NOTIFICATION";
+    PutBucketNotification => "s3:PutBucketNotification", "NOTIFICATION";
+    GetBucketReplication => "s3:GetReplicationConfiguration", "REPLICATION";
+    PutBucketReplication => "s3:PutReplicationConfiguration", "REPLICATION";
+    DeleteBucketReplication => "s3:PutReplicationConfiguration", "REPLICATION";
+    GetObjectLockConfiguration => "s3:GetBucketObjectLockConfiguration", "OBJECTLOCKCONFIGURATION";
+    PutObjectLockConfiguration => "s3:PutBucketObjectLockConfiguration", "OBJECTLOCKCONFIGURATION";
+    GetBucketLogging => "s3:GetBucketLogging", "LOGGINGSTATUS";
+    PutBucketLogging => "s3:PutBucketLogging", "LOGGINGSTATUS";
+    GetBucketOwnershipControls => "s3:GetBucketOwnershipControls", "OWNERSHIPCONTROLS";
+    PutBucketOwnershipControls => "s3:PutBucketOwnershipControls", "OWNERSHIPCONTROLS";
+    DeleteBucketOwnershipControls => "s3:PutBucketOwnershipControls", "OWNERSHIPCONTROLS";
+    GetPublicAccessBlock => "s3:GetBucketPublicAccessBlock", "PUBLICACCESSBLOCK";
+    PutPublicAccessBlock => "s3:PutBucketPublicAccessBlock", "PUBLICACCESSBLOCK";
+    DeletePublicAccessBlock => "s3:PutBucketPublicAccessBlock", "PUBLICACCESSBLOCK";
+    GetBucketAccelerateConfiguration => "s3:GetAccelerateConfiguration", "ACCELERATE";
+    PutBucketAccelerateConfiguration => "s3:PutAccelerateConfiguration", "ACCELERATE";
+    GetBucketRequestPayment => "s3:GetBucketRequestPayment", "REQUESTPAYMENT";
+    PutBucketRequestPayment => "s3:PutBucketRequestPayment", "REQUESTPAYMENT";
+    GetBucketAnalyticsConfiguration => "s3:GetAnalyticsConfiguration", "ANALYTICS";
+    PutBucketAnalyticsConfiguration => "s3:PutAnalyticsConfiguration", "ANALYTICS";
+    DeleteBucketAnalyticsConfiguration => "s3:PutAnalyticsConfiguration", "ANALYTICS";
+    GetBucketMetricsConfiguration => "s3:GetMetricsConfiguration", "METRICS";
+    PutBucketMetricsConfiguration => "s3:PutMetricsConfiguration", "METRICS";
+    DeleteBucketMetricsConfiguration => "s3:PutMetricsConfiguration", "METRICS";
+    GetBucketInventoryConfiguration => "s3:GetInventoryConfiguration", "INVENTORY";
+    PutBucketInventoryConfiguration => "s3:PutInventoryConfiguration", "INVENTORY";
+    DeleteBucketInventoryConfiguration => "s3:PutInventoryConfiguration", "INVENTORY";
+    GetBucketIntelligentTieringConfiguration => "s3:GetIntelligentTieringConfiguration", "INTELLIGENT_TIERING";
+    PutBucketIntelligentTieringConfiguration => "s3:PutIntelligentTieringConfiguration", "INTELLIGENT_TIERING";
+    DeleteBucketIntelligentTieringConfiguration => "s3:PutIntelligentTieringConfiguration", "INTELLIGENT_TIERING";
+
+    GetObject => "s3:GetObject", "OBJECT";
+    HeadObject => "s3:GetObject", "OBJECT";
+    PutObject => "s3:PutObject", "OBJECT";
+    CopyObject => "s3:PutObject", "OBJECT";
+    DeleteObject => "s3:DeleteObject", "OBJECT";
+    GetObjectAcl => "s3:GetObjectAcl", "ACL";
+    PutObjectAcl => "s3:PutObjectAcl", "ACL";
+    GetObjectTagging => "s3:GetObjectTagging", "OBJECT_TAGGING";
+    PutObjectTagging => "s3:PutObjectTagging", "OBJECT_TAGGING";
+    DeleteObjectTagging => "s3:DeleteObjectTagging", "OBJECT_TAGGING";
+    GetObjectAttributes => "s3:GetObjectAttributes", "OBJECT_ATTRIBUTES";
+    GetObjectRetention => "s3:GetObjectRetention", "RETENTION";
+    PutObjectRetention => "s3:PutObjectRetention", "RETENTION";
+    GetObjectLegalHold => "s3:GetObjectLegalHold", "LEGALHOLD";
+    PutObjectLegalHold => "s3:PutObjectLegalHold", "LEGALHOLD";
+    GetObjectTorrent => "s3:GetObject", "TORRENT";
+    RestoreObject => "s3:RestoreObject", "RESTORE";
+    SelectObjectContent => "s3:GetObject", "SELECT";
+    OptionsObject => "s3:GetObject", "PREFLIGHT";
+    CreateMultipartUpload => "s3:PutObject", "UPLOADS";
+    UploadPart => "s3:PutObject", "PART";
+    UploadPartCopy => "s3:PutObject", "PART";
+    CompleteMultipartUpload => "s3:PutObject", "UPLOAD";
+    AbortMultipartUpload => "s3:AbortMultipartUpload", "UPLOAD";
+    ListParts => "s3:ListMultipartUploadParts", "UPLOAD";
+}
+
+/// The routing table, see find_route() for how a route is picked among the matching ones.
+/// Sub-resources of buckets and objects share the query param (e.g. `?acl`),
+/// the target tells them apart.
+pub fn routes() -> &'static [Route] {
+    use self::Op::*;
+    use self::Target::*;
+    const GET: Method = Method::GET;
+    const HEAD: Method = Method::HEAD;
+    const PUT: Method = Method::PUT;
+    const POST: Method = Method::POST;
+    const DELETE: Method = Method::DELETE;
+    const OPTIONS: Method = Method::OPTIONS;
+    const fn r(method: Method, target: Target, query: &'static [&'static str], op: Op) -> Route {
+        Route {
+            method,
+            target,
+            query,
+            header: None,
+            op,
+        }
+    }
+    const fn h(
+        method: Method,
+        target: Target,
+        query: &'static [&'static str],
+        header: &'static str,
+        op: Op,
+    ) -> Route {
+        Route {
+            method,
+            target,
+            query,
+            header: Some(header),
+            op,
+        }
+    }
+    const ROUTES: &[Route] = &[
+        r(GET, Service, &[], ListBuckets),
+        // bucket
+        r(GET, Bucket, &[], ListObjects),
+        r(GET, Bucket, &["list-type"], ListObjectsV2),
+        r(GET, Bucket, &["versions"], ListObjectVersions),
+        r(GET, Bucket, &["uploads"], ListMultipartUploads),
+        r(HEAD, Bucket, &[], HeadBucket),
+        r(PUT, Bucket, &[], CreateBucket),
+        r(DELETE, Bucket, &[], DeleteBucket),
+        r(POST, Bucket, &["delete"], DeleteObjects),
+        r(POST, Bucket, &[], PostObject),
+        r(OPTIONS, Bucket, &[], OptionsObject),
+        r(GET, Bucket, &["policy"], GetBucketPolicy),
+        r(PUT, Bucket, &["policy"], PutBucketPolicy),
+        r(DELETE, Bucket, &["policy"], DeleteBucketPolicy),
+        r(GET, Bucket, &["policyStatus"], GetBucketPolicyStatus),
+        r(GET, Bucket, &["acl"], GetBucketAcl),
+        r(PUT, Bucket, &["acl"], PutBucketAcl),
+        r(GET, Bucket, &["location"], GetBucketLocation),
+        r(GET, Bucket, &["versioning"], GetBucketVersioning),
+        r(PUT, Bucket, &["versioning"], PutBucketVersioning),
+        r(GET, Bucket, &["tagging"], GetBucketTagging),
+        r(PUT, Bucket, &["tagging"], PutBucketTagging),
+        r(DELETE, Bucket, &["tagging"], DeleteBucketTagging),
+        r(GET, Bucket, &["lifecycle"], GetBucketLifecycle),
+        r(PUT, Bucket, &["lifecycle"], PutBucketLifecycle),
+        r(DELETE, Bucket, &["lifecycle"], DeleteBucketLifecycle),
+        r(GET, Bucket, &["cors"], GetBucketCors),
+        r(PUT, Bucket, &["cors"], PutBucketCors),
+        r(DELETE, Bucket, &["cors"], DeleteBucketCors),
+        r(GET, Bucket, &["website"], GetBucketWebsite),
+        r(PUT, Bucket, &["website"], PutBucketWebsite),
+        r(DELETE, Bucket, &["website"], DeleteBucketWebsite),
+        r(GET, Bucket, &["encryption"], GetBucketEncryption),
+        r(PUT, Bucket, &["encryption"], PutBucketEncryption),
+        r(DELETE, Bucket, &["encryption"], DeleteBucketEncryption),
+        r(GET, Bucket, &["notification"], GetBucketNotification),
+        r(PUT, Bucket, &["notification"], PutBucketNotification),
+        r(GET, Bucket, &["replication"], GetBucketReplication),
+        r(PUT, Bucket, &["replication"], PutBucketReplication),
+        r(DELETE, Bucket, &["replication"], DeleteBucketReplication),
+        r(GET, Bucket, &["object-lock"], GetObjectLockConfiguration),
+        r(PUT, Bucket, &["object-lock"], PutObjectLockConfiguration),
+        r(GET, Bucket, &["logging"], GetBucketLogging),
+        r(PUT, Bucket, &["logging"], PutBucketLogging),
+        r(
+            GET,
+            Bucket,
+            &["ownershipControls"],
+            GetBucketOwnershipControls,
+        ),
+        r(
+            PUT,
+            Bucket,
+            &["ownershipControls"],
+            PutBucketOwnershipControls,
+        ),
+        r(
+            DELETE,
+            Bucket,
+            &["ownershipControls"],
+            DeleteBucketOwnershipControls,
+        ),
+        r(GET, Bucket, &["publicAccessBlock"], GetPublicAccessBlock),
+        r(PUT, Bucket, &["publicAccessBlock"], PutPublicAccessBlock),
+        r(
+            DELETE,
+            Bucket,
+            &["publicAccessBlock"],
+            DeletePublicAccessBlock,
+        ),
+        r(
+            GET,
+            Bucket,
+            &["accelerate"],
+            GetBucketAccelerateConfiguration,
+        ),
+        r(
+            PUT,
+            Bucket,
+            &["accelerate"],
+            PutBucketAccelerateConfiguration,
+        ),
+        r(GET, Bucket, &["requestPayment"], GetBucketRequestPayment),
+        r(PUT, Bucket, &["requestPayment"], PutBucketRequestPayment),
+        r(GET, Bucket, &["analytics"], GetBucketAnalyticsConfiguration),
+        r(PUT, Bucket, &["analytics"], PutBucketAnalyticsConfiguration),
+        r(
+            DELETE,
+            Bucket,
+            &["analytics"],
+            DeleteBucketAnalyticsConfiguration,
+        ),
+        r(GET, Bucket, &["metrics"], GetBucketMetricsConfiguration),
+        r(PUT, Bucket, &["metrics"], PutBucketMetricsConfiguration),
+        r(
+            DELETE,
+            Bucket,
+            &["metrics"],
+            DeleteBucketMetricsConfiguration,
+        ),
+        r(GET, Bucket, &["inventory"], GetBucketInventoryConfiguration),
+        r(PUT, Bucket, &["inventory"], PutBucketInventoryConfiguration),
+        r(
+            DELETE,
+            Bucket,
+            &["inventory"],
+            DeleteBucketInventoryConfiguration,
+        ),
+        r(
+            GET,
+            Bucket,
+            &["intelligent-tiering"],
+            GetBucketIntelligentTieringConfiguration,
+        ),
+        r(
+            PUT,
+            Bucket,
+            &["intelligent-tiering"],
+            PutBucketIntelligentTieringConfiguration,
+        ),
+        r(
+            DELETE,
+            Bucket,
+            &["intelligent-tiering"],
+            DeleteBucketIntelligentTieringConfiguration,
+        ),
+        // object
+        r(GET, Object, &[], GetObject),
+        r(HEAD, Object, &[], HeadObject),
+        r(PUT, Object, &[], PutObject),
+        h(PUT, Object, &[], "x-amz-copy-source", CopyObject),
+        r(DELETE, Object, &[], DeleteObject),
+        r(OPTIONS, Object, &[], OptionsObject),
+        r(GET, Object, &["acl"], GetObjectAcl),
+        r(PUT, Object, &["acl"], PutObjectAcl),
+        r(GET, Object, &["tagging"], GetObjectTagging),
+        r(PUT, Object, &["tagging"], PutObjectTagging),
+        r(DELETE, Object, &["tagging"], DeleteObjectTagging),
+        r(GET, Object, &["attributes"], GetObjectAttributes),
+        r(GET, Object, &["retention"], GetObjectRetention),
+        r(PUT, Object, &["retention"], PutObjectRetention),
+        r(GET, Object, &["legal-hold"], GetObjectLegalHold),
+        r(PUT, Object, &["legal-hold"], PutObjectLegalHold),
+        r(GET, Object, &["torrent"], GetObjectTorrent),
+        r(POST, Object, &["restore"], RestoreObject),
+        r(
+            POST,
+            Object,
+            &["select", "select-type"],
+            SelectObjectContent,
+        ),
+        r(POST, Object, &["uploads"], CreateMultipartUpload),
+        r(PUT, Object, &["partNumber", "uploadId"], UploadPart),
+        h(
+            PUT,
+            Object,
+            &["partNumber", "uploadId"],
+            "x-amz-copy-source",
+            UploadPartCopy,
+        ),
+        r(POST, Object, &["uploadId"], CompleteMultipartUpload),
+        r(DELETE, Object, &["uploadId"], AbortMultipartUpload),
+        r(GET, Object, &["uploadId"], ListParts),
+    ];
+    ROUTES
+}
+
+impl Route {
+    /// How many conditions beyond method and target the route has,
+    /// the most specific matching route wins.
+    fn specificity(&self) -> usize {
+        self.query.len() + self.header.map_or(0, |_| 1)
+    }
+
+    fn matches(
+        &self,
+        method: &Method,
+        target: Target,
+        has_param: &dyn Fn(&str) -> bool,
+        headers: &HeaderMap,
+    ) -> bool {
+        self.method == *method
+            && self.target == target
+            && self.query.iter().all(|q| has_param(q))
+            && self.header.is_none_or(|h| headers.contains_key(h))
+    }
+}
+
+/// Find the route for the request, None when no route matches the method and target.
+/// Among matching routes the most specific one wins, e.g. `PUT /bucket/key` with
+/// `x-amz-copy-source` is CopyObject rather than PutObject, and among equally
+/// specific ones the route listed first.
+pub fn find_route(
+    method: &Method,
+    target: Target,
+    has_param: &dyn Fn(&str) -> bool,
+    headers: &HeaderMap,
+) -> Option<&'static Route> {
+    routes()
+        .iter()
+        .filter(|r| r.matches(method, target, has_param, headers))
+        // max_by_key keeps the last of equal keys
+        .rev()
+        .max_by_key(|r| r.specificity())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(method: Method, target: Target, params: &[&str], headers: &[&'static str]) -> Option<Op> {
+        let mut map = HeaderMap::new();
+        for h in headers {
+            map.insert(*h, "x".parse().unwrap());
+        }
+        find_route(&method, target, &|p| params.contains(&p), &map).map(|r| r.op)
+    }
+
+    #[test]
+    fn routes_are_unique() {
+        let routes = routes();
+        for (i, a) in routes.iter().enumerate() {
+            let same = routes[i + 1..].iter().find(|b| {
+                a.method == b.method
+                    && a.target == b.target
+                    && a.query == b.query
+                    && a.header == b.header
+            });
+            assert!(same.is_none(), "{:?} is routed twice", a.op);
+        }
+    }
+
+    #[test]
+    fn plain_routes() {
+        assert_eq!(
+            op(Method::GET, Target::Service, &[], &[]),
+            Some(Op::ListBuckets)
+        );
+        assert_eq!(
+            op(Method::GET, Target::Bucket, &[], &[]),
+            Some(Op::ListObjects)
+        );
+        assert_eq!(
+            op(Method::GET, Target::Object, &[], &[]),
+            Some(Op::GetObject)
+        );
+        assert_eq!(
+            op(Method::HEAD, Target::Object, &[], &[]),
+            Some(Op::HeadObject)
+        );
+        assert_eq!(op(Method::DELETE, Target::Service, &[], &[]), None);
+        assert_eq!(op(Method::PATCH, Target::Object, &[], &[]), None);
+    }
+
+    #[test]
+    fn sub_resources_by_target() {
+        assert_eq!(
+            op(Method::GET, Target::Bucket, &["acl"], &[]),
+            Some(Op::GetBucketAcl)
+        );
+        assert_eq!(
+            op(Method::GET, Target::Object, &["acl"], &[]),
+            Some(Op::GetObjectAcl)
+        );
+        assert_eq!(
+            op(Method::DELETE, Target::Bucket, &["tagging"], &[]),
+            Some(Op::DeleteBucketTagging)
+        );
+        assert_eq!(
+            op(Method::DELETE, Target::Object, &["tagging"], &[]),
+            Some(Op::DeleteObjectTagging)
+        );
+        // unknown params fall back to the plain route
+        assert_eq!(
+            op(Method::GET, Target::Object, &["foo"], &[]),
+            Some(Op::GetObject)
+        );
+        // a sub-resource of the other target does not match
+        assert_eq!(
+            op(Method::GET, Target::Bucket, &["retention"], &[]),
+            Some(Op::ListObjects)
+        );
+    }
+
+    #[test]
+    fn most_specific_wins() {
+        let copy = "x-amz-copy-source";
+        assert_eq!(
+            op(Method::PUT, Target::Object, &[], &[]),
+            Some(Op::PutObject)
+        );
+        assert_eq!(
+            op(Method::PUT, Target::Object, &[], &[copy]),
+            Some(Op::CopyObject)
+        );
+        assert_eq!(
+            op(
+                Method::PUT,
+                Target::Object,
+                &["partNumber", "uploadId"],
+                &[]
+            ),
+            Some(Op::UploadPart)
+        );
+        assert_eq!(
+            op(
+                Method::PUT,
+                Target::Object,
+                &["partNumber", "uploadId"],
+                &[copy]
+            ),
+            Some(Op::UploadPartCopy)
+        );
+        // both params are required
+        assert_eq!(
+            op(Method::PUT, Target::Object, &["uploadId"], &[]),
+            Some(Op::PutObject)
+        );
+        assert_eq!(
+            op(
+                Method::POST,
+                Target::Object,
+                &["select", "select-type"],
+                &[]
+            ),
+            Some(Op::SelectObjectContent)
+        );
+        assert_eq!(op(Method::POST, Target::Object, &["select"], &[]), None);
+    }
+
+    #[test]
+    fn ties_go_to_the_first_route() {
+        assert_eq!(
+            op(Method::GET, Target::Bucket, &["list-type", "versions"], &[]),
+            Some(Op::ListObjectsV2)
+        );
+        assert_eq!(
+            op(Method::GET, Target::Object, &["tagging", "acl"], &[]),
+            Some(Op::GetObjectAcl)
+        );
+    }
+}
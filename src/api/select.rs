@@ -0,0 +1,1059 @@
+use crate::api::*;
+use hyper::{
+    body::{HttpBody, Sender},
+    Body,
+};
+use serde::{de::IgnoredAny, Deserialize};
+use serde_json::Value;
+use std::{
+    io::{self, BufRead, Read},
+    sync::Arc,
+};
+
+/// Records are sent in messages of about this size.
+const RECORDS_MESSAGE_SIZE: usize = 64 * 1024;
+/// Input is decompressed in pieces of this size, so a small compressed chunk
+/// can't expand into more than a piece before its records are checked.
+const DECODE_SIZE: usize = 64 * 1024;
+/// Input and output records are limited to 1 MiB like S3.
+pub const MAX_RECORD_SIZE: usize = 1024 * 1024;
+
+/// SelectRequest is the body of SelectObjectContent.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SelectRequest {
+    pub expression: String,
+    pub expression_type: String,
+    #[serde(default)]
+    pub request_progress: Option<RequestProgress>,
+    pub input_serialization: InputSerialization,
+    pub output_serialization: OutputSerialization,
+    #[serde(default)]
+    pub scan_range: Option<IgnoredAny>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RequestProgress {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// The input has one of CSV, JSON or Parquet, optionally compressed.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InputSerialization {
+    /// NONE, GZIP or BZIP2
+    #[serde(default)]
+    pub compression_type: Option<String>,
+    #[serde(rename = "CSV", default)]
+    pub csv: Option<CsvInput>,
+    #[serde(rename = "JSON", default)]
+    pub json: Option<JsonInput>,
+    #[serde(default)]
+    pub parquet: Option<IgnoredAny>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CsvInput {
+    /// USE, IGNORE or NONE
+    #[serde(default)]
+    pub file_header_info: Option<String>,
+    #[serde(default)]
+    pub comments: Option<String>,
+    #[serde(default)]
+    pub quote_escape_character: Option<String>,
+    #[serde(default)]
+    pub record_delimiter: Option<String>,
+    #[serde(default)]
+    pub field_delimiter: Option<String>,
+    #[serde(default)]
+    pub quote_character: Option<String>,
+    #[serde(default)]
+    pub allow_quoted_record_delimiter: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct JsonInput {
+    /// DOCUMENT or LINES
+    #[serde(default)]
+    pub r#type: Option<String>,
+}
+
+/// The output has one of CSV or JSON.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct OutputSerialization {
+    #[serde(rename = "CSV", default)]
+    pub csv: Option<CsvOutput>,
+    #[serde(rename = "JSON", default)]
+    pub json: Option<JsonOutput>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CsvOutput {
+    /// ALWAYS or ASNEEDED
+    #[serde(default)]
+    pub quote_fields: Option<String>,
+    #[serde(default)]
+    pub quote_escape_character: Option<String>,
+    #[serde(default)]
+    pub record_delimiter: Option<String>,
+    #[serde(default)]
+    pub field_delimiter: Option<String>,
+    #[serde(default)]
+    pub quote_character: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct JsonOutput {
+    #[serde(default)]
+    pub record_delimiter: Option<String>,
+}
+
+/// The CSV dialect of the input or the output, with the defaults filled in.
+#[derive(Debug, Clone)]
+struct CsvFormat {
+    field_delimiter: Vec<u8>,
+    record_delimiter: Vec<u8>,
+    quote: u8,
+    /// the quote is escaped by doubling it when the escape is the quote
+    escape: u8,
+    comments: Option<u8>,
+    /// quote all output fields, not only the ones that need it
+    quote_always: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileHeader {
+    Use,
+    Ignore,
+    None,
+}
+
+#[derive(Debug, Clone)]
+enum InputFormat {
+    Csv(CsvFormat, FileHeader),
+    Json,
+}
+
+#[derive(Debug, Clone)]
+enum OutputFormat {
+    Csv(CsvFormat),
+    Json { record_delimiter: Vec<u8> },
+}
+
+/// Decoder decompresses the input as it arrives.
+enum Decoder {
+    None(Input),
+    Gzip(Box<flate2::bufread::MultiGzDecoder<Input>>),
+    Bzip2(Box<bzip2::bufread::MultiBzDecoder<Input>>),
+}
+
+/// Input is the object data that arrived and wasn't decompressed yet.
+/// Reading past it fails with WouldBlock until the end of the object,
+/// so the decoders wait for the next chunk instead of failing.
+#[derive(Default)]
+struct Input {
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+/// Select runs a query over the records of an object.
+struct Select {
+    query: Query,
+    input: InputFormat,
+    output: OutputFormat,
+    progress: bool,
+    decoder: Decoder,
+    /// decompressed input that is not a complete record yet
+    buf: Vec<u8>,
+    /// the column names of CSV input with FileHeaderInfo USE
+    header: Option<Arc<Vec<String>>>,
+    /// whether the first CSV record was seen, which is the header unless NONE
+    header_seen: bool,
+    aggregates: Vec<AggregateState>,
+    out: Vec<u8>,
+    records: u64,
+    bytes_scanned: u64,
+    bytes_processed: u64,
+    bytes_returned: u64,
+}
+
+impl SelectRequest {
+    /// Read the request from the body and check what this server supports.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let request: SelectRequest = from_xml(&escape_whitespace_text(&buf))?;
+        if !request.expression_type.eq_ignore_ascii_case("SQL") {
+            return Err(S3Error::InvalidExpressionType);
+        }
+        if request.scan_range.is_some() || request.input_serialization.parquet.is_some() {
+            return Err(S3Error::NotImplemented);
+        }
+        Ok(request)
+    }
+
+    fn input_format(&self) -> Result<InputFormat, S3Error> {
+        let input = &self.input_serialization;
+        match (&input.csv, &input.json) {
+            (Some(csv), None) => {
+                let header = match csv
+                    .file_header_info
+                    .as_deref()
+                    .map(str::to_uppercase)
+                    .as_deref()
+                {
+                    None | Some("NONE") => FileHeader::None,
+                    Some("USE") => FileHeader::Use,
+                    Some("IGNORE") => FileHeader::Ignore,
+                    Some(_) => return Err(S3Error::InvalidArgument),
+                };
+                let format = CsvFormat::new(
+                    csv.field_delimiter.as_deref(),
+                    csv.record_delimiter.as_deref(),
+                    csv.quote_character.as_deref(),
+                    csv.quote_escape_character.as_deref(),
+                )?;
+                let comments = match csv.comments.as_deref() {
+                    None | Some("") => None,
+                    Some(c) if c.len() == 1 => Some(c.as_bytes()[0]),
+                    Some(_) => return Err(S3Error::InvalidArgument),
+                };
+                Ok(InputFormat::Csv(CsvFormat { comments, ..format }, header))
+            }
+            (None, Some(json)) => match json.r#type.as_deref().map(str::to_uppercase).as_deref() {
+                // documents and lines are both read as a sequence of JSON values
+                None | Some("DOCUMENT") | Some("LINES") => Ok(InputFormat::Json),
+                Some(_) => Err(S3Error::InvalidArgument),
+            },
+            _ => Err(S3Error::MalformedXML),
+        }
+    }
+
+    fn output_format(&self) -> Result<OutputFormat, S3Error> {
+        let output = &self.output_serialization;
+        match (&output.csv, &output.json) {
+            (Some(csv), None) => {
+                let quote_always = match csv
+                    .quote_fields
+                    .as_deref()
+                    .map(str::to_uppercase)
+                    .as_deref()
+                {
+                    None | Some("ASNEEDED") => false,
+                    Some("ALWAYS") => true,
+                    Some(_) => return Err(S3Error::InvalidArgument),
+                };
+                let format = CsvFormat::new(
+                    csv.field_delimiter.as_deref(),
+                    csv.record_delimiter.as_deref(),
+                    csv.quote_character.as_deref(),
+                    csv.quote_escape_character.as_deref(),
+                )?;
+                Ok(OutputFormat::Csv(CsvFormat {
+                    quote_always,
+                    ..format
+                }))
+            }
+            (None, Some(json)) => Ok(OutputFormat::Json {
+                record_delimiter: delimiter(json.record_delimiter.as_deref(), "\n"),
+            }),
+            _ => Err(S3Error::MalformedXML),
+        }
+    }
+
+    fn decoder(&self) -> Result<Decoder, S3Error> {
+        let compression = self.input_serialization.compression_type.as_deref();
+        match compression.map(str::to_uppercase).as_deref() {
+            None | Some("NONE") => Ok(Decoder::None(Input::default())),
+            Some("GZIP") => Ok(Decoder::Gzip(Box::new(
+                flate2::bufread::MultiGzDecoder::new(Input::default()),
+            ))),
+            Some("BZIP2") => Ok(Decoder::Bzip2(Box::new(
+                bzip2::bufread::MultiBzDecoder::new(Input::default()),
+            ))),
+            Some(_) => Err(S3Error::InvalidCompressionFormat),
+        }
+    }
+}
+
+/// The elements whose text may be only whitespace, e.g. a tab or a newline.
+const WHITESPACE_ELEMENTS: &[&str] = &[
+    "Comments",
+    "FieldDelimiter",
+    "QuoteCharacter",
+    "QuoteEscapeCharacter",
+    "RecordDelimiter",
+];
+
+/// Replace the whitespace in the text of the delimiter elements by character
+/// references, which the XML deserializer does not trim like whitespace text.
+fn escape_whitespace_text(xml: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(xml.len());
+    let mut i = 0;
+    while i < xml.len() {
+        let element = match xml[i] {
+            b'<' => WHITESPACE_ELEMENTS
+                .iter()
+                .find(|name| xml[i + 1..].starts_with(format!("{}>", name).as_bytes())),
+            _ => None,
+        };
+        let name = match element {
+            Some(name) => name,
+            None => {
+                out.push(xml[i]);
+                i += 1;
+                continue;
+            }
+        };
+        let start = i + name.len() + 2;
+        out.extend_from_slice(&xml[i..start]);
+        i = start;
+        while i < xml.len() && xml[i] != b'<' {
+            match xml[i] {
+                b' ' | b'\t' | b'\n' | b'\r' => {
+                    out.extend_from_slice(format!("&#{};", xml[i]).as_bytes())
+                }
+                b => out.push(b),
+            }
+            i += 1;
+        }
+    }
+    out
+}
+
+/// An empty or missing delimiter is the default.
+fn delimiter(value: Option<&str>, default: &str) -> Vec<u8> {
+    match value {
+        None | Some("") => default.as_bytes().to_vec(),
+        Some(value) => value.as_bytes().to_vec(),
+    }
+}
+
+fn single_byte(value: Option<&str>, default: u8) -> Result<u8, S3Error> {
+    match value {
+        None | Some("") => Ok(default),
+        Some(value) if value.len() == 1 => Ok(value.as_bytes()[0]),
+        Some(_) => Err(S3Error::InvalidArgument),
+    }
+}
+
+impl CsvFormat {
+    fn new(
+        field_delimiter: Option<&str>,
+        record_delimiter: Option<&str>,
+        quote: Option<&str>,
+        escape: Option<&str>,
+    ) -> Result<Self, S3Error> {
+        let quote = single_byte(quote, b'"')?;
+        Ok(CsvFormat {
+            field_delimiter: delimiter(field_delimiter, ","),
+            record_delimiter: delimiter(record_delimiter, "\n"),
+            quote,
+            escape: single_byte(escape, quote)?,
+            comments: None,
+            quote_always: false,
+        })
+    }
+
+    /// The next record of the buffer and the bytes it takes, None when the buffer
+    /// ends within the record and more input may follow. Comment lines are
+    /// returned as empty records.
+    fn next_record(&self, buf: &[u8], eof: bool) -> Option<(Vec<String>, usize)> {
+        let mut fields = Vec::new();
+        let mut field = Vec::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        let mut i = 0;
+        if self.comments.is_some() && buf.first() == self.comments.as_ref() {
+            return match find(buf, &self.record_delimiter) {
+                Some(end) => Some((Vec::new(), end + self.record_delimiter.len())),
+                None if eof => Some((Vec::new(), buf.len())),
+                None => None,
+            };
+        }
+        while i < buf.len() {
+            let b = buf[i];
+            if in_quotes {
+                if b == self.escape && self.escape != self.quote && i + 1 < buf.len() {
+                    field.push(buf[i + 1]);
+                    i += 2;
+                } else if b == self.quote {
+                    if i + 1 == buf.len() && !eof {
+                        return None;
+                    }
+                    if self.escape == self.quote && buf.get(i + 1) == Some(&self.quote) {
+                        field.push(self.quote);
+                        i += 2;
+                    } else {
+                        in_quotes = false;
+                        i += 1;
+                    }
+                } else {
+                    field.push(b);
+                    i += 1;
+                }
+            } else if b == self.quote && field.is_empty() && !quoted {
+                in_quotes = true;
+                quoted = true;
+                i += 1;
+            } else if buf[i..].starts_with(&self.record_delimiter) {
+                push_field(
+                    &mut fields,
+                    &mut field,
+                    quoted,
+                    self.record_delimiter == b"\n",
+                );
+                return Some((fields, i + self.record_delimiter.len()));
+            } else if buf[i..].starts_with(&self.field_delimiter) {
+                push_field(&mut fields, &mut field, quoted, false);
+                quoted = false;
+                i += self.field_delimiter.len();
+            } else if quoted
+                && b == b'\r'
+                && self.record_delimiter == b"\n"
+                && buf.get(i + 1) == Some(&b'\n')
+            {
+                // the \r of a \r\n after the closing quote is not in the field
+                i += 1;
+            } else {
+                field.push(b);
+                i += 1;
+            }
+        }
+        if !eof || buf.is_empty() {
+            return None;
+        }
+        push_field(
+            &mut fields,
+            &mut field,
+            quoted,
+            self.record_delimiter == b"\n",
+        );
+        Some((fields, buf.len()))
+    }
+
+    /// Append the fields as a record, quoting them as needed.
+    fn write_record(&self, out: &mut Vec<u8>, fields: &[String]) {
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                out.extend_from_slice(&self.field_delimiter);
+            }
+            let bytes = field.as_bytes();
+            let quote = self.quote_always
+                || bytes.contains(&self.quote)
+                || bytes.contains(&b'\n')
+                || bytes.contains(&b'\r')
+                || find(bytes, &self.field_delimiter).is_some()
+                || find(bytes, &self.record_delimiter).is_some();
+            if !quote {
+                out.extend_from_slice(bytes);
+                continue;
+            }
+            out.push(self.quote);
+            for b in bytes {
+                if *b == self.quote {
+                    out.push(self.escape);
+                }
+                out.push(*b);
+            }
+            out.push(self.quote);
+        }
+        out.extend_from_slice(&self.record_delimiter);
+    }
+}
+
+/// Add the field, a \r before a \n record delimiter is removed from unquoted fields.
+fn push_field(fields: &mut Vec<String>, field: &mut Vec<u8>, quoted: bool, crlf: bool) {
+    if crlf && !quoted && field.last() == Some(&b'\r') {
+        field.pop();
+    }
+    fields.push(String::from_utf8_lossy(field).to_string());
+    field.clear();
+}
+
+fn find(buf: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    buf.windows(needle.len()).position(|w| w == needle)
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Input {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() && !self.eof {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+impl Decoder {
+    fn input(&mut self) -> &mut Input {
+        match self {
+            Decoder::None(input) => input,
+            Decoder::Gzip(d) => d.get_mut(),
+            Decoder::Bzip2(d) => d.get_mut(),
+        }
+    }
+
+    /// Add a chunk of the object.
+    fn push(&mut self, chunk: &[u8]) {
+        let input = self.input();
+        input.buf.drain(..input.pos);
+        input.pos = 0;
+        input.buf.extend_from_slice(chunk);
+    }
+
+    /// Mark the end of the object, the last pieces complete it.
+    fn finish(&mut self) {
+        self.input().eof = true;
+    }
+
+    /// The next piece of decompressed data, None when the decoder needs the
+    /// next chunk or the object ended.
+    fn next(&mut self) -> Result<Option<Vec<u8>>, S3Error> {
+        let mut piece = vec![0; DECODE_SIZE];
+        let res = match self {
+            Decoder::None(input) => input.read(&mut piece),
+            Decoder::Gzip(d) => d.read(&mut piece),
+            Decoder::Bzip2(d) => d.read(&mut piece),
+        };
+        match res {
+            Ok(0) => Ok(None),
+            Ok(n) => {
+                piece.truncate(n);
+                Ok(Some(piece))
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => {
+                tracing::debug!(%err, "select input decompression failed");
+                Err(S3Error::InvalidCompressionFormat)
+            }
+        }
+    }
+}
+
+impl Select {
+    fn new(request: &SelectRequest) -> Result<Self, S3Error> {
+        let query = Query::parse(&request.expression)?;
+        Ok(Select {
+            input: request.input_format()?,
+            output: request.output_format()?,
+            progress: request.request_progress.as_ref().is_some_and(|p| p.enabled),
+            decoder: request.decoder()?,
+            aggregates: query.aggregate_states(),
+            query,
+            buf: Vec::new(),
+            header: None,
+            header_seen: false,
+            out: Vec::new(),
+            records: 0,
+            bytes_scanned: 0,
+            bytes_processed: 0,
+            bytes_returned: 0,
+        })
+    }
+
+    /// Whether the query needs no more records.
+    fn is_done(&self) -> bool {
+        !self.query.has_aggregates() && self.query.limit.is_some_and(|l| self.records >= l)
+    }
+
+    /// Add decompressed input and run the query on the records it completes.
+    fn push(&mut self, data: Vec<u8>, eof: bool) -> Result<(), S3Error> {
+        self.bytes_processed += data.len() as u64;
+        if self.buf.is_empty() {
+            self.buf = data;
+        } else {
+            self.buf.extend_from_slice(&data);
+        }
+        let mut pos = 0;
+        while !self.is_done() {
+            let rest = &self.buf[pos..];
+            let (record, len) = match &self.input {
+                InputFormat::Csv(csv, header) => match csv.next_record(rest, eof) {
+                    Some((fields, len)) => {
+                        let header = *header;
+                        (self.csv_record(fields, header), len)
+                    }
+                    None => break,
+                },
+                InputFormat::Json => match next_json(rest, eof)? {
+                    Some((value, len)) => (Some(Record::Json(value)), len),
+                    None => break,
+                },
+            };
+            if len > MAX_RECORD_SIZE {
+                return Err(S3Error::OverMaxRecordSize);
+            }
+            pos += len;
+            if let Some(record) = record {
+                self.process(&record)?;
+            }
+        }
+        self.buf.drain(..pos);
+        if self.buf.len() > MAX_RECORD_SIZE {
+            return Err(S3Error::OverMaxRecordSize);
+        }
+        Ok(())
+    }
+
+    /// The record of the CSV fields, None for the header, comments and empty lines.
+    fn csv_record(&mut self, fields: Vec<String>, header: FileHeader) -> Option<Record> {
+        if fields.is_empty() || (fields.len() == 1 && fields[0].is_empty()) {
+            return None;
+        }
+        if !self.header_seen && header != FileHeader::None {
+            self.header_seen = true;
+            if header == FileHeader::Use {
+                self.header = Some(Arc::new(fields));
+            }
+            return None;
+        }
+        Some(Record::Csv {
+            fields,
+            header: self.header.clone(),
+        })
+    }
+
+    fn process(&mut self, record: &Record) -> Result<(), S3Error> {
+        if !self.query.matches(record)? {
+            return Ok(());
+        }
+        if self.query.has_aggregates() {
+            return self.query.accumulate(record, &mut self.aggregates);
+        }
+        match &self.query.items {
+            None => self.write_record(record),
+            Some(_) => {
+                let values = self.query.project(Some(record), &[])?;
+                self.write_values(&values);
+            }
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Write the whole input record, for `SELECT *`.
+    fn write_record(&mut self, record: &Record) {
+        match (record, &self.output) {
+            (Record::Csv { fields, .. }, OutputFormat::Csv(csv)) => {
+                csv.write_record(&mut self.out, fields)
+            }
+            (Record::Csv { fields, header }, OutputFormat::Json { record_delimiter }) => {
+                let names: Vec<String> = match header {
+                    Some(header) => (0..fields.len())
+                        .map(|i| match header.get(i) {
+                            Some(name) => name.to_owned(),
+                            None => format!("_{}", i + 1),
+                        })
+                        .collect(),
+                    None => (1..=fields.len()).map(|i| format!("_{}", i)).collect(),
+                };
+                let values: Vec<(String, SqlValue)> = names
+                    .into_iter()
+                    .zip(fields.iter().map(|f| SqlValue::String(f.to_owned())))
+                    .collect();
+                write_json(&mut self.out, &values, record_delimiter);
+            }
+            (Record::Json(value), OutputFormat::Csv(csv)) => {
+                let fields: Vec<String> = match value {
+                    Value::Object(map) => map
+                        .values()
+                        .map(|v| SqlValue::from_json(v).to_text())
+                        .collect(),
+                    _ => vec![SqlValue::from_json(value).to_text()],
+                };
+                csv.write_record(&mut self.out, &fields);
+            }
+            (Record::Json(value), OutputFormat::Json { record_delimiter }) => {
+                self.out.extend_from_slice(value.to_string().as_bytes());
+                self.out.extend_from_slice(record_delimiter);
+            }
+        }
+    }
+
+    fn write_values(&mut self, values: &[(String, SqlValue)]) {
+        match &self.output {
+            OutputFormat::Csv(csv) => {
+                let fields: Vec<String> = values.iter().map(|(_, v)| v.to_text()).collect();
+                csv.write_record(&mut self.out, &fields);
+            }
+            OutputFormat::Json { record_delimiter } => {
+                write_json(&mut self.out, values, record_delimiter)
+            }
+        }
+    }
+
+    /// Run the query on the next piece of the input, returning whether there
+    /// was one. Pieces are checked one at a time, a record that doesn't end
+    /// within MAX_RECORD_SIZE fails before more of the input is decompressed.
+    fn decode(&mut self) -> Result<bool, S3Error> {
+        if self.is_done() {
+            return Ok(false);
+        }
+        match self.decoder.next()? {
+            Some(data) => {
+                self.push(data, false)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Write the result of the aggregates at the end of the input,
+    /// after the last pieces were decoded.
+    fn finish(&mut self) -> Result<(), S3Error> {
+        self.push(Vec::new(), true)?;
+        if self.query.has_aggregates() {
+            let results: Vec<SqlValue> = self.aggregates.iter().map(|a| a.result()).collect();
+            let values = self.query.project(None, &results)?;
+            self.write_values(&values);
+        }
+        Ok(())
+    }
+
+    fn stats_xml(&self, element: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><{0}><BytesScanned>{1}</BytesScanned>\
+             <BytesProcessed>{2}</BytesProcessed><BytesReturned>{3}</BytesReturned></{0}>",
+            element, self.bytes_scanned, self.bytes_processed, self.bytes_returned
+        )
+    }
+
+    /// Send the records written so far, with a progress message when asked for.
+    async fn flush(&mut self, sender: &mut Sender) -> Result<(), hyper::Error> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        self.bytes_returned += self.out.len() as u64;
+        let records = std::mem::take(&mut self.out);
+        sender.send_data(event_message("Records", &records)).await?;
+        if self.progress {
+            let progress = self.stats_xml("Progress");
+            sender
+                .send_data(event_message("Progress", progress.as_bytes()))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Decode the input that arrived, sending the records as they add up.
+    async fn decode_all(&mut self, sender: &mut Sender) -> Result<(), SyncError> {
+        while self.decode()? {
+            if self.out.len() >= RECORDS_MESSAGE_SIZE {
+                self.flush(sender).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the query over the object and send the records as they are found.
+    async fn run(&mut self, mut input: Body, sender: &mut Sender) -> Result<(), SyncError> {
+        while !self.is_done() {
+            let chunk = match input.data().await {
+                Some(chunk) => chunk?,
+                None => break,
+            };
+            self.bytes_scanned += chunk.len() as u64;
+            self.decoder.push(&chunk);
+            self.decode_all(sender).await?;
+        }
+        if !self.is_done() {
+            self.decoder.finish();
+            self.decode_all(sender).await?;
+            self.finish()?;
+        }
+        self.flush(sender).await?;
+        let stats = self.stats_xml("Stats");
+        sender
+            .send_data(event_message("Stats", stats.as_bytes()))
+            .await?;
+        sender.send_data(event_message("End", &[])).await?;
+        Ok(())
+    }
+}
+
+/// The next JSON value of the buffer and the bytes it takes, None when the buffer
+/// ends within the value and more input may follow.
+fn next_json(buf: &[u8], eof: bool) -> Result<Option<(Value, usize)>, S3Error> {
+    let mut values = serde_json::Deserializer::from_slice(buf).into_iter::<Value>();
+    match values.next() {
+        Some(Ok(value)) => Ok(Some((value, values.byte_offset()))),
+        Some(Err(err)) if err.is_eof() && !eof => Ok(None),
+        Some(Err(err)) => {
+            tracing::debug!(%err, "select json input failed");
+            Err(S3Error::JSONParsingError)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Append the values as a JSON object, in the order of the items.
+fn write_json(out: &mut Vec<u8>, values: &[(String, SqlValue)], record_delimiter: &[u8]) {
+    out.push(b'{');
+    for (i, (name, value)) in values.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        out.extend_from_slice(Value::String(name.to_owned()).to_string().as_bytes());
+        out.push(b':');
+        out.extend_from_slice(value.to_json().to_string().as_bytes());
+    }
+    out.push(b'}');
+    out.extend_from_slice(record_delimiter);
+}
+
+/// Run a SelectObjectContent request on the object read from the layer.
+///
+/// The request and the query are checked before the response starts, so their
+/// errors are returned as usual. The records are then streamed as they are found
+/// in event stream messages, and input errors end the stream with an error message.
+pub async fn select_object_content<API: ApiLayer + ?Sized>(
+    api: &API,
+    req: select_object_content::Req,
+) -> select_object_content::Ret {
+    let (parts, params) = req.into_parts();
+    let request = SelectRequest::read(params.body).await?;
+    let mut select = Select::new(&request)?;
+    let get = get_object::Req::from_parts(
+        parts,
+        get_object::Params {
+            bucket: params.bucket,
+            key: params.key,
+            version_id: String::new(),
+            head_only: false,
+            range: ObjectRange {
+                start: None,
+                end: None,
+            },
+            sse_customer: params.sse_customer,
+        },
+    );
+    let object = api.get_object(get).await?.into_body();
+    let input = object.body.unwrap_or_else(Body::empty);
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Err(err) = select.run(input, &mut sender).await {
+            let code = match err.downcast_ref::<S3Error>() {
+                Some(err) => err.clone(),
+                None => {
+                    tracing::debug!(%err, "select failed");
+                    S3Error::InternalError
+                }
+            };
+            let _ = sender.send_data(error_message(&code)).await;
+        }
+    });
+    Ok(select_object_content::Res::new(
+        select_object_content::Reply { body },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn csv_request(expression: &str, input: CsvInput, output: CsvOutput) -> SelectRequest {
+        SelectRequest {
+            expression: expression.to_string(),
+            expression_type: String::from("SQL"),
+            input_serialization: InputSerialization {
+                csv: Some(input),
+                ..Default::default()
+            },
+            output_serialization: OutputSerialization {
+                csv: Some(output),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Run the request over the input split into chunks of the size.
+    fn run(request: &SelectRequest, input: &[u8], chunk_size: usize) -> String {
+        try_run(request, input, chunk_size).unwrap()
+    }
+
+    fn try_run(
+        request: &SelectRequest,
+        input: &[u8],
+        chunk_size: usize,
+    ) -> Result<String, S3Error> {
+        let mut select = Select::new(request)?;
+        for chunk in input.chunks(chunk_size) {
+            select.decoder.push(chunk);
+            while select.decode()? {}
+        }
+        select.decoder.finish();
+        while select.decode()? {}
+        select.finish()?;
+        Ok(String::from_utf8(select.out).unwrap())
+    }
+
+    fn compressed(compression: &str) -> SelectRequest {
+        let mut request = csv_request(
+            "SELECT COUNT(*) FROM S3Object",
+            CsvInput::default(),
+            CsvOutput::default(),
+        );
+        request.input_serialization.compression_type = Some(compression.to_string());
+        request
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn bzip2(data: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn csv_quotes_and_escapes() {
+        let input = b"name,note\r\n\"a, b\",\"say \"\"hi\"\"\"\r\nc,\"two\nlines\"\r\n";
+        let request = csv_request(
+            "SELECT note, name FROM S3Object",
+            CsvInput {
+                file_header_info: Some(String::from("USE")),
+                ..Default::default()
+            },
+            CsvOutput::default(),
+        );
+        assert_eq!(
+            run(&request, input, input.len()),
+            "\"say \"\"hi\"\"\",\"a, b\"\n\"two\nlines\",c\n"
+        );
+        // a quote escape other than the quote, in the input and the output
+        let request = csv_request(
+            "SELECT * FROM S3Object",
+            CsvInput {
+                quote_escape_character: Some(String::from("\\")),
+                ..Default::default()
+            },
+            CsvOutput {
+                quote_fields: Some(String::from("ALWAYS")),
+                quote_escape_character: Some(String::from("\\")),
+                field_delimiter: Some(String::from(";")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(run(&request, b"\"a\\\"b\",c\n", 64), "\"a\\\"b\";\"c\"\n");
+    }
+
+    #[test]
+    fn csv_records_across_chunks() {
+        let input = b"id,text\n1,\"x,\"\"y\"\"\"\n2,plain\n3,\"multi\nline\"\n4,last";
+        let request = csv_request(
+            "SELECT id, text FROM S3Object WHERE CAST(id AS INT) > 1",
+            CsvInput {
+                file_header_info: Some(String::from("USE")),
+                ..Default::default()
+            },
+            CsvOutput::default(),
+        );
+        let expected = "2,plain\n3,\"multi\nline\"\n4,last\n";
+        // every split point, including within quotes and escaped quotes
+        for chunk_size in 1..=input.len() {
+            assert_eq!(run(&request, input, chunk_size), expected, "{}", chunk_size);
+        }
+        // a record delimiter of two bytes split between chunks
+        let request = csv_request(
+            "SELECT COUNT(*), SUM(_1) FROM S3Object LIMIT 1",
+            CsvInput {
+                record_delimiter: Some(String::from("\r\n")),
+                ..Default::default()
+            },
+            CsvOutput::default(),
+        );
+        for chunk_size in 1..=4 {
+            assert_eq!(run(&request, b"1\r\n2\r\n3", chunk_size), "3,6\n");
+        }
+    }
+
+    #[test]
+    fn limit_stops_reading() {
+        let request = csv_request(
+            "SELECT * FROM S3Object LIMIT 2",
+            CsvInput::default(),
+            CsvOutput::default(),
+        );
+        let mut select = Select::new(&request).unwrap();
+        select.push(b"a\nb\nc\n".to_vec(), false).unwrap();
+        assert!(select.is_done());
+        assert_eq!(select.out, b"a\nb\n");
+    }
+
+    #[test]
+    fn compressed_input_is_decoded() {
+        let records = "a\n".repeat(20_000);
+        let data = records.as_bytes();
+        // concatenated gzip members are one input, like gzip -d
+        let mut members = gzip(&data[..1000]);
+        members.extend(gzip(&data[1000..]));
+        for (compression, input) in [
+            ("GZIP", gzip(data)),
+            ("GZIP", members),
+            ("BZIP2", bzip2(data)),
+            ("NONE", data.to_vec()),
+        ] {
+            let request = compressed(compression);
+            for chunk_size in [7, 1000, input.len()] {
+                assert_eq!(run(&request, &input, chunk_size), "20000\n");
+            }
+        }
+        for compression in ["GZIP", "BZIP2"] {
+            let request = compressed(compression);
+            let err = try_run(&request, b"not compressed\n", 4).err();
+            assert_eq!(err, Some(S3Error::InvalidCompressionFormat));
+        }
+        // a truncated input fails at the end
+        let input = gzip(data);
+        let err = try_run(&compressed("GZIP"), &input[..input.len() / 2], 100).err();
+        assert_eq!(err, Some(S3Error::InvalidCompressionFormat));
+    }
+
+    #[test]
+    fn decompression_is_bounded() {
+        // a chunk of a few KiB expanding to a record of 4 MiB
+        let data = vec![b'a'; 4 * MAX_RECORD_SIZE];
+        for (compression, input) in [("GZIP", gzip(&data)), ("BZIP2", bzip2(&data))] {
+            let request = compressed(compression);
+            let mut select = Select::new(&request).unwrap();
+            select.decoder.push(&input);
+            let mut err = None;
+            while err.is_none() {
+                match select.decode() {
+                    Ok(more) => assert!(more),
+                    Err(e) => err = Some(e),
+                }
+            }
+            assert_eq!(err, Some(S3Error::OverMaxRecordSize));
+            assert!(select.bytes_processed <= (MAX_RECORD_SIZE + DECODE_SIZE) as u64);
+        }
+    }
+}
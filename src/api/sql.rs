@@ -0,0 +1,1596 @@
+use crate::api::*;
+use serde_json::Value;
+use std::{cmp::Ordering, sync::Arc};
+
+/// SQL expressions are limited to 256 KB like S3.
+pub const MAX_SQL_LENGTH: usize = 256 * 1024;
+/// The deepest expression tree, which keeps the recursive evaluation in the stack.
+const MAX_DEPTH: usize = 200;
+/// The deepest nesting of parentheses, NOT and signs, which the parser recurses into.
+const MAX_NESTING: usize = 100;
+
+/// Query is the SQL subset of S3 Select:
+///
+/// ```text
+/// SELECT * | item [, item ...] FROM S3Object[*] [[AS] alias]
+///     [WHERE condition] [LIMIT n]
+/// ```
+///
+/// Items are expressions with an optional `AS` name, or the aggregates COUNT, SUM,
+/// AVG, MIN and MAX, which can't be mixed with columns since there is no GROUP BY.
+/// Expressions have columns, literals, arithmetic, `||`, comparisons, AND, OR, NOT,
+/// IS [NOT] NULL, [NOT] LIKE, [NOT] BETWEEN, [NOT] IN, CAST and a few functions.
+/// Columns are names (of the CSV header or JSON keys), positions `_1`, `_2`, ...
+/// of CSV fields, or JSON paths like `s.user.name` or `s.tags[0]`.
+#[derive(Debug, Clone)]
+pub struct Query {
+    /// None for `SELECT *`
+    pub items: Option<Vec<SelectItem>>,
+    pub filter: Option<Expr>,
+    pub limit: Option<u64>,
+    /// the number of aggregates in the items, see Expr::Aggregate
+    pub aggregates: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectItem {
+    pub expr: Expr,
+    /// the AS name, or the name of the column
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal(SqlValue),
+    Column(Vec<PathSeg>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    IsNull(Box<Expr>, bool),
+    Like(Box<Expr>, Box<Expr>, Option<char>, bool),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>, bool),
+    In(Box<Expr>, Vec<Expr>, bool),
+    Cast(Box<Expr>, CastType),
+    Function(Function, Vec<Expr>),
+    /// the index of the aggregate in the query, its argument is None for COUNT(*)
+    Aggregate(usize, Aggregate, Option<Box<Expr>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSeg {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastType {
+    Int,
+    Float,
+    String,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Lower,
+    Upper,
+    CharLength,
+    Trim,
+    Substring,
+    Coalesce,
+    NullIf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// SqlValue is the value of an expression. CSV fields are strings, which are
+/// compared and computed as numbers when the other operand is a number.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// JSON objects and arrays
+    Json(Value),
+}
+
+/// Record is an input record of the query.
+#[derive(Debug, Clone)]
+pub enum Record {
+    Csv {
+        fields: Vec<String>,
+        /// the column names when the file header is used
+        header: Option<Arc<Vec<String>>>,
+    },
+    Json(Value),
+}
+
+/// AggregateState accumulates an aggregate over the records.
+#[derive(Debug, Clone)]
+pub struct AggregateState {
+    func: Aggregate,
+    count: u64,
+    sum: Option<SqlValue>,
+    best: Option<SqlValue>,
+}
+
+impl Query {
+    /// Parse the SQL expression of a select request.
+    pub fn parse(sql: &str) -> Result<Self, S3Error> {
+        if sql.len() > MAX_SQL_LENGTH {
+            return Err(S3Error::ExpressionTooLong);
+        }
+        let tokens = tokenize(sql)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            alias: None,
+            aggregates: 0,
+            nesting: 0,
+        };
+        parser.query()
+    }
+
+    pub fn has_aggregates(&self) -> bool {
+        self.aggregates > 0
+    }
+
+    /// The new states of the aggregates, in the order of their indexes.
+    pub fn aggregate_states(&self) -> Vec<AggregateState> {
+        let mut states = vec![AggregateState::new(Aggregate::Count); self.aggregates];
+        for item in self.items.iter().flatten() {
+            item.expr.visit(&mut |e| {
+                if let Expr::Aggregate(i, func, _) = e {
+                    states[*i] = AggregateState::new(*func);
+                }
+            });
+        }
+        states
+    }
+
+    /// Whether the record passes the WHERE condition.
+    pub fn matches(&self, record: &Record) -> Result<bool, S3Error> {
+        match &self.filter {
+            Some(filter) => Ok(filter.eval(Some(record), &[])? == SqlValue::Bool(true)),
+            None => Ok(true),
+        }
+    }
+
+    /// Add the record to the aggregates.
+    pub fn accumulate(
+        &self,
+        record: &Record,
+        states: &mut [AggregateState],
+    ) -> Result<(), S3Error> {
+        for item in self.items.iter().flatten() {
+            let mut result = Ok(());
+            item.expr.visit(&mut |e| {
+                if let Expr::Aggregate(i, _, arg) = e {
+                    let value = match arg {
+                        Some(arg) => arg.eval(Some(record), &[]),
+                        None => Ok(SqlValue::Int(1)),
+                    };
+                    if let Err(err) = value.and_then(|v| states[*i].add(v)) {
+                        result = Err(err);
+                    }
+                }
+            });
+            result?;
+        }
+        Ok(())
+    }
+
+    /// The output values of the items, for the record or for the aggregates.
+    pub fn project(
+        &self,
+        record: Option<&Record>,
+        aggregates: &[SqlValue],
+    ) -> Result<Vec<(String, SqlValue)>, S3Error> {
+        let mut values = Vec::new();
+        for (i, item) in self.items.iter().flatten().enumerate() {
+            let name = match &item.name {
+                Some(name) => name.to_owned(),
+                None => format!("_{}", i + 1),
+            };
+            values.push((name, item.expr.eval(record, aggregates)?));
+        }
+        Ok(values)
+    }
+}
+
+/// Expressions are walked recursively, which is safe since parsed expressions are
+/// at most MAX_DEPTH deep. Deeper trees are only built while parsing and are
+/// dropped without recursion.
+impl Expr {
+    /// The expressions this one contains.
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column(_) => Vec::new(),
+            Expr::Neg(e) | Expr::Not(e) | Expr::IsNull(e, _) | Expr::Cast(e, _) => vec![e],
+            Expr::Binary(_, a, b) | Expr::Like(a, b, _, _) => vec![a, b],
+            Expr::Between(a, b, c, _) => vec![a, b, c],
+            Expr::In(e, list, _) => std::iter::once(&**e).chain(list).collect(),
+            Expr::Function(_, args) => args.iter().collect(),
+            Expr::Aggregate(_, _, arg) => arg.iter().map(|e| &**e).collect(),
+        }
+    }
+
+    /// Move the expressions this one contains to the list.
+    fn take_children(&mut self, list: &mut Vec<Expr>) {
+        let mut take = |e: &mut Box<Expr>| {
+            list.push(std::mem::replace(&mut **e, Expr::Literal(SqlValue::Null)))
+        };
+        match self {
+            Expr::Literal(_) | Expr::Column(_) => {}
+            Expr::Neg(e) | Expr::Not(e) | Expr::IsNull(e, _) | Expr::Cast(e, _) => take(e),
+            Expr::Binary(_, a, b) | Expr::Like(a, b, _, _) => {
+                take(a);
+                take(b);
+            }
+            Expr::Between(a, b, c, _) => {
+                take(a);
+                take(b);
+                take(c);
+            }
+            Expr::In(e, items, _) => {
+                take(e);
+                list.append(items);
+            }
+            Expr::Function(_, args) => list.append(args),
+            Expr::Aggregate(_, _, arg) => list.extend(arg.take().map(|e| *e)),
+        }
+    }
+
+    /// Whether the expression is deeper than the limit, found without recursion.
+    fn deeper_than(&self, limit: usize) -> bool {
+        let mut stack = vec![(self, 1)];
+        while let Some((e, depth)) = stack.pop() {
+            if depth > limit {
+                return true;
+            }
+            stack.extend(e.children().into_iter().map(|c| (c, depth + 1)));
+        }
+        false
+    }
+
+    /// Call the function on the expression and the expressions it contains.
+    fn visit<F: FnMut(&Expr)>(&self, f: &mut F) {
+        f(self);
+        for e in self.children() {
+            e.visit(f);
+        }
+    }
+
+    fn has_aggregate(&self) -> bool {
+        let mut found = false;
+        self.visit(&mut |e| found |= matches!(e, Expr::Aggregate(..)));
+        found
+    }
+
+    /// Whether the expression has columns outside of aggregates.
+    fn has_bare_column(&self) -> bool {
+        match self {
+            Expr::Column(_) => true,
+            Expr::Literal(_) | Expr::Aggregate(..) => false,
+            Expr::Neg(e) | Expr::Not(e) | Expr::IsNull(e, _) | Expr::Cast(e, _) => {
+                e.has_bare_column()
+            }
+            Expr::Binary(_, a, b) | Expr::Like(a, b, _, _) => {
+                a.has_bare_column() || b.has_bare_column()
+            }
+            Expr::Between(a, b, c, _) => {
+                a.has_bare_column() || b.has_bare_column() || c.has_bare_column()
+            }
+            Expr::In(e, list, _) => e.has_bare_column() || list.iter().any(|e| e.has_bare_column()),
+            Expr::Function(_, args) => args.iter().any(|e| e.has_bare_column()),
+        }
+    }
+
+    /// Evaluate the expression on the record, aggregates take their results.
+    pub fn eval(
+        &self,
+        record: Option<&Record>,
+        aggregates: &[SqlValue],
+    ) -> Result<SqlValue, S3Error> {
+        let eval = |e: &Expr| e.eval(record, aggregates);
+        Ok(match self {
+            Expr::Literal(v) => v.clone(),
+            Expr::Column(path) => match record {
+                Some(record) => record.get(path),
+                None => SqlValue::Null,
+            },
+            Expr::Neg(e) => match eval(e)?.to_number() {
+                Some(SqlValue::Int(i)) => i
+                    .checked_neg()
+                    .map_or(SqlValue::Float(-(i as f64)), SqlValue::Int),
+                Some(SqlValue::Float(f)) => SqlValue::Float(-f),
+                _ => SqlValue::Null,
+            },
+            Expr::Not(e) => match eval(e)? {
+                SqlValue::Bool(b) => SqlValue::Bool(!b),
+                _ => SqlValue::Null,
+            },
+            Expr::Binary(BinOp::And, a, b) => match (eval(a)?, eval(b)?) {
+                (SqlValue::Bool(false), _) | (_, SqlValue::Bool(false)) => SqlValue::Bool(false),
+                (SqlValue::Bool(true), SqlValue::Bool(true)) => SqlValue::Bool(true),
+                _ => SqlValue::Null,
+            },
+            Expr::Binary(BinOp::Or, a, b) => match (eval(a)?, eval(b)?) {
+                (SqlValue::Bool(true), _) | (_, SqlValue::Bool(true)) => SqlValue::Bool(true),
+                (SqlValue::Bool(false), SqlValue::Bool(false)) => SqlValue::Bool(false),
+                _ => SqlValue::Null,
+            },
+            Expr::Binary(op, a, b) => binary(*op, eval(a)?, eval(b)?),
+            Expr::IsNull(e, negated) => SqlValue::Bool((eval(e)? == SqlValue::Null) != *negated),
+            Expr::Like(e, pattern, escape, negated) => match (eval(e)?, eval(pattern)?) {
+                (SqlValue::Null, _) | (_, SqlValue::Null) => SqlValue::Null,
+                (v, p) => {
+                    let text: Vec<char> = v.to_text().chars().collect();
+                    let pattern: Vec<char> = p.to_text().chars().collect();
+                    SqlValue::Bool(like(&text, &pattern, *escape) != *negated)
+                }
+            },
+            Expr::Between(e, low, high, negated) => {
+                let v = eval(e)?;
+                match (compare(&v, &eval(low)?), compare(&v, &eval(high)?)) {
+                    (Some(l), Some(h)) => {
+                        SqlValue::Bool((l != Ordering::Less && h != Ordering::Greater) != *negated)
+                    }
+                    _ => SqlValue::Null,
+                }
+            }
+            Expr::In(e, list, negated) => {
+                let v = eval(e)?;
+                let mut found = false;
+                for item in list {
+                    found |= compare(&v, &eval(item)?) == Some(Ordering::Equal);
+                }
+                SqlValue::Bool(found != *negated)
+            }
+            Expr::Cast(e, to) => eval(e)?.cast(*to)?,
+            Expr::Function(func, args) => {
+                let args = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+                function(*func, args)
+            }
+            Expr::Aggregate(i, _, _) => aggregates.get(*i).cloned().unwrap_or(SqlValue::Null),
+        })
+    }
+}
+
+fn binary(op: BinOp, a: SqlValue, b: SqlValue) -> SqlValue {
+    if a == SqlValue::Null || b == SqlValue::Null {
+        return SqlValue::Null;
+    }
+    let cmp = || compare(&a, &b);
+    match op {
+        BinOp::Eq => cmp().map_or(SqlValue::Bool(false), |o| {
+            SqlValue::Bool(o == Ordering::Equal)
+        }),
+        BinOp::Ne => cmp().map_or(SqlValue::Bool(true), |o| {
+            SqlValue::Bool(o != Ordering::Equal)
+        }),
+        BinOp::Lt => cmp().map_or(SqlValue::Null, |o| SqlValue::Bool(o == Ordering::Less)),
+        BinOp::Le => cmp().map_or(SqlValue::Null, |o| SqlValue::Bool(o != Ordering::Greater)),
+        BinOp::Gt => cmp().map_or(SqlValue::Null, |o| SqlValue::Bool(o == Ordering::Greater)),
+        BinOp::Ge => cmp().map_or(SqlValue::Null, |o| SqlValue::Bool(o != Ordering::Less)),
+        BinOp::Concat => SqlValue::String(a.to_text() + &b.to_text()),
+        _ => arithmetic(op, a, b),
+    }
+}
+
+fn arithmetic(op: BinOp, a: SqlValue, b: SqlValue) -> SqlValue {
+    match (a.to_number(), b.to_number()) {
+        (Some(SqlValue::Int(x)), Some(SqlValue::Int(y))) => {
+            let result = match op {
+                BinOp::Add => x.checked_add(y),
+                BinOp::Sub => x.checked_sub(y),
+                BinOp::Mul => x.checked_mul(y),
+                BinOp::Div => x.checked_div(y),
+                BinOp::Mod => x.checked_rem(y),
+                _ => None,
+            };
+            match result {
+                Some(v) => SqlValue::Int(v),
+                None if y == 0 => SqlValue::Null,
+                None => arithmetic(op, SqlValue::Float(x as f64), SqlValue::Float(y as f64)),
+            }
+        }
+        (Some(x), Some(y)) => {
+            let (x, y) = (x.to_f64().unwrap_or(0.0), y.to_f64().unwrap_or(0.0));
+            let v = match op {
+                BinOp::Add => x + y,
+                BinOp::Sub => x - y,
+                BinOp::Mul => x * y,
+                BinOp::Div if y != 0.0 => x / y,
+                BinOp::Mod if y != 0.0 => x % y,
+                _ => return SqlValue::Null,
+            };
+            SqlValue::Float(v)
+        }
+        _ => SqlValue::Null,
+    }
+}
+
+/// Compare values of the same kind, strings are compared as numbers to numbers.
+fn compare(a: &SqlValue, b: &SqlValue) -> Option<Ordering> {
+    match (a, b) {
+        (SqlValue::Null, _) | (_, SqlValue::Null) => None,
+        (SqlValue::String(x), SqlValue::String(y)) => Some(x.cmp(y)),
+        (SqlValue::Bool(x), SqlValue::Bool(y)) => Some(x.cmp(y)),
+        (SqlValue::Bool(x), SqlValue::String(y)) | (SqlValue::String(y), SqlValue::Bool(x)) => {
+            let y = y.parse::<bool>().ok()?;
+            let o = x.cmp(&y);
+            Some(match a {
+                SqlValue::Bool(_) => o,
+                _ => o.reverse(),
+            })
+        }
+        (SqlValue::Json(x), SqlValue::Json(y)) => match x == y {
+            true => Some(Ordering::Equal),
+            false => None,
+        },
+        _ => match (a.to_number()?, b.to_number()?) {
+            (SqlValue::Int(x), SqlValue::Int(y)) => Some(x.cmp(&y)),
+            (x, y) => x.to_f64()?.partial_cmp(&y.to_f64()?),
+        },
+    }
+}
+
+fn function(func: Function, args: Vec<SqlValue>) -> SqlValue {
+    let first = args.first().cloned().unwrap_or(SqlValue::Null);
+    if first == SqlValue::Null && !matches!(func, Function::Coalesce) {
+        return SqlValue::Null;
+    }
+    match func {
+        Function::Lower => SqlValue::String(first.to_text().to_lowercase()),
+        Function::Upper => SqlValue::String(first.to_text().to_uppercase()),
+        Function::CharLength => SqlValue::Int(first.to_text().chars().count() as i64),
+        Function::Trim => SqlValue::String(first.to_text().trim().to_string()),
+        Function::Substring => {
+            let text: Vec<char> = first.to_text().chars().collect();
+            let start = match args.get(1).and_then(SqlValue::to_i64) {
+                Some(start) => start,
+                None => return SqlValue::Null,
+            };
+            // positions start at 1, and a start before it shortens the length
+            let end = match args.get(2) {
+                Some(len) => match len.to_i64() {
+                    Some(len) => start.saturating_add(len.max(0)),
+                    None => return SqlValue::Null,
+                },
+                None => i64::MAX,
+            };
+            let from = (start.max(1) - 1).min(text.len() as i64) as usize;
+            let to = (end.max(1) - 1).min(text.len() as i64).max(from as i64) as usize;
+            SqlValue::String(text[from..to].iter().collect())
+        }
+        Function::Coalesce => args
+            .into_iter()
+            .find(|v| *v != SqlValue::Null)
+            .unwrap_or(SqlValue::Null),
+        Function::NullIf => match args.get(1).and_then(|b| compare(&first, b)) {
+            Some(Ordering::Equal) => SqlValue::Null,
+            _ => first,
+        },
+    }
+}
+
+impl Drop for Expr {
+    /// Drop the contained expressions from a list, since deep trees would
+    /// overflow the stack when dropped recursively.
+    fn drop(&mut self) {
+        let mut list = Vec::new();
+        self.take_children(&mut list);
+        while let Some(mut e) = list.pop() {
+            e.take_children(&mut list);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LikeItem {
+    /// `%`
+    Any,
+    /// `_`
+    One,
+    Char(char),
+}
+
+/// Match the LIKE pattern, `%` is any text and `_` any character.
+///
+/// The text is matched from the last `%` again when the rest of the pattern
+/// fails, which takes at most the length of the text times the pattern.
+fn like(text: &[char], pattern: &[char], escape: Option<char>) -> bool {
+    let mut items = Vec::with_capacity(pattern.len());
+    let mut i = 0;
+    while i < pattern.len() {
+        let c = pattern[i];
+        i += 1;
+        items.push(match c {
+            c if Some(c) == escape && i < pattern.len() => {
+                i += 1;
+                LikeItem::Char(pattern[i - 1])
+            }
+            '%' => LikeItem::Any,
+            '_' => LikeItem::One,
+            c => LikeItem::Char(c),
+        });
+    }
+    let (mut t, mut p) = (0, 0);
+    // the pattern position after the last % and the text position it matches from
+    let mut retry: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match items.get(p) {
+            Some(LikeItem::Any) => {
+                p += 1;
+                retry = Some((p, t));
+            }
+            Some(LikeItem::One) => {
+                t += 1;
+                p += 1;
+            }
+            Some(LikeItem::Char(c)) if *c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match retry {
+                Some((after, from)) => {
+                    p = after;
+                    t = from + 1;
+                    retry = Some((after, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    items[p..].iter().all(|item| *item == LikeItem::Any)
+}
+
+impl SqlValue {
+    pub fn from_json(value: &Value) -> Self {
+        match value {
+            Value::Null => SqlValue::Null,
+            Value::Bool(b) => SqlValue::Bool(*b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => SqlValue::Int(i),
+                None => SqlValue::Float(n.as_f64().unwrap_or(0.0)),
+            },
+            Value::String(s) => SqlValue::String(s.to_owned()),
+            Value::Array(_) | Value::Object(_) => SqlValue::Json(value.clone()),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            SqlValue::Null => Value::Null,
+            SqlValue::Bool(b) => Value::Bool(*b),
+            SqlValue::Int(i) => Value::from(*i),
+            SqlValue::Float(f) => Value::from(*f),
+            SqlValue::String(s) => Value::String(s.to_owned()),
+            SqlValue::Json(v) => v.clone(),
+        }
+    }
+
+    /// The text of the value in CSV output, empty for null.
+    pub fn to_text(&self) -> String {
+        match self {
+            SqlValue::Null => String::new(),
+            SqlValue::Bool(b) => b.to_string(),
+            SqlValue::Int(i) => i.to_string(),
+            SqlValue::Float(f) => f.to_string(),
+            SqlValue::String(s) => s.to_owned(),
+            SqlValue::Json(v) => v.to_string(),
+        }
+    }
+
+    /// The value as Int or Float, strings are parsed.
+    fn to_number(&self) -> Option<SqlValue> {
+        match self {
+            SqlValue::Int(_) | SqlValue::Float(_) => Some(self.clone()),
+            SqlValue::String(s) => {
+                let s = s.trim();
+                match s.parse::<i64>() {
+                    Ok(i) => Some(SqlValue::Int(i)),
+                    Err(_) => s.parse::<f64>().ok().map(SqlValue::Float),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        match self.to_number()? {
+            SqlValue::Int(i) => Some(i as f64),
+            SqlValue::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        match self.to_number()? {
+            SqlValue::Int(i) => Some(i),
+            SqlValue::Float(f) => Some(f as i64),
+            _ => None,
+        }
+    }
+
+    fn cast(self, to: CastType) -> Result<SqlValue, S3Error> {
+        if self == SqlValue::Null {
+            return Ok(SqlValue::Null);
+        }
+        let value = match to {
+            CastType::Int => match &self {
+                SqlValue::Bool(b) => Some(SqlValue::Int(*b as i64)),
+                _ => self.to_i64().map(SqlValue::Int),
+            },
+            CastType::Float => match &self {
+                SqlValue::Bool(b) => Some(SqlValue::Float(*b as i64 as f64)),
+                _ => self.to_f64().map(SqlValue::Float),
+            },
+            CastType::String => Some(SqlValue::String(self.to_text())),
+            CastType::Bool => match &self {
+                SqlValue::Bool(b) => Some(SqlValue::Bool(*b)),
+                SqlValue::Int(i) => Some(SqlValue::Bool(*i != 0)),
+                SqlValue::String(s) => s.trim().to_lowercase().parse().ok().map(SqlValue::Bool),
+                _ => None,
+            },
+        };
+        value.ok_or(S3Error::CastFailed)
+    }
+}
+
+impl Record {
+    /// The value of the column, null when the record has none.
+    pub fn get(&self, path: &[PathSeg]) -> SqlValue {
+        match self {
+            Record::Csv { fields, header } => {
+                let name = match path {
+                    [PathSeg::Key(name)] => name,
+                    _ => return SqlValue::Null,
+                };
+                let index = match header {
+                    Some(header) => header
+                        .iter()
+                        .position(|h| h == name)
+                        .or_else(|| header.iter().position(|h| h.eq_ignore_ascii_case(name))),
+                    None => None,
+                };
+                let index = index.or_else(|| {
+                    name.strip_prefix('_')
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|n| *n > 0)
+                        .map(|n| n - 1)
+                });
+                match index.and_then(|i| fields.get(i)) {
+                    Some(field) => SqlValue::String(field.to_owned()),
+                    None => SqlValue::Null,
+                }
+            }
+            Record::Json(value) => {
+                let mut value = value;
+                for seg in path {
+                    let next = match (seg, value) {
+                        (PathSeg::Key(key), Value::Object(map)) => map.get(key).or_else(|| {
+                            map.iter()
+                                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                                .map(|(_, v)| v)
+                        }),
+                        (PathSeg::Index(i), Value::Array(list)) => list.get(*i),
+                        _ => None,
+                    };
+                    value = match next {
+                        Some(next) => next,
+                        None => return SqlValue::Null,
+                    };
+                }
+                SqlValue::from_json(value)
+            }
+        }
+    }
+}
+
+impl AggregateState {
+    fn new(func: Aggregate) -> Self {
+        AggregateState {
+            func,
+            count: 0,
+            sum: None,
+            best: None,
+        }
+    }
+
+    /// Nulls are skipped by all aggregates but COUNT(*), which adds a 1 for every record.
+    fn add(&mut self, value: SqlValue) -> Result<(), S3Error> {
+        if value == SqlValue::Null {
+            return Ok(());
+        }
+        self.count += 1;
+        match self.func {
+            Aggregate::Count => {}
+            Aggregate::Sum | Aggregate::Avg => {
+                let value = value.to_number().ok_or(S3Error::CastFailed)?;
+                self.sum = Some(match self.sum.take() {
+                    Some(sum) => arithmetic(BinOp::Add, sum, value),
+                    None => value,
+                });
+            }
+            Aggregate::Min | Aggregate::Max => {
+                let value = value.to_number().unwrap_or(value);
+                let want = match self.func {
+                    Aggregate::Min => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                let better = match &self.best {
+                    Some(best) => compare(&value, best) == Some(want),
+                    None => true,
+                };
+                if better {
+                    self.best = Some(value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn result(&self) -> SqlValue {
+        match self.func {
+            Aggregate::Count => SqlValue::Int(self.count as i64),
+            Aggregate::Sum => self.sum.clone().unwrap_or(SqlValue::Null),
+            Aggregate::Avg => match (&self.sum, self.count) {
+                (Some(sum), count) if count > 0 => {
+                    SqlValue::Float(sum.to_f64().unwrap_or(0.0) / count as f64)
+                }
+                _ => SqlValue::Null,
+            },
+            Aggregate::Min | Aggregate::Max => self.best.clone().unwrap_or(SqlValue::Null),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// unquoted identifiers and keywords
+    Ident(String),
+    /// "quoted" identifiers, which are never keywords
+    Quoted(String),
+    Str(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "<>", "!=", "||", "=", "<", ">", "+", "-", "*", "/", "%", "(", ")", ",", ".", "[",
+    "]",
+];
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, S3Error> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            // quotes are escaped by doubling them
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(S3Error::ParseUnexpectedToken),
+                    Some(q) if *q == c && chars.get(i + 1) == Some(&c) => {
+                        text.push(c);
+                        i += 2;
+                    }
+                    Some(q) if *q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(ch) => {
+                        text.push(*ch);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(match c {
+                '\'' => Token::Str(text),
+                _ => Token::Quoted(text),
+            });
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                i += 1;
+                if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(*s))
+                .ok_or(S3Error::ParseUnexpectedToken)?;
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Words that end an expression or a clause, so they can't be aliases.
+const RESERVED: &[&str] = &[
+    "select", "from", "where", "limit", "as", "and", "or", "not", "is", "like", "escape",
+    "between", "in", "null", "true", "false", "missing", "cast", "group", "order", "by",
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// the alias of S3Object in the FROM clause
+    alias: Option<String>,
+    aggregates: usize,
+    /// the parse functions entered for parentheses, NOT and signs, see MAX_NESTING
+    nesting: usize,
+}
+
+impl Parser {
+    fn query(&mut self) -> Result<Query, S3Error> {
+        self.expect_keyword("select")?;
+        // the alias is only known after FROM, so the items are parsed again when it is set
+        let items_start = self.pos;
+        self.skip_to_keyword("from")?;
+        self.expect_keyword("from")?;
+        self.from()?;
+        let after_from = self.pos;
+        self.pos = items_start;
+        let items = self.items()?;
+        self.expect_keyword("from")?;
+        self.pos = after_from;
+        let mut query = Query {
+            items,
+            filter: None,
+            limit: None,
+            aggregates: 0,
+        };
+        if self.eat_keyword("where") {
+            let filter = self.expr()?;
+            if filter.has_aggregate() {
+                return Err(S3Error::UnsupportedSyntax);
+            }
+            query.filter = Some(filter);
+        }
+        if self.eat_keyword("limit") {
+            query.limit = match self.next() {
+                Some(Token::Number(n)) => {
+                    Some(n.parse().map_err(|_| S3Error::ParseUnexpectedToken)?)
+                }
+                _ => return Err(S3Error::ParseUnexpectedToken),
+            };
+        }
+        if self.eat_keyword("group") || self.eat_keyword("order") {
+            return Err(S3Error::UnsupportedSyntax);
+        }
+        if self.pos < self.tokens.len() {
+            return Err(S3Error::ParseUnexpectedToken);
+        }
+        query.aggregates = self.aggregates;
+        if let Some(items) = &query.items {
+            if query.aggregates > 0 && items.iter().any(|i| i.expr.has_bare_column()) {
+                return Err(S3Error::UnsupportedSyntax);
+            }
+        }
+        Ok(query)
+    }
+
+    /// `S3Object[*] [[AS] alias]`
+    fn from(&mut self) -> Result<(), S3Error> {
+        match self.next() {
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case("s3object") => {}
+            _ => return Err(S3Error::ParseUnexpectedToken),
+        }
+        if self.eat_symbol("[") && !(self.eat_symbol("*") && self.eat_symbol("]")) {
+            return Err(S3Error::UnsupportedSyntax);
+        }
+        if self.peek() == Some(&Token::Symbol(".")) {
+            return Err(S3Error::UnsupportedSyntax);
+        }
+        let explicit = self.eat_keyword("as");
+        match self.peek() {
+            Some(Token::Ident(name)) if !is_reserved(name) => {
+                self.alias = Some(name.to_owned());
+                self.pos += 1;
+            }
+            Some(Token::Quoted(name)) => {
+                self.alias = Some(name.to_owned());
+                self.pos += 1;
+            }
+            _ if explicit => return Err(S3Error::ParseUnexpectedToken),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn items(&mut self) -> Result<Option<Vec<SelectItem>>, S3Error> {
+        if self.eat_symbol("*") {
+            return Ok(None);
+        }
+        // alias.*
+        if let (Some(Token::Ident(name)), Some(Token::Symbol(".")), Some(Token::Symbol("*"))) = (
+            self.tokens.get(self.pos),
+            self.tokens.get(self.pos + 1),
+            self.tokens.get(self.pos + 2),
+        ) {
+            if self.is_alias(name) {
+                self.pos += 3;
+                return Ok(None);
+            }
+        }
+        let mut items = Vec::new();
+        loop {
+            let expr = self.expr()?;
+            let name = match self.eat_keyword("as") {
+                true => match self.next() {
+                    Some(Token::Ident(name)) | Some(Token::Quoted(name)) => Some(name),
+                    _ => return Err(S3Error::ParseUnexpectedToken),
+                },
+                false => match &expr {
+                    Expr::Column(path) => match path.last() {
+                        Some(PathSeg::Key(name)) => Some(name.to_owned()),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+            };
+            items.push(SelectItem { expr, name });
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        Ok(Some(items))
+    }
+
+    /// A whole expression, all other expressions are in one of them or end
+    /// with NOT or a sign, so it checks the depth of the tree.
+    fn expr(&mut self) -> Result<Expr, S3Error> {
+        let expr = self.nested(|p| {
+            let mut left = p.and()?;
+            while p.eat_keyword("or") {
+                left = Expr::Binary(BinOp::Or, Box::new(left), Box::new(p.and()?));
+            }
+            Ok(left)
+        })?;
+        if expr.deeper_than(MAX_DEPTH) {
+            return Err(S3Error::ExpressionTooLong);
+        }
+        Ok(expr)
+    }
+
+    /// Run the parse function one level deeper, which fails past MAX_NESTING
+    /// levels instead of overflowing the stack.
+    fn nested<F>(&mut self, parse: F) -> Result<Expr, S3Error>
+    where
+        F: FnOnce(&mut Self) -> Result<Expr, S3Error>,
+    {
+        if self.nesting >= MAX_NESTING {
+            return Err(S3Error::ExpressionTooLong);
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    fn and(&mut self) -> Result<Expr, S3Error> {
+        let mut left = self.not()?;
+        while self.eat_keyword("and") {
+            left = Expr::Binary(BinOp::And, Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, S3Error> {
+        match self.eat_keyword("not") {
+            true => Ok(Expr::Not(Box::new(self.nested(Self::not)?))),
+            false => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, S3Error> {
+        let left = self.additive()?;
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            if !(self.eat_keyword("null") || self.eat_keyword("missing")) {
+                return Err(S3Error::ParseUnexpectedToken);
+            }
+            return Ok(Expr::IsNull(Box::new(left), negated));
+        }
+        let negated = self.eat_keyword("not");
+        if self.eat_keyword("like") {
+            let pattern = self.additive()?;
+            let escape = match self.eat_keyword("escape") {
+                true => match self.next() {
+                    Some(Token::Str(s)) if s.chars().count() == 1 => s.chars().next(),
+                    _ => return Err(S3Error::ParseUnexpectedToken),
+                },
+                false => None,
+            };
+            return Ok(Expr::Like(
+                Box::new(left),
+                Box::new(pattern),
+                escape,
+                negated,
+            ));
+        }
+        if self.eat_keyword("between") {
+            let low = self.additive()?;
+            self.expect_keyword("and")?;
+            let high = self.additive()?;
+            return Ok(Expr::Between(
+                Box::new(left),
+                Box::new(low),
+                Box::new(high),
+                negated,
+            ));
+        }
+        if self.eat_keyword("in") {
+            self.expect_symbol("(")?;
+            let mut list = vec![self.expr()?];
+            while self.eat_symbol(",") {
+                list.push(self.expr()?);
+            }
+            self.expect_symbol(")")?;
+            return Ok(Expr::In(Box::new(left), list, negated));
+        }
+        if negated {
+            return Err(S3Error::ParseUnexpectedToken);
+        }
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => BinOp::Eq,
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => BinOp::Ne,
+            Some(Token::Symbol("<")) => BinOp::Lt,
+            Some(Token::Symbol("<=")) => BinOp::Le,
+            Some(Token::Symbol(">")) => BinOp::Gt,
+            Some(Token::Symbol(">=")) => BinOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.additive()?;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn additive(&mut self) -> Result<Expr, S3Error> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("+")) => BinOp::Add,
+                Some(Token::Symbol("-")) => BinOp::Sub,
+                Some(Token::Symbol("||")) => BinOp::Concat,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, S3Error> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("*")) => BinOp::Mul,
+                Some(Token::Symbol("/")) => BinOp::Div,
+                Some(Token::Symbol("%")) => BinOp::Mod,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, S3Error> {
+        if self.eat_symbol("-") {
+            return Ok(Expr::Neg(Box::new(self.nested(Self::unary)?)));
+        }
+        if self.eat_symbol("+") {
+            return self.nested(Self::unary);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, S3Error> {
+        let token = self.next().ok_or(S3Error::ParseUnexpectedToken)?;
+        match token {
+            Token::Number(n) => {
+                let value = match n.parse::<i64>() {
+                    Ok(i) => SqlValue::Int(i),
+                    Err(_) => {
+                        SqlValue::Float(n.parse().map_err(|_| S3Error::ParseUnexpectedToken)?)
+                    }
+                };
+                Ok(Expr::Literal(value))
+            }
+            Token::Str(s) => Ok(Expr::Literal(SqlValue::String(s))),
+            Token::Symbol("(") => {
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Token::Quoted(name) => self.column(name),
+            Token::Ident(name) => {
+                let lower = name.to_lowercase();
+                match lower.as_str() {
+                    "null" | "missing" => return Ok(Expr::Literal(SqlValue::Null)),
+                    "true" => return Ok(Expr::Literal(SqlValue::Bool(true))),
+                    "false" => return Ok(Expr::Literal(SqlValue::Bool(false))),
+                    _ => {}
+                }
+                if self.peek() == Some(&Token::Symbol("(")) {
+                    self.pos += 1;
+                    return self.call(&lower);
+                }
+                if is_reserved(&name) {
+                    return Err(S3Error::ParseUnexpectedToken);
+                }
+                self.column(name)
+            }
+            Token::Symbol(_) => Err(S3Error::ParseUnexpectedToken),
+        }
+    }
+
+    /// A function call after its opening parenthesis.
+    fn call(&mut self, name: &str) -> Result<Expr, S3Error> {
+        let aggregate = match name {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "avg" => Some(Aggregate::Avg),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            _ => None,
+        };
+        if let Some(func) = aggregate {
+            let arg = match func == Aggregate::Count && self.eat_symbol("*") {
+                true => None,
+                false => {
+                    let arg = self.expr()?;
+                    if arg.has_aggregate() {
+                        return Err(S3Error::UnsupportedSyntax);
+                    }
+                    Some(Box::new(arg))
+                }
+            };
+            self.expect_symbol(")")?;
+            let index = self.aggregates;
+            self.aggregates += 1;
+            return Ok(Expr::Aggregate(index, func, arg));
+        }
+        if name == "cast" {
+            let expr = self.expr()?;
+            self.expect_keyword("as")?;
+            let to = match self.next() {
+                Some(Token::Ident(t)) => match t.to_lowercase().as_str() {
+                    "int" | "integer" | "bigint" | "smallint" => CastType::Int,
+                    "float" | "real" | "double" | "decimal" | "numeric" => CastType::Float,
+                    "string" | "varchar" | "char" | "text" => CastType::String,
+                    "bool" | "boolean" => CastType::Bool,
+                    _ => return Err(S3Error::UnsupportedSyntax),
+                },
+                _ => return Err(S3Error::ParseUnexpectedToken),
+            };
+            self.expect_symbol(")")?;
+            return Ok(Expr::Cast(Box::new(expr), to));
+        }
+        let (func, min_args, max_args) = match name {
+            "lower" => (Function::Lower, 1, 1),
+            "upper" => (Function::Upper, 1, 1),
+            "char_length" | "character_length" => (Function::CharLength, 1, 1),
+            "trim" => (Function::Trim, 1, 1),
+            "substring" => (Function::Substring, 2, 3),
+            "coalesce" => (Function::Coalesce, 1, usize::MAX),
+            "nullif" => (Function::NullIf, 2, 2),
+            _ => return Err(S3Error::UnsupportedSyntax),
+        };
+        let mut args = Vec::new();
+        if !self.eat_symbol(")") {
+            args.push(self.expr()?);
+            // SUBSTRING(text FROM start FOR length) is also accepted
+            while self.eat_symbol(",") || self.eat_keyword("from") || self.eat_keyword("for") {
+                args.push(self.expr()?);
+            }
+            self.expect_symbol(")")?;
+        }
+        if args.len() < min_args || args.len() > max_args {
+            return Err(S3Error::ParseUnexpectedToken);
+        }
+        Ok(Expr::Function(func, args))
+    }
+
+    /// A column path, starting with the alias or S3Object for JSON paths.
+    fn column(&mut self, first: String) -> Result<Expr, S3Error> {
+        let mut path = Vec::new();
+        if !(self.is_alias(&first) && self.peek() == Some(&Token::Symbol("."))) {
+            path.push(PathSeg::Key(first));
+        }
+        loop {
+            if self.eat_symbol(".") {
+                match self.next() {
+                    Some(Token::Ident(name)) | Some(Token::Quoted(name)) => {
+                        path.push(PathSeg::Key(name))
+                    }
+                    _ => return Err(S3Error::ParseUnexpectedToken),
+                }
+            } else if self.eat_symbol("[") {
+                match self.next() {
+                    Some(Token::Number(n)) => path.push(PathSeg::Index(
+                        n.parse().map_err(|_| S3Error::ParseUnexpectedToken)?,
+                    )),
+                    Some(Token::Str(key)) => path.push(PathSeg::Key(key)),
+                    _ => return Err(S3Error::ParseUnexpectedToken),
+                }
+                self.expect_symbol("]")?;
+            } else {
+                break;
+            }
+        }
+        Ok(Expr::Column(path))
+    }
+
+    fn is_alias(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case("s3object")
+            || self
+                .alias
+                .as_deref()
+                .is_some_and(|a| a.eq_ignore_ascii_case(name))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), S3Error> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(S3Error::ParseUnexpectedToken),
+        }
+    }
+
+    /// Move to the keyword outside of parentheses, e.g. the FROM after the items.
+    fn skip_to_keyword(&mut self, keyword: &str) -> Result<(), S3Error> {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Symbol("(") => depth += 1,
+                Token::Symbol(")") => depth -= 1,
+                Token::Ident(name) if depth == 0 && name.eq_ignore_ascii_case(keyword) => {
+                    return Ok(())
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        Err(S3Error::ParseUnexpectedToken)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Symbol(s)) if *s == symbol => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), S3Error> {
+        match self.eat_symbol(symbol) {
+            true => Ok(()),
+            false => Err(S3Error::ParseUnexpectedToken),
+        }
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    RESERVED.iter().any(|r| r.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn record() -> Record {
+        Record::Csv {
+            fields: vec![
+                String::from("alice"),
+                String::from("30"),
+                String::new(),
+                String::from("2.5"),
+            ],
+            header: Some(Arc::new(vec![
+                String::from("name"),
+                String::from("age"),
+                String::from("empty"),
+                String::from("score"),
+            ])),
+        }
+    }
+
+    fn eval(expr: &str) -> Result<SqlValue, S3Error> {
+        let query = Query::parse(&format!("SELECT {} FROM S3Object", expr))?;
+        let mut values = query.project(Some(&record()), &[])?;
+        Ok(values.remove(0).1)
+    }
+
+    fn check(expr: &str) -> bool {
+        match eval(expr) {
+            Ok(SqlValue::Bool(b)) => b,
+            other => panic!("{} is {:?}", expr, other),
+        }
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), SqlValue::Int(7));
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), SqlValue::Int(9));
+        assert_eq!(eval("10 - 4 - 3").unwrap(), SqlValue::Int(3));
+        assert_eq!(eval("-2 * -3").unwrap(), SqlValue::Int(6));
+        assert_eq!(eval("7 % 4 || 'x'").unwrap(), SqlValue::String("3x".into()));
+        assert!(check("NOT 1 = 2 AND 2 = 2"));
+        assert!(check("1 = 1 OR 1 = 2 AND 1 = 2"));
+        assert!(!check("(1 = 1 OR 1 = 2) AND 1 = 2"));
+        assert!(check("age + 1 > 30"));
+        assert_eq!(eval("1 / 0").unwrap(), SqlValue::Null);
+        assert_eq!(eval("NULL AND 1 = 2").unwrap(), SqlValue::Bool(false));
+        assert_eq!(eval("NULL OR 1 = 2").unwrap(), SqlValue::Null);
+    }
+
+    #[test]
+    fn predicates() {
+        assert!(check("missing_column IS NULL"));
+        assert!(check("name IS NOT MISSING"));
+        assert!(!check("empty IS NULL"));
+        assert!(check("name LIKE 'a%e'"));
+        assert!(check("name LIKE '_lic_'"));
+        assert!(!check("name LIKE 'alic'"));
+        assert!(check("name NOT LIKE 'b%'"));
+        assert!(check("'50%' LIKE '50!%' ESCAPE '!'"));
+        assert!(!check("'500' LIKE '50!%' ESCAPE '!'"));
+        assert!(check("'a_c' LIKE '%!_%' ESCAPE '!'"));
+        assert!(check("age BETWEEN 18 AND 30"));
+        assert!(check("age NOT BETWEEN 31 AND 40"));
+        assert!(check("age IN (1, 30, 'x')"));
+        assert!(check("name NOT IN ('bob', 'carol')"));
+        assert_eq!(eval("NULL LIKE '%'").unwrap(), SqlValue::Null);
+        assert!(matches!(
+            eval("name IS 1"),
+            Err(S3Error::ParseUnexpectedToken)
+        ));
+        assert!(matches!(
+            eval("name LIKE 'a' ESCAPE 'ab'"),
+            Err(S3Error::ParseUnexpectedToken)
+        ));
+    }
+
+    #[test]
+    fn like_matches() {
+        let matches = |text: &str, pattern: &str| {
+            let text: Vec<char> = text.chars().collect();
+            let pattern: Vec<char> = pattern.chars().collect();
+            like(&text, &pattern, Some('\\'))
+        };
+        assert!(matches("", ""));
+        assert!(matches("", "%%"));
+        assert!(!matches("", "_"));
+        assert!(matches("abc", "%"));
+        assert!(matches("abcbc", "a%bc"));
+        assert!(matches("abcbd", "a%b_"));
+        assert!(!matches("abcbc", "a%bd"));
+        assert!(matches("aXbXc", "%X%X%"));
+        assert!(matches("a%", "a\\%"));
+        assert!(!matches("ab", "a\\%"));
+        assert!(matches("a\\", "a\\"));
+        // backtracking patterns take at most the text times the pattern
+        let text = "a".repeat(100_000);
+        let pattern = format!("{}b", "%a".repeat(1000));
+        let start = Instant::now();
+        assert!(!matches(&text, &pattern));
+        assert!(matches(&text, &"%a".repeat(1000)));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn casts_and_functions() {
+        assert_eq!(eval("CAST(age AS INT)").unwrap(), SqlValue::Int(30));
+        assert_eq!(eval("CAST(score AS FLOAT)").unwrap(), SqlValue::Float(2.5));
+        assert_eq!(
+            eval("CAST(' true ' AS BOOL)").unwrap(),
+            SqlValue::Bool(true)
+        );
+        assert_eq!(eval("CAST(NULL AS INT)").unwrap(), SqlValue::Null);
+        assert!(matches!(
+            eval("CAST(name AS INT)"),
+            Err(S3Error::CastFailed)
+        ));
+        assert!(matches!(
+            eval("CAST('2' AS BOOL)"),
+            Err(S3Error::CastFailed)
+        ));
+        assert!(matches!(
+            eval("CAST(age AS DATE)"),
+            Err(S3Error::UnsupportedSyntax)
+        ));
+        let substring = |expr: &str| match eval(expr).unwrap() {
+            SqlValue::String(s) => s,
+            other => panic!("{} is {:?}", expr, other),
+        };
+        assert_eq!(substring("SUBSTRING('hello', 2)"), "ello");
+        assert_eq!(substring("SUBSTRING('hello', 2, 3)"), "ell");
+        assert_eq!(substring("SUBSTRING('hello' FROM 2 FOR 3)"), "ell");
+        assert_eq!(substring("SUBSTRING('hello', 0, 2)"), "h");
+        assert_eq!(substring("SUBSTRING('hello', -5, 2)"), "");
+        assert_eq!(substring("SUBSTRING('hello', 4, 10)"), "lo");
+        assert_eq!(substring("SUBSTRING('hello', 9)"), "");
+        assert_eq!(substring("SUBSTRING('hello', 2, -1)"), "");
+        assert_eq!(substring("SUBSTRING('héllo', 2, 1)"), "é");
+        assert_eq!(eval("SUBSTRING(NULL, 1)").unwrap(), SqlValue::Null);
+        assert!(matches!(
+            eval("SUBSTRING('hello')"),
+            Err(S3Error::ParseUnexpectedToken)
+        ));
+        assert_eq!(eval("CHAR_LENGTH(name)").unwrap(), SqlValue::Int(5));
+        assert_eq!(
+            eval("UPPER(TRIM('  a '))").unwrap(),
+            SqlValue::String("A".into())
+        );
+        assert_eq!(
+            eval("COALESCE(NULL, empty, 'x')").unwrap(),
+            SqlValue::String("".into())
+        );
+        assert_eq!(eval("NULLIF(age, 30)").unwrap(), SqlValue::Null);
+    }
+
+    #[test]
+    fn aggregates_and_limit() {
+        let query = Query::parse("SELECT COUNT(*), SUM(age), AVG(age), MIN(name), MAX(age) FROM S3Object s WHERE s.age > 1 LIMIT 5").unwrap();
+        assert_eq!(query.limit, Some(5));
+        assert!(query.has_aggregates());
+        let mut states = query.aggregate_states();
+        for age in ["10", "20", "x", ""] {
+            let record = Record::Csv {
+                fields: vec![String::from("n"), age.to_string()],
+                header: Some(Arc::new(vec![String::from("name"), String::from("age")])),
+            };
+            if query.matches(&record).unwrap() {
+                query.accumulate(&record, &mut states).unwrap();
+            }
+        }
+        let results: Vec<SqlValue> = states.iter().map(|s| s.result()).collect();
+        let values: Vec<SqlValue> = query
+            .project(None, &results)
+            .unwrap()
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                SqlValue::Int(2),
+                SqlValue::Int(30),
+                SqlValue::Float(15.0),
+                SqlValue::String("n".into()),
+                SqlValue::Int(20),
+            ]
+        );
+        assert!(matches!(
+            Query::parse("SELECT name, COUNT(*) FROM S3Object"),
+            Err(S3Error::UnsupportedSyntax)
+        ));
+        assert!(matches!(
+            Query::parse("SELECT * FROM S3Object WHERE COUNT(*) > 1"),
+            Err(S3Error::UnsupportedSyntax)
+        ));
+        assert!(matches!(
+            Query::parse("SELECT * FROM S3Object LIMIT x"),
+            Err(S3Error::ParseUnexpectedToken)
+        ));
+    }
+
+    #[test]
+    fn deep_expressions_are_rejected() {
+        for deep in [
+            format!("{}1", "NOT ".repeat(10_000)),
+            format!("{}1", "-".repeat(10_000)),
+            format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000)),
+            format!("{}1{}", "LOWER(".repeat(10_000), ")".repeat(10_000)),
+        ] {
+            assert!(matches!(eval(&deep), Err(S3Error::ExpressionTooLong)));
+        }
+        // long chains of operators are not nested in the parser but in the tree
+        let chain = format!("1{}", " + 1".repeat(50_000));
+        assert!(matches!(eval(&chain), Err(S3Error::ExpressionTooLong)));
+        let chain = format!("1{}", " + 1".repeat(MAX_DEPTH - 1));
+        assert_eq!(eval(&chain).unwrap(), SqlValue::Int(MAX_DEPTH as i64));
+        assert_eq!(
+            eval(&format!("{}1{}", "(".repeat(50), ")".repeat(50))).unwrap(),
+            SqlValue::Int(1)
+        );
+    }
+
+    #[test]
+    fn long_expressions_are_rejected() {
+        let long = format!(
+            "SELECT * FROM S3Object WHERE name = '{}'",
+            "a".repeat(MAX_SQL_LENGTH)
+        );
+        assert!(matches!(
+            Query::parse(&long),
+            Err(S3Error::ExpressionTooLong)
+        ));
+        let fits = format!("SELECT * FROM S3Object WHERE name = '{}'", "a".repeat(1000));
+        assert!(Query::parse(&fits).is_ok());
+    }
+
+    #[test]
+    fn deep_trees_drop_without_recursion() {
+        let mut expr = Expr::Literal(SqlValue::Int(1));
+        for _ in 0..1_000_000 {
+            expr = Expr::Not(Box::new(expr));
+        }
+        assert!(expr.deeper_than(MAX_DEPTH));
+        drop(expr);
+    }
+}
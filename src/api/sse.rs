@@ -0,0 +1,330 @@
+use crate::api::*;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hyper::{body::Bytes, HeaderMap};
+use md5::{Digest, Md5};
+use rand::RngCore;
+use ring::{aead, digest};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// The only algorithm of server-side encryption with customer-provided keys.
+pub const SSE_C_ALGORITHM: &str = "AES256";
+
+pub const SSE_C_ALGORITHM_HEADER: &str = "x-amz-server-side-encryption-customer-algorithm";
+pub const SSE_C_KEY_HEADER: &str = "x-amz-server-side-encryption-customer-key";
+pub const SSE_C_KEY_MD5_HEADER: &str = "x-amz-server-side-encryption-customer-key-MD5";
+
+pub const SSE_C_COPY_SOURCE_ALGORITHM_HEADER: &str =
+    "x-amz-copy-source-server-side-encryption-customer-algorithm";
+pub const SSE_C_COPY_SOURCE_KEY_HEADER: &str =
+    "x-amz-copy-source-server-side-encryption-customer-key";
+pub const SSE_C_COPY_SOURCE_KEY_MD5_HEADER: &str =
+    "x-amz-copy-source-server-side-encryption-customer-key-MD5";
+
+pub const SSE_HEADER: &str = "x-amz-server-side-encryption";
+pub const SSE_KMS_KEY_ID_HEADER: &str = "x-amz-server-side-encryption-aws-kms-key-id";
+
+const SALT_LEN: usize = 16;
+
+/// SseCustomerKey is the key of a request with server-side encryption with
+/// customer-provided keys (SSE-C). The key is only used for the request,
+/// objects keep a salted fingerprint of it to check the key of later requests.
+#[derive(Clone)]
+pub struct SseCustomerKey {
+    key: [u8; 32],
+    key_md5: String,
+}
+
+/// SseFingerprint identifies the key of an SSE-C object without keeping the key,
+/// it is a SHA-256 of a random salt and the key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SseFingerprint {
+    salt: String,
+    hash: String,
+}
+
+/// SseAlgorithm is the encryption of an object with server managed keys,
+/// SSE-S3 (`AES256`) or SSE-KMS (`aws:kms`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SseAlgorithm {
+    Aes256,
+    AwsKms,
+}
+
+/// ServerSideEncryption is requested by `x-amz-server-side-encryption` or by
+/// the bucket default encryption, the KMS key id is empty for the default key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSideEncryption {
+    pub algorithm: SseAlgorithm,
+    pub kms_key_id: String,
+}
+
+impl SseAlgorithm {
+    pub fn parse(s: &str) -> Result<Self, S3Error> {
+        match s {
+            "AES256" => Ok(SseAlgorithm::Aes256),
+            "aws:kms" => Ok(SseAlgorithm::AwsKms),
+            _ => Err(S3Error::InvalidArgument),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SseAlgorithm::Aes256 => "AES256",
+            SseAlgorithm::AwsKms => "aws:kms",
+        }
+    }
+}
+
+impl ServerSideEncryption {
+    /// The encryption requested by the headers, None without `x-amz-server-side-encryption`.
+    /// A KMS key id is only valid with `aws:kms`.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, S3Error> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| v.to_str().map_err(|_| S3Error::InvalidArgument))
+                .transpose()
+        };
+        let kms_key_id = header(SSE_KMS_KEY_ID_HEADER)?.unwrap_or_default();
+        let algorithm = match header(SSE_HEADER)? {
+            Some(v) => SseAlgorithm::parse(v)?,
+            None if kms_key_id.is_empty() => return Ok(None),
+            None => return Err(S3Error::InvalidArgument),
+        };
+        if algorithm != SseAlgorithm::AwsKms && !kms_key_id.is_empty() {
+            return Err(S3Error::InvalidArgument);
+        }
+        Ok(Some(ServerSideEncryption {
+            algorithm,
+            kms_key_id: kms_key_id.to_string(),
+        }))
+    }
+}
+
+impl std::fmt::Debug for SseCustomerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SseCustomerKey")
+            .field("key", &"<redacted>")
+            .field("key_md5", &self.key_md5)
+            .finish()
+    }
+}
+
+impl SseCustomerKey {
+    /// The key of the request, None when the request has no SSE-C headers.
+    /// All three headers are required, with a 256 bit key that matches its MD5.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, S3Error> {
+        Self::from_named_headers(
+            headers,
+            [
+                SSE_C_ALGORITHM_HEADER,
+                SSE_C_KEY_HEADER,
+                SSE_C_KEY_MD5_HEADER,
+            ],
+        )
+    }
+
+    /// The key of the copy source of a CopyObject request, from the
+    /// x-amz-copy-source-server-side-encryption-customer-* headers.
+    pub fn from_copy_source_headers(headers: &HeaderMap) -> Result<Option<Self>, S3Error> {
+        Self::from_named_headers(
+            headers,
+            [
+                SSE_C_COPY_SOURCE_ALGORITHM_HEADER,
+                SSE_C_COPY_SOURCE_KEY_HEADER,
+                SSE_C_COPY_SOURCE_KEY_MD5_HEADER,
+            ],
+        )
+    }
+
+    fn from_named_headers(headers: &HeaderMap, names: [&str; 3]) -> Result<Option<Self>, S3Error> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| v.to_str().map_err(|_| S3Error::InvalidArgument))
+                .transpose()
+        };
+        let [algorithm, key, key_md5] = names;
+        let (algorithm, key, key_md5) = (header(algorithm)?, header(key)?, header(key_md5)?);
+        let (algorithm, key, key_md5) = match (algorithm, key, key_md5) {
+            (None, None, None) => return Ok(None),
+            (Some(algorithm), Some(key), Some(key_md5)) => (algorithm, key, key_md5),
+            _ => return Err(S3Error::InvalidArgument),
+        };
+        if algorithm != SSE_C_ALGORITHM {
+            return Err(S3Error::InvalidEncryptionAlgorithmError);
+        }
+        let key: [u8; 32] = BASE64
+            .decode(key)
+            .ok()
+            .and_then(|k| k.try_into().ok())
+            .ok_or(S3Error::InvalidArgument)?;
+        if BASE64.encode(Md5::digest(key)) != key_md5 {
+            return Err(S3Error::InvalidArgument);
+        }
+        Ok(Some(SseCustomerKey {
+            key,
+            key_md5: key_md5.to_string(),
+        }))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_bytes(key: [u8; 32]) -> Self {
+        SseCustomerKey {
+            key,
+            key_md5: BASE64.encode(Md5::digest(key)),
+        }
+    }
+
+    /// The base64 MD5 of the key, returned with the algorithm in responses.
+    pub fn key_md5(&self) -> &str {
+        &self.key_md5
+    }
+
+    /// A new fingerprint of the key to keep with the object.
+    pub fn fingerprint(&self) -> SseFingerprint {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        SseFingerprint {
+            salt: to_hex(&salt),
+            hash: to_hex(self.hash(&salt).as_ref()),
+        }
+    }
+
+    fn hash(&self, salt: &[u8]) -> digest::Digest {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(salt);
+        ctx.update(&self.key);
+        ctx.finish()
+    }
+
+    /// Encrypt object data with AES-256-GCM, the random nonce is stored before the sealed data.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Bytes, S3Error> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = data.to_vec();
+        self.aead_key()
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| S3Error::InternalError)?;
+        let mut buf = Vec::with_capacity(nonce.len() + sealed.len());
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&sealed);
+        Ok(buf.into())
+    }
+
+    /// Decrypt object data sealed by encrypt(), data that fails authentication
+    /// was sealed with another key or was corrupted at rest.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Bytes, S3Error> {
+        if data.len() < aead::NONCE_LEN {
+            return Err(S3Error::InternalError);
+        }
+        let (nonce, sealed) = data.split_at(aead::NONCE_LEN);
+        let nonce =
+            aead::Nonce::try_assume_unique_for_key(nonce).map_err(|_| S3Error::InternalError)?;
+        let mut buf = sealed.to_vec();
+        let len = self
+            .aead_key()
+            .open_in_place(nonce, aead::Aad::empty(), &mut buf)
+            .map_err(|_| {
+                tracing::warn!("SSE-C object failed authentication");
+                S3Error::InternalError
+            })?
+            .len();
+        buf.truncate(len);
+        Ok(buf.into())
+    }
+
+    fn aead_key(&self) -> aead::LessSafeKey {
+        let key = aead::UnboundKey::new(&aead::AES_256_GCM, &self.key)
+            .expect("AES-256 takes a 256 bit key");
+        aead::LessSafeKey::new(key)
+    }
+}
+
+impl SseFingerprint {
+    pub fn matches(&self, key: &SseCustomerKey) -> bool {
+        match from_hex(&self.salt) {
+            Some(salt) => {
+                let hash = to_hex(key.hash(&salt).as_ref());
+                // compare in constant time, like any other secret
+                hash.len() == self.hash.len()
+                    && hash
+                        .bytes()
+                        .zip(self.hash.bytes())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b))
+                        == 0
+            }
+            None => false,
+        }
+    }
+}
+
+/// Like S3, customer keys are only accepted over HTTPS so they never travel in
+/// plain text, requests with any of the SSE-C headers over HTTP are InvalidRequest.
+pub fn check_sse_c_transport(headers: &HeaderMap, secure: bool) -> Result<(), S3Error> {
+    let has_key = [
+        SSE_C_ALGORITHM_HEADER,
+        SSE_C_KEY_HEADER,
+        SSE_C_KEY_MD5_HEADER,
+        SSE_C_COPY_SOURCE_ALGORITHM_HEADER,
+        SSE_C_COPY_SOURCE_KEY_HEADER,
+        SSE_C_COPY_SOURCE_KEY_MD5_HEADER,
+    ]
+    .iter()
+    .any(|name| headers.contains_key(*name));
+    match has_key && !secure {
+        true => Err(S3Error::InvalidRequest),
+        false => Ok(()),
+    }
+}
+
+/// Check the SSE-C key of a request to an object like S3, objects stored with
+/// SSE-C require the same key and other objects must not be given one.
+pub fn check_sse_key(
+    stored: Option<&SseFingerprint>,
+    key: Option<&SseCustomerKey>,
+) -> Result<(), S3Error> {
+    match (stored, key) {
+        (None, None) => Ok(()),
+        (Some(_), None) | (None, Some(_)) => Err(S3Error::InvalidRequest),
+        (Some(fingerprint), Some(key)) => match fingerprint.matches(key) {
+            true => Ok(()),
+            false => Err(S3Error::AccessDenied),
+        },
+    }
+}
+
+/// Set the encryption response headers of an object, SSE-C with the key MD5
+/// or the algorithm and KMS key id of server managed keys, nothing for plain objects.
+pub fn set_sse_headers(res: &mut HttpResponse, object: &ObjectInfo) -> Result<(), S3Error> {
+    if !object.sse_customer_key_md5.is_empty() {
+        set_header(res, SSE_C_ALGORITHM_HEADER, SSE_C_ALGORITHM)?;
+        set_header(res, SSE_C_KEY_MD5_HEADER, &object.sse_customer_key_md5)?;
+    }
+    if !object.sse_algorithm.is_empty() {
+        set_header(res, SSE_HEADER, &object.sse_algorithm)?;
+    }
+    if !object.sse_kms_key_id.is_empty() {
+        set_header(res, SSE_KMS_KEY_ID_HEADER, &object.sse_kms_key_id)?;
+    }
+    Ok(())
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,180 @@
+use crate::api::*;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+
+/// Tag limits of S3, see
+/// https://docs.aws.amazon.com/AmazonS3/latest/userguide/object-tagging.html
+pub const MAX_OBJECT_TAGS: usize = 10;
+pub const MAX_BUCKET_TAGS: usize = 50;
+pub const MAX_TAG_KEY_LEN: usize = 128;
+pub const MAX_TAG_VALUE_LEN: usize = 256;
+
+/// Tagging is the tag set of a bucket or an object,
+/// it serializes to the `Tagging` document of the tagging API's.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tagging {
+    #[serde(rename = "TagSet", default)]
+    pub tag_set: TagSet,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagSet {
+    #[serde(rename = "Tag", default)]
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tag {
+    #[serde(rename = "Key")]
+    pub key: String,
+    #[serde(rename = "Value", default)]
+    pub value: String,
+}
+
+impl Tagging {
+    pub fn new(tags: Vec<Tag>) -> Self {
+        Tagging {
+            tag_set: TagSet { tags },
+        }
+    }
+
+    /// Parse the x-amz-tagging header of uploads, which is url encoded
+    /// like a query string (`k1=v1&k2=v2`).
+    pub fn from_header(value: &str) -> Result<Self, S3Error> {
+        let qs = QueryStr::parse(value).map_err(|_| S3Error::InvalidTag)?;
+        let tags = qs
+            .iter()
+            .map(|(key, value)| Tag {
+                key: key.to_string(),
+                value: value.unwrap_or("").to_string(),
+            })
+            .collect();
+        let tagging = Tagging::new(tags);
+        tagging.validate(MAX_OBJECT_TAGS)?;
+        Ok(tagging)
+    }
+
+    /// Read and validate a Tagging document from a request body.
+    pub async fn read(body: Option<Body>, max_tags: usize) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let tagging: Tagging = from_xml(&buf)?;
+        tagging.validate(max_tags)?;
+        Ok(tagging)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("Tagging", self)
+    }
+
+    pub fn tags(&self) -> &[Tag] {
+        &self.tag_set.tags
+    }
+
+    pub fn len(&self) -> usize {
+        self.tag_set.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tag_set.tags.is_empty()
+    }
+
+    /// Check the S3 limits: the number of tags, unique keys that are not
+    /// in the reserved `aws:` prefix, the key and value lengths in characters
+    /// and the allowed characters (letters, digits, spaces and `+ - = . _ : / @`).
+    pub fn validate(&self, max_tags: usize) -> Result<(), S3Error> {
+        if self.len() > max_tags {
+            return Err(S3Error::InvalidTag);
+        }
+        let valid_chars = |s: &str| {
+            s.chars()
+                .all(|c| c.is_alphanumeric() || c.is_whitespace() || "+-=._:/@".contains(c))
+        };
+        for (i, tag) in self.tags().iter().enumerate() {
+            let key_len = tag.key.chars().count();
+            let valid = (1..=MAX_TAG_KEY_LEN).contains(&key_len)
+                && tag.value.chars().count() <= MAX_TAG_VALUE_LEN
+                && valid_chars(&tag.key)
+                && valid_chars(&tag.value)
+                && !tag.key.starts_with("aws:")
+                && !self.tags()[..i].iter().any(|t| t.key == tag.key);
+            if !valid {
+                return Err(S3Error::InvalidTag);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(key: &str, value: &str) -> Tag {
+        Tag {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn tags(count: usize) -> Tagging {
+        Tagging::new((0..count).map(|i| tag(&format!("k{}", i), "v")).collect())
+    }
+
+    #[test]
+    fn tag_sets_are_limited() {
+        assert!(tags(MAX_OBJECT_TAGS).validate(MAX_OBJECT_TAGS).is_ok());
+        assert_eq!(
+            tags(MAX_OBJECT_TAGS + 1).validate(MAX_OBJECT_TAGS),
+            Err(S3Error::InvalidTag)
+        );
+        assert!(tags(MAX_BUCKET_TAGS).validate(MAX_BUCKET_TAGS).is_ok());
+        assert!(tags(MAX_BUCKET_TAGS + 1).validate(MAX_BUCKET_TAGS).is_err());
+        assert!(Tagging::default().validate(MAX_OBJECT_TAGS).is_ok());
+    }
+
+    #[test]
+    fn tags_are_checked() {
+        let valid =
+            |key: &str, value: &str| Tagging::new(vec![tag(key, value)]).validate(1).is_ok();
+        // lengths are in characters, not bytes
+        assert!(valid(
+            &"é".repeat(MAX_TAG_KEY_LEN),
+            &"é".repeat(MAX_TAG_VALUE_LEN)
+        ));
+        assert!(!valid(&"k".repeat(MAX_TAG_KEY_LEN + 1), "v"));
+        assert!(!valid("k", &"v".repeat(MAX_TAG_VALUE_LEN + 1)));
+        assert!(!valid("", "v"));
+        assert!(valid("k", ""));
+        assert!(valid("team name", "a+b-c=d._:/@"));
+        assert!(!valid("k", "a&b"));
+        assert!(!valid("k*", "v"));
+        assert!(!valid("aws:k", "v"));
+        let duplicate = Tagging::new(vec![tag("k", "a"), tag("k", "b")]);
+        assert_eq!(duplicate.validate(2), Err(S3Error::InvalidTag));
+    }
+
+    #[test]
+    fn header_tags_are_url_encoded() {
+        let tagging = Tagging::from_header("team=a%20b&env=prod&empty").unwrap();
+        assert_eq!(
+            tagging.tags(),
+            [tag("team", "a b"), tag("env", "prod"), tag("empty", "")]
+        );
+        let header = (0..=MAX_OBJECT_TAGS)
+            .map(|i| format!("k{}=v", i))
+            .collect::<Vec<_>>()
+            .join("&");
+        assert_eq!(Tagging::from_header(&header), Err(S3Error::InvalidTag));
+        assert_eq!(Tagging::from_header("aws:k=v"), Err(S3Error::InvalidTag));
+    }
+
+    #[tokio::test]
+    async fn documents_round_trip() {
+        let tagging = Tagging::new(vec![tag("a", "1"), tag("b", "")]);
+        let xml = tagging.to_xml().unwrap();
+        let read = Tagging::read(Some(Body::from(xml)), MAX_OBJECT_TAGS).await;
+        assert_eq!(read.unwrap(), tagging);
+        let read = Tagging::read(Some(Body::from(tags(3).to_xml().unwrap())), 2).await;
+        assert_eq!(read, Err(S3Error::InvalidTag));
+    }
+}
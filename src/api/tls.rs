@@ -0,0 +1,146 @@
+use crate::api::*;
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::{
+    rustls::{self, server::WebPkiClientVerifier, RootCertStore},
+    TlsAcceptor,
+};
+
+/// How often the certificate files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// TlsConfig points to the PEM files used by an HTTPS listener.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// when set clients must present a certificate signed by one of these CA's (mTLS)
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// TlsReloader holds the current acceptor and replaces it when the PEM files change
+/// or on SIGHUP. Connections keep the acceptor they were accepted with,
+/// so reloading only affects new connections and never drops existing ones.
+#[derive(Clone)]
+pub struct TlsReloader {
+    config: TlsConfig,
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+    mtimes: Arc<Mutex<Vec<Option<SystemTime>>>>,
+}
+
+impl TlsConfig {
+    /// S3D_TLS_CERT and S3D_TLS_KEY enable HTTPS, S3D_TLS_CLIENT_CA enables mTLS.
+    pub fn from_env() -> Option<TlsConfig> {
+        let cert = std::env::var("S3D_TLS_CERT").ok()?;
+        let key = std::env::var("S3D_TLS_KEY").ok()?;
+        Some(TlsConfig {
+            cert_path: PathBuf::from(cert),
+            key_path: PathBuf::from(key),
+            client_ca_path: std::env::var("S3D_TLS_CLIENT_CA").ok().map(PathBuf::from),
+        })
+    }
+
+    fn paths(&self) -> Vec<&PathBuf> {
+        let mut paths = vec![&self.cert_path, &self.key_path];
+        if let Some(ca) = &self.client_ca_path {
+            paths.push(ca);
+        }
+        paths
+    }
+
+    fn mtimes(&self) -> Vec<Option<SystemTime>> {
+        self.paths()
+            .iter()
+            .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn load(&self) -> Result<TlsAcceptor, SyncError> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&self.cert_path)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&self.key_path)?))?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no private key found"))?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca_path {
+            None => builder.with_no_client_auth(),
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for ca in rustls_pemfile::certs(&mut BufReader::new(File::open(ca_path)?)) {
+                    roots.add(ca?)?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+        let mut config = builder.with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+impl TlsReloader {
+    pub fn new(config: TlsConfig) -> Result<TlsReloader, SyncError> {
+        let mtimes = config.mtimes();
+        let acceptor = config.load()?;
+        Ok(TlsReloader {
+            config,
+            acceptor: Arc::new(RwLock::new(acceptor)),
+            mtimes: Arc::new(Mutex::new(mtimes)),
+        })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Load the files again, on failure the previous certificate stays in use.
+    pub fn reload(&self) -> Result<(), SyncError> {
+        *self.mtimes.lock().unwrap() = self.config.mtimes();
+        let acceptor = self.config.load()?;
+        *self.acceptor.write().unwrap() = acceptor;
+        tracing::info!(cert = ?self.config.cert_path, "TLS certificates reloaded");
+        Ok(())
+    }
+
+    /// Spawn a task that reloads on SIGHUP or when any of the files is modified.
+    pub fn watch(&self) -> Result<(), SyncError> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let reloader = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                let hungup = tokio::select! {
+                    _ = hangup.recv() => true,
+                    _ = interval.tick() => false,
+                };
+                // the files are checked and read on a blocking thread, not on the runtime
+                let reloader = reloader.clone();
+                let reloaded = tokio::task::spawn_blocking(move || {
+                    if !hungup && *reloader.mtimes.lock().unwrap() == reloader.config.mtimes() {
+                        return Ok(());
+                    }
+                    reloader.reload()
+                })
+                .await;
+                match reloaded {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => {
+                        tracing::warn!(%err, "TLS reload failed, keeping previous certificates")
+                    }
+                    Err(err) => tracing::warn!(%err, "TLS reload task failed"),
+                }
+            }
+        });
+        Ok(())
+    }
+}
@@ -1,18 +1,46 @@
 use crate::api::*;
-use hyper::{http::request::Parts, Body, Request, Response};
-use std::collections::HashMap;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hyper::{
+    body::{Bytes, HttpBody},
+    header::HeaderValue,
+    http::request::Parts,
+    Body, Request, Response,
+};
+use md5::{Digest, Md5};
+use percent_encoding::percent_decode_str;
+use rand::{distributions::Alphanumeric, Rng};
+use std::time::Duration;
+use tokio::time::timeout;
 
 pub type HttpRequest = Request<Body>;
 pub type HttpResponse = Response<Body>;
 pub type HttpResult = Result<HttpResponse, hyper::Error>;
 pub type SyncError = Box<dyn std::error::Error + Send + Sync>;
 
-pub trait ReqParser {
-    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Self;
+/// Generate a request id in the same format as S3 (16 uppercase hex digits).
+pub fn new_request_id() -> String {
+    format!("{:016X}", rand::thread_rng().gen::<u64>())
 }
 
+/// Generate an extended request id for `x-amz-id-2`, opaque like the S3 host id.
+pub fn new_host_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
+
+/// ReqParser parses the op params from the request, malformed requests fail
+/// with the S3Error to respond with.
+pub trait ReqParser: Sized {
+    fn parse(req: HttpRequest, bucket: &str, key: &str) -> Result<Self, S3Error>;
+}
+
+/// ResWriter writes the op reply, replies that cannot be sent
+/// (e.g. a header value with a newline) fail with the S3Error to respond with.
 pub trait ResWriter {
-    fn write(self) -> HttpResponse;
+    fn write(self) -> Result<HttpResponse, S3Error>;
 }
 
 pub trait RetWriter {
@@ -22,12 +50,109 @@ pub trait RetWriter {
 impl<T: ResWriter> RetWriter for Result<T, S3Error> {
     fn write(self) -> HttpResult {
         match self {
-            Ok(res) => Ok(res.write()),
+            Ok(res) => Ok(res.write().unwrap_or_else(|err| err.write())),
             Err(err) => Ok(err.write()),
         }
     }
 }
 
+/// Set a response header, values that are not valid in a header fail with InternalError
+/// since they come from stored data rather than from the request.
+pub fn set_header(res: &mut HttpResponse, name: &'static str, value: &str) -> Result<(), S3Error> {
+    let value = HeaderValue::from_str(value).map_err(|_| {
+        tracing::warn!(header = name, "invalid response header value");
+        S3Error::InternalError
+    })?;
+    res.headers_mut().insert(name, value);
+    Ok(())
+}
+
+/// The largest object a single PUT uploads, like S3.
+pub const MAX_OBJECT_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The largest configuration document (XML or JSON) a request sends.
+pub const MAX_DOCUMENT_SIZE: u64 = 1024 * 1024;
+
+/// The longest object key in bytes, like S3.
+pub const MAX_KEY_LEN: usize = 1024;
+
+/// How long a body may send nothing before the read gives up, like the S3 idle timeout.
+pub const BODY_IDLE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Read the whole request body of an object, up to MAX_OBJECT_SIZE or EntityTooLarge.
+/// A body that fails mid-way (e.g. the client disconnected) is IncompleteBody,
+/// and one that stalls for BODY_IDLE_TIMEOUT is RequestTimeout.
+pub async fn read_body(body: Option<Body>) -> Result<Bytes, S3Error> {
+    read_body_up_to(body, MAX_OBJECT_SIZE, S3Error::EntityTooLarge).await
+}
+
+/// Read the whole body of a configuration document like read_body, up to
+/// MAX_DOCUMENT_SIZE or MaxMessageLengthExceeded.
+pub async fn read_document(body: Option<Body>) -> Result<Bytes, S3Error> {
+    read_body_up_to(body, MAX_DOCUMENT_SIZE, S3Error::MaxMessageLengthExceeded).await
+}
+
+async fn read_body_up_to(
+    body: Option<Body>,
+    limit: u64,
+    too_large: S3Error,
+) -> Result<Bytes, S3Error> {
+    let mut body = match body {
+        None => return Ok(Bytes::new()),
+        Some(body) => body,
+    };
+    // a declared Content-Length fails before anything is read
+    if body.size_hint().lower() > limit {
+        return Err(too_large);
+    }
+    let mut buf = Vec::new();
+    loop {
+        let chunk = match timeout(BODY_IDLE_TIMEOUT, body.data()).await {
+            Err(_) => return Err(S3Error::RequestTimeout),
+            Ok(None) => break,
+            Ok(Some(chunk)) => chunk.map_err(|_| S3Error::IncompleteBody)?,
+        };
+        if (buf.len() + chunk.len()) as u64 > limit {
+            return Err(too_large);
+        }
+        // most bodies are a single chunk, which is kept without a copy
+        if buf.is_empty() && body.is_end_stream() {
+            return Ok(chunk);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(buf))
+}
+
+/// Check the body of a request with Content-MD5 against it, a header that is not
+/// a base64 MD5 is InvalidDigest and a body that doesn't match is BadDigest.
+/// The body is read like read_body and put back in the request.
+pub async fn check_content_md5(req: HttpRequest) -> Result<HttpRequest, S3Error> {
+    let expected = match req.headers().get("content-md5") {
+        None => return Ok(req),
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| BASE64.decode(v.trim()).ok())
+            .filter(|digest| digest.len() == 16)
+            .ok_or(S3Error::InvalidDigest)?,
+    };
+    let (parts, body) = req.into_parts();
+    let buf = read_body(Some(body)).await?;
+    if Md5::digest(&buf).as_slice() != expected.as_slice() {
+        return Err(S3Error::BadDigest);
+    }
+    Ok(Request::from_parts(parts, Body::from(buf)))
+}
+
+/// Check the length of an object key, longer keys are KeyTooLongError.
+pub fn validate_key(key: &str) -> Result<(), S3Error> {
+    if key.len() > MAX_KEY_LEN {
+        return Err(S3Error::KeyTooLongError);
+    }
+    Ok(())
+}
+
 pub struct BodyWriter {
     buf: Vec<u8>,
 }
@@ -38,50 +163,240 @@ impl BodyWriter {
     }
     pub fn new_xml() -> Self {
         let mut w = Self::new();
-        w.append("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        w.append(XML_DECL);
         w
     }
     pub fn append(&mut self, s: &str) -> &mut Self {
         self.buf.extend_from_slice(s.as_bytes());
         self
     }
+    /// Append an element, the content is escaped.
     pub fn append_xml(&mut self, tag: &str, content: &str) -> &mut Self {
-        self.append(format!("<{0}>{1}</{0}>", tag, content).as_str())
+        self.append(format!("<{0}>{1}</{0}>", tag, escape_xml(content)).as_str())
     }
     pub fn _str(self) -> String {
-        String::from_utf8(self.buf).unwrap()
+        String::from_utf8_lossy(&self.buf).to_string()
     }
     pub fn body(self) -> Body {
         Body::from(self.buf)
     }
 }
 
-#[derive(Debug, Clone)]
+impl Default for BodyWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check the S3 bucket naming rules, see
+/// https://docs.aws.amazon.com/AmazonS3/latest/userguide/bucketnamingrules.html
+/// - 3 to 63 characters of lowercase letters, digits, dots and hyphens
+/// - starts and ends with a letter or digit, no adjacent dots
+/// - not formatted like an IPv4 address
+/// - none of the prefixes and suffixes S3 reserves for access points
+pub fn validate_bucket_name(name: &str) -> Result<(), S3Error> {
+    let valid_chars = name
+        .bytes()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'.' || c == b'-');
+    let edge = |c: Option<char>| c.is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    let valid = (3..=63).contains(&name.len())
+        && valid_chars
+        && edge(name.chars().next())
+        && edge(name.chars().last())
+        && !name.contains("..")
+        && name.parse::<std::net::Ipv4Addr>().is_err()
+        && !name.starts_with("xn--")
+        && !name.starts_with("sthree-")
+        && !name.ends_with("-s3alias")
+        && !name.ends_with("--ol-s3");
+    match valid {
+        true => Ok(()),
+        false => Err(S3Error::InvalidBucketName),
+    }
+}
+
+/// Names of the targets in the server configuration, webhooks and replication
+/// destinations, are ASCII letters, digits, `-` and `_`, as they appear in ARNs
+/// and directory names.
+pub fn valid_target_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// The tags of the object, empty when it has none or the layer does not keep them.
+pub async fn object_tags<API: ApiLayer + ?Sized>(api: &API, bucket: &str, key: &str) -> Vec<Tag> {
+    let req = get_object_tagging::Req::new(get_object_tagging::Params {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        version_id: String::new(),
+    });
+    match api.get_object_tagging(req).await {
+        Ok(res) => res.into_body().tagging.tag_set.tags,
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Split a path style request path (`/bucket/key`) to the decoded bucket and key.
+/// Paths that are not absolute (e.g. `OPTIONS *`) fail with InvalidRequest.
+pub fn parse_path(path: &str) -> Result<(String, String), S3Error> {
+    let path = path.strip_prefix('/').ok_or(S3Error::InvalidRequest)?;
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    Ok((decode_path(bucket)?, decode_path(key)?))
+}
+
+/// Percent-decode a path segment, `+` is a literal plus in paths.
+pub fn decode_path(s: &str) -> Result<String, S3Error> {
+    percent_decode_str(s)
+        .decode_utf8()
+        .map(|s| s.into_owned())
+        .map_err(|_| S3Error::InvalidURI)
+}
+
+/// Percent-decode a query param, `+` is a space in queries
+/// (clients encode a literal plus as `%2B`).
+pub fn decode_query(s: &str) -> Result<String, S3Error> {
+    decode_path(s.replace('+', " ").as_str())
+}
+
+fn decode_query_lossy(s: &str) -> String {
+    percent_decode_str(s.replace('+', " ").as_str())
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// QueryStr holds the decoded query params in request order.
+/// Keys can repeat, and valueless params (e.g. `?uploads`) are kept apart
+/// from params with an empty value (e.g. `?prefix=`).
+#[derive(Debug, Clone, Default)]
 pub struct QueryStr {
-    map: HashMap<String, String>,
+    params: Vec<(String, Option<String>)>,
 }
 
 impl QueryStr {
+    /// Parse the query leniently, malformed encodings are replaced rather than rejected.
+    /// The server validates the query with parse() before the ops get to see it.
     pub fn new(query: String) -> Self {
-        let mut qs = QueryStr {
-            map: HashMap::new(),
-        };
-        for q in query.split('&') {
-            let kv: Vec<&str> = q.split('=').collect();
-            qs.map.insert(
-                kv.get(0).unwrap_or(&"").to_string(),
-                kv.get(1).unwrap_or(&"").to_string(),
-            );
+        let params = query
+            .split('&')
+            .filter(|q| !q.is_empty())
+            .map(|q| match q.split_once('=') {
+                Some((k, v)) => (decode_query_lossy(k), Some(decode_query_lossy(v))),
+                None => (decode_query_lossy(q), None),
+            })
+            .collect();
+        QueryStr { params }
+    }
+    /// Parse the query, params that don't decode to UTF-8 fail with InvalidURI.
+    pub fn parse(query: &str) -> Result<Self, S3Error> {
+        let mut params = Vec::new();
+        for q in query.split('&').filter(|q| !q.is_empty()) {
+            params.push(match q.split_once('=') {
+                Some((k, v)) => (decode_query(k)?, Some(decode_query(v)?)),
+                None => (decode_query(q)?, None),
+            });
         }
-        qs
+        Ok(QueryStr { params })
     }
     pub fn from_parts(parts: &Parts) -> Self {
         Self::new(parts.uri.query().unwrap_or("").to_string())
     }
+    /// The first value of the param, empty when missing or valueless.
     pub fn get(&self, key: &str) -> String {
-        self.map.get(key).unwrap_or(&"".to_string()).to_string()
+        self.value(key).unwrap_or("").to_string()
+    }
+    /// The first value of the param, None when missing or valueless.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.as_deref())
     }
-    pub fn get_i32(&self, key: &str) -> i32 {
-        self.get(key).parse::<i32>().unwrap_or(0)
+    /// All the values of a repeated param, valueless occurrences are empty.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.params
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_deref().unwrap_or(""))
+            .collect()
+    }
+    /// The param appears, with or without a value.
+    pub fn has(&self, key: &str) -> bool {
+        self.params.iter().any(|(k, _)| k == key)
+    }
+    /// The param appears without a value, like sub-resources (e.g. `?versioning`).
+    pub fn is_flag(&self, key: &str) -> bool {
+        self.params.iter().any(|(k, v)| k == key && v.is_none())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_deref()))
+    }
+    /// The param as a number, None when missing and InvalidArgument when not a number.
+    pub fn get_i32(&self, key: &str) -> Result<Option<i32>, S3Error> {
+        match self.params.iter().find(|(k, _)| k == key) {
+            None => Ok(None),
+            Some((_, v)) => v
+                .as_deref()
+                .unwrap_or("")
+                .parse()
+                .map(Some)
+                .map_err(|_| S3Error::InvalidArgument),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(body: &'static str, md5: Option<&str>) -> HttpRequest {
+        let mut req = Request::builder().method("PUT").uri("/bucket/key");
+        if let Some(md5) = md5 {
+            req = req.header("content-md5", md5);
+        }
+        req.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn documents_are_limited() {
+        let doc = vec![b'x'; MAX_DOCUMENT_SIZE as usize];
+        let buf = read_document(Some(Body::from(doc.clone()))).await.unwrap();
+        assert_eq!(buf.len(), doc.len());
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let chunk = Bytes::from(vec![b'x'; MAX_DOCUMENT_SIZE as usize / 2 + 1]);
+                if sender.send_data(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+        assert_eq!(
+            read_document(Some(body)).await,
+            Err(S3Error::MaxMessageLengthExceeded)
+        );
+    }
+
+    #[tokio::test]
+    async fn content_md5_is_checked() {
+        // base64 of the md5 of "data"
+        let md5 = "jXd/OF09/siBXSD3SWAm3A==";
+        let req = check_content_md5(put("data", Some(md5))).await.unwrap();
+        assert_eq!(read_body(Some(req.into_body())).await.unwrap(), "data");
+        assert!(check_content_md5(put("data", None)).await.is_ok());
+        let err = check_content_md5(put("other", Some(md5))).await.err();
+        assert_eq!(err, Some(S3Error::BadDigest));
+        let err = check_content_md5(put("data", Some("not md5"))).await.err();
+        assert_eq!(err, Some(S3Error::InvalidDigest));
+    }
+
+    #[test]
+    fn keys_are_limited() {
+        assert!(validate_key(&"k".repeat(MAX_KEY_LEN)).is_ok());
+        assert_eq!(
+            validate_key(&"k".repeat(MAX_KEY_LEN + 1)),
+            Err(S3Error::KeyTooLongError)
+        );
     }
 }
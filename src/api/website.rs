@@ -0,0 +1,722 @@
+use crate::api::*;
+use crate::auth::Principal;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE, HOST, LOCATION},
+    Body, Method, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+/// Limits of the website configuration and redirect locations, like S3.
+pub const MAX_ROUTING_RULES: usize = 50;
+pub const MAX_REDIRECT_LOCATION_LEN: usize = 2048;
+
+/// WebsiteConfiguration is the `?website` document of a bucket, served by the website listeners.
+///
+/// It either redirects all requests to another host, or serves the index document
+/// of directory-like paths with an optional error document and routing rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct WebsiteConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_document: Option<ErrorDocument>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_document: Option<IndexDocument>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_all_requests_to: Option<RedirectAllRequestsTo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing_rules: Option<RoutingRules>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorDocument {
+    pub key: String,
+}
+
+/// The suffix is appended to paths that end with a slash, e.g. index.html.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct IndexDocument {
+    pub suffix: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RedirectAllRequestsTo {
+    pub host_name: String,
+    /// http or https, by default the protocol of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingRules {
+    #[serde(rename = "RoutingRule", default)]
+    pub rules: Vec<RoutingRule>,
+}
+
+/// A rule without a condition applies to every request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<RoutingCondition>,
+    pub redirect: RoutingRedirect,
+}
+
+/// Rules with an error code apply to the requests that failed with it,
+/// the others apply before the object is read.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingCondition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_error_code_returned_equals: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_prefix_equals: Option<String>,
+}
+
+/// Unset fields keep the value of the request, the key can be replaced
+/// either whole or by its prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingRedirect {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_redirect_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace_key_prefix_with: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace_key_with: Option<String>,
+}
+
+impl WebsiteConfiguration {
+    /// Read and validate a WebsiteConfiguration document from a request body.
+    pub async fn read(body: Option<Body>) -> Result<Self, S3Error> {
+        let buf = read_document(body).await?;
+        let config: WebsiteConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_xml(&self) -> Result<String, S3Error> {
+        to_xml("WebsiteConfiguration", self)
+    }
+
+    /// Check the configuration like S3 does, a redirect of all requests
+    /// excludes the other settings which require an index document.
+    pub fn validate(&self) -> Result<(), S3Error> {
+        if let Some(to) = &self.redirect_all_requests_to {
+            if self.index_document.is_some()
+                || self.error_document.is_some()
+                || self.routing_rules.is_some()
+            {
+                return Err(S3Error::InvalidArgument);
+            }
+            if to.host_name.is_empty() {
+                return Err(S3Error::InvalidArgument);
+            }
+            return validate_protocol(to.protocol.as_deref());
+        }
+        match &self.index_document {
+            Some(index) if !index.suffix.is_empty() && !index.suffix.contains('/') => {}
+            _ => return Err(S3Error::InvalidArgument),
+        }
+        if let Some(error) = &self.error_document {
+            if error.key.is_empty() {
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        if let Some(rules) = &self.routing_rules {
+            if rules.rules.is_empty() || rules.rules.len() > MAX_ROUTING_RULES {
+                return Err(S3Error::MalformedXML);
+            }
+            for rule in rules.rules.iter() {
+                rule.validate()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rules(&self) -> &[RoutingRule] {
+        self.routing_rules.as_ref().map_or(&[], |r| &r.rules)
+    }
+}
+
+impl RoutingRule {
+    fn validate(&self) -> Result<(), S3Error> {
+        if let Some(cond) = &self.condition {
+            if cond.http_error_code_returned_equals.is_none() && cond.key_prefix_equals.is_none() {
+                return Err(S3Error::InvalidArgument);
+            }
+            if let Some(code) = &cond.http_error_code_returned_equals {
+                match code.parse::<u16>() {
+                    Ok(400..=599) => {}
+                    _ => return Err(S3Error::InvalidArgument),
+                }
+            }
+        }
+        let r = &self.redirect;
+        if r.replace_key_with.is_some() && r.replace_key_prefix_with.is_some() {
+            return Err(S3Error::InvalidArgument);
+        }
+        if let Some(code) = &r.http_redirect_code {
+            match code.parse::<u16>() {
+                Ok(300..=399) => {}
+                _ => return Err(S3Error::InvalidArgument),
+            }
+        }
+        validate_protocol(r.protocol.as_deref())
+    }
+
+    /// Whether the rule applies to the key before it is read (no error),
+    /// or after reading it failed with the error status.
+    pub fn matches(&self, key: &str, error: Option<u16>) -> bool {
+        let cond = match &self.condition {
+            None => return error.is_none(),
+            Some(cond) => cond,
+        };
+        let prefix_matches = cond
+            .key_prefix_equals
+            .as_ref()
+            .is_none_or(|p| key.starts_with(p.as_str()));
+        let error_matches = match (&cond.http_error_code_returned_equals, error) {
+            (None, None) => true,
+            (Some(code), Some(status)) => *code == status.to_string(),
+            _ => false,
+        };
+        prefix_matches && error_matches
+    }
+
+    /// The redirect response of the rule for the key it matched.
+    fn redirect(&self, host: &str, key: &str) -> Result<HttpResponse, S3Error> {
+        let r = &self.redirect;
+        let key = match (&r.replace_key_with, &r.replace_key_prefix_with) {
+            (Some(replace), _) => replace.to_owned(),
+            (None, Some(replace)) => {
+                let prefix = self
+                    .condition
+                    .as_ref()
+                    .and_then(|c| c.key_prefix_equals.as_deref())
+                    .unwrap_or("");
+                format!("{}{}", replace, &key[prefix.len()..])
+            }
+            (None, None) => key.to_string(),
+        };
+        let status = r
+            .http_redirect_code
+            .as_ref()
+            .and_then(|c| c.parse().ok())
+            .and_then(|c| StatusCode::from_u16(c).ok())
+            .unwrap_or(StatusCode::MOVED_PERMANENTLY);
+        let location = format!(
+            "{}://{}/{}",
+            r.protocol.as_deref().unwrap_or("http"),
+            r.host_name.as_deref().unwrap_or(host),
+            encode_url(&key)
+        );
+        redirect(status, &location)
+    }
+}
+
+fn validate_protocol(protocol: Option<&str>) -> Result<(), S3Error> {
+    match protocol {
+        None | Some("http") | Some("https") => Ok(()),
+        Some(_) => Err(S3Error::InvalidArgument),
+    }
+}
+
+/// Check an x-amz-website-redirect-location, which is a path in the bucket
+/// or an absolute http(s) URL.
+pub fn validate_redirect_location(location: &str) -> Result<(), S3Error> {
+    let valid = location.starts_with('/')
+        || location.starts_with("http://")
+        || location.starts_with("https://");
+    if !valid || location.len() > MAX_REDIRECT_LOCATION_LEN {
+        return Err(S3Error::InvalidRedirectLocation);
+    }
+    Ok(())
+}
+
+impl<API: ApiLayer> S3Server<API> {
+    /// Serve buckets as static websites by their website configuration,
+    /// this is not the S3 API and answers anonymous GET and HEAD requests only.
+    ///
+    /// The bucket is named by the Host header, either the whole host name
+    /// (e.g. `www.example.com`) or its first label (e.g. `site.localhost`).
+    /// Errors are HTML pages, or the error document of the bucket with the error status.
+    pub async fn website_handler(&self, req: HttpRequest) -> HttpResult {
+        let request_id = new_request_id();
+        let head_only = req.method() == Method::HEAD;
+        let mut res = match self.website(&req).await {
+            Ok(res) => res,
+            Err(err) => website_error(err, &request_id, head_only),
+        };
+        if let Ok(v) = HeaderValue::from_str(&request_id) {
+            res.headers_mut().insert("x-amz-request-id", v);
+        }
+        tracing::debug!(
+            method = %req.method(),
+            uri = %req.uri(),
+            status = res.status().as_u16(),
+            "website request completed"
+        );
+        Ok(res)
+    }
+
+    async fn website(&self, req: &HttpRequest) -> Result<HttpResponse, S3Error> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return Err(S3Error::MethodNotAllowed);
+        }
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(S3Error::InvalidRequest)?;
+        let host = host.rsplit_once(':').map_or(host, |(h, _)| h);
+        let (bucket, config) = self.website_config(host).await?;
+        let path = req.uri().path();
+        if let Some(to) = &config.redirect_all_requests_to {
+            let protocol = to.protocol.as_deref().unwrap_or("http");
+            let location = format!("{}://{}{}", protocol, to.host_name, path);
+            return redirect(StatusCode::MOVED_PERMANENTLY, &location);
+        }
+
+        let key = decode_path(path.strip_prefix('/').ok_or(S3Error::InvalidRequest)?)?;
+        if let Some(rule) = config.rules().iter().find(|r| r.matches(&key, None)) {
+            return rule.redirect(host, &key);
+        }
+        let suffix = config
+            .index_document
+            .as_ref()
+            .map_or("index.html", |i| i.suffix.as_str());
+        let err = match key.is_empty() || key.ends_with('/') {
+            true => {
+                self.website_object(req, &bucket, &(key.clone() + suffix))
+                    .await
+            }
+            false => self.website_object(req, &bucket, &key).await,
+        };
+        let err = match err {
+            Ok(res) => return Ok(res),
+            Err(err) => err,
+        };
+
+        // a directory without the trailing slash redirects to it, like S3
+        if err == S3Error::NoSuchKey && !key.is_empty() && !key.ends_with('/') {
+            let index = format!("{}/{}", key, suffix);
+            if self.website_object(req, &bucket, &index).await.is_ok() {
+                return redirect(StatusCode::FOUND, &format!("/{}/", encode_url(&key)));
+            }
+        }
+        let status = err.info().status_code;
+        if let Some(rule) = config
+            .rules()
+            .iter()
+            .find(|r| r.matches(&key, Some(status.as_u16())))
+        {
+            return rule.redirect(host, &key);
+        }
+        match &config.error_document {
+            Some(doc) if status.is_client_error() => {
+                match self.website_object(req, &bucket, &doc.key).await {
+                    Ok(mut res) if res.status() == StatusCode::OK => {
+                        *res.status_mut() = status;
+                        Ok(res)
+                    }
+                    _ => Err(err),
+                }
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// The bucket named by the host and its website configuration.
+    async fn website_config(&self, host: &str) -> Result<(String, WebsiteConfiguration), S3Error> {
+        let mut names = vec![host];
+        if let Some((label, _)) = host.split_once('.') {
+            names.push(label);
+        }
+        for bucket in names {
+            if validate_bucket_name(bucket).is_err() {
+                continue;
+            }
+            let req = get_bucket_website::Req::new(get_bucket_website::Params {
+                bucket: bucket.to_string(),
+            });
+            match self.api().get_bucket_website(req).await {
+                Ok(res) => return Ok((bucket.to_string(), res.into_body().config)),
+                Err(S3Error::NoSuchBucket) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(S3Error::NoSuchBucket)
+    }
+
+    /// Read the object as the anonymous user,
+    /// objects with a website redirect location redirect to it.
+    async fn website_object(
+        &self,
+        req: &HttpRequest,
+        bucket: &str,
+        key: &str,
+    ) -> Result<HttpResponse, S3Error> {
+        let principal = Principal::Anonymous;
+        self.authorize(
+            req,
+            &principal,
+            Op::GetObject.action(),
+            bucket,
+            key,
+            &QueryStr::default(),
+        )
+        .await?;
+        let mut get_req = get_object::Req::new(get_object::Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            version_id: String::new(),
+            head_only: req.method() == Method::HEAD,
+            range: ObjectRange::parse(None),
+            sse_customer: None,
+        });
+        get_req.extensions_mut().insert(principal);
+        let res = self.api().get_object(get_req).await?;
+        let location = res.body().object.website_redirect_location.to_owned();
+        if !location.is_empty() {
+            return redirect(StatusCode::MOVED_PERMANENTLY, &location);
+        }
+        let mut res = res.write()?;
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type(key)));
+        Ok(res)
+    }
+}
+
+fn redirect(status: StatusCode, location: &str) -> Result<HttpResponse, S3Error> {
+    let mut res = HttpResponse::new(Body::empty());
+    *res.status_mut() = status;
+    let location = HeaderValue::from_str(location).map_err(|_| S3Error::InvalidRedirectLocation)?;
+    res.headers_mut().insert(LOCATION, location);
+    Ok(res)
+}
+
+/// Website errors are HTML pages like the S3 website endpoints, not XML documents.
+fn website_error(err: S3Error, request_id: &str, head_only: bool) -> HttpResponse {
+    let info = err.info();
+    let title = format!(
+        "{} {}",
+        info.status_code.as_u16(),
+        info.status_code.canonical_reason().unwrap_or("")
+    );
+    let body = match head_only {
+        true => Body::empty(),
+        false => Body::from(format!(
+            "<html>\n<head><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<ul>\n\
+             <li>Code: {}</li>\n<li>Message: {}</li>\n<li>RequestId: {}</li>\n</ul>\n\
+             <hr/>\n</body>\n</html>\n",
+            info.code,
+            escape_xml(&info.msg),
+            request_id,
+            title = title,
+        )),
+    };
+    let mut res = HttpResponse::new(body);
+    *res.status_mut() = info.status_code;
+    res.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    res
+}
+
+/// The content type of website documents by the key extension,
+/// since objects don't keep the content type they were uploaded with.
+fn content_type(key: &str) -> &'static str {
+    let ext = key.rsplit_once('.').map_or("", |(_, ext)| ext);
+    match ext.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AclHeaders;
+    use crate::layers::MemLayer;
+
+    fn alice() -> Principal {
+        Principal::User(UserInfo {
+            id: String::from("alice"),
+            display_name: String::from("alice"),
+        })
+    }
+
+    fn index(suffix: &str) -> Option<IndexDocument> {
+        Some(IndexDocument {
+            suffix: suffix.to_string(),
+        })
+    }
+
+    fn rule(prefix: Option<&str>, error: Option<&str>, redirect: RoutingRedirect) -> RoutingRule {
+        RoutingRule {
+            condition: match (prefix, error) {
+                (None, None) => None,
+                _ => Some(RoutingCondition {
+                    key_prefix_equals: prefix.map(str::to_string),
+                    http_error_code_returned_equals: error.map(str::to_string),
+                }),
+            },
+            redirect,
+        }
+    }
+
+    #[test]
+    fn configurations_are_validated() {
+        let config = |f: fn(&mut WebsiteConfiguration)| {
+            let mut config = WebsiteConfiguration {
+                index_document: index("index.html"),
+                ..WebsiteConfiguration::default()
+            };
+            f(&mut config);
+            config.validate()
+        };
+        assert!(config(|_| {}).is_ok());
+        assert!(config(|c| c.index_document = None).is_err());
+        assert!(config(|c| c.index_document = index("a/index.html")).is_err());
+        assert!(config(|c| {
+            c.redirect_all_requests_to = Some(RedirectAllRequestsTo {
+                host_name: String::from("example.com"),
+                protocol: None,
+            })
+        })
+        .is_err());
+        assert!(config(|c| {
+            c.routing_rules = Some(RoutingRules {
+                rules: vec![rule(None, Some("200"), RoutingRedirect::default())],
+            })
+        })
+        .is_err());
+        assert!(config(|c| {
+            let redirect = RoutingRedirect {
+                replace_key_with: Some(String::from("a")),
+                replace_key_prefix_with: Some(String::from("b")),
+                ..RoutingRedirect::default()
+            };
+            c.routing_rules = Some(RoutingRules {
+                rules: vec![rule(Some("x/"), None, redirect)],
+            })
+        })
+        .is_err());
+        assert!(config(|c| c.routing_rules = Some(RoutingRules::default())).is_err());
+        let redirect_all = WebsiteConfiguration {
+            redirect_all_requests_to: Some(RedirectAllRequestsTo {
+                host_name: String::from("example.com"),
+                protocol: Some(String::from("ftp")),
+            }),
+            ..WebsiteConfiguration::default()
+        };
+        assert!(redirect_all.validate().is_err());
+        assert!(validate_redirect_location("/a").is_ok());
+        assert!(validate_redirect_location("https://example.com/a").is_ok());
+        assert!(validate_redirect_location("a").is_err());
+    }
+
+    #[test]
+    fn routing_rules_match_before_and_after_reads() {
+        let by_prefix = rule(Some("docs/"), None, RoutingRedirect::default());
+        assert!(by_prefix.matches("docs/a", None));
+        assert!(!by_prefix.matches("img/a", None));
+        assert!(!by_prefix.matches("docs/a", Some(404)));
+        let by_error = rule(None, Some("404"), RoutingRedirect::default());
+        assert!(!by_error.matches("a", None));
+        assert!(by_error.matches("a", Some(404)));
+        assert!(!by_error.matches("a", Some(403)));
+        let always = rule(None, None, RoutingRedirect::default());
+        assert!(always.matches("a", None));
+        assert!(!always.matches("a", Some(404)));
+    }
+
+    fn object(key: &str, data: &'static str, location: &str) -> put_object::Req {
+        let mut req = put_object::Req::new(put_object::Params {
+            bucket: String::from("site"),
+            key: key.to_string(),
+            acl: AclHeaders::default(),
+            tagging: Tagging::default(),
+            website_redirect_location: location.to_string(),
+            sse_customer: None,
+            sse: None,
+            object_lock: ObjectLock::default(),
+            plaintext_size: None,
+            body: Some(Body::from(data)),
+        });
+        req.extensions_mut().insert(alice());
+        req
+    }
+
+    async fn site() -> S3Server<MemLayer> {
+        let srv = S3Server::with_layer(MemLayer::new());
+        let mut req = put_bucket::Req::new(put_bucket::Params {
+            bucket: String::from("site"),
+            class: String::new(),
+            region: String::new(),
+            acl: AclHeaders::default(),
+            object_lock_enabled: false,
+            body: None,
+        });
+        req.extensions_mut().insert(alice());
+        srv.api().put_bucket(req).await.unwrap();
+        for (key, data, location) in [
+            ("index.html", "home", ""),
+            ("docs/index.html", "docs", ""),
+            ("404.html", "not found", ""),
+            ("moved.html", "moved", ""),
+            ("old-page", "", "/new-page"),
+        ] {
+            let req = object(key, data, location);
+            srv.api().put_object(req).await.unwrap();
+        }
+        // anyone may read the objects, missing keys answer 404 instead of 403
+        let policy = r#"{"Statement":[{"Effect":"Allow","Principal":"*",
+            "Action":"s3:GetObject","Resource":"arn:aws:s3:::site/*"}]}"#;
+        let mut req = put_bucket_policy::Req::new(put_bucket_policy::Params {
+            bucket: String::from("site"),
+            body: Some(Body::from(policy)),
+        });
+        req.extensions_mut().insert(alice());
+        srv.api().put_bucket_policy(req).await.unwrap();
+        let config = WebsiteConfiguration {
+            index_document: index("index.html"),
+            error_document: Some(ErrorDocument {
+                key: String::from("404.html"),
+            }),
+            routing_rules: Some(RoutingRules {
+                rules: vec![
+                    rule(
+                        Some("blog/"),
+                        None,
+                        RoutingRedirect {
+                            host_name: Some(String::from("example.com")),
+                            protocol: Some(String::from("https")),
+                            replace_key_prefix_with: Some(String::from("posts/")),
+                            ..RoutingRedirect::default()
+                        },
+                    ),
+                    rule(
+                        Some("old/"),
+                        Some("404"),
+                        RoutingRedirect {
+                            replace_key_with: Some(String::from("moved.html")),
+                            http_redirect_code: Some(String::from("302")),
+                            ..RoutingRedirect::default()
+                        },
+                    ),
+                ],
+            }),
+            ..WebsiteConfiguration::default()
+        };
+        let req = put_bucket_website::Req::new(put_bucket_website::Params {
+            bucket: String::from("site"),
+            body: Some(Body::from(config.to_xml().unwrap())),
+        });
+        srv.api().put_bucket_website(req).await.unwrap();
+        srv
+    }
+
+    async fn get(srv: &S3Server<MemLayer>, method: Method, host: &str, path: &str) -> HttpResponse {
+        let req = hyper::Request::builder()
+            .method(method)
+            .uri(path)
+            .header(HOST, host)
+            .body(Body::empty())
+            .unwrap();
+        srv.website_handler(req).await.unwrap()
+    }
+
+    async fn body(res: HttpResponse) -> String {
+        let buf = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(buf.to_vec()).unwrap()
+    }
+
+    fn location(res: &HttpResponse) -> &str {
+        res.headers().get(LOCATION).unwrap().to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_are_routed() {
+        let srv = site().await;
+        let host = "site.localhost:8080";
+        let res = get(&srv, Method::GET, host, "/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(body(res).await, "home");
+        let res = get(&srv, Method::GET, host, "/docs/").await;
+        assert_eq!(body(res).await, "docs");
+        // directories redirect to their index with the slash
+        let res = get(&srv, Method::GET, host, "/docs").await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(location(&res), "/docs/");
+        // missing keys serve the error document with the error status
+        let res = get(&srv, Method::GET, host, "/missing.html").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(res).await, "not found");
+        let res = get(&srv, Method::HEAD, host, "/missing.html").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(res).await, "");
+
+        let res = get(&srv, Method::GET, host, "/blog/2024/a.html").await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(location(&res), "https://example.com/posts/2024/a.html");
+        let res = get(&srv, Method::GET, host, "/old/a.html").await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(location(&res), "http://site.localhost/moved.html");
+        let res = get(&srv, Method::GET, host, "/old-page").await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(location(&res), "/new-page");
+
+        let res = get(&srv, Method::PUT, host, "/").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let res = get(&srv, Method::GET, "other.localhost", "/").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(body(res).await.contains("NoSuchBucket"));
+    }
+
+    #[tokio::test]
+    async fn all_requests_redirect_to_a_host() {
+        let srv = site().await;
+        let config = WebsiteConfiguration {
+            redirect_all_requests_to: Some(RedirectAllRequestsTo {
+                host_name: String::from("www.example.com"),
+                protocol: Some(String::from("https")),
+            }),
+            ..WebsiteConfiguration::default()
+        };
+        let req = put_bucket_website::Req::new(put_bucket_website::Params {
+            bucket: String::from("site"),
+            body: Some(Body::from(config.to_xml().unwrap())),
+        });
+        srv.api().put_bucket_website(req).await.unwrap();
+        let res = get(&srv, Method::GET, "site.localhost", "/a/b.html").await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(location(&res), "https://www.example.com/a/b.html");
+    }
+}
@@ -0,0 +1,80 @@
+use crate::api::*;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Cow;
+
+/// The namespace of S3 request and response documents.
+pub const S3_XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+pub const XML_DECL: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+
+/// Request bodies larger than this are rejected before parsing,
+/// S3 documents like Delete or CompleteMultipartUpload stay well below it.
+pub const MAX_XML_BODY: usize = 2 << 20;
+
+/// Characters kept as is by encoding-type=url, like S3 which leaves
+/// the unreserved characters and the `/` separator readable.
+const URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Serialize the value as an S3 document with the given root element,
+/// the declaration and the S3 namespace.
+pub fn to_xml<T: Serialize>(root: &str, value: &T) -> Result<String, S3Error> {
+    let doc = quick_xml::se::to_string_with_root(root, value).map_err(|err| {
+        tracing::warn!(root, %err, "xml serialize failed");
+        S3Error::InternalError
+    })?;
+    // the serializer has no attribute for the namespace of the root,
+    // so it goes right after the root name (`<Root>` or `<Root/>`)
+    let rest = &doc[root.len() + 1..];
+    Ok(format!(
+        "{}<{} xmlns=\"{}\"{}",
+        XML_DECL, root, S3_XMLNS, rest
+    ))
+}
+
+/// Deserialize an S3 document from a request body, the root element name
+/// and unknown elements are ignored. Anything that does not parse is MalformedXML.
+pub fn from_xml<T: DeserializeOwned>(body: &[u8]) -> Result<T, S3Error> {
+    if body.is_empty() || body.len() > MAX_XML_BODY {
+        return Err(S3Error::MalformedXML);
+    }
+    let text = std::str::from_utf8(body).map_err(|_| S3Error::MalformedXML)?;
+    quick_xml::de::from_str(text).map_err(|err| {
+        tracing::debug!(%err, "xml deserialize failed");
+        S3Error::MalformedXML
+    })
+}
+
+/// Escape text for element content and attribute values.
+pub fn escape_xml(s: &str) -> Cow<'_, str> {
+    quick_xml::escape::escape(s)
+}
+
+/// Encode a key or prefix for listings requested with encoding-type=url,
+/// which lets clients list keys with characters that XML 1.0 cannot carry.
+pub fn encode_url(s: &str) -> String {
+    utf8_percent_encode(s, URL_ENCODE_SET).to_string()
+}
+
+/// Owner element of listings and ACLs.
+#[derive(Debug, Serialize)]
+pub struct Owner<'a> {
+    #[serde(rename = "ID")]
+    pub id: &'a str,
+    #[serde(rename = "DisplayName")]
+    pub display_name: &'a str,
+}
+
+impl<'a> From<&'a UserInfo> for Owner<'a> {
+    fn from(user: &'a UserInfo) -> Self {
+        Owner {
+            id: &user.id,
+            display_name: &user.display_name,
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use crate::api::*;
+use crate::auth::*;
+use hyper::HeaderMap;
+use quick_xml::{events::Event, Reader};
+
+pub const ALL_USERS: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+pub const AUTHENTICATED_USERS: &str = "http://acs.amazonaws.com/groups/global/AuthenticatedUsers";
+pub const LOG_DELIVERY: &str = "http://acs.amazonaws.com/groups/s3/LogDelivery";
+
+const MAX_GRANTS: usize = 100;
+
+/// Acl is the access control list of a bucket or an object.
+/// The owner always keeps READ_ACP and WRITE_ACP regardless of the grants.
+#[derive(Debug, Clone)]
+pub struct Acl {
+    pub owner: UserInfo,
+    pub grants: Vec<Grant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub grantee: Grantee,
+    pub permission: Permission,
+}
+
+#[derive(Debug, Clone)]
+pub enum Grantee {
+    CanonicalUser(UserInfo),
+    Group(String),
+    Email(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    FullControl,
+    Read,
+    Write,
+    ReadAcp,
+    WriteAcp,
+}
+
+/// AclHeaders holds the `x-amz-acl` and `x-amz-grant-*` request headers.
+/// They are resolved to an Acl by the layer once the owner is known.
+#[derive(Debug, Clone, Default)]
+pub struct AclHeaders {
+    pub canned: String,
+    pub grants: Vec<(Permission, String)>,
+}
+
+/// AclCheck is the ACL permission an action requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclCheck {
+    /// service level actions (list buckets, create bucket) are not covered by ACLs
+    Unchecked,
+    Bucket(Permission),
+    Object(Permission),
+    BucketOwner,
+}
+
+impl Permission {
+    pub fn parse(s: &str) -> Result<Permission, S3Error> {
+        match s {
+            "FULL_CONTROL" => Ok(Permission::FullControl),
+            "READ" => Ok(Permission::Read),
+            "WRITE" => Ok(Permission::Write),
+            "READ_ACP" => Ok(Permission::ReadAcp),
+            "WRITE_ACP" => Ok(Permission::WriteAcp),
+            _ => Err(S3Error::MalformedACLError),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::FullControl => "FULL_CONTROL",
+            Permission::Read => "READ",
+            Permission::Write => "WRITE",
+            Permission::ReadAcp => "READ_ACP",
+            Permission::WriteAcp => "WRITE_ACP",
+        }
+    }
+}
+
+impl Grantee {
+    fn matches(&self, user: Option<&UserInfo>) -> bool {
+        match (self, user) {
+            (Grantee::Group(uri), _) if uri == ALL_USERS => true,
+            (Grantee::Group(uri), Some(u)) if uri == AUTHENTICATED_USERS => u.id != ANONYMOUS_ID,
+            (Grantee::CanonicalUser(g), Some(u)) => g.id == u.id,
+            _ => false,
+        }
+    }
+}
+
+impl Acl {
+    pub fn private(owner: &UserInfo) -> Acl {
+        Acl {
+            owner: owner.to_owned(),
+            grants: vec![Grant {
+                grantee: Grantee::CanonicalUser(owner.to_owned()),
+                permission: Permission::FullControl,
+            }],
+        }
+    }
+
+    /// Build a canned ACL, see https://docs.aws.amazon.com/AmazonS3/latest/userguide/acl-overview.html#canned-acl
+    /// `bucket_owner` is only used by the bucket-owner-* ACLs on objects.
+    pub fn canned(name: &str, owner: &UserInfo, bucket_owner: &UserInfo) -> Result<Acl, S3Error> {
+        let mut acl = Acl::private(owner);
+        let mut grant = |grantee: Grantee, permission: Permission| {
+            acl.grants.push(Grant {
+                grantee,
+                permission,
+            })
+        };
+        match name {
+            "" | "private" => {}
+            "public-read" => grant(Grantee::Group(ALL_USERS.to_string()), Permission::Read),
+            "public-read-write" => {
+                grant(Grantee::Group(ALL_USERS.to_string()), Permission::Read);
+                grant(Grantee::Group(ALL_USERS.to_string()), Permission::Write);
+            }
+            "authenticated-read" => grant(
+                Grantee::Group(AUTHENTICATED_USERS.to_string()),
+                Permission::Read,
+            ),
+            "aws-exec-read" => {}
+            "bucket-owner-read" => grant(
+                Grantee::CanonicalUser(bucket_owner.to_owned()),
+                Permission::Read,
+            ),
+            "bucket-owner-full-control" => grant(
+                Grantee::CanonicalUser(bucket_owner.to_owned()),
+                Permission::FullControl,
+            ),
+            "log-delivery-write" => {
+                grant(Grantee::Group(LOG_DELIVERY.to_string()), Permission::Write);
+                grant(
+                    Grantee::Group(LOG_DELIVERY.to_string()),
+                    Permission::ReadAcp,
+                );
+            }
+            _ => return Err(S3Error::InvalidArgument),
+        }
+        Ok(acl)
+    }
+
+    /// Check if the user (None for anonymous) has the permission.
+    pub fn allows(&self, user: Option<&UserInfo>, permission: Permission) -> bool {
+        if let Some(u) = user {
+            if u.id == self.owner.id
+                && (permission == Permission::ReadAcp || permission == Permission::WriteAcp)
+            {
+                return true;
+            }
+        }
+        self.grants.iter().any(|g| {
+            (g.permission == permission || g.permission == Permission::FullControl)
+                && g.grantee.matches(user)
+        })
+    }
+
+    pub fn is_owner(&self, user: Option<&UserInfo>) -> bool {
+        user.is_some_and(|u| u.id == self.owner.id)
+    }
+
+    /// Parse an AccessControlPolicy document. The owner is not taken from the
+    /// document since ownership cannot be changed by updating the ACL.
+    pub fn parse_xml(text: &str, owner: &UserInfo) -> Result<Acl, S3Error> {
+        let mut reader = Reader::from_str(text);
+        reader.config_mut().trim_text(true);
+        let mut path = Vec::<String>::new();
+        let mut grants = Vec::<Grant>::new();
+        let mut grantee_type = String::new();
+        let mut id = String::new();
+        let mut display_name = String::new();
+        let mut uri = String::new();
+        let mut email = String::new();
+        let mut permission = String::new();
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) => {
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                    if name == "Grantee" {
+                        for attr in e.attributes().flatten() {
+                            if attr.key.local_name().as_ref() == b"type" {
+                                grantee_type = attr
+                                    .unescape_value()
+                                    .map_err(|_| S3Error::MalformedACLError)?
+                                    .to_string();
+                            }
+                        }
+                    }
+                    path.push(name);
+                }
+                Ok(Event::Text(t)) => {
+                    let text = t.unescape().map_err(|_| S3Error::MalformedACLError)?;
+                    let in_grantee = path.iter().any(|p| p == "Grantee");
+                    match path.last().map(|s| s.as_str()) {
+                        Some("ID") if in_grantee => id = text.to_string(),
+                        Some("DisplayName") if in_grantee => display_name = text.to_string(),
+                        Some("URI") if in_grantee => uri = text.to_string(),
+                        Some("EmailAddress") if in_grantee => email = text.to_string(),
+                        Some("Permission") => permission = text.to_string(),
+                        _ => {}
+                    }
+                }
+                Ok(Event::End(_)) => {
+                    if path.pop().as_deref() == Some("Grant") {
+                        let grantee = match grantee_type.as_str() {
+                            "CanonicalUser" if !id.is_empty() => Grantee::CanonicalUser(UserInfo {
+                                id: id.to_owned(),
+                                display_name: display_name.to_owned(),
+                            }),
+                            "Group" if !uri.is_empty() => Grantee::Group(uri.to_owned()),
+                            "AmazonCustomerByEmail" if !email.is_empty() => {
+                                Grantee::Email(email.to_owned())
+                            }
+                            _ => return Err(S3Error::MalformedACLError),
+                        };
+                        grants.push(Grant {
+                            grantee,
+                            permission: Permission::parse(permission.as_str())?,
+                        });
+                        grantee_type.clear();
+                        id.clear();
+                        display_name.clear();
+                        uri.clear();
+                        email.clear();
+                        permission.clear();
+                    }
+                }
+                Ok(Event::Eof) => break,
+                Ok(_) => {}
+                Err(_) => return Err(S3Error::MalformedACLError),
+            }
+        }
+        if grants.len() > MAX_GRANTS {
+            return Err(S3Error::MalformedACLError);
+        }
+        Ok(Acl {
+            owner: owner.to_owned(),
+            grants,
+        })
+    }
+
+    pub fn write_xml(&self, w: &mut BodyWriter) {
+        w.append("<AccessControlPolicy>");
+        w.append("<Owner>");
+        w.append_xml("ID", self.owner.id.as_str());
+        w.append_xml("DisplayName", self.owner.display_name.as_str());
+        w.append("</Owner>");
+        w.append("<AccessControlList>");
+        for g in self.grants.iter() {
+            w.append("<Grant>");
+            match &g.grantee {
+                Grantee::CanonicalUser(u) => {
+                    w.append("<Grantee xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:type=\"CanonicalUser\">");
+                    w.append_xml("ID", u.id.as_str());
+                    w.append_xml("DisplayName", u.display_name.as_str());
+                }
+                Grantee::Group(uri) => {
+                    w.append("<Grantee xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:type=\"Group\">");
+                    w.append_xml("URI", uri.as_str());
+                }
+                Grantee::Email(email) => {
+                    w.append("<Grantee xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:type=\"AmazonCustomerByEmail\">");
+                    w.append_xml("EmailAddress", email.as_str());
+                }
+            }
+            w.append("</Grantee>");
+            w.append_xml("Permission", g.permission.as_str());
+            w.append("</Grant>");
+        }
+        w.append("</AccessControlList>");
+        w.append("</AccessControlPolicy>");
+    }
+}
+
+impl AclHeaders {
+    /// Header values that are not visible ASCII fail with InvalidArgument.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, S3Error> {
+        let get = |name: &str| match headers.get(name) {
+            None => Ok(String::new()),
+            Some(v) => v
+                .to_str()
+                .map(|v| v.to_string())
+                .map_err(|_| S3Error::InvalidArgument),
+        };
+        let mut grants = Vec::new();
+        for (name, permission) in [
+            ("x-amz-grant-full-control", Permission::FullControl),
+            ("x-amz-grant-read", Permission::Read),
+            ("x-amz-grant-write", Permission::Write),
+            ("x-amz-grant-read-acp", Permission::ReadAcp),
+            ("x-amz-grant-write-acp", Permission::WriteAcp),
+        ] {
+            let v = get(name)?;
+            if !v.is_empty() {
+                grants.push((permission, v));
+            }
+        }
+        Ok(AclHeaders {
+            canned: get("x-amz-acl")?,
+            grants,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.canned.is_empty() && self.grants.is_empty()
+    }
+
+    /// Resolve to an Acl - canned and explicit grants cannot be combined.
+    /// Grant headers are lists like `id="111", uri="http://...", emailAddress="a@b.c"`.
+    pub fn to_acl(&self, owner: &UserInfo, bucket_owner: &UserInfo) -> Result<Acl, S3Error> {
+        if self.grants.is_empty() {
+            return Acl::canned(self.canned.as_str(), owner, bucket_owner);
+        }
+        if !self.canned.is_empty() {
+            return Err(S3Error::InvalidArgument);
+        }
+        let mut acl = Acl {
+            owner: owner.to_owned(),
+            grants: Vec::new(),
+        };
+        for (permission, list) in self.grants.iter() {
+            for item in list.split(',') {
+                let (kind, value) = item
+                    .trim()
+                    .split_once('=')
+                    .ok_or(S3Error::InvalidArgument)?;
+                let value = value.trim().trim_matches('"').to_string();
+                let grantee = match kind.trim() {
+                    "id" => Grantee::CanonicalUser(UserInfo {
+                        id: value.to_owned(),
+                        display_name: value,
+                    }),
+                    "uri" => Grantee::Group(value),
+                    "emailAddress" => Grantee::Email(value),
+                    _ => return Err(S3Error::InvalidArgument),
+                };
+                acl.grants.push(Grant {
+                    grantee,
+                    permission: *permission,
+                });
+            }
+        }
+        if acl.grants.len() > MAX_GRANTS {
+            return Err(S3Error::InvalidArgument);
+        }
+        Ok(acl)
+    }
+}
+
+impl AclCheck {
+    pub fn for_action(action: &str) -> AclCheck {
+        match action {
+            "s3:ListBucket" => AclCheck::Bucket(Permission::Read),
+            "s3:PutObject" | "s3:DeleteObject" => AclCheck::Bucket(Permission::Write),
+            "s3:GetBucketAcl" => AclCheck::Bucket(Permission::ReadAcp),
+            "s3:PutBucketAcl" => AclCheck::Bucket(Permission::WriteAcp),
+            "s3:GetObject" => AclCheck::Object(Permission::Read),
+            "s3:GetObjectAcl" => AclCheck::Object(Permission::ReadAcp),
+            "s3:PutObjectAcl" => AclCheck::Object(Permission::WriteAcp),
+            "s3:ListAllMyBuckets" | "s3:CreateBucket" => AclCheck::Unchecked,
+            _ => AclCheck::BucketOwner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str) -> UserInfo {
+        UserInfo {
+            id: id.to_string(),
+            display_name: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn action_checks() {
+        for (action, check) in [
+            ("s3:ListBucket", AclCheck::Bucket(Permission::Read)),
+            ("s3:PutObject", AclCheck::Bucket(Permission::Write)),
+            ("s3:DeleteObject", AclCheck::Bucket(Permission::Write)),
+            ("s3:GetBucketAcl", AclCheck::Bucket(Permission::ReadAcp)),
+            ("s3:PutBucketAcl", AclCheck::Bucket(Permission::WriteAcp)),
+            ("s3:GetObject", AclCheck::Object(Permission::Read)),
+            ("s3:GetObjectAcl", AclCheck::Object(Permission::ReadAcp)),
+            ("s3:PutObjectAcl", AclCheck::Object(Permission::WriteAcp)),
+            ("s3:ListAllMyBuckets", AclCheck::Unchecked),
+            ("s3:CreateBucket", AclCheck::Unchecked),
+            ("s3:DeleteBucket", AclCheck::BucketOwner),
+            ("s3:PutBucketPolicy", AclCheck::BucketOwner),
+        ] {
+            assert_eq!(AclCheck::for_action(action), check, "{}", action);
+        }
+    }
+
+    #[test]
+    fn canned() {
+        let (alice, bob) = (user("alice"), user("bob"));
+        let acl = Acl::canned("private", &alice, &alice).unwrap();
+        assert!(acl.allows(Some(&alice), Permission::Write));
+        assert!(!acl.allows(Some(&bob), Permission::Read));
+        assert!(!acl.allows(None, Permission::Read));
+
+        let acl = Acl::canned("public-read", &alice, &alice).unwrap();
+        assert!(acl.allows(None, Permission::Read));
+        assert!(acl.allows(Some(&bob), Permission::Read));
+        assert!(!acl.allows(Some(&bob), Permission::Write));
+
+        let acl = Acl::canned("authenticated-read", &alice, &alice).unwrap();
+        assert!(acl.allows(Some(&bob), Permission::Read));
+        assert!(!acl.allows(None, Permission::Read));
+        assert!(!acl.allows(Some(&user(ANONYMOUS_ID)), Permission::Read));
+
+        let acl = Acl::canned("bucket-owner-full-control", &bob, &alice).unwrap();
+        assert!(acl.allows(Some(&alice), Permission::WriteAcp));
+        assert!(acl.is_owner(Some(&bob)));
+        assert!(!acl.is_owner(Some(&alice)));
+
+        assert_eq!(
+            Acl::canned("bogus", &alice, &alice).unwrap_err(),
+            S3Error::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn owner_keeps_acp() {
+        let alice = user("alice");
+        let acl = Acl {
+            owner: alice.to_owned(),
+            grants: Vec::new(),
+        };
+        assert!(acl.allows(Some(&alice), Permission::ReadAcp));
+        assert!(acl.allows(Some(&alice), Permission::WriteAcp));
+        assert!(!acl.allows(Some(&alice), Permission::Read));
+    }
+
+    #[test]
+    fn grant_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-amz-grant-read",
+            "id=\"bob\", uri=\"http://acs.amazonaws.com/groups/global/AuthenticatedUsers\""
+                .parse()
+                .unwrap(),
+        );
+        headers.insert("x-amz-grant-write", "id=carol".parse().unwrap());
+        let h = AclHeaders::from_headers(&headers).unwrap();
+        let alice = user("alice");
+        let acl = h.to_acl(&alice, &alice).unwrap();
+        assert_eq!(acl.grants.len(), 3);
+        assert!(acl.allows(Some(&user("bob")), Permission::Read));
+        assert!(acl.allows(Some(&user("dave")), Permission::Read));
+        assert!(acl.allows(Some(&user("carol")), Permission::Write));
+        assert!(!acl.allows(Some(&user("bob")), Permission::Write));
+        // grants replace the owner's default FULL_CONTROL
+        assert!(!acl.allows(Some(&alice), Permission::Write));
+
+        headers.insert("x-amz-acl", "public-read".parse().unwrap());
+        let h = AclHeaders::from_headers(&headers).unwrap();
+        assert_eq!(
+            h.to_acl(&alice, &alice).unwrap_err(),
+            S3Error::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn xml_round_trip() {
+        let alice = user("alice");
+        let mut acl = Acl::canned("public-read", &alice, &alice).unwrap();
+        acl.grants.push(Grant {
+            grantee: Grantee::Email("x@example.com".to_string()),
+            permission: Permission::ReadAcp,
+        });
+        let mut w = BodyWriter::new_xml();
+        acl.write_xml(&mut w);
+        let text = w._str();
+        let parsed = Acl::parse_xml(&text, &user("other")).unwrap();
+        // ownership is not taken from the document
+        assert_eq!(parsed.owner.id, "other");
+        assert_eq!(parsed.grants.len(), 3);
+        assert!(parsed.allows(None, Permission::Read));
+        assert!(parsed.allows(Some(&alice), Permission::Write));
+
+        let bad = "<AccessControlPolicy><AccessControlList><Grant><Grantee xsi:type=\"Group\"></Grantee><Permission>READ</Permission></Grant></AccessControlList></AccessControlPolicy>";
+        assert_eq!(
+            Acl::parse_xml(bad, &alice).unwrap_err(),
+            S3Error::MalformedACLError
+        );
+    }
+}
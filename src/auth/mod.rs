@@ -0,0 +1,9 @@
+pub mod acl;
+pub mod policy;
+pub mod principal;
+pub mod signature;
+
+pub use self::acl::*;
+pub use self::policy::*;
+pub use self::principal::*;
+pub use self::signature::*;
@@ -0,0 +1,583 @@
+use crate::api::*;
+use crate::auth::*;
+use serde_json::Value;
+use std::{collections::HashMap, net::IpAddr};
+
+/// Policy is an IAM-style bucket policy document.
+/// See https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_policies_elements.html
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub version: String,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+/// Decision is the outcome of evaluating a policy for a request.
+/// NoMatch means no statement applied and the caller decides the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    NoMatch,
+}
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub sid: String,
+    pub effect: Effect,
+    pub principal: Option<Vec<String>>,
+    pub not_principal: Option<Vec<String>>,
+    pub action: Option<Vec<String>>,
+    pub not_action: Option<Vec<String>>,
+    pub resource: Option<Vec<String>>,
+    pub not_resource: Option<Vec<String>>,
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub operator: String,
+    pub key: String,
+    pub values: Vec<String>,
+}
+
+/// PolicyContext holds the request attributes a policy is evaluated against.
+/// Condition keys are stored lowercased since IAM compares them case-insensitively.
+#[derive(Debug, Clone)]
+pub struct PolicyContext {
+    pub principal: Principal,
+    pub action: String,
+    pub resource: String,
+    pub keys: HashMap<String, Vec<String>>,
+}
+
+const CONDITION_OPERATORS: &[&str] = &[
+    "StringEquals",
+    "StringNotEquals",
+    "StringEqualsIgnoreCase",
+    "StringNotEqualsIgnoreCase",
+    "StringLike",
+    "StringNotLike",
+    "NumericEquals",
+    "NumericNotEquals",
+    "NumericLessThan",
+    "NumericLessThanEquals",
+    "NumericGreaterThan",
+    "NumericGreaterThanEquals",
+    "Bool",
+    "IpAddress",
+    "NotIpAddress",
+    "ArnEquals",
+    "ArnNotEquals",
+    "ArnLike",
+    "ArnNotLike",
+    "Null",
+];
+
+impl Policy {
+    pub fn parse(text: &str) -> Result<Policy, S3Error> {
+        let doc: Value = serde_json::from_str(text).map_err(|_| S3Error::MalformedPolicy)?;
+        let doc = doc.as_object().ok_or(S3Error::MalformedPolicy)?;
+        let version = match doc.get("Version") {
+            Some(v) => v.as_str().ok_or(S3Error::MalformedPolicy)?.to_string(),
+            None => String::from("2008-10-17"),
+        };
+        let statements = match doc.get("Statement") {
+            Some(Value::Array(list)) => list
+                .iter()
+                .map(Statement::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(s @ Value::Object(_)) => vec![Statement::parse(s)?],
+            _ => return Err(S3Error::MalformedPolicy),
+        };
+        if statements.is_empty() {
+            return Err(S3Error::MalformedPolicy);
+        }
+        Ok(Policy {
+            version,
+            statements,
+        })
+    }
+
+    /// Evaluate with explicit deny precedence -
+    /// any matching Deny wins over any number of matching Allow statements.
+    pub fn evaluate(&self, ctx: &PolicyContext) -> Decision {
+        let mut decision = Decision::NoMatch;
+        for s in self.statements.iter() {
+            if !s.matches(ctx) {
+                continue;
+            }
+            match s.effect {
+                Effect::Deny => return Decision::Deny,
+                Effect::Allow => decision = Decision::Allow,
+            }
+        }
+        decision
+    }
+}
+
+impl Statement {
+    fn parse(v: &Value) -> Result<Statement, S3Error> {
+        let s = v.as_object().ok_or(S3Error::MalformedPolicy)?;
+        let effect = match s.get("Effect").and_then(|e| e.as_str()) {
+            Some("Allow") => Effect::Allow,
+            Some("Deny") => Effect::Deny,
+            _ => return Err(S3Error::MalformedPolicy),
+        };
+        let st = Statement {
+            sid: match s.get("Sid") {
+                Some(v) => v.as_str().ok_or(S3Error::MalformedPolicy)?.to_string(),
+                None => String::new(),
+            },
+            effect,
+            principal: s.get("Principal").map(parse_principal).transpose()?,
+            not_principal: s.get("NotPrincipal").map(parse_principal).transpose()?,
+            action: s.get("Action").map(parse_strings).transpose()?,
+            not_action: s.get("NotAction").map(parse_strings).transpose()?,
+            resource: s.get("Resource").map(parse_strings).transpose()?,
+            not_resource: s.get("NotResource").map(parse_strings).transpose()?,
+            conditions: match s.get("Condition") {
+                Some(c) => parse_conditions(c)?,
+                None => Vec::new(),
+            },
+        };
+        // bucket policies are resource based and must name the principal
+        if st.principal.is_some() == st.not_principal.is_some()
+            || st.action.is_some() == st.not_action.is_some()
+            || st.resource.is_some() == st.not_resource.is_some()
+        {
+            return Err(S3Error::MalformedPolicy);
+        }
+        Ok(st)
+    }
+
+    fn matches(&self, ctx: &PolicyContext) -> bool {
+        let principal_ok = match (&self.principal, &self.not_principal) {
+            (Some(p), _) => p.iter().any(|p| principal_match(p, &ctx.principal)),
+            (_, Some(p)) => !p.iter().any(|p| principal_match(p, &ctx.principal)),
+            _ => false,
+        };
+        let action_ok = match (&self.action, &self.not_action) {
+            (Some(a), _) => a.iter().any(|a| wildcard_match(a, &ctx.action, true)),
+            (_, Some(a)) => !a.iter().any(|a| wildcard_match(a, &ctx.action, true)),
+            _ => false,
+        };
+        let resource_ok = match (&self.resource, &self.not_resource) {
+            (Some(r), _) => r.iter().any(|r| wildcard_match(r, &ctx.resource, false)),
+            (_, Some(r)) => !r.iter().any(|r| wildcard_match(r, &ctx.resource, false)),
+            _ => false,
+        };
+        principal_ok && action_ok && resource_ok && self.conditions.iter().all(|c| c.matches(ctx))
+    }
+}
+
+impl Condition {
+    fn matches(&self, ctx: &PolicyContext) -> bool {
+        let (op, if_exists) = match self.operator.strip_suffix("IfExists") {
+            Some(op) => (op, true),
+            None => (self.operator.as_str(), false),
+        };
+        let found = ctx.keys.get(&self.key.to_lowercase());
+        if op == "Null" {
+            // Null:true means the key must be absent
+            let want_absent = self.values.iter().any(|v| v == "true");
+            return want_absent == found.is_none();
+        }
+        let negated = op.contains("Not");
+        let found = match found {
+            Some(f) => f,
+            None => return if_exists || negated,
+        };
+        let any = |f: &dyn Fn(&str, &str) -> bool| {
+            self.values
+                .iter()
+                .any(|want| found.iter().any(|have| f(want, have)))
+        };
+        match op {
+            "StringEquals" | "ArnEquals" => any(&|w, h| w == h),
+            "StringNotEquals" | "ArnNotEquals" => !any(&|w, h| w == h),
+            "StringEqualsIgnoreCase" => any(&|w, h| w.eq_ignore_ascii_case(h)),
+            "StringNotEqualsIgnoreCase" => !any(&|w, h| w.eq_ignore_ascii_case(h)),
+            "StringLike" | "ArnLike" => any(&|w, h| wildcard_match(w, h, false)),
+            "StringNotLike" | "ArnNotLike" => !any(&|w, h| wildcard_match(w, h, false)),
+            "Bool" => any(&|w, h| w.eq_ignore_ascii_case(h)),
+            "IpAddress" => any(&ip_match),
+            "NotIpAddress" => !any(&ip_match),
+            "NumericEquals" => any(&|w, h| numeric(w, h, |a, b| a == b)),
+            "NumericNotEquals" => !any(&|w, h| numeric(w, h, |a, b| a == b)),
+            "NumericLessThan" => any(&|w, h| numeric(w, h, |a, b| b < a)),
+            "NumericLessThanEquals" => any(&|w, h| numeric(w, h, |a, b| b <= a)),
+            "NumericGreaterThan" => any(&|w, h| numeric(w, h, |a, b| b > a)),
+            "NumericGreaterThanEquals" => any(&|w, h| numeric(w, h, |a, b| b >= a)),
+            _ => false,
+        }
+    }
+}
+
+impl PolicyContext {
+    pub fn new(principal: Principal, action: &str, bucket: &str, key: &str) -> Self {
+        let mut ctx = PolicyContext {
+            principal,
+            action: action.to_string(),
+            resource: resource_arn(bucket, key),
+            keys: HashMap::new(),
+        };
+        let principal_type = match &ctx.principal {
+            Principal::Anonymous => "Anonymous",
+            Principal::User(_) => "User",
+        };
+        ctx.set("aws:PrincipalType", principal_type);
+        if let Some(user) = ctx.principal.user().cloned() {
+            ctx.set("aws:userid", user.id.as_str());
+            ctx.set("aws:username", user.display_name.as_str());
+        }
+        ctx
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> &mut Self {
+        if !value.is_empty() {
+            self.keys
+                .insert(key.to_lowercase(), vec![value.to_string()]);
+        }
+        self
+    }
+}
+
+pub fn resource_arn(bucket: &str, key: &str) -> String {
+    if bucket.is_empty() {
+        String::from("arn:aws:s3:::*")
+    } else if key.is_empty() {
+        format!("arn:aws:s3:::{}", bucket)
+    } else {
+        format!("arn:aws:s3:::{}/{}", bucket, key)
+    }
+}
+
+/// Match `*` (any sequence) and `?` (any single char) wildcards.
+pub fn wildcard_match(pattern: &str, text: &str, ignore_case: bool) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = if ignore_case {
+        (
+            pattern.to_lowercase().chars().collect(),
+            text.to_lowercase().chars().collect(),
+        )
+    } else {
+        (pattern.chars().collect(), text.chars().collect())
+    };
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+fn principal_match(pattern: &str, principal: &Principal) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match principal.user() {
+        None => false,
+        Some(u) => {
+            pattern == u.id
+                || pattern == format!("arn:aws:iam::{}:root", u.id)
+                || pattern.ends_with(format!(":user/{}", u.id).as_str())
+        }
+    }
+}
+
+/// Match an address against a single ip or a CIDR block (`10.0.0.0/8`, `2001:db8::/32`).
+fn ip_match(cidr: &str, addr: &str) -> bool {
+    let addr: IpAddr = match addr.parse() {
+        Ok(a) => a,
+        Err(_) => return false,
+    };
+    let (net, bits) = match cidr.split_once('/') {
+        Some((n, b)) => (n, b.parse::<u32>().ok()),
+        None => (cidr, None),
+    };
+    let net: IpAddr = match net.parse() {
+        Ok(n) => n,
+        Err(_) => return false,
+    };
+    let (a, n, width) = match (addr, net) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(n)) => (u128::from(a), u128::from(n), 128),
+        (IpAddr::V6(a), IpAddr::V4(n)) => match a.to_ipv4_mapped() {
+            Some(a) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+            None => return false,
+        },
+        _ => return false,
+    };
+    let bits = bits.unwrap_or(width).min(width);
+    if bits == 0 {
+        return true;
+    }
+    let shift = width - bits;
+    (a >> shift) == (n >> shift)
+}
+
+fn numeric(want: &str, have: &str, cmp: fn(f64, f64) -> bool) -> bool {
+    match (want.parse::<f64>(), have.parse::<f64>()) {
+        (Ok(w), Ok(h)) => cmp(w, h),
+        _ => false,
+    }
+}
+
+fn parse_strings(v: &Value) -> Result<Vec<String>, S3Error> {
+    match v {
+        Value::String(s) => Ok(vec![s.to_owned()]),
+        Value::Bool(b) => Ok(vec![b.to_string()]),
+        Value::Number(n) => Ok(vec![n.to_string()]),
+        Value::Array(list) => list
+            .iter()
+            .map(|i| match i {
+                Value::String(s) => Ok(s.to_owned()),
+                Value::Bool(b) => Ok(b.to_string()),
+                Value::Number(n) => Ok(n.to_string()),
+                _ => Err(S3Error::MalformedPolicy),
+            })
+            .collect(),
+        _ => Err(S3Error::MalformedPolicy),
+    }
+}
+
+/// Principal is either "*" or a map like {"AWS": [...]} / {"CanonicalUser": "..."}
+fn parse_principal(v: &Value) -> Result<Vec<String>, S3Error> {
+    match v {
+        Value::String(s) if s == "*" => Ok(vec![s.to_owned()]),
+        Value::Object(m) => {
+            let mut list = Vec::new();
+            for (k, v) in m.iter() {
+                if k != "AWS" && k != "CanonicalUser" {
+                    return Err(S3Error::MalformedPolicy);
+                }
+                list.extend(parse_strings(v)?);
+            }
+            Ok(list)
+        }
+        _ => Err(S3Error::MalformedPolicy),
+    }
+}
+
+fn parse_conditions(v: &Value) -> Result<Vec<Condition>, S3Error> {
+    let ops = v.as_object().ok_or(S3Error::MalformedPolicy)?;
+    let mut conditions = Vec::new();
+    for (op, keys) in ops.iter() {
+        let base = op.strip_suffix("IfExists").unwrap_or(op);
+        if !CONDITION_OPERATORS.contains(&base) {
+            return Err(S3Error::MalformedPolicy);
+        }
+        let keys = keys.as_object().ok_or(S3Error::MalformedPolicy)?;
+        for (key, values) in keys.iter() {
+            conditions.push(Condition {
+                operator: op.to_owned(),
+                key: key.to_owned(),
+                values: parse_strings(values)?,
+            });
+        }
+    }
+    Ok(conditions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str) -> Principal {
+        Principal::User(UserInfo {
+            id: id.to_string(),
+            display_name: id.to_string(),
+        })
+    }
+
+    fn policy(statements: &str) -> Policy {
+        Policy::parse(&format!(
+            r#"{{"Version":"2012-10-17","Statement":[{}]}}"#,
+            statements
+        ))
+        .unwrap()
+    }
+
+    const ALLOW_ALL: &str =
+        r#"{"Effect":"Allow","Principal":"*","Action":"s3:*","Resource":"arn:aws:s3:::b/*"}"#;
+
+    #[test]
+    fn deny_overrides_allow() {
+        let p = policy(&format!(
+            r#"{},{{"Effect":"Deny","Principal":{{"AWS":"bob"}},"Action":"s3:GetObject","Resource":"arn:aws:s3:::b/secret/*"}}"#,
+            ALLOW_ALL
+        ));
+        let ctx = PolicyContext::new(user("bob"), "s3:GetObject", "b", "secret/x");
+        assert_eq!(p.evaluate(&ctx), Decision::Deny);
+        let ctx = PolicyContext::new(user("alice"), "s3:GetObject", "b", "secret/x");
+        assert_eq!(p.evaluate(&ctx), Decision::Allow);
+        let ctx = PolicyContext::new(user("bob"), "s3:GetObject", "b", "public/x");
+        assert_eq!(p.evaluate(&ctx), Decision::Allow);
+        // the order of the statements does not matter
+        let p = policy(&format!(
+            r#"{{"Effect":"Deny","Principal":"*","Action":"s3:*","Resource":"arn:aws:s3:::b/*"}},{}"#,
+            ALLOW_ALL
+        ));
+        let ctx = PolicyContext::new(user("bob"), "s3:PutObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::Deny);
+    }
+
+    #[test]
+    fn no_statement_matches() {
+        let p = policy(ALLOW_ALL);
+        let ctx = PolicyContext::new(user("bob"), "s3:GetObject", "other", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+        // the bucket itself is not matched by b/*
+        let ctx = PolicyContext::new(user("bob"), "s3:ListBucket", "b", "");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("s3:Get*", "s3:GetObject", false));
+        assert!(wildcard_match("s3:get*", "s3:GetObject", true));
+        assert!(!wildcard_match("s3:get*", "s3:GetObject", false));
+        assert!(wildcard_match(
+            "arn:aws:s3:::b/?.txt",
+            "arn:aws:s3:::b/a.txt",
+            false
+        ));
+        assert!(!wildcard_match(
+            "arn:aws:s3:::b/?.txt",
+            "arn:aws:s3:::b/ab.txt",
+            false
+        ));
+        assert!(wildcard_match("*a*b*c", "xxaxxbxxbxc", false));
+        assert!(!wildcard_match("*a*b*c", "xxaxxbxxbx", false));
+        assert!(wildcard_match("**", "", false));
+    }
+
+    #[test]
+    fn not_action_and_not_principal() {
+        let p = policy(
+            r#"{"Effect":"Deny","NotPrincipal":{"AWS":["admin"]},"NotAction":"s3:Get*","Resource":"arn:aws:s3:::b/*"}"#,
+        );
+        let ctx = PolicyContext::new(user("bob"), "s3:PutObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::Deny);
+        let ctx = PolicyContext::new(user("bob"), "s3:GetObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+        let ctx = PolicyContext::new(user("admin"), "s3:PutObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+    }
+
+    #[test]
+    fn conditions() {
+        let p = policy(
+            r#"{"Effect":"Allow","Principal":"*","Action":"s3:GetObject","Resource":"arn:aws:s3:::b/*",
+                "Condition":{"IpAddress":{"aws:SourceIp":"10.0.0.0/8"},"StringLikeIfExists":{"aws:Referer":"https://*.example.com/*"}}}"#,
+        );
+        let mut ctx = PolicyContext::new(Principal::Anonymous, "s3:GetObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+        ctx.set("aws:SourceIp", "10.1.2.3");
+        assert_eq!(p.evaluate(&ctx), Decision::Allow);
+        ctx.set("aws:referer", "https://www.example.com/page");
+        assert_eq!(p.evaluate(&ctx), Decision::Allow);
+        ctx.set("aws:Referer", "https://evil.test/");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+        ctx.set("aws:Referer", "https://www.example.com/page");
+        ctx.set("aws:SourceIp", "192.168.1.1");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+    }
+
+    #[test]
+    fn numeric_bool_and_null_conditions() {
+        let p = policy(
+            r#"{"Effect":"Deny","Principal":"*","Action":"s3:ListBucket","Resource":"arn:aws:s3:::b",
+                "Condition":{"NumericGreaterThan":{"s3:max-keys":"100"}}}"#,
+        );
+        let mut ctx = PolicyContext::new(user("bob"), "s3:ListBucket", "b", "");
+        ctx.set("s3:max-keys", "1000");
+        assert_eq!(p.evaluate(&ctx), Decision::Deny);
+        ctx.set("s3:max-keys", "10");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+
+        let p = policy(
+            r#"{"Effect":"Deny","Principal":"*","Action":"s3:*","Resource":"arn:aws:s3:::b/*",
+                "Condition":{"Bool":{"aws:SecureTransport":"false"}}}"#,
+        );
+        let mut ctx = PolicyContext::new(user("bob"), "s3:GetObject", "b", "k");
+        ctx.set("aws:SecureTransport", "false");
+        assert_eq!(p.evaluate(&ctx), Decision::Deny);
+        ctx.set("aws:SecureTransport", "true");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+
+        let p = policy(
+            r#"{"Effect":"Deny","Principal":"*","Action":"s3:PutObject","Resource":"arn:aws:s3:::b/*",
+                "Condition":{"Null":{"s3:x-amz-server-side-encryption":"true"}}}"#,
+        );
+        let mut ctx = PolicyContext::new(user("bob"), "s3:PutObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::Deny);
+        ctx.set("s3:x-amz-server-side-encryption", "AES256");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+    }
+
+    #[test]
+    fn principals() {
+        let p = policy(
+            r#"{"Effect":"Allow","Principal":{"AWS":["arn:aws:iam::alice:root","arn:aws:iam::123:user/bob"]},"Action":"s3:*","Resource":"*"}"#,
+        );
+        for id in ["alice", "bob"] {
+            let ctx = PolicyContext::new(user(id), "s3:GetObject", "b", "k");
+            assert_eq!(p.evaluate(&ctx), Decision::Allow);
+        }
+        let ctx = PolicyContext::new(user("carol"), "s3:GetObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+        let ctx = PolicyContext::new(Principal::Anonymous, "s3:GetObject", "b", "k");
+        assert_eq!(p.evaluate(&ctx), Decision::NoMatch);
+    }
+
+    #[test]
+    fn malformed() {
+        for text in [
+            "",
+            "[]",
+            r#"{"Statement":[]}"#,
+            r#"{"Statement":{"Effect":"Maybe","Principal":"*","Action":"s3:*","Resource":"*"}}"#,
+            // no principal
+            r#"{"Statement":{"Effect":"Allow","Action":"s3:*","Resource":"*"}}"#,
+            // both Action and NotAction
+            r#"{"Statement":{"Effect":"Allow","Principal":"*","Action":"s3:*","NotAction":"s3:*","Resource":"*"}}"#,
+            r#"{"Statement":{"Effect":"Allow","Principal":"*","Action":"s3:*","Resource":"*","Condition":{"Bogus":{"a":"b"}}}}"#,
+        ] {
+            assert_eq!(
+                Policy::parse(text).unwrap_err(),
+                S3Error::MalformedPolicy,
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn cidr() {
+        assert!(ip_match("10.0.0.0/8", "10.255.0.1"));
+        assert!(!ip_match("10.0.0.0/8", "11.0.0.1"));
+        assert!(ip_match("192.168.1.1", "192.168.1.1"));
+        assert!(ip_match("0.0.0.0/0", "1.2.3.4"));
+        assert!(ip_match("2001:db8::/32", "2001:db8::1"));
+        assert!(ip_match("10.0.0.0/8", "::ffff:10.0.0.1"));
+        assert!(!ip_match("10.0.0.0/8", "not-an-ip"));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::api::*;
+use crate::auth::*;
+use hyper::Request;
+
+/// The user id that owns resources created by anonymous requests.
+pub const ANONYMOUS_ID: &str = "anonymous";
+
+/// Principal identifies the requester by the access key of the request signature.
+/// Requests without a signature are anonymous, see verify_signature.
+#[derive(Debug, Clone)]
+pub enum Principal {
+    Anonymous,
+    User(UserInfo),
+}
+
+impl Principal {
+    /// Authenticate the request by its signature, requests whose signature
+    /// can't be verified are rejected rather than taken as anonymous.
+    pub fn from_request(req: &HttpRequest, credentials: &[Credentials]) -> Result<Self, S3Error> {
+        Ok(match verify_signature(req, credentials)? {
+            Some(access_key) => Principal::User(UserInfo {
+                id: access_key.to_owned(),
+                display_name: access_key,
+            }),
+            None => Principal::Anonymous,
+        })
+    }
+
+    /// The principal the server attached to the request extensions.
+    pub fn of<T>(req: &Request<T>) -> Self {
+        req.extensions()
+            .get::<Principal>()
+            .cloned()
+            .unwrap_or(Principal::Anonymous)
+    }
+
+    /// The user that owns resources created by this principal.
+    /// Anonymous requests create resources owned by the anonymous user.
+    pub fn owner(&self) -> UserInfo {
+        match self {
+            Principal::User(u) => u.to_owned(),
+            Principal::Anonymous => UserInfo {
+                id: ANONYMOUS_ID.to_string(),
+                display_name: ANONYMOUS_ID.to_string(),
+            },
+        }
+    }
+
+    /// Check that the claimed identity can be used at all. The anonymous user id
+    /// is reserved, claiming it would pass as the owner of anonymous resources.
+    pub fn check(&self) -> Result<(), S3Error> {
+        match self.user() {
+            Some(u) if u.id.eq_ignore_ascii_case(ANONYMOUS_ID) => Err(S3Error::InvalidAccessKeyId),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn user(&self) -> Option<&UserInfo> {
+        match self {
+            Principal::Anonymous => None,
+            Principal::User(u) => Some(u),
+        }
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.user().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str) -> Principal {
+        Principal::User(UserInfo {
+            id: id.to_string(),
+            display_name: id.to_string(),
+        })
+    }
+
+    #[test]
+    fn unsigned_requests_are_anonymous() {
+        let req = hyper::Request::get("/b/k")
+            .body(hyper::Body::empty())
+            .unwrap();
+        assert!(Principal::from_request(&req, &[]).unwrap().is_anonymous());
+        let req = hyper::Request::get("/b/k")
+            .header("Authorization", "AWS bob:sig")
+            .body(hyper::Body::empty())
+            .unwrap();
+        assert_eq!(
+            Principal::from_request(&req, &[]).unwrap_err(),
+            S3Error::InvalidAccessKeyId
+        );
+    }
+
+    #[test]
+    fn anonymous_id_is_reserved() {
+        assert_eq!(user("anonymous").check(), Err(S3Error::InvalidAccessKeyId));
+        assert_eq!(user("Anonymous").check(), Err(S3Error::InvalidAccessKeyId));
+        assert_eq!(user("alice").check(), Ok(()));
+        assert_eq!(Principal::Anonymous.check(), Ok(()));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::api::*;
+pub use crate::auth::Credentials;
+use crate::auth::*;
+use crate::layers::*;
+use hyper::{body::Bytes, Body};
+use rand::{distributions::Alphanumeric, Rng};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+/// How long shutdown() waits for in-flight requests.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// S3dServer is an s3d instance running inside the current process,
+/// for example a throwaway endpoint for integration tests.
+/// Instances share no state so tests can run them in parallel.
+///
+/// ```no_run
+/// use s3d::{layers::MemLayer, S3dServer};
+/// use s3d::api::ApiLayer;
+///
+/// # async fn example() -> Result<(), s3d::api::SyncError> {
+/// let server = S3dServer::builder()
+///     .layer(MemLayer::new())
+///     .bind_ephemeral()
+///     .bucket("test")
+///     .object("test", "hello.txt", "hello world")
+///     .start()
+///     .await?;
+/// let endpoint = server.url();
+/// let creds = server.credentials();
+/// // ... point an S3 client at the endpoint ...
+/// server.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Dropping the server stops the listeners without waiting for connections.
+pub struct S3dServer<API: ApiLayer + 'static> {
+    srv: Arc<S3Server<API>>,
+    urls: Vec<String>,
+    admin_urls: Vec<String>,
+    website_urls: Vec<String>,
+    credentials: Credentials,
+    shutdown: ShutdownHandle,
+    tasks: Vec<JoinHandle<Result<(), SyncError>>>,
+}
+
+pub struct S3dServerBuilder<API: ApiLayer + 'static> {
+    layer: Option<API>,
+    listeners: Vec<Listener>,
+    credentials: Credentials,
+    users: Vec<Credentials>,
+    allow_anonymous: bool,
+    region: String,
+    lifecycle_interval: Option<Duration>,
+    access_log: Option<PathBuf>,
+    webhooks: Vec<(String, String)>,
+    notify_queue_dir: Option<PathBuf>,
+    admin_token: Option<String>,
+    buckets: Vec<String>,
+    objects: Vec<(String, String, Bytes)>,
+}
+
+impl S3dServer<MemLayer> {
+    /// Start building a server, by default over a new MemLayer
+    /// and listening on an ephemeral localhost port.
+    pub fn builder() -> S3dServerBuilder<MemLayer> {
+        S3dServerBuilder {
+            layer: None,
+            listeners: Vec::new(),
+            credentials: Credentials::random(),
+            users: Vec::new(),
+            allow_anonymous: true,
+            region: DEFAULT_REGION.to_string(),
+            lifecycle_interval: None,
+            access_log: None,
+            webhooks: Vec::new(),
+            notify_queue_dir: None,
+            admin_token: None,
+            buckets: Vec::new(),
+            objects: Vec::new(),
+        }
+    }
+}
+
+impl<API: ApiLayer + 'static> S3dServerBuilder<API> {
+    pub fn layer<L: ApiLayer + 'static>(self, layer: L) -> S3dServerBuilder<L> {
+        S3dServerBuilder {
+            layer: Some(layer),
+            listeners: self.listeners,
+            credentials: self.credentials,
+            users: self.users,
+            allow_anonymous: self.allow_anonymous,
+            region: self.region,
+            lifecycle_interval: self.lifecycle_interval,
+            access_log: self.access_log,
+            webhooks: self.webhooks,
+            notify_queue_dir: self.notify_queue_dir,
+            admin_token: self.admin_token,
+            buckets: self.buckets,
+            objects: self.objects,
+        }
+    }
+
+    pub fn listener(mut self, listener: Listener) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    pub fn listeners(mut self, listeners: Vec<Listener>) -> Self {
+        self.listeners.extend(listeners);
+        self
+    }
+
+    pub fn bind(self, addr: SocketAddr) -> Self {
+        self.listener(Listener::Http(addr))
+    }
+
+    /// Serve the admin endpoints (e.g. /metrics) on a separate address, see S3dServer::admin_url().
+    /// Addresses other than loopback need an admin_token().
+    pub fn admin(self, addr: SocketAddr) -> Self {
+        self.listener(Listener::Admin(addr))
+    }
+
+    /// Require `Authorization: Bearer <token>` on the admin endpoints but the health probes.
+    pub fn admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.to_string());
+        self
+    }
+
+    /// Serve the buckets as static websites on a separate address, see S3dServer::website_url().
+    pub fn website(self, addr: SocketAddr) -> Self {
+        self.listener(Listener::Website(addr))
+    }
+
+    /// Listen on a free port picked by the OS, see S3dServer::url().
+    pub fn bind_ephemeral(self) -> Self {
+        self.bind(([127, 0, 0, 1], 0).into())
+    }
+
+    /// The credentials of the server, see S3dServer::credentials(),
+    /// by default a random access key and secret.
+    pub fn credentials(mut self, access_key_id: &str, secret_access_key: &str) -> Self {
+        self.credentials = Credentials {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+        };
+        self
+    }
+
+    /// Other users that can sign requests besides the server credentials.
+    pub fn users(mut self, users: Vec<Credentials>) -> Self {
+        self.users.extend(users);
+        self
+    }
+
+    /// See S3Server::allow_anonymous
+    pub fn anonymous(mut self, allow: bool) -> Self {
+        self.allow_anonymous = allow;
+        self
+    }
+
+    /// See S3Server::region
+    pub fn region(mut self, region: &str) -> Self {
+        self.region = region.to_string();
+        self
+    }
+
+    /// Apply the bucket lifecycle rules every interval in the background,
+    /// by default they are only applied by the admin `lifecycle` task.
+    pub fn lifecycle_interval(mut self, interval: Duration) -> Self {
+        self.lifecycle_interval = Some(interval);
+        self
+    }
+
+    /// Append every request to the file in the S3 server access log format.
+    pub fn access_log<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.access_log = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a webhook target for bucket notifications, configurations name it
+    /// by `arn:s3d:webhook::{name}` and events are POSTed to the http url.
+    pub fn webhook(mut self, name: &str, url: &str) -> Self {
+        self.webhooks.push((name.to_string(), url.to_string()));
+        self
+    }
+
+    /// Keep the events queued for webhooks in the directory until they are delivered,
+    /// by default they are only kept in memory and lost on restart.
+    pub fn notify_queue_dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.notify_queue_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Create the bucket on start, owned by the server credentials.
+    pub fn bucket(mut self, bucket: &str) -> Self {
+        self.buckets.push(bucket.to_string());
+        self
+    }
+
+    /// Put the object on start, owned by the server credentials.
+    pub fn object(mut self, bucket: &str, key: &str, data: impl Into<Bytes>) -> Self {
+        self.objects
+            .push((bucket.to_string(), key.to_string(), data.into()));
+        self
+    }
+
+    /// Seed the layer, bind all listeners and start serving.
+    pub async fn start(self) -> Result<S3dServer<API>, SyncError> {
+        // the admin endpoints delete buckets, so only loopback is open without a token
+        if self.admin_token.is_none() {
+            for listener in self.listeners.iter() {
+                if let Listener::Admin(addr) = listener {
+                    if !addr.ip().is_loopback() {
+                        return Err(format!(
+                            "admin listener {} is not on loopback and needs S3D_ADMIN_TOKEN",
+                            addr
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+        let mut srv = S3Server::with_layer(self.layer.unwrap_or_else(API::new));
+        srv.allow_anonymous = self.allow_anonymous;
+        srv.credentials = std::iter::once(self.credentials.to_owned())
+            .chain(self.users)
+            .collect();
+        srv.region = self.region;
+        srv.admin_token = self.admin_token;
+        if let Some(path) = &self.access_log {
+            srv.access_log = Some(AccessLog::open(path)?);
+        }
+        if !self.webhooks.is_empty() {
+            let notifier = Notifier::open(&self.webhooks, self.notify_queue_dir.as_deref()).await?;
+            srv.notifier = Some(Arc::new(notifier));
+        }
+        let mut server = S3dServer {
+            srv: Arc::new(srv),
+            urls: Vec::new(),
+            admin_urls: Vec::new(),
+            website_urls: Vec::new(),
+            credentials: self.credentials,
+            shutdown: ShutdownHandle::new(),
+            tasks: Vec::new(),
+        };
+        for bucket in self.buckets {
+            server.create_bucket(bucket.as_str()).await?;
+        }
+        for (bucket, key, data) in self.objects {
+            server
+                .put_object(bucket.as_str(), key.as_str(), data)
+                .await?;
+        }
+        let mut listeners = self.listeners;
+        if listeners
+            .iter()
+            .all(|l| matches!(l, Listener::Admin(_) | Listener::Website(_)))
+        {
+            listeners.push(Listener::Http(([127, 0, 0, 1], 0).into()));
+        }
+        for listener in listeners {
+            let bound = listener.bind().await?;
+            match (bound.is_admin(), bound.is_website()) {
+                (true, _) => server.admin_urls.push(bound.url()),
+                (_, true) => server.website_urls.push(bound.url()),
+                _ => server.urls.push(bound.url()),
+            }
+            server.tasks.push(tokio::spawn(
+                bound.run(Arc::clone(&server.srv), server.shutdown.subscribe()),
+            ));
+        }
+        if let Some(notifier) = &server.srv.notifier {
+            server.tasks.push(tokio::spawn(notification_worker(
+                Arc::clone(notifier),
+                server.shutdown.subscribe(),
+            )));
+        }
+        if let Some(interval) = self.lifecycle_interval {
+            server.tasks.push(tokio::spawn(lifecycle_worker(
+                Arc::clone(&server.srv),
+                interval,
+                server.shutdown.subscribe(),
+            )));
+        }
+        Ok(server)
+    }
+}
+
+impl<API: ApiLayer + 'static> S3dServer<API> {
+    /// The URL of the first listener, e.g. `http://127.0.0.1:41234`
+    pub fn url(&self) -> &str {
+        self.urls.first().map_or("", |u| u.as_str())
+    }
+
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    /// The URL of the first admin listener, empty without one.
+    pub fn admin_url(&self) -> &str {
+        self.admin_urls.first().map_or("", |u| u.as_str())
+    }
+
+    /// The URL of the first website listener, empty without one.
+    /// Requests name the bucket by the Host header, see S3Server::website_handler().
+    pub fn website_url(&self) -> &str {
+        self.website_urls.first().map_or("", |u| u.as_str())
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    pub fn api(&self) -> &API {
+        self.srv.api()
+    }
+
+    pub async fn create_bucket(&self, bucket: &str) -> Result<BucketInfo, S3Error> {
+        let mut req = put_bucket::Req::new(put_bucket::Params {
+            bucket: bucket.to_string(),
+            class: String::new(),
+            region: self.srv.region.to_owned(),
+            acl: AclHeaders::default(),
+            object_lock_enabled: false,
+            body: None,
+        });
+        req.extensions_mut().insert(self.principal());
+        let res = self.srv.api().put_bucket(req).await?;
+        Ok(res.into_body().info)
+    }
+
+    pub async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: impl Into<Bytes>,
+    ) -> Result<ObjectInfo, S3Error> {
+        let mut req = put_object::Req::new(put_object::Params {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            acl: AclHeaders::default(),
+            tagging: Tagging::default(),
+            website_redirect_location: String::new(),
+            sse_customer: None,
+            sse: None,
+            object_lock: ObjectLock::default(),
+            plaintext_size: None,
+            body: Some(Body::from(data.into())),
+        });
+        req.extensions_mut().insert(self.principal());
+        let res = self.srv.api().put_object(req).await?;
+        Ok(res.into_body().object)
+    }
+
+    /// Stop accepting, wait for in-flight requests and flush the layer.
+    pub async fn shutdown(self) {
+        self.shutdown_timeout(SHUTDOWN_TIMEOUT).await
+    }
+
+    pub async fn shutdown_timeout(self, timeout: Duration) {
+        if !self.shutdown.shutdown(timeout).await {
+            tracing::warn!("shutdown timeout expired with connections still open");
+        }
+        for task in self.tasks {
+            match task.await {
+                Ok(Err(err)) => tracing::error!(%err, "listener error"),
+                Err(err) => tracing::error!(%err, "listener task failed"),
+                Ok(Ok(())) => {}
+            }
+        }
+        self.srv.shutdown().await;
+    }
+
+    fn principal(&self) -> Principal {
+        Principal::User(UserInfo {
+            id: self.credentials.access_key_id.to_owned(),
+            display_name: self.credentials.access_key_id.to_owned(),
+        })
+    }
+}
+
+impl Credentials {
+    pub fn random() -> Self {
+        let random = |len: usize| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(len)
+                .map(char::from)
+                .collect::<String>()
+        };
+        Credentials {
+            access_key_id: random(20).to_uppercase(),
+            secret_access_key: random(40),
+        }
+    }
+}
@@ -0,0 +1,923 @@
+use crate::api::*;
+use crate::auth::*;
+use crate::layers::*;
+use async_trait::async_trait;
+use hyper::{body::Bytes, Body};
+use ring::aead;
+use std::{convert::TryInto, sync::Arc};
+
+/// Plaintext bytes of each encrypted chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const MAGIC: &[u8; 4] = b"S3DE";
+const FORMAT_VERSION: u8 = 1;
+const TAG_LEN: usize = aead::MAX_TAG_LEN;
+/// Reads of an object that is overwritten while it is read.
+const READ_ATTEMPTS: usize = 3;
+/// magic, format version, algorithm, key id length and padded key id,
+/// master key version and the wrapped data key.
+const HEADER_LEN: usize = 4 + 1 + 1 + 1 + MAX_KEY_ID_LEN + 4 + WRAPPED_KEY_LEN;
+
+/// EncryptionLayer encrypts object data at rest in the inner layer,
+/// with SSE-S3 (`AES256`) or SSE-KMS (`aws:kms`) keys from a Keyring.
+///
+/// Every object is encrypted, with `x-amz-server-side-encryption`, the bucket
+/// default encryption, or else SSE-S3 like S3 does. The stored object is a fixed
+/// size header with the wrapped data key, followed by the data in AES-256-GCM
+/// chunks that are authenticated on their own, so a byte range is decrypted
+/// from the chunks that cover it. Objects without the header are returned as is.
+#[derive(Debug, Clone)]
+pub struct EncryptionLayer<Inner: ApiLayer> {
+    inner: Inner,
+    keyring: Arc<Keyring>,
+}
+
+/// Envelope is the header of an encrypted object.
+struct Envelope {
+    algorithm: SseAlgorithm,
+    key_id: String,
+    version: u32,
+    wrapped: Vec<u8>,
+}
+
+#[async_trait]
+impl<Inner: ApiLayer> ApiLayer for EncryptionLayer<Inner> {
+    /// A layer over a new inner layer with an in-memory keyring.
+    fn new() -> Self {
+        Self::with_keyring(Inner::new(), Keyring::memory())
+    }
+
+    async fn list_buckets(&self, req: list_buckets::Req) -> list_buckets::Ret {
+        self.inner.list_buckets(req).await
+    }
+
+    async fn get_bucket(&self, req: get_bucket::Req) -> get_bucket::Ret {
+        self.inner.get_bucket(req).await
+    }
+
+    async fn put_bucket(&self, req: put_bucket::Req) -> put_bucket::Ret {
+        self.inner.put_bucket(req).await
+    }
+
+    async fn delete_bucket(&self, req: delete_bucket::Req) -> delete_bucket::Ret {
+        self.inner.delete_bucket(req).await
+    }
+
+    async fn get_bucket_location(&self, req: get_bucket_location::Req) -> get_bucket_location::Ret {
+        self.inner.get_bucket_location(req).await
+    }
+
+    /// Sizes of encrypted objects are the plaintext sizes kept with them by put_object.
+    async fn list_objects(&self, req: list_objects::Req) -> list_objects::Ret {
+        let mut res = self.inner.list_objects(req).await?;
+        for object in res.body_mut().objects.iter_mut() {
+            if let Some(size) = object.plaintext_size {
+                object.size = size;
+            }
+        }
+        Ok(res)
+    }
+
+    /// The header is read first for the size and keys of the object, and then
+    /// only the chunks that cover the requested range, none for HEAD. An object
+    /// overwritten between the two reads is read again.
+    async fn get_object(&self, req: get_object::Req) -> get_object::Ret {
+        let principal = Principal::of(&req);
+        let params = req.into_body();
+        for _ in 0..READ_ATTEMPTS {
+            if let Some(res) = self.read_object(&principal, &params).await? {
+                return Ok(res);
+            }
+        }
+        tracing::warn!(
+            bucket = params.bucket.as_str(),
+            key = params.key.as_str(),
+            "object kept changing while it was read"
+        );
+        Err(S3Error::SlowDown)
+    }
+
+    /// Objects with customer keys (SSE-C) are encrypted by the inner layer
+    /// with the key of the request, like S3 an object has one kind of encryption.
+    async fn put_object(&self, req: put_object::Req) -> put_object::Ret {
+        let (parts, mut params) = req.into_parts();
+        if params.sse_customer.is_some() {
+            return self
+                .inner
+                .put_object(put_object::Req::from_parts(parts, params))
+                .await;
+        }
+        let sse = match params.sse.take() {
+            Some(sse) => sse,
+            None => bucket_encryption(&self.inner, &params.bucket)
+                .await
+                .and_then(|c| c.default_encryption())
+                .unwrap_or(ServerSideEncryption {
+                    algorithm: SseAlgorithm::Aes256,
+                    kms_key_id: String::new(),
+                }),
+        };
+        let key_id = match sse.algorithm {
+            SseAlgorithm::Aes256 => SSE_S3_KEY_ID,
+            SseAlgorithm::AwsKms if sse.kms_key_id.is_empty() => DEFAULT_KMS_KEY_ID,
+            SseAlgorithm::AwsKms => sse.kms_key_id.as_str(),
+        };
+        let data_key = self.keyring.generate_data_key(key_id).await?;
+        let envelope = Envelope {
+            algorithm: sse.algorithm,
+            key_id: data_key.key_id.to_owned(),
+            version: data_key.version,
+            wrapped: data_key.wrapped.to_vec(),
+        };
+        let buf = read_body(params.body.take()).await?;
+        let mut sealed = Vec::with_capacity(HEADER_LEN + buf.len() + buf.len() / CHUNK_SIZE);
+        envelope.write(&mut sealed)?;
+        seal_chunks(&data_key.key, &buf, &mut sealed)?;
+        params.body = Some(Body::from(sealed));
+        params.plaintext_size = Some(buf.len() as u64);
+        let mut res = self
+            .inner
+            .put_object(put_object::Req::from_parts(parts, params))
+            .await?;
+        let object = &mut res.body_mut().object;
+        object.size = buf.len() as u64;
+        envelope.set_object_info(object);
+        Ok(res)
+    }
+
+    async fn delete_object(&self, req: delete_object::Req) -> delete_object::Ret {
+        self.inner.delete_object(req).await
+    }
+
+    async fn get_bucket_policy(&self, req: get_bucket_policy::Req) -> get_bucket_policy::Ret {
+        self.inner.get_bucket_policy(req).await
+    }
+
+    async fn put_bucket_policy(&self, req: put_bucket_policy::Req) -> put_bucket_policy::Ret {
+        self.inner.put_bucket_policy(req).await
+    }
+
+    async fn delete_bucket_policy(
+        &self,
+        req: delete_bucket_policy::Req,
+    ) -> delete_bucket_policy::Ret {
+        self.inner.delete_bucket_policy(req).await
+    }
+
+    async fn get_bucket_acl(&self, req: get_bucket_acl::Req) -> get_bucket_acl::Ret {
+        self.inner.get_bucket_acl(req).await
+    }
+
+    async fn put_bucket_acl(&self, req: put_bucket_acl::Req) -> put_bucket_acl::Ret {
+        self.inner.put_bucket_acl(req).await
+    }
+
+    async fn get_object_acl(&self, req: get_object_acl::Req) -> get_object_acl::Ret {
+        self.inner.get_object_acl(req).await
+    }
+
+    async fn put_object_acl(&self, req: put_object_acl::Req) -> put_object_acl::Ret {
+        self.inner.put_object_acl(req).await
+    }
+
+    async fn get_bucket_tagging(&self, req: get_bucket_tagging::Req) -> get_bucket_tagging::Ret {
+        self.inner.get_bucket_tagging(req).await
+    }
+
+    async fn put_bucket_tagging(&self, req: put_bucket_tagging::Req) -> put_bucket_tagging::Ret {
+        self.inner.put_bucket_tagging(req).await
+    }
+
+    async fn delete_bucket_tagging(
+        &self,
+        req: delete_bucket_tagging::Req,
+    ) -> delete_bucket_tagging::Ret {
+        self.inner.delete_bucket_tagging(req).await
+    }
+
+    async fn get_object_tagging(&self, req: get_object_tagging::Req) -> get_object_tagging::Ret {
+        self.inner.get_object_tagging(req).await
+    }
+
+    async fn put_object_tagging(&self, req: put_object_tagging::Req) -> put_object_tagging::Ret {
+        self.inner.put_object_tagging(req).await
+    }
+
+    async fn delete_object_tagging(
+        &self,
+        req: delete_object_tagging::Req,
+    ) -> delete_object_tagging::Ret {
+        self.inner.delete_object_tagging(req).await
+    }
+
+    async fn get_bucket_lifecycle(
+        &self,
+        req: get_bucket_lifecycle::Req,
+    ) -> get_bucket_lifecycle::Ret {
+        self.inner.get_bucket_lifecycle(req).await
+    }
+
+    async fn put_bucket_lifecycle(
+        &self,
+        req: put_bucket_lifecycle::Req,
+    ) -> put_bucket_lifecycle::Ret {
+        self.inner.put_bucket_lifecycle(req).await
+    }
+
+    async fn delete_bucket_lifecycle(
+        &self,
+        req: delete_bucket_lifecycle::Req,
+    ) -> delete_bucket_lifecycle::Ret {
+        self.inner.delete_bucket_lifecycle(req).await
+    }
+
+    async fn get_bucket_cors(&self, req: get_bucket_cors::Req) -> get_bucket_cors::Ret {
+        self.inner.get_bucket_cors(req).await
+    }
+
+    async fn put_bucket_cors(&self, req: put_bucket_cors::Req) -> put_bucket_cors::Ret {
+        self.inner.put_bucket_cors(req).await
+    }
+
+    async fn delete_bucket_cors(&self, req: delete_bucket_cors::Req) -> delete_bucket_cors::Ret {
+        self.inner.delete_bucket_cors(req).await
+    }
+
+    async fn get_bucket_website(&self, req: get_bucket_website::Req) -> get_bucket_website::Ret {
+        self.inner.get_bucket_website(req).await
+    }
+
+    async fn put_bucket_website(&self, req: put_bucket_website::Req) -> put_bucket_website::Ret {
+        self.inner.put_bucket_website(req).await
+    }
+
+    async fn delete_bucket_website(
+        &self,
+        req: delete_bucket_website::Req,
+    ) -> delete_bucket_website::Ret {
+        self.inner.delete_bucket_website(req).await
+    }
+
+    async fn get_bucket_encryption(
+        &self,
+        req: get_bucket_encryption::Req,
+    ) -> get_bucket_encryption::Ret {
+        self.inner.get_bucket_encryption(req).await
+    }
+
+    /// The default KMS key must exist, like S3 checks it with KMS.
+    async fn put_bucket_encryption(
+        &self,
+        req: put_bucket_encryption::Req,
+    ) -> put_bucket_encryption::Ret {
+        let (parts, mut params) = req.into_parts();
+        let buf = read_document(params.body.take()).await?;
+        let config: ServerSideEncryptionConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        if let Some(sse) = config.default_encryption() {
+            if !sse.kms_key_id.is_empty() && !self.keyring.key_ids().await.contains(&sse.kms_key_id)
+            {
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        params.body = Some(Body::from(buf));
+        self.inner
+            .put_bucket_encryption(put_bucket_encryption::Req::from_parts(parts, params))
+            .await
+    }
+
+    async fn delete_bucket_encryption(
+        &self,
+        req: delete_bucket_encryption::Req,
+    ) -> delete_bucket_encryption::Ret {
+        self.inner.delete_bucket_encryption(req).await
+    }
+
+    async fn get_object_lock_configuration(
+        &self,
+        req: get_object_lock_configuration::Req,
+    ) -> get_object_lock_configuration::Ret {
+        self.inner.get_object_lock_configuration(req).await
+    }
+
+    async fn put_object_lock_configuration(
+        &self,
+        req: put_object_lock_configuration::Req,
+    ) -> put_object_lock_configuration::Ret {
+        self.inner.put_object_lock_configuration(req).await
+    }
+
+    async fn get_object_retention(
+        &self,
+        req: get_object_retention::Req,
+    ) -> get_object_retention::Ret {
+        self.inner.get_object_retention(req).await
+    }
+
+    async fn put_object_retention(
+        &self,
+        req: put_object_retention::Req,
+    ) -> put_object_retention::Ret {
+        self.inner.put_object_retention(req).await
+    }
+
+    async fn get_object_legal_hold(
+        &self,
+        req: get_object_legal_hold::Req,
+    ) -> get_object_legal_hold::Ret {
+        self.inner.get_object_legal_hold(req).await
+    }
+
+    async fn put_object_legal_hold(
+        &self,
+        req: put_object_legal_hold::Req,
+    ) -> put_object_legal_hold::Ret {
+        self.inner.put_object_legal_hold(req).await
+    }
+
+    async fn get_bucket_notification(
+        &self,
+        req: get_bucket_notification::Req,
+    ) -> get_bucket_notification::Ret {
+        self.inner.get_bucket_notification(req).await
+    }
+
+    async fn put_bucket_notification(
+        &self,
+        req: put_bucket_notification::Req,
+    ) -> put_bucket_notification::Ret {
+        self.inner.put_bucket_notification(req).await
+    }
+
+    async fn get_bucket_replication(
+        &self,
+        req: get_bucket_replication::Req,
+    ) -> get_bucket_replication::Ret {
+        self.inner.get_bucket_replication(req).await
+    }
+
+    async fn put_bucket_replication(
+        &self,
+        req: put_bucket_replication::Req,
+    ) -> put_bucket_replication::Ret {
+        self.inner.put_bucket_replication(req).await
+    }
+
+    async fn delete_bucket_replication(
+        &self,
+        req: delete_bucket_replication::Req,
+    ) -> delete_bucket_replication::Ret {
+        self.inner.delete_bucket_replication(req).await
+    }
+
+    async fn flush(&self) {
+        self.inner.flush().await
+    }
+
+    fn describe(&self) -> LayerInfo {
+        let keyring = match self.keyring.path() {
+            Some(path) => path.to_string_lossy().to_string(),
+            None => "memory".to_string(),
+        };
+        LayerInfo::new("EncryptionLayer")
+            .config("keyring", &keyring)
+            .inner(self.inner.describe())
+    }
+
+    async fn probe(&self) -> Result<(), SyncError> {
+        self.inner.probe().await
+    }
+
+    /// The bytes are of the encrypted objects as the inner layer stores them.
+    async fn usage(&self) -> Result<Vec<BucketUsage>, S3Error> {
+        self.inner.usage().await
+    }
+
+    fn tasks(&self) -> Vec<&'static str> {
+        let mut tasks = self.inner.tasks();
+        tasks.push("rotate-keys");
+        tasks
+    }
+
+    /// `rotate-keys` adds a version to every master key, new objects use the new versions.
+    async fn run_task(&self, name: &str) -> Result<String, SyncError> {
+        match name {
+            "rotate-keys" => {
+                let count = self.keyring.rotate_all().await?;
+                Ok(format!("rotated {} keys", count))
+            }
+            _ => self.inner.run_task(name).await,
+        }
+    }
+
+    async fn stats(&self) -> Vec<LayerStat> {
+        let mut stats = self.inner.stats().await;
+        stats.push(LayerStat::gauge(
+            "s3d_encryption_keys",
+            "Master keys in the keyring.",
+            self.keyring.key_ids().await.len() as f64,
+        ));
+        stats
+    }
+}
+
+impl<Inner: ApiLayer> EncryptionLayer<Inner> {
+    pub fn with_keyring(inner: Inner, keyring: Keyring) -> Self {
+        Self::with_shared_keyring(inner, Arc::new(keyring))
+    }
+
+    /// A layer with the keyring of other layers, e.g. the replication destinations
+    /// that store the objects encrypted like the source.
+    pub fn with_shared_keyring(inner: Inner, keyring: Arc<Keyring>) -> Self {
+        EncryptionLayer { inner, keyring }
+    }
+
+    pub fn inner(&self) -> &Inner {
+        &self.inner
+    }
+
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    /// Read the object like get_object, None if it was overwritten between the
+    /// read of its header and the read of its data.
+    async fn read_object(
+        &self,
+        principal: &Principal,
+        params: &get_object::Params,
+    ) -> Result<Option<get_object::Res>, S3Error> {
+        let with_principal = |params: get_object::Params| {
+            let mut req = get_object::Req::new(params);
+            req.extensions_mut().insert(principal.to_owned());
+            req
+        };
+        let header = with_principal(get_object::Params {
+            head_only: false,
+            range: header_range(),
+            ..params.clone()
+        });
+        let (mut res, envelope) = match self.read_envelope(header).await {
+            Ok((res, Some(envelope))) => (res, envelope),
+            // plain objects are returned as is
+            Ok((header, None)) => {
+                let res = self
+                    .inner
+                    .get_object(with_principal(params.clone()))
+                    .await?;
+                return Ok(Some(res).filter(|res| same_object(&header, res)));
+            }
+            // and so are empty ones, which have no header range
+            Err(S3Error::InvalidRange) => {
+                return self
+                    .inner
+                    .get_object(with_principal(params.clone()))
+                    .await
+                    .map(Some)
+            }
+            Err(err) => return Err(err),
+        };
+        let reply = res.body_mut();
+        let stored = reply.object.size;
+        let size = plaintext_size(stored).ok_or(S3Error::InternalError)?;
+        reply.object.size = size;
+        envelope.set_object_info(&mut reply.object);
+        reply.range = params.range.resolve(size)?;
+        if params.head_only {
+            return Ok(Some(res));
+        }
+        let (start, end) = reply.range.unwrap_or((0, size));
+        let chunks = ChunkRange::new(stored, start, end);
+        let sealed = self
+            .inner
+            .get_object(with_principal(get_object::Params {
+                head_only: false,
+                range: ObjectRange {
+                    start: Some(chunks.stored_start),
+                    end: Some(chunks.stored_end - 1),
+                },
+                ..params.clone()
+            }))
+            .await?;
+        if !same_object(&res, &sealed) {
+            return Ok(None);
+        }
+        let sealed = sealed.into_body();
+        let sealed = match sealed.range {
+            Some(_) => read_body(sealed.body).await?,
+            // layers that ignore ranges return the whole object
+            None => read_body(sealed.body)
+                .await?
+                .slice(chunks.stored_start as usize..chunks.stored_end as usize),
+        };
+        let key = self
+            .keyring
+            .decrypt_data_key(&envelope.key_id, envelope.version, &envelope.wrapped)
+            .await?;
+        let data = open_range(&key, &sealed, &chunks, start, end)?;
+        res.body_mut().body = Some(Body::from(data));
+        Ok(Some(res))
+    }
+
+    /// Read the header of the object, None for objects without one.
+    async fn read_envelope(
+        &self,
+        req: get_object::Req,
+    ) -> Result<(get_object::Res, Option<Envelope>), S3Error> {
+        let mut res = self.inner.get_object(req).await?;
+        let buf = read_body(res.body_mut().body.take()).await?;
+        // layers that ignore ranges return the whole object
+        let envelope = Envelope::parse(&buf[..buf.len().min(HEADER_LEN)])?;
+        Ok((res, envelope))
+    }
+}
+
+/// Whether two reads of an object are of the same write of it.
+fn same_object(a: &get_object::Res, b: &get_object::Res) -> bool {
+    let (a, b) = (&a.body().object, &b.body().object);
+    a.etag == b.etag && a.last_modified == b.last_modified
+}
+
+/// The range of the stored object with the header, as it is read for the envelope.
+fn header_range() -> ObjectRange {
+    ObjectRange {
+        start: Some(0),
+        end: Some(HEADER_LEN as u64 - 1),
+    }
+}
+
+/// ChunkRange is the sealed chunks that cover a range of the data of an object.
+#[derive(Debug)]
+struct ChunkRange {
+    /// the chunks of the object
+    count: u64,
+    first: u64,
+    last: u64,
+    /// the bytes of the chunks in the stored object
+    stored_start: u64,
+    stored_end: u64,
+}
+
+impl ChunkRange {
+    /// The chunks of the data bytes start..end of an object of the stored size.
+    fn new(stored: u64, start: u64, end: u64) -> Self {
+        let sealed_chunk = (CHUNK_SIZE + TAG_LEN) as u64;
+        let sealed = stored.saturating_sub(HEADER_LEN as u64);
+        let count = sealed.div_ceil(sealed_chunk).max(1);
+        let first = (start / CHUNK_SIZE as u64).min(count - 1);
+        let last = (end.saturating_sub(1) / CHUNK_SIZE as u64).clamp(first, count - 1);
+        ChunkRange {
+            count,
+            first,
+            last,
+            stored_start: HEADER_LEN as u64 + first * sealed_chunk,
+            stored_end: stored.min(HEADER_LEN as u64 + (last + 1) * sealed_chunk),
+        }
+    }
+}
+
+impl Envelope {
+    /// The header of an encrypted object, None for objects without one.
+    fn parse(buf: &[u8]) -> Result<Option<Self>, S3Error> {
+        if buf.len() < HEADER_LEN || &buf[..4] != MAGIC {
+            return Ok(None);
+        }
+        let corrupt = || {
+            tracing::warn!("encrypted object header is corrupt");
+            S3Error::InternalError
+        };
+        if buf[4] != FORMAT_VERSION {
+            return Err(corrupt());
+        }
+        let algorithm = match buf[5] {
+            1 => SseAlgorithm::Aes256,
+            2 => SseAlgorithm::AwsKms,
+            _ => return Err(corrupt()),
+        };
+        let key_id_len = buf[6] as usize;
+        if key_id_len > MAX_KEY_ID_LEN {
+            return Err(corrupt());
+        }
+        let key_id = std::str::from_utf8(&buf[7..7 + key_id_len]).map_err(|_| corrupt())?;
+        let offset = 7 + MAX_KEY_ID_LEN;
+        let version = u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap());
+        Ok(Some(Envelope {
+            algorithm,
+            key_id: key_id.to_string(),
+            version,
+            wrapped: buf[offset + 4..HEADER_LEN].to_vec(),
+        }))
+    }
+
+    /// Key ids longer than the header has room for are rejected by the keyring,
+    /// one that gets here anyway fails the upload.
+    fn write(&self, buf: &mut Vec<u8>) -> Result<(), S3Error> {
+        if self.key_id.len() > MAX_KEY_ID_LEN {
+            tracing::error!(
+                key_id = self.key_id.as_str(),
+                "key id too long for the header"
+            );
+            return Err(S3Error::InternalError);
+        }
+        let mut key_id = [0u8; MAX_KEY_ID_LEN];
+        key_id[..self.key_id.len()].copy_from_slice(self.key_id.as_bytes());
+        buf.extend_from_slice(MAGIC);
+        buf.push(FORMAT_VERSION);
+        buf.push(match self.algorithm {
+            SseAlgorithm::Aes256 => 1,
+            SseAlgorithm::AwsKms => 2,
+        });
+        buf.push(self.key_id.len() as u8);
+        buf.extend_from_slice(&key_id);
+        buf.extend_from_slice(&self.version.to_be_bytes());
+        buf.extend_from_slice(&self.wrapped);
+        Ok(())
+    }
+
+    fn set_object_info(&self, object: &mut ObjectInfo) {
+        object.sse_algorithm = self.algorithm.as_str().to_string();
+        if self.algorithm == SseAlgorithm::AwsKms {
+            object.sse_kms_key_id = self.key_id.to_owned();
+        }
+    }
+}
+
+/// The size of the data of an encrypted object from its stored size,
+/// None if the size can't be of an encrypted object.
+fn plaintext_size(stored: u64) -> Option<u64> {
+    let sealed = stored.checked_sub(HEADER_LEN as u64)?;
+    let chunks = sealed.div_ceil((CHUNK_SIZE + TAG_LEN) as u64).max(1);
+    sealed.checked_sub(chunks * TAG_LEN as u64)
+}
+
+/// Chunk nonces are unique per data key by the chunk index, and mark the
+/// last chunk so a truncated object fails authentication.
+fn chunk_nonce(index: u64, last: bool) -> aead::Nonce {
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    nonce[0] = last as u8;
+    nonce[4..].copy_from_slice(&index.to_be_bytes());
+    aead::Nonce::assume_unique_for_key(nonce)
+}
+
+fn chunk_key(key: &[u8]) -> Result<aead::LessSafeKey, S3Error> {
+    let key = aead::UnboundKey::new(&aead::AES_256_GCM, key).map_err(|_| S3Error::InternalError)?;
+    Ok(aead::LessSafeKey::new(key))
+}
+
+/// Seal the data in chunks, empty data is a single empty chunk.
+fn seal_chunks(key: &[u8], data: &[u8], out: &mut Vec<u8>) -> Result<(), S3Error> {
+    let key = chunk_key(key)?;
+    let count = data.len().div_ceil(CHUNK_SIZE).max(1);
+    for index in 0..count {
+        let chunk = &data[index * CHUNK_SIZE..data.len().min((index + 1) * CHUNK_SIZE)];
+        let start = out.len();
+        out.extend_from_slice(chunk);
+        let tag = key
+            .seal_in_place_separate_tag(
+                chunk_nonce(index as u64, index + 1 == count),
+                aead::Aad::empty(),
+                &mut out[start..],
+            )
+            .map_err(|_| S3Error::InternalError)?;
+        out.extend_from_slice(tag.as_ref());
+    }
+    Ok(())
+}
+
+/// Decrypt the bytes start..end of the data from the sealed chunks that cover them.
+fn open_range(
+    key: &[u8],
+    sealed: &[u8],
+    chunks: &ChunkRange,
+    start: u64,
+    end: u64,
+) -> Result<Bytes, S3Error> {
+    let key = chunk_key(key)?;
+    let sealed_chunk = CHUNK_SIZE + TAG_LEN;
+    let mut data = Vec::with_capacity((chunks.last - chunks.first + 1) as usize * CHUNK_SIZE);
+    for (i, chunk) in sealed.chunks(sealed_chunk).enumerate() {
+        let index = chunks.first + i as u64;
+        if index > chunks.last {
+            break;
+        }
+        let mut buf = chunk.to_vec();
+        let plain = key
+            .open_in_place(
+                chunk_nonce(index, index + 1 == chunks.count),
+                aead::Aad::empty(),
+                &mut buf,
+            )
+            .map_err(|_| {
+                tracing::warn!(chunk = index, "encrypted object failed authentication");
+                S3Error::InternalError
+            })?;
+        data.extend_from_slice(plain);
+    }
+    let offset = (start - chunks.first * CHUNK_SIZE as u64) as usize;
+    let len = (end - start) as usize;
+    if offset + len > data.len() {
+        tracing::warn!("encrypted object is truncated");
+        return Err(S3Error::InternalError);
+    }
+    Ok(Bytes::from(data).slice(offset..offset + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alice() -> Principal {
+        Principal::User(UserInfo {
+            id: String::from("alice"),
+            display_name: String::from("alice"),
+        })
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn sealed(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; HEADER_LEN];
+        seal_chunks(key, data, &mut out).unwrap();
+        out
+    }
+
+    /// Open start..end of the sealed object like get_object, from the covering chunks.
+    fn open(key: &[u8], stored: &[u8], start: u64, end: u64) -> Result<Bytes, S3Error> {
+        let chunks = ChunkRange::new(stored.len() as u64, start, end);
+        let sealed = &stored[chunks.stored_start as usize..chunks.stored_end as usize];
+        open_range(key, sealed, &chunks, start, end)
+    }
+
+    #[test]
+    fn chunks_round_trip_across_boundaries() {
+        let key = [7u8; 32];
+        for len in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE + 5,
+        ] {
+            let data = data(len);
+            let stored = sealed(&key, &data);
+            assert_eq!(plaintext_size(stored.len() as u64), Some(len as u64));
+            assert_eq!(open(&key, &stored, 0, len as u64).unwrap(), data);
+        }
+        let data = data(3 * CHUNK_SIZE + 5);
+        let stored = sealed(&key, &data);
+        let len = data.len() as u64;
+        let chunk = CHUNK_SIZE as u64;
+        for (start, end) in [
+            (chunk - 1, chunk + 1),
+            (chunk, 2 * chunk),
+            (10, 2 * chunk + 10),
+            (3 * chunk, len),
+            (len - 1, len),
+        ] {
+            let range = open(&key, &stored, start, end).unwrap();
+            assert_eq!(range, &data[start as usize..end as usize]);
+        }
+    }
+
+    #[test]
+    fn tampering_fails_authentication() {
+        let key = [7u8; 32];
+        let data = data(2 * CHUNK_SIZE + 1);
+        let mut stored = sealed(&key, &data);
+        let len = data.len() as u64;
+        let flipped = HEADER_LEN + CHUNK_SIZE + TAG_LEN + 3;
+        stored[flipped] ^= 1;
+        assert!(open(&key, &stored, 0, CHUNK_SIZE as u64).is_ok());
+        assert!(open(&key, &stored, 0, len).is_err());
+        stored[flipped] ^= 1;
+        assert!(open(&[8u8; 32], &stored, 0, len).is_err());
+        // dropping the last chunk leaves a chunk that is not sealed as the last one
+        let truncated = &stored[..HEADER_LEN + 2 * (CHUNK_SIZE + TAG_LEN)];
+        assert!(open(&key, truncated, 0, 2 * CHUNK_SIZE as u64).is_err());
+        // swapping chunks changes their nonces
+        let mut swapped = stored.clone();
+        let (a, b) = (HEADER_LEN, HEADER_LEN + CHUNK_SIZE + TAG_LEN);
+        let first = stored[a..b].to_vec();
+        swapped.copy_within(b..b + CHUNK_SIZE + TAG_LEN, a);
+        swapped[b..b + CHUNK_SIZE + TAG_LEN].copy_from_slice(&first);
+        assert!(open(&key, &swapped, 0, CHUNK_SIZE as u64).is_err());
+    }
+
+    #[test]
+    fn envelope_rejects_long_key_ids() {
+        let envelope = Envelope {
+            algorithm: SseAlgorithm::AwsKms,
+            key_id: "k".repeat(MAX_KEY_ID_LEN + 1),
+            version: 1,
+            wrapped: vec![0u8; WRAPPED_KEY_LEN],
+        };
+        assert!(envelope.write(&mut Vec::new()).is_err());
+    }
+
+    async fn layer() -> EncryptionLayer<MemLayer> {
+        let layer = EncryptionLayer::<MemLayer>::new();
+        let mut req = put_bucket::Req::new(put_bucket::Params {
+            bucket: String::from("bucket"),
+            class: String::new(),
+            region: String::new(),
+            acl: AclHeaders::default(),
+            object_lock_enabled: false,
+            body: None,
+        });
+        req.extensions_mut().insert(alice());
+        layer.put_bucket(req).await.unwrap();
+        layer
+    }
+
+    fn put(key: &str, data: Vec<u8>) -> put_object::Req {
+        let mut req = put_object::Req::new(put_object::Params {
+            bucket: String::from("bucket"),
+            key: key.to_string(),
+            acl: AclHeaders::default(),
+            tagging: Tagging::default(),
+            website_redirect_location: String::new(),
+            sse_customer: None,
+            sse: None,
+            object_lock: ObjectLock::default(),
+            plaintext_size: None,
+            body: Some(Body::from(data)),
+        });
+        req.extensions_mut().insert(alice());
+        req
+    }
+
+    fn get(key: &str, head_only: bool, range: ObjectRange) -> get_object::Req {
+        get_object::Req::new(get_object::Params {
+            bucket: String::from("bucket"),
+            key: key.to_string(),
+            version_id: String::new(),
+            head_only,
+            range,
+            sse_customer: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn range_reads() {
+        let layer = layer().await;
+        let data = data(2 * CHUNK_SIZE + 100);
+        layer.put_object(put("key", data.clone())).await.unwrap();
+        let range = ObjectRange::parse(Some("bytes=65530-65545"));
+        let reply = layer.get_object(get("key", false, range)).await;
+        let reply = reply.unwrap().into_body();
+        assert_eq!(reply.object.size, data.len() as u64);
+        assert_eq!(reply.range, Some((65530, 65546)));
+        assert_eq!(read_body(reply.body).await.unwrap(), &data[65530..65546]);
+        let range = ObjectRange::parse(Some("bytes=-10"));
+        let reply = layer.get_object(get("key", false, range)).await;
+        let body = reply.unwrap().into_body().body;
+        assert_eq!(read_body(body).await.unwrap(), &data[data.len() - 10..]);
+        let range = ObjectRange::parse(Some("bytes=200000-"));
+        assert!(matches!(
+            layer.get_object(get("key", false, range)).await,
+            Err(S3Error::InvalidRange)
+        ));
+        let full = ObjectRange::parse(None);
+        let reply = layer.get_object(get("key", true, full)).await;
+        let reply = reply.unwrap().into_body();
+        assert_eq!(reply.object.size, data.len() as u64);
+        assert_eq!(reply.object.sse_algorithm, "AES256");
+        assert!(reply.body.is_none());
+    }
+
+    #[tokio::test]
+    async fn plain_objects_keep_their_size() {
+        let layer = layer().await;
+        layer.put_object(put("encrypted", data(10))).await.unwrap();
+        // written under the layer, e.g. before encryption was enabled
+        let plain = data(HEADER_LEN + 100);
+        layer.inner().put_object(put("plain", plain)).await.unwrap();
+        let full = ObjectRange::parse(None);
+        let reply = layer.get_object(get("plain", false, full)).await;
+        let body = reply.unwrap().into_body().body;
+        assert_eq!(read_body(body).await.unwrap().len(), HEADER_LEN + 100);
+        let req = list_objects::Req::new(list_objects::Params {
+            bucket: String::from("bucket"),
+            prefix: String::new(),
+            delimiter: String::new(),
+            marker: String::new(),
+            max_keys: list_objects::DEFAULT_MAX_KEYS,
+            encoding_type: String::new(),
+            v2: false,
+            start_after: String::new(),
+            continuation_token: String::new(),
+            fetch_owner: false,
+        });
+        let objects = layer.list_objects(req).await.unwrap().into_body().objects;
+        // the MemLayer lists in no particular order
+        let mut sizes: Vec<_> = objects.iter().map(|o| (o.key.as_str(), o.size)).collect();
+        sizes.sort();
+        assert_eq!(
+            sizes,
+            [("encrypted", 10), ("plain", HEADER_LEN as u64 + 100)]
+        );
+    }
+}
@@ -1,126 +1,1059 @@
 use crate::api::*;
+use crate::auth::*;
 use async_trait::async_trait;
-use hyper::{body::to_bytes, Body};
+use chrono::{SecondsFormat, Utc};
+use hyper::Body;
+use md5::{Digest, Md5};
+use ring::digest;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{ErrorKind, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{Mutex, OwnedMutexGuard},
+};
 
+/// The owner, region and tags of a bucket, kept in the bucket directory.
+const BUCKET_FILE: &str = ".s3d-bucket.json";
+/// The directory of the object metadata in the bucket directory, a JSON file per
+/// object named by the SHA-256 of the key so any key maps to a valid name.
+const META_DIR: &str = ".s3d-meta";
+/// The directory of the object data in the bucket directory, each write has
+/// its own file which the metadata of the object names.
+const DATA_DIR: &str = ".s3d-data";
+/// Names in the bucket directory that belong to the layer, anything else makes
+/// the bucket non-empty.
+const LAYER_FILES_PREFIX: &str = ".s3d-";
+/// Files being written are named with this prefix until they are renamed in place.
+const TMP_PREFIX: &str = ".tmp-";
+
+/// FSLayer stores buckets as directories under the root directory.
+/// Each object has a metadata file, with its tags, that names the data file of
+/// its last write. Writes replace the metadata file atomically and then remove
+/// the data of the previous write, so readers see either version whole.
+/// Directories created outside the layer are buckets too, owned by whoever asks.
 #[derive(Debug, Clone)]
-pub struct FSLayer;
+pub struct FSLayer {
+    root: PathBuf,
+    /// serializes the read-modify-write of the metadata files of each bucket
+    locks: Arc<std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BucketFile {
+    owner_id: String,
+    owner_name: String,
+    class: String,
+    region: String,
+    #[serde(default)]
+    tagging: Option<Tagging>,
+}
+
+/// ObjectEntry is the metadata file of an object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ObjectEntry {
+    key: String,
+    /// the name of the data file in DATA_DIR
+    data: String,
+    size: u64,
+    etag: String,
+    last_modified: String,
+    owner_id: String,
+    owner_name: String,
+    #[serde(default)]
+    website_redirect_location: String,
+    /// set for SSE-C objects, whose data is encrypted with the customer key
+    #[serde(default)]
+    sse: Option<SseFingerprint>,
+    #[serde(default)]
+    sse_customer_key_md5: String,
+    #[serde(default)]
+    tagging: Tagging,
+    /// see ObjectInfo::plaintext_size
+    #[serde(default)]
+    plaintext_size: Option<u64>,
+}
 
 #[async_trait]
 impl ApiLayer for FSLayer {
+    /// The root is S3D_FS_ROOT or `.s3d` in the working directory.
     fn new() -> Self {
-        FSLayer {}
+        let root = std::env::var("S3D_FS_ROOT").unwrap_or_else(|_| ".s3d".to_string());
+        Self::with_root(root)
+    }
+
+    fn describe(&self) -> LayerInfo {
+        LayerInfo::new("FSLayer").config("root", self.root.to_string_lossy().as_ref())
+    }
+
+    /// The root is writable when a file can be created and removed in it.
+    async fn probe(&self) -> Result<(), SyncError> {
+        let path = self.root.join(format!(".probe-{}", new_request_id()));
+        tokio::fs::write(&path, b"").await?;
+        tokio::fs::remove_file(&path).await?;
+        Ok(())
+    }
+
+    async fn usage(&self) -> Result<Vec<BucketUsage>, S3Error> {
+        let mut usage = Vec::new();
+        for name in self.bucket_names().await? {
+            let objects = self.object_entries(&name, "").await?;
+            usage.push(BucketUsage {
+                info: self.bucket_info(&name, None).await?,
+                objects: objects.len() as u64,
+                bytes: objects.values().map(|o| o.size).sum(),
+            });
+        }
+        Ok(usage)
     }
 
-    async fn list_buckets(&self, _req: list_buckets::Req) -> list_buckets::Ret {
-        let buckets = Vec::<BucketInfo>::new();
+    async fn list_buckets(&self, req: list_buckets::Req) -> list_buckets::Ret {
+        // only the buckets owned by the requester are listed
+        let owner = Principal::of(&req).owner();
+        let mut buckets = Vec::<BucketInfo>::new();
+        for name in self.bucket_names().await? {
+            let info = self.bucket_info(&name, Some(&owner)).await?;
+            if info.owner.id == owner.id {
+                buckets.push(info);
+            }
+        }
         Ok(list_buckets::Res::new(list_buckets::Reply {
             buckets,
             next_marker: String::new(),
             is_truncated: false,
-            owner: UserInfo {
-                id: String::from("222"),
-                display_name: String::from("user222"),
-            },
+            owner,
         }))
     }
 
     async fn get_bucket(&self, req: get_bucket::Req) -> get_bucket::Ret {
+        let owner = Principal::of(&req).owner();
         let body = &req.into_body();
-        let info = self.make_bucket_info(body.bucket.as_str());
+        let info = self.bucket_info(&body.bucket, Some(&owner)).await?;
         Ok(get_bucket::Res::new(get_bucket::Reply { info }))
     }
 
     async fn put_bucket(&self, req: put_bucket::Req) -> put_bucket::Ret {
+        let owner = Principal::of(&req).owner();
         let body = &req.into_body();
-        let info = self.make_bucket_info(body.bucket.as_str());
+        // directories keep no object lock state to enforce
+        if body.object_lock_enabled {
+            return Err(S3Error::NotImplemented);
+        }
+        let dir = self.bucket_dir(&body.bucket)?;
+        let created = async {
+            tokio::fs::create_dir_all(&self.root).await?;
+            tokio::fs::create_dir(&dir).await
+        };
+        match created.await {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                let info = self.bucket_info(&body.bucket, Some(&owner)).await?;
+                return match info.owner.id == owner.id {
+                    true => Err(S3Error::BucketAlreadyOwnedByYou),
+                    false => Err(S3Error::BucketAlreadyExists),
+                };
+            }
+            Err(err) => {
+                tracing::warn!(?dir, %err, "create bucket failed");
+                return Err(S3Error::InternalError);
+            }
+        }
+        let file = BucketFile {
+            owner_id: owner.id,
+            owner_name: owner.display_name,
+            class: body.class.to_owned(),
+            region: body.region.to_owned(),
+            tagging: None,
+        };
+        write_json(&dir.join(BUCKET_FILE), &file).await?;
+        let info = self.bucket_info(&body.bucket, None).await?;
         Ok(put_bucket::Res::new(put_bucket::Reply { info }))
     }
 
     async fn delete_bucket(&self, req: delete_bucket::Req) -> delete_bucket::Ret {
         let body = &req.into_body();
-        let info = self.make_bucket_info(body.bucket.as_str());
+        let _lock = self.lock_bucket(&body.bucket).await;
+        let info = self.bucket_info(&body.bucket, None).await?;
+        let dir = self.bucket_dir(&body.bucket)?;
+        // anything kept under the bucket makes it non-empty, S3 deletes only empty buckets
+        if !body.force
+            && (self.has_objects(&body.bucket).await? || self.has_foreign_files(&dir).await?)
+        {
+            return Err(S3Error::BucketNotEmpty);
+        }
+        tokio::fs::remove_dir_all(&dir).await.map_err(|err| {
+            tracing::warn!(?dir, %err, "delete bucket failed");
+            S3Error::InternalError
+        })?;
         Ok(delete_bucket::Res::new(delete_bucket::Reply { info }))
     }
 
+    /// Every listing reads the metadata of the objects under the prefix,
+    /// the files are named by hash so they have no order of their own.
     async fn list_objects(&self, req: list_objects::Req) -> list_objects::Ret {
         let body = &req.into_body();
-        let mut objects = Vec::<ObjectInfo>::new();
-        for i in 1..4 {
-            let object =
-                self.make_object_info(body.bucket.as_str(), format!("object_{}", i).as_str());
-            if object.key.starts_with(&body.prefix) {
-                objects.push(object.clone());
-            }
-        }
-        let common_prefixes = Vec::<String>::new();
+        let entries = self.object_entries(&body.bucket, &body.prefix).await?;
+        let page = list_page(&entries, body);
+        let objects = page
+            .objects
+            .iter()
+            .map(|entry| entry.to_object_info(&body.bucket))
+            .collect();
         Ok(list_objects::Res::new(list_objects::Reply {
             objects,
-            common_prefixes,
-            next_marker: String::new(),
-            is_truncated: false,
+            common_prefixes: page.common_prefixes,
+            next_marker: page.next_marker,
+            is_truncated: page.is_truncated,
             bucket: body.bucket.to_owned(),
             prefix: body.prefix.to_owned(),
             delimiter: body.delimiter.to_owned(),
             marker: body.marker.to_owned(),
             max_keys: body.max_keys,
             encoding_type: body.encoding_type.to_owned(),
+            v2: body.v2,
+            start_after: body.start_after.to_owned(),
+            continuation_token: body.continuation_token.to_owned(),
+            fetch_owner: body.fetch_owner,
         }))
     }
 
     async fn get_object(&self, req: get_object::Req) -> get_object::Ret {
         let body = &req.into_body();
-        let object = self.make_object_info(body.bucket.as_str(), body.key.as_str());
-        Ok(get_object::Res::new(get_object::Reply {
-            object,
-            body: if body.head_only {
-                None
-            } else {
-                Some(Body::from(""))
-            },
-        }))
+        // an overwrite removes the data file it replaced, which a read that
+        // loaded the metadata before finds missing, and then reads the new one
+        let mut retries = 1;
+        loop {
+            let entry = self.object_entry(&body.bucket, &body.key).await?;
+            check_sse_key(entry.sse.as_ref(), body.sse_customer.as_ref())?;
+            let object = entry.to_object_info(&body.bucket);
+            let range = body.range.resolve(object.size)?;
+            if body.head_only {
+                return Ok(get_object::Res::new(get_object::Reply {
+                    object,
+                    range,
+                    body: None,
+                }));
+            }
+            let path = self.data_dir(&body.bucket)?.join(&entry.data);
+            // encrypted data is sealed as a whole, plain data is read from the range only
+            let read = match (&body.sse_customer, range) {
+                (None, Some((start, end))) => read_range(&path, start, end).await,
+                _ => tokio::fs::read(&path).await,
+            };
+            let data = match read {
+                Ok(data) => data,
+                Err(err) if err.kind() == ErrorKind::NotFound && retries > 0 => {
+                    retries -= 1;
+                    continue;
+                }
+                Err(err) => {
+                    tracing::warn!(?path, %err, "read object failed");
+                    return Err(match err.kind() {
+                        ErrorKind::NotFound => S3Error::NoSuchKey,
+                        _ => S3Error::InternalError,
+                    });
+                }
+            };
+            let data = match (&body.sse_customer, range) {
+                (Some(key), Some((start, end))) => {
+                    key.decrypt(&data)?.slice(start as usize..end as usize)
+                }
+                (Some(key), None) => key.decrypt(&data)?,
+                (None, _) => data.into(),
+            };
+            return Ok(get_object::Res::new(get_object::Reply {
+                object,
+                range,
+                body: Some(Body::from(data)),
+            }));
+        }
     }
 
     async fn put_object(&self, req: put_object::Req) -> put_object::Ret {
+        let owner = Principal::of(&req).owner();
         let body = req.into_body();
-        let buf = to_bytes(body.body.unwrap()).await.unwrap();
-        let mut object = self.make_object_info(body.bucket.as_str(), body.key.as_str());
-        object.size = buf.len() as u64;
-        Ok(put_object::Res::new(put_object::Reply { object }))
+        if !body.object_lock.is_empty() {
+            return Err(S3Error::InvalidRequest);
+        }
+        self.existing_bucket_dir(&body.bucket).await?;
+        let buf = read_body(body.body).await?;
+        let mut entry = ObjectEntry {
+            key: body.key.to_owned(),
+            data: format!("{}-{}", key_hash(&body.key), new_request_id()),
+            size: buf.len() as u64,
+            etag: format!("{:x}", Md5::digest(&buf)),
+            last_modified: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            owner_id: owner.id,
+            owner_name: owner.display_name,
+            website_redirect_location: body.website_redirect_location,
+            sse: None,
+            sse_customer_key_md5: String::new(),
+            tagging: body.tagging,
+            plaintext_size: body.plaintext_size,
+        };
+        // only the key fingerprint is kept, never the key
+        let data = match &body.sse_customer {
+            Some(key) => {
+                entry.sse = Some(key.fingerprint());
+                entry.sse_customer_key_md5 = key.key_md5().to_string();
+                key.encrypt(&buf)?
+            }
+            None => buf,
+        };
+        // the data file is new, it becomes the object when the metadata names it
+        let data_dir = self.data_dir(&body.bucket)?;
+        let path = data_dir.join(&entry.data);
+        let written = async {
+            tokio::fs::create_dir_all(&data_dir).await?;
+            tokio::fs::write(&path, &data).await
+        };
+        if let Err(err) = written.await {
+            tracing::warn!(?path, %err, "write object failed");
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(S3Error::InternalError);
+        }
+        let replaced = {
+            let _lock = self.lock_bucket(&body.bucket).await;
+            let replaced = self.find_entry(&body.bucket, &body.key).await;
+            match self.write_entry(&body.bucket, &entry).await {
+                Ok(()) => replaced,
+                Err(err) => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    return Err(err);
+                }
+            }
+        };
+        if let Ok(Some(replaced)) = replaced {
+            self.remove_data(&body.bucket, &replaced).await;
+        }
+        Ok(put_object::Res::new(put_object::Reply {
+            object: entry.to_object_info(&body.bucket),
+        }))
     }
 
     async fn delete_object(&self, req: delete_object::Req) -> delete_object::Ret {
         let body = &req.into_body();
-        let object = self.make_object_info(body.bucket.as_str(), body.key.as_str());
-        Ok(delete_object::Res::new(delete_object::Reply { object }))
+        let entry = {
+            let _lock = self.lock_bucket(&body.bucket).await;
+            let entry = self.object_entry(&body.bucket, &body.key).await?;
+            let path = self.meta_path(&body.bucket, &body.key)?;
+            tokio::fs::remove_file(&path).await.map_err(|err| {
+                tracing::warn!(?path, %err, "delete object failed");
+                match err.kind() {
+                    ErrorKind::NotFound => S3Error::NoSuchKey,
+                    _ => S3Error::InternalError,
+                }
+            })?;
+            entry
+        };
+        self.remove_data(&body.bucket, &entry).await;
+        Ok(delete_object::Res::new(delete_object::Reply {
+            object: entry.to_object_info(&body.bucket),
+        }))
+    }
+
+    async fn get_bucket_tagging(&self, req: get_bucket_tagging::Req) -> get_bucket_tagging::Ret {
+        let body = &req.into_body();
+        let file = self.bucket_file(&body.bucket).await?;
+        let tagging = file.tagging.ok_or(S3Error::NoSuchTagSet)?;
+        Ok(get_bucket_tagging::Res::new(get_bucket_tagging::Reply {
+            tagging,
+        }))
+    }
+
+    async fn put_bucket_tagging(&self, req: put_bucket_tagging::Req) -> put_bucket_tagging::Ret {
+        let body = req.into_body();
+        let tagging = Tagging::read(body.body, MAX_BUCKET_TAGS).await?;
+        self.update_bucket_file(&body.bucket, |file| file.tagging = Some(tagging))
+            .await?;
+        Ok(put_bucket_tagging::Res::new(put_bucket_tagging::Reply {}))
+    }
+
+    async fn delete_bucket_tagging(
+        &self,
+        req: delete_bucket_tagging::Req,
+    ) -> delete_bucket_tagging::Ret {
+        let body = &req.into_body();
+        self.update_bucket_file(&body.bucket, |file| file.tagging = None)
+            .await?;
+        Ok(delete_bucket_tagging::Res::new(
+            delete_bucket_tagging::Reply {},
+        ))
+    }
+
+    async fn get_object_tagging(&self, req: get_object_tagging::Req) -> get_object_tagging::Ret {
+        let body = &req.into_body();
+        let entry = self.object_entry(&body.bucket, &body.key).await?;
+        Ok(get_object_tagging::Res::new(get_object_tagging::Reply {
+            version_id: String::new(),
+            tagging: entry.tagging,
+        }))
+    }
+
+    async fn put_object_tagging(&self, req: put_object_tagging::Req) -> put_object_tagging::Ret {
+        let body = req.into_body();
+        self.object_entry(&body.bucket, &body.key).await?;
+        let tagging = Tagging::read(body.body, MAX_OBJECT_TAGS).await?;
+        self.update_entry(&body.bucket, &body.key, |entry| entry.tagging = tagging)
+            .await?;
+        Ok(put_object_tagging::Res::new(put_object_tagging::Reply {
+            version_id: String::new(),
+        }))
+    }
+
+    async fn delete_object_tagging(
+        &self,
+        req: delete_object_tagging::Req,
+    ) -> delete_object_tagging::Ret {
+        let body = &req.into_body();
+        self.update_entry(&body.bucket, &body.key, |entry| {
+            entry.tagging = Tagging::default()
+        })
+        .await?;
+        Ok(delete_object_tagging::Res::new(
+            delete_object_tagging::Reply {
+                version_id: String::new(),
+            },
+        ))
     }
 }
 
 impl FSLayer {
-    fn make_bucket_info(&self, bucket: &str) -> BucketInfo {
-        BucketInfo {
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        FSLayer {
+            root: root.as_ref().to_path_buf(),
+            locks: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Bucket names that S3 would not accept never map to a directory,
+    /// which keeps names like `..` from escaping the root.
+    fn bucket_dir(&self, bucket: &str) -> Result<PathBuf, S3Error> {
+        validate_bucket_name(bucket).map_err(|_| S3Error::NoSuchBucket)?;
+        Ok(self.root.join(bucket))
+    }
+
+    /// The bucket directory, or NoSuchBucket when it doesn't exist.
+    async fn existing_bucket_dir(&self, bucket: &str) -> Result<PathBuf, S3Error> {
+        let dir = self.bucket_dir(bucket)?;
+        match tokio::fs::metadata(&dir).await {
+            Ok(meta) if meta.is_dir() => Ok(dir),
+            Ok(_) => Err(S3Error::NoSuchBucket),
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(S3Error::NoSuchBucket),
+            Err(err) => {
+                tracing::warn!(?dir, %err, "stat bucket failed");
+                Err(S3Error::InternalError)
+            }
+        }
+    }
+
+    /// Hold the lock of the bucket to change its bucket file or object metadata.
+    async fn lock_bucket(&self, bucket: &str) -> OwnedMutexGuard<()> {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(bucket.to_string())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+
+    /// The directories under the root that are valid bucket names.
+    async fn bucket_names(&self) -> Result<Vec<String>, S3Error> {
+        let mut names = Vec::new();
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(names),
+            Err(err) => {
+                tracing::warn!(root = ?self.root, %err, "list buckets failed");
+                return Err(S3Error::InternalError);
+            }
+        };
+        while let Some(entry) = entries.next_entry().await.map_err(|err| {
+            tracing::warn!(root = ?self.root, %err, "list buckets failed");
+            S3Error::InternalError
+        })? {
+            let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+            if let Some(name) = entry.file_name().to_str() {
+                if is_dir && validate_bucket_name(name).is_ok() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Directories created outside the layer have no bucket file,
+    /// and are owned by the requester when given.
+    async fn bucket_info(
+        &self,
+        bucket: &str,
+        owner: Option<&UserInfo>,
+    ) -> Result<BucketInfo, S3Error> {
+        let file = self.bucket_file(bucket).await?;
+        let owner = match (file.owner_id.is_empty(), owner) {
+            (true, Some(owner)) => owner.clone(),
+            _ => UserInfo {
+                id: file.owner_id,
+                display_name: file.owner_name,
+            },
+        };
+        Ok(BucketInfo {
             name: bucket.to_string(),
-            class: format!("class_{}", bucket),
-            region: format!("region_{}", bucket),
+            class: file.class,
+            region: file.region,
+            owner,
+        })
+    }
+
+    /// Whether the bucket directory has entries that the layer didn't create.
+    async fn has_foreign_files(&self, dir: &Path) -> Result<bool, S3Error> {
+        let list = async {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if !entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(LAYER_FILES_PREFIX)
+                {
+                    return Ok(true);
+                }
+            }
+            Ok::<bool, std::io::Error>(false)
+        };
+        list.await.map_err(|err| {
+            tracing::warn!(?dir, %err, "list bucket failed");
+            S3Error::InternalError
+        })
+    }
+
+    /// Whether the bucket has objects, or metadata files being written.
+    async fn has_objects(&self, bucket: &str) -> Result<bool, S3Error> {
+        let dir = self.bucket_dir(bucket)?.join(META_DIR);
+        let list = async {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err),
+            };
+            Ok(entries.next_entry().await?.is_some())
+        };
+        list.await.map_err(|err| {
+            tracing::warn!(?dir, %err, "list objects failed");
+            S3Error::InternalError
+        })
+    }
+
+    fn meta_path(&self, bucket: &str, key: &str) -> Result<PathBuf, S3Error> {
+        let name = format!("{}.json", key_hash(key));
+        Ok(self.bucket_dir(bucket)?.join(META_DIR).join(name))
+    }
+
+    fn data_dir(&self, bucket: &str) -> Result<PathBuf, S3Error> {
+        Ok(self.bucket_dir(bucket)?.join(DATA_DIR))
+    }
+
+    /// The metadata of the object, or NoSuchKey.
+    async fn object_entry(&self, bucket: &str, key: &str) -> Result<ObjectEntry, S3Error> {
+        self.find_entry(bucket, key)
+            .await?
+            .ok_or(S3Error::NoSuchKey)
+    }
+
+    /// The metadata of the object, None when there is no such key.
+    async fn find_entry(&self, bucket: &str, key: &str) -> Result<Option<ObjectEntry>, S3Error> {
+        self.existing_bucket_dir(bucket).await?;
+        read_json(&self.meta_path(bucket, key)?).await
+    }
+
+    /// The metadata of the objects whose keys start with the prefix, by key.
+    async fn object_entries(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<BTreeMap<String, ObjectEntry>, S3Error> {
+        let dir = self.existing_bucket_dir(bucket).await?.join(META_DIR);
+        let mut objects = BTreeMap::new();
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(objects),
+            Err(err) => {
+                tracing::warn!(?dir, %err, "list objects failed");
+                return Err(S3Error::InternalError);
+            }
+        };
+        while let Some(file) = entries.next_entry().await.map_err(|err| {
+            tracing::warn!(?dir, %err, "list objects failed");
+            S3Error::InternalError
+        })? {
+            if file.file_name().to_string_lossy().starts_with(TMP_PREFIX) {
+                continue;
+            }
+            // removed by a racing delete since the directory was read
+            if let Some(entry) = read_json::<ObjectEntry>(&file.path()).await? {
+                if entry.key.starts_with(prefix) {
+                    objects.insert(entry.key.to_owned(), entry);
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Replace the metadata of the object, callers hold the bucket lock.
+    async fn write_entry(&self, bucket: &str, entry: &ObjectEntry) -> Result<(), S3Error> {
+        let path = self.meta_path(bucket, &entry.key)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(|err| {
+                tracing::warn!(?dir, %err, "write object failed");
+                S3Error::InternalError
+            })?;
+        }
+        write_json(&path, entry).await
+    }
+
+    /// Apply the change to the metadata of the object.
+    async fn update_entry<F>(&self, bucket: &str, key: &str, change: F) -> Result<(), S3Error>
+    where
+        F: FnOnce(&mut ObjectEntry),
+    {
+        let _lock = self.lock_bucket(bucket).await;
+        let mut entry = self.object_entry(bucket, key).await?;
+        change(&mut entry);
+        self.write_entry(bucket, &entry).await
+    }
+
+    /// Remove the data file of a write that was replaced or deleted.
+    async fn remove_data(&self, bucket: &str, entry: &ObjectEntry) {
+        let path = match self.data_dir(bucket) {
+            Ok(dir) => dir.join(&entry.data),
+            Err(_) => return,
+        };
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                tracing::warn!(?path, %err, "remove object data failed");
+            }
+            _ => {}
+        }
+    }
+
+    async fn bucket_file(&self, bucket: &str) -> Result<BucketFile, S3Error> {
+        let path = self.existing_bucket_dir(bucket).await?.join(BUCKET_FILE);
+        Ok(read_json(&path).await?.unwrap_or_default())
+    }
+
+    /// Apply the change to the bucket file.
+    async fn update_bucket_file<F>(&self, bucket: &str, change: F) -> Result<(), S3Error>
+    where
+        F: FnOnce(&mut BucketFile),
+    {
+        let _lock = self.lock_bucket(bucket).await;
+        let mut file = self.bucket_file(bucket).await?;
+        change(&mut file);
+        write_json(&self.bucket_dir(bucket)?.join(BUCKET_FILE), &file).await
+    }
+}
+
+/// The hex SHA-256 of the key, which names its files.
+fn key_hash(key: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, key.as_bytes());
+    hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Read a JSON file of the layer, None when it doesn't exist.
+async fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, S3Error> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            tracing::warn!(?path, %err, "read metadata failed");
+            return Err(S3Error::InternalError);
+        }
+    };
+    serde_json::from_slice(&data).map(Some).map_err(|err| {
+        tracing::warn!(?path, %err, "parse metadata failed");
+        S3Error::InternalError
+    })
+}
+
+/// Write a JSON file of the layer, to a temp file first that is renamed
+/// over it, so readers see either the old or the new file whole.
+async fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), S3Error> {
+    let tmp = path.with_file_name(format!("{}{}", TMP_PREFIX, new_request_id()));
+    let data = serde_json::to_vec(value).map_err(|err| {
+        tracing::warn!(?path, %err, "serialize metadata failed");
+        S3Error::InternalError
+    })?;
+    let written = async {
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, path).await
+    };
+    if let Err(err) = written.await {
+        tracing::warn!(?path, %err, "write metadata failed");
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(S3Error::InternalError);
+    }
+    Ok(())
+}
+
+/// ListPage is the part of a listing that a request returns.
+#[derive(Debug)]
+struct ListPage<T> {
+    objects: Vec<T>,
+    common_prefixes: Vec<String>,
+    is_truncated: bool,
+    next_marker: String,
+}
+
+/// The page of the sorted entries after the marker, up to max-keys. With a
+/// delimiter the keys that have it after the prefix are rolled up into their
+/// common prefix, which counts as a single key like in S3.
+fn list_page<'a, T>(
+    entries: &'a BTreeMap<String, T>,
+    params: &list_objects::Params,
+) -> ListPage<&'a T> {
+    let max_keys = params.max_keys.max(0) as usize;
+    let mut page = ListPage {
+        objects: Vec::new(),
+        common_prefixes: Vec::new(),
+        is_truncated: false,
+        next_marker: String::new(),
+    };
+    let start = std::ops::Bound::Excluded(params.marker.to_owned());
+    for (key, entry) in entries.range((start, std::ops::Bound::Unbounded)) {
+        if !key.starts_with(&params.prefix) {
+            continue;
+        }
+        let rest = &key[params.prefix.len()..];
+        let common_prefix = match params.delimiter.is_empty() {
+            true => None,
+            false => rest
+                .find(&params.delimiter)
+                .map(|i| key[..params.prefix.len() + i + params.delimiter.len()].to_string()),
+        };
+        if let Some(prefix) = &common_prefix {
+            // the marker can be a common prefix returned by the previous page
+            if *prefix <= params.marker || page.common_prefixes.last() == Some(prefix) {
+                continue;
+            }
+        }
+        if page.objects.len() + page.common_prefixes.len() >= max_keys {
+            page.is_truncated = true;
+            break;
+        }
+        match common_prefix {
+            Some(prefix) => {
+                page.next_marker = prefix.to_owned();
+                page.common_prefixes.push(prefix);
+            }
+            None => {
+                page.next_marker = key.to_owned();
+                page.objects.push(entry);
+            }
+        }
+    }
+    if !page.is_truncated {
+        page.next_marker.clear();
+    }
+    page
+}
+
+async fn read_range(path: &Path, start: u64, end: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut data = vec![0u8; (end - start) as usize];
+    file.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+impl ObjectEntry {
+    fn to_object_info(&self, bucket: &str) -> ObjectInfo {
+        ObjectInfo {
+            bucket: bucket.to_string(),
+            key: self.key.to_owned(),
+            version_id: String::new(),
+            size: self.size,
+            last_modified: self.last_modified.to_owned(),
+            etag: self.etag.to_owned(),
+            storage_class: String::from("STANDARD"),
             owner: UserInfo {
-                id: format!("user_id_{}", bucket),
-                display_name: format!("user_name_{}", bucket),
+                id: self.owner_id.to_owned(),
+                display_name: self.owner_name.to_owned(),
             },
+            tag_count: self.tagging.len(),
+            website_redirect_location: self.website_redirect_location.to_owned(),
+            sse_customer_key_md5: self.sse_customer_key_md5.to_owned(),
+            sse_algorithm: String::new(),
+            sse_kms_key_id: String::new(),
+            object_lock: ObjectLock::default(),
+            replication_status: String::new(),
+            plaintext_size: self.plaintext_size,
         }
     }
+}
 
-    fn make_object_info(&self, bucket: &str, key: &str) -> ObjectInfo {
-        ObjectInfo {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::Bytes;
+
+    fn alice() -> Principal {
+        Principal::User(UserInfo {
+            id: String::from("alice"),
+            display_name: String::from("alice"),
+        })
+    }
+
+    fn temp_layer() -> FSLayer {
+        FSLayer::with_root(std::env::temp_dir().join(format!("s3d-fs-{}", new_request_id())))
+    }
+
+    fn sse_key(byte: u8) -> SseCustomerKey {
+        SseCustomerKey::from_bytes([byte; 32])
+    }
+
+    async fn put_bucket(layer: &FSLayer, bucket: &str) -> Result<BucketInfo, S3Error> {
+        let mut req = put_bucket::Req::new(put_bucket::Params {
             bucket: bucket.to_string(),
+            class: String::new(),
+            region: String::new(),
+            acl: AclHeaders::default(),
+            object_lock_enabled: false,
+            body: None,
+        });
+        req.extensions_mut().insert(alice());
+        Ok(layer.put_bucket(req).await?.into_body().info)
+    }
+
+    async fn put_object(
+        layer: &FSLayer,
+        key: &str,
+        data: &'static str,
+        sse_customer: Option<SseCustomerKey>,
+    ) -> Result<ObjectInfo, S3Error> {
+        let mut req = put_object::Req::new(put_object::Params {
+            bucket: String::from("bucket"),
             key: key.to_string(),
-            version_id: format!("version_id_{}_{}", bucket, key),
-            last_modified: format!("last_modified_{}_{}", bucket, key),
-            etag: format!("etag_{}_{}", bucket, key),
-            storage_class: format!("storage_class_{}_{}", bucket, key),
-            size: 0,
-            owner: UserInfo {
-                id: format!("user_id_{}_{}", bucket, key),
-                display_name: format!("user_name_{}_{}", bucket, key),
+            acl: AclHeaders::default(),
+            tagging: Tagging::default(),
+            website_redirect_location: String::new(),
+            sse_customer,
+            sse: None,
+            object_lock: ObjectLock::default(),
+            plaintext_size: None,
+            body: Some(Body::from(data)),
+        });
+        req.extensions_mut().insert(alice());
+        Ok(layer.put_object(req).await?.into_body().object)
+    }
+
+    async fn get_object(
+        layer: &FSLayer,
+        key: &str,
+        sse_customer: Option<SseCustomerKey>,
+    ) -> Result<Bytes, S3Error> {
+        let req = get_object::Req::new(get_object::Params {
+            bucket: String::from("bucket"),
+            key: key.to_string(),
+            version_id: String::new(),
+            head_only: false,
+            range: ObjectRange {
+                start: None,
+                end: None,
             },
+            sse_customer,
+        });
+        let body = layer.get_object(req).await?.into_body().body;
+        read_body(body).await
+    }
+
+    async fn delete_bucket(layer: &FSLayer, force: bool) -> Result<(), S3Error> {
+        let req = delete_bucket::Req::new(delete_bucket::Params {
+            bucket: String::from("bucket"),
+            force,
+        });
+        layer.delete_bucket(req).await.map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn objects_are_stored() {
+        let layer = temp_layer();
+        let info = put_bucket(&layer, "bucket").await.unwrap();
+        assert_eq!(info.owner.id, "alice");
+        assert!(matches!(
+            put_bucket(&layer, "bucket").await,
+            Err(S3Error::BucketAlreadyOwnedByYou)
+        ));
+        let object = put_object(&layer, "a/b", "hello", None).await.unwrap();
+        assert_eq!(object.size, 5);
+        assert_eq!(object.etag, "5d41402abc4b2a76b9719d911017c592");
+        assert_eq!(get_object(&layer, "a/b", None).await.unwrap(), "hello");
+        assert!(matches!(
+            get_object(&layer, "missing", None).await,
+            Err(S3Error::NoSuchKey)
+        ));
+        let usage = layer.usage().await.unwrap();
+        assert_eq!((usage[0].objects, usage[0].bytes), (1, 5));
+        std::fs::remove_dir_all(layer.root()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sse_c_objects_are_stored_encrypted() {
+        let layer = temp_layer();
+        put_bucket(&layer, "bucket").await.unwrap();
+        put_object(&layer, "secret", "plaintext", Some(sse_key(1)))
+            .await
+            .unwrap();
+        let entry = layer.object_entry("bucket", "secret").await.unwrap();
+        let stored = std::fs::read(layer.data_dir("bucket").unwrap().join(entry.data)).unwrap();
+        assert!(!stored.windows(9).any(|w| w == b"plaintext"));
+        let data = get_object(&layer, "secret", Some(sse_key(1))).await;
+        assert_eq!(data.unwrap(), "plaintext");
+        assert!(matches!(
+            get_object(&layer, "secret", Some(sse_key(2))).await,
+            Err(S3Error::AccessDenied)
+        ));
+        assert!(matches!(
+            get_object(&layer, "secret", None).await,
+            Err(S3Error::InvalidRequest)
+        ));
+        std::fs::remove_dir_all(layer.root()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_bucket_refuses_non_empty_buckets() {
+        let layer = temp_layer();
+        put_bucket(&layer, "bucket").await.unwrap();
+        put_object(&layer, "key", "data", None).await.unwrap();
+        assert!(matches!(
+            delete_bucket(&layer, false).await,
+            Err(S3Error::BucketNotEmpty)
+        ));
+        let req = delete_object::Req::new(delete_object::Params {
+            bucket: String::from("bucket"),
+            key: String::from("key"),
+            version_id: String::new(),
+            bypass_governance: false,
+        });
+        layer.delete_object(req).await.unwrap();
+        // files put in the directory by hand count too
+        let stray = layer.root().join("bucket").join("stray");
+        std::fs::write(&stray, b"").unwrap();
+        assert!(matches!(
+            delete_bucket(&layer, false).await,
+            Err(S3Error::BucketNotEmpty)
+        ));
+        std::fs::remove_file(&stray).unwrap();
+        delete_bucket(&layer, false).await.unwrap();
+        assert!(matches!(
+            delete_bucket(&layer, false).await,
+            Err(S3Error::NoSuchBucket)
+        ));
+        put_bucket(&layer, "bucket").await.unwrap();
+        put_object(&layer, "key", "data", None).await.unwrap();
+        delete_bucket(&layer, true).await.unwrap();
+        assert!(!layer.root().join("bucket").exists());
+        std::fs::remove_dir_all(layer.root()).unwrap();
+    }
+
+    fn list(prefix: &str, delimiter: &str, marker: &str, max_keys: i32) -> list_objects::Req {
+        list_objects::Req::new(list_objects::Params {
+            bucket: String::from("bucket"),
+            prefix: prefix.to_string(),
+            delimiter: delimiter.to_string(),
+            marker: marker.to_string(),
+            max_keys,
+            encoding_type: String::new(),
+            v2: false,
+            start_after: String::new(),
+            continuation_token: String::new(),
+            fetch_owner: false,
+        })
+    }
+
+    /// The keys and common prefixes of the page, and the next marker when truncated.
+    async fn list_keys(layer: &FSLayer, req: list_objects::Req) -> (Vec<String>, Option<String>) {
+        let reply = layer.list_objects(req).await.unwrap().into_body();
+        let mut keys: Vec<String> = reply.objects.into_iter().map(|o| o.key).collect();
+        keys.extend(reply.common_prefixes);
+        match reply.is_truncated {
+            true => (keys, Some(reply.next_marker)),
+            false => (keys, None),
         }
     }
+
+    #[tokio::test]
+    async fn objects_are_listed_in_pages() {
+        let layer = temp_layer();
+        put_bucket(&layer, "bucket").await.unwrap();
+        for key in &["a", "b/1", "b/2", "c/1", "d"] {
+            put_object(&layer, key, "data", None).await.unwrap();
+        }
+        let (keys, next) = list_keys(&layer, list("", "", "", 1000)).await;
+        assert_eq!(keys, ["a", "b/1", "b/2", "c/1", "d"]);
+        assert_eq!(next, None);
+        let (keys, next) = list_keys(&layer, list("", "/", "", 1000)).await;
+        assert_eq!(keys, ["a", "d", "b/", "c/"]);
+        assert_eq!(next, None);
+        // each common prefix counts as one key, the next page starts after it
+        let (keys, next) = list_keys(&layer, list("", "/", "", 2)).await;
+        assert_eq!(keys, ["a", "b/"]);
+        assert_eq!(next.as_deref(), Some("b/"));
+        let (keys, next) = list_keys(&layer, list("", "/", "b/", 2)).await;
+        assert_eq!(keys, ["d", "c/"]);
+        assert_eq!(next, None);
+        let (keys, next) = list_keys(&layer, list("b/", "", "b/1", 1)).await;
+        assert_eq!(keys, ["b/2"]);
+        assert_eq!(next, None);
+        let (keys, next) = list_keys(&layer, list("", "", "", 0)).await;
+        assert!(keys.is_empty());
+        assert!(next.is_some());
+        std::fs::remove_dir_all(layer.root()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overwrites_replace_the_data_and_tags() {
+        let layer = temp_layer();
+        put_bucket(&layer, "bucket").await.unwrap();
+        let mut req = put_object::Req::new(put_object::Params {
+            bucket: String::from("bucket"),
+            key: String::from("key"),
+            acl: AclHeaders::default(),
+            tagging: Tagging::from_header("team=a").unwrap(),
+            website_redirect_location: String::new(),
+            sse_customer: None,
+            sse: None,
+            object_lock: ObjectLock::default(),
+            plaintext_size: None,
+            body: Some(Body::from("first")),
+        });
+        req.extensions_mut().insert(alice());
+        assert_eq!(
+            layer
+                .put_object(req)
+                .await
+                .unwrap()
+                .into_body()
+                .object
+                .tag_count,
+            1
+        );
+        put_object(&layer, "key", "second", None).await.unwrap();
+        assert_eq!(get_object(&layer, "key", None).await.unwrap(), "second");
+        let entry = layer.object_entry("bucket", "key").await.unwrap();
+        assert!(entry.tagging.is_empty());
+        // only the data of the last write is kept
+        let data_files = std::fs::read_dir(layer.data_dir("bucket").unwrap()).unwrap();
+        assert_eq!(data_files.count(), 1);
+        std::fs::remove_dir_all(layer.root()).unwrap();
+    }
 }
@@ -0,0 +1,339 @@
+use crate::api::*;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{SecondsFormat, Utc};
+use rand::RngCore;
+use ring::aead;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+/// The master key of SSE-S3 (AES256) objects, created on first use.
+pub const SSE_S3_KEY_ID: &str = "s3d/sse-s3";
+/// The KMS key of aws:kms objects without a key id, created on first use like the aws/s3 key of S3.
+pub const DEFAULT_KMS_KEY_ID: &str = "aws/s3";
+pub const MAX_KEY_ID_LEN: usize = 64;
+
+/// The length of a data key wrapped by a master key, the nonce, the sealed key and its tag.
+pub const WRAPPED_KEY_LEN: usize = aead::NONCE_LEN + DATA_KEY_LEN + aead::MAX_TAG_LEN;
+const DATA_KEY_LEN: usize = 32;
+
+/// Keyring is a local stand-in for KMS, it keeps versioned 256 bit master keys
+/// by key id, optionally in a JSON file.
+///
+/// Objects are encrypted with their own data key, which is stored wrapped by
+/// the latest version of a master key. Rotation adds a new version for new objects,
+/// the older versions are kept to unwrap the data keys of existing objects.
+pub struct Keyring {
+    /// None keeps the keys only in memory
+    path: Option<PathBuf>,
+    file: Mutex<KeyringFile>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeyringFile {
+    keys: BTreeMap<String, MasterKey>,
+}
+
+/// The versions of a master key, the last one is current.
+#[derive(Debug, Serialize, Deserialize)]
+struct MasterKey {
+    versions: Vec<KeyVersion>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyVersion {
+    created: String,
+    /// base64 of the 256 bit key
+    material: String,
+}
+
+/// DataKey encrypts a single object, see Keyring::generate_data_key().
+pub struct DataKey {
+    pub key: [u8; DATA_KEY_LEN],
+    pub key_id: String,
+    pub version: u32,
+    pub wrapped: [u8; WRAPPED_KEY_LEN],
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring").field("path", &self.path).finish()
+    }
+}
+
+impl Keyring {
+    /// A keyring that lives only as long as the process,
+    /// objects encrypted with it can't be read after a restart.
+    pub fn memory() -> Self {
+        Keyring {
+            path: None,
+            file: Mutex::new(KeyringFile::default()),
+        }
+    }
+
+    /// Open the keyring file, or create it when missing.
+    /// The file holds the master keys so it is only readable by the owner.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, SyncError> {
+        let path = path.as_ref().to_path_buf();
+        let file = match tokio::fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => KeyringFile::default(),
+            Err(err) => return Err(err.into()),
+        };
+        // the key id goes in the header of every object, see create_key
+        if let Some(key_id) = file.keys.keys().find(|id| !valid_key_id(id)) {
+            return Err(format!("invalid key id {:?} in keyring {:?}", key_id, path).into());
+        }
+        // every key needs a current version with valid material to wrap data keys
+        for (key_id, master) in file.keys.iter() {
+            if master.versions.is_empty() || master.versions.iter().any(|v| v.aead_key().is_err()) {
+                return Err(
+                    format!("invalid versions of key {:?} in keyring {:?}", key_id, path).into(),
+                );
+            }
+        }
+        let keyring = Keyring {
+            path: Some(path),
+            file: Mutex::new(file),
+        };
+        keyring.save(&*keyring.file.lock().await).await?;
+        Ok(keyring)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub async fn key_ids(&self) -> Vec<String> {
+        self.file.lock().await.keys.keys().cloned().collect()
+    }
+
+    /// Create a master key, keys that exist are kept as is.
+    pub async fn create_key(&self, key_id: &str) -> Result<(), S3Error> {
+        if !valid_key_id(key_id) {
+            return Err(S3Error::InvalidArgument);
+        }
+        let mut file = self.file.lock().await;
+        if file.keys.contains_key(key_id) {
+            return Ok(());
+        }
+        file.keys.insert(
+            key_id.to_string(),
+            MasterKey {
+                versions: vec![KeyVersion::random()],
+            },
+        );
+        self.save_or_log(&file).await
+    }
+
+    /// Add a new version of the master key, returning its number.
+    pub async fn rotate_key(&self, key_id: &str) -> Result<u32, S3Error> {
+        let mut file = self.file.lock().await;
+        let master = file.keys.get_mut(key_id).ok_or(S3Error::InvalidArgument)?;
+        master.versions.push(KeyVersion::random());
+        let version = master.versions.len() as u32;
+        self.save_or_log(&file).await?;
+        Ok(version)
+    }
+
+    /// Rotate every master key, returning how many were rotated.
+    pub async fn rotate_all(&self) -> Result<usize, S3Error> {
+        let mut file = self.file.lock().await;
+        for master in file.keys.values_mut() {
+            master.versions.push(KeyVersion::random());
+        }
+        self.save_or_log(&file).await?;
+        Ok(file.keys.len())
+    }
+
+    /// A new data key wrapped by the current version of the master key.
+    /// The default keys are created on first use, other keys must exist like in KMS.
+    pub async fn generate_data_key(&self, key_id: &str) -> Result<DataKey, S3Error> {
+        if key_id == SSE_S3_KEY_ID || key_id == DEFAULT_KMS_KEY_ID {
+            self.create_key(key_id).await?;
+        }
+        let file = self.file.lock().await;
+        let master = match file.keys.get(key_id) {
+            Some(master) => master,
+            None => {
+                tracing::debug!(key_id, "no such master key");
+                return Err(S3Error::InvalidArgument);
+            }
+        };
+        let version = master.versions.len() as u32;
+        let master_key = master
+            .versions
+            .get((version as usize).wrapping_sub(1))
+            .ok_or_else(|| {
+                tracing::warn!(key_id, "master key has no versions");
+                S3Error::InternalError
+            })?
+            .aead_key()?;
+        let mut key = [0u8; DATA_KEY_LEN];
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = key.to_vec();
+        master_key
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(key_aad(key_id, version)),
+                &mut sealed,
+            )
+            .map_err(|_| S3Error::InternalError)?;
+        let mut wrapped = [0u8; WRAPPED_KEY_LEN];
+        wrapped[..aead::NONCE_LEN].copy_from_slice(&nonce);
+        wrapped[aead::NONCE_LEN..].copy_from_slice(&sealed);
+        Ok(DataKey {
+            key,
+            key_id: key_id.to_string(),
+            version,
+            wrapped,
+        })
+    }
+
+    /// Unwrap the data key of an object with the master key version that wrapped it.
+    pub async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        version: u32,
+        wrapped: &[u8],
+    ) -> Result<[u8; DATA_KEY_LEN], S3Error> {
+        let file = self.file.lock().await;
+        let master_key = match file
+            .keys
+            .get(key_id)
+            .and_then(|m| m.versions.get((version as usize).checked_sub(1)?))
+        {
+            Some(v) => v.aead_key()?,
+            None => {
+                tracing::warn!(key_id, version, "master key of object not in the keyring");
+                return Err(S3Error::InternalError);
+            }
+        };
+        if wrapped.len() != WRAPPED_KEY_LEN {
+            return Err(S3Error::InternalError);
+        }
+        let (nonce, sealed) = wrapped.split_at(aead::NONCE_LEN);
+        let nonce =
+            aead::Nonce::try_assume_unique_for_key(nonce).map_err(|_| S3Error::InternalError)?;
+        let mut buf = sealed.to_vec();
+        let key = master_key
+            .open_in_place(nonce, aead::Aad::from(key_aad(key_id, version)), &mut buf)
+            .map_err(|_| {
+                tracing::warn!(key_id, version, "data key failed authentication");
+                S3Error::InternalError
+            })?;
+        let mut out = [0u8; DATA_KEY_LEN];
+        out.copy_from_slice(key);
+        Ok(out)
+    }
+
+    async fn save_or_log(&self, file: &KeyringFile) -> Result<(), S3Error> {
+        self.save(file).await.map_err(|err| {
+            tracing::error!(path = ?self.path, %err, "write keyring failed");
+            S3Error::InternalError
+        })
+    }
+
+    /// Write the keyring to a temp file first so a crash never leaves a partial file.
+    async fn save(&self, file: &KeyringFile) -> Result<(), SyncError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let tmp = path.with_extension(format!("tmp.{}", new_request_id()));
+        let data = serde_json::to_vec_pretty(file)?;
+        let mut out = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)
+            .await?;
+        out.write_all(&data).await?;
+        out.sync_all().await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+impl KeyVersion {
+    fn random() -> Self {
+        let mut material = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut material);
+        KeyVersion {
+            created: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            material: BASE64.encode(material),
+        }
+    }
+
+    fn aead_key(&self) -> Result<aead::LessSafeKey, S3Error> {
+        let material = BASE64.decode(&self.material).map_err(|_| {
+            tracing::warn!("master key material is not base64");
+            S3Error::InternalError
+        })?;
+        let key = aead::UnboundKey::new(&aead::AES_256_GCM, &material)
+            .map_err(|_| S3Error::InternalError)?;
+        Ok(aead::LessSafeKey::new(key))
+    }
+}
+
+/// Data keys are bound to the master key that wrapped them.
+/// Key ids are visible ASCII that fits the header of encrypted objects.
+fn valid_key_id(key_id: &str) -> bool {
+    !key_id.is_empty()
+        && key_id.len() <= MAX_KEY_ID_LEN
+        && key_id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn key_aad(key_id: &str, version: u32) -> Vec<u8> {
+    let mut aad = key_id.as_bytes().to_vec();
+    aad.extend_from_slice(&version.to_be_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn open_rejects_key_ids_that_dont_fit_the_header() {
+        let path = std::env::temp_dir().join(format!("s3d-keyring-{}", new_request_id()));
+        let key_id = "k".repeat(MAX_KEY_ID_LEN + 1);
+        let version = serde_json::to_string(&KeyVersion::random()).unwrap();
+        let file = format!(
+            r#"{{"keys":{{"{}":{{"versions":[{}]}}}}}}"#,
+            key_id, version
+        );
+        tokio::fs::write(&path, file).await.unwrap();
+        assert!(Keyring::open(&path).await.is_err());
+        tokio::fs::remove_file(&path).await.unwrap();
+        let keyring = Keyring::memory();
+        assert!(keyring.create_key(&key_id).await.is_err());
+        assert!(keyring.create_key("kms/ok").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn open_rejects_keys_without_versions() {
+        let path = std::env::temp_dir().join(format!("s3d-keyring-{}", new_request_id()));
+        let version = serde_json::to_string(&KeyVersion::random()).unwrap();
+        for versions in &[
+            String::new(),
+            String::from(r#"{"created":"","material":"short"}"#),
+        ] {
+            let file = format!(r#"{{"keys":{{"kms/a":{{"versions":[{}]}}}}}}"#, versions);
+            tokio::fs::write(&path, file).await.unwrap();
+            assert!(Keyring::open(&path).await.is_err());
+        }
+        let file = format!(r#"{{"keys":{{"kms/a":{{"versions":[{}]}}}}}}"#, version);
+        tokio::fs::write(&path, file).await.unwrap();
+        let keyring = Keyring::open(&path).await.unwrap();
+        let key = keyring.generate_data_key("kms/a").await.unwrap();
+        assert_eq!(key.version, 1);
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -1,9 +1,8 @@
 use crate::api::*;
+use crate::auth::*;
 use async_trait::async_trait;
-use hyper::{
-    body::{to_bytes, Bytes},
-    Body,
-};
+use chrono::{SecondsFormat, Utc};
+use hyper::{body::Bytes, Body};
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
@@ -22,12 +21,34 @@ pub struct MemLayer {
 struct Bucket {
     info: BucketInfo,
     objects: HashMap<String, ObjectArc>,
+    policy: Option<String>,
+    acl: Acl,
+    tagging: Option<Tagging>,
+    lifecycle: Option<LifecycleConfiguration>,
+    cors: Option<CorsConfiguration>,
+    website: Option<WebsiteConfiguration>,
+    encryption: Option<ServerSideEncryptionConfiguration>,
+    /// set when the bucket is created with Object Lock enabled
+    object_lock: Option<ObjectLockConfiguration>,
+    notification: Option<NotificationConfiguration>,
+    replication: Option<ReplicationConfiguration>,
+}
+
+impl Bucket {
+    /// Anything kept under the bucket makes it non-empty, S3 deletes only empty buckets.
+    fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Object {
     object: ObjectInfo,
     buf: Bytes,
+    acl: Acl,
+    tags: Tagging,
+    /// set for SSE-C objects, whose buf is encrypted with the customer key
+    sse: Option<SseFingerprint>,
 }
 
 #[async_trait]
@@ -37,23 +58,24 @@ impl ApiLayer for MemLayer {
         MemLayer { buckets_arc }
     }
 
-    async fn list_buckets(&self, _req: list_buckets::Req) -> list_buckets::Ret {
+    async fn list_buckets(&self, req: list_buckets::Req) -> list_buckets::Ret {
+        // only the buckets owned by the requester are listed
+        let owner = Principal::of(&req).owner();
         let mut buckets = Vec::<BucketInfo>::new();
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let buckets_rlock = buckets_arc.read().unwrap();
         for it in buckets_rlock.values() {
             let bucket_arc = Arc::clone(it);
             let bucket_rlock = bucket_arc.read().unwrap();
-            buckets.push(bucket_rlock.info.clone());
+            if bucket_rlock.info.owner.id == owner.id {
+                buckets.push(bucket_rlock.info.clone());
+            }
         }
         Ok(list_buckets::Res::new(list_buckets::Reply {
             buckets,
             next_marker: String::new(),
             is_truncated: false,
-            owner: UserInfo {
-                id: String::from("222"),
-                display_name: String::from("user222"),
-            },
+            owner,
         }))
     }
 
@@ -62,7 +84,7 @@ impl ApiLayer for MemLayer {
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let buckets_rlock = buckets_arc.read().unwrap();
         let bucket_arc = match buckets_rlock.get(&body.bucket) {
-            Some(a) => Arc::clone(&a),
+            Some(a) => Arc::clone(a),
             None => return Err(S3Error::NoSuchBucket),
         };
         let bucket_rlock = bucket_arc.read().unwrap();
@@ -71,16 +93,36 @@ impl ApiLayer for MemLayer {
     }
 
     async fn put_bucket(&self, req: put_bucket::Req) -> put_bucket::Ret {
+        let owner = Principal::of(&req).owner();
         let body = &req.into_body();
-        let info = self.make_bucket_info(body.bucket.as_str());
+        let mut info = self.make_bucket_info(body.bucket.as_str());
+        info.owner = owner.clone();
+        info.region = body.region.to_owned();
+        let acl = body.acl.to_acl(&owner, &owner)?;
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let mut buckets_wlock = buckets_arc.write().unwrap();
-        if buckets_wlock.contains_key(&body.bucket) {
+        if let Some(existing) = buckets_wlock.get(&body.bucket) {
+            if existing.read().unwrap().info.owner.id == owner.id {
+                return Err(S3Error::BucketAlreadyOwnedByYou);
+            }
             return Err(S3Error::BucketAlreadyExists);
         }
         let bucket_arc = Arc::new(RwLock::new(Bucket {
             info: info.clone(),
             objects: HashMap::new(),
+            policy: None,
+            acl,
+            tagging: None,
+            lifecycle: None,
+            cors: None,
+            website: None,
+            encryption: None,
+            object_lock: match body.object_lock_enabled {
+                true => Some(ObjectLockConfiguration::enabled()),
+                false => None,
+            },
+            notification: None,
+            replication: None,
         }));
         buckets_wlock.insert(body.bucket.to_owned(), bucket_arc);
         Ok(put_bucket::Res::new(put_bucket::Reply { info }))
@@ -90,11 +132,25 @@ impl ApiLayer for MemLayer {
         let body = &req.into_body();
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let mut buckets_wlock = buckets_arc.write().unwrap();
-        let bucket_arc = match buckets_wlock.remove(&body.bucket) {
-            Some(a) => Arc::clone(&a),
+        let bucket_arc = match buckets_wlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
             None => return Err(S3Error::NoSuchBucket),
         };
         let mut bucket_wlock = bucket_arc.write().unwrap();
+        if !body.force && !bucket_wlock.is_empty() {
+            return Err(S3Error::BucketNotEmpty);
+        }
+        // not even a forced delete removes objects under legal hold or COMPLIANCE retention
+        let now = Utc::now();
+        for object_arc in bucket_wlock.objects.values() {
+            object_arc
+                .read()
+                .unwrap()
+                .object
+                .object_lock
+                .check_delete(true, now)?;
+        }
+        buckets_wlock.remove(&body.bucket);
         let info = bucket_wlock.info.clone();
         bucket_wlock.objects.clear();
         Ok(delete_bucket::Res::new(delete_bucket::Reply { info }))
@@ -105,7 +161,7 @@ impl ApiLayer for MemLayer {
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let buckets_rlock = buckets_arc.read().unwrap();
         let bucket_arc = match buckets_rlock.get(&body.bucket) {
-            Some(a) => Arc::clone(&a),
+            Some(a) => Arc::clone(a),
             None => return Err(S3Error::NoSuchBucket),
         };
         let bucket_rlock = bucket_arc.read().unwrap();
@@ -129,6 +185,10 @@ impl ApiLayer for MemLayer {
             marker: body.marker.to_owned(),
             max_keys: body.max_keys,
             encoding_type: body.encoding_type.to_owned(),
+            v2: body.v2,
+            start_after: body.start_after.to_owned(),
+            continuation_token: body.continuation_token.to_owned(),
+            fetch_owner: body.fetch_owner,
         }))
     }
 
@@ -137,40 +197,82 @@ impl ApiLayer for MemLayer {
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let buckets_rlock = buckets_arc.read().unwrap();
         let bucket_arc = match buckets_rlock.get(&body.bucket) {
-            Some(a) => Arc::clone(&a),
+            Some(a) => Arc::clone(a),
             None => return Err(S3Error::NoSuchBucket),
         };
         let bucket_rlock = bucket_arc.read().unwrap();
         let object_arc = match bucket_rlock.objects.get(&body.key) {
-            Some(a) => Arc::clone(&a),
+            Some(a) => Arc::clone(a),
             None => return Err(S3Error::NoSuchKey),
         };
         let object_rlock = object_arc.read().unwrap();
+        check_sse_key(object_rlock.sse.as_ref(), body.sse_customer.as_ref())?;
+        let range = body.range.resolve(object_rlock.object.size)?;
+        let buf = match (&body.sse_customer, body.head_only) {
+            (_, true) => None,
+            (Some(key), false) => Some(key.decrypt(&object_rlock.buf)?),
+            (None, false) => Some(object_rlock.buf.clone()),
+        };
+        let buf = match range {
+            Some((start, end)) => buf.map(|b| b.slice(start as usize..end as usize)),
+            None => buf,
+        };
         Ok(get_object::Res::new(get_object::Reply {
             object: object_rlock.object.clone(),
-            body: if body.head_only {
-                None
-            } else {
-                Some(Body::from(object_rlock.buf.clone()))
-            },
+            range,
+            body: buf.map(Body::from),
         }))
     }
 
     async fn put_object(&self, req: put_object::Req) -> put_object::Ret {
+        let owner = Principal::of(&req).owner();
         let body = req.into_body();
-        let buf = to_bytes(body.body.unwrap()).await.unwrap();
+        let buf = read_body(body.body).await?;
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let buckets_rlock = buckets_arc.read().unwrap();
         let bucket_arc = match buckets_rlock.get(&body.bucket) {
-            Some(a) => Arc::clone(&a),
+            Some(a) => Arc::clone(a),
             None => return Err(S3Error::NoSuchBucket),
         };
         let mut bucket_wlock = bucket_arc.write().unwrap();
+        let acl = body.acl.to_acl(&owner, &bucket_wlock.info.owner)?;
+        // without versions an overwrite loses the protected data like a delete
+        let now = Utc::now();
+        if let Some(existing) = bucket_wlock.objects.get(&body.key) {
+            existing
+                .read()
+                .unwrap()
+                .object
+                .object_lock
+                .check_delete(false, now)?;
+        }
+        let mut object_lock = body.object_lock;
+        match &bucket_wlock.object_lock {
+            Some(config) => config.apply_default(&mut object_lock, now),
+            None if !object_lock.is_empty() => return Err(S3Error::InvalidRequest),
+            None => {}
+        }
         let mut object = self.make_object_info(body.bucket.as_str(), body.key.as_str());
         object.size = buf.len() as u64;
+        object.owner = owner;
+        object.last_modified = now.to_rfc3339_opts(SecondsFormat::Millis, true);
+        object.tag_count = body.tagging.len();
+        object.object_lock = object_lock;
+        object.website_redirect_location = body.website_redirect_location;
+        object.plaintext_size = body.plaintext_size;
+        let (buf, sse) = match &body.sse_customer {
+            Some(key) => {
+                object.sse_customer_key_md5 = key.key_md5().to_string();
+                (key.encrypt(&buf)?, Some(key.fingerprint()))
+            }
+            None => (buf, None),
+        };
         let object_arc = Arc::new(RwLock::new(Object {
             object: object.clone(),
             buf,
+            acl,
+            tags: body.tagging,
+            sse,
         }));
         bucket_wlock.objects.insert(body.key.to_owned(), object_arc);
         Ok(put_object::Res::new(put_object::Reply { object }))
@@ -181,10 +283,18 @@ impl ApiLayer for MemLayer {
         let buckets_arc = Arc::clone(&self.buckets_arc);
         let buckets_rlock = buckets_arc.read().unwrap();
         let bucket_arc = match buckets_rlock.get(&body.bucket) {
-            Some(a) => Arc::clone(&a),
+            Some(a) => Arc::clone(a),
             None => return Err(S3Error::NoSuchBucket),
         };
         let mut bucket_wlock = bucket_arc.write().unwrap();
+        if let Some(object_arc) = bucket_wlock.objects.get(&body.key) {
+            object_arc
+                .read()
+                .unwrap()
+                .object
+                .object_lock
+                .check_delete(body.bypass_governance, Utc::now())?;
+        }
         let object_arc = match bucket_wlock.objects.remove(&body.key) {
             Some(a) => Arc::clone(&a),
             None => return Err(S3Error::NoSuchKey),
@@ -194,14 +304,754 @@ impl ApiLayer for MemLayer {
         object_wlock.buf.clear();
         Ok(delete_object::Res::new(delete_object::Reply { object }))
     }
+
+    async fn get_bucket_policy(&self, req: get_bucket_policy::Req) -> get_bucket_policy::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let policy = match &bucket_rlock.policy {
+            Some(p) => p.clone(),
+            None => return Err(S3Error::NoSuchBucketPolicy),
+        };
+        Ok(get_bucket_policy::Res::new(get_bucket_policy::Reply {
+            policy,
+        }))
+    }
+
+    async fn put_bucket_policy(&self, req: put_bucket_policy::Req) -> put_bucket_policy::Ret {
+        let body = req.into_body();
+        let buf = read_document(body.body).await?;
+        let policy = String::from_utf8(buf.to_vec()).map_err(|_| S3Error::MalformedPolicy)?;
+        Policy::parse(policy.as_str())?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.policy = Some(policy.clone());
+        Ok(put_bucket_policy::Res::new(put_bucket_policy::Reply {
+            policy,
+        }))
+    }
+
+    async fn delete_bucket_policy(
+        &self,
+        req: delete_bucket_policy::Req,
+    ) -> delete_bucket_policy::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.policy = None;
+        Ok(delete_bucket_policy::Res::new(
+            delete_bucket_policy::Reply {},
+        ))
+    }
+
+    async fn get_bucket_acl(&self, req: get_bucket_acl::Req) -> get_bucket_acl::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let acl = bucket_rlock.acl.clone();
+        Ok(get_bucket_acl::Res::new(get_bucket_acl::Reply { acl }))
+    }
+
+    async fn put_bucket_acl(&self, req: put_bucket_acl::Req) -> put_bucket_acl::Ret {
+        let body = req.into_body();
+        let buf = read_document(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        let owner = bucket_wlock.info.owner.clone();
+        let acl = self.make_acl(&body.acl, &buf, &owner, &owner)?;
+        bucket_wlock.acl = acl.clone();
+        Ok(put_bucket_acl::Res::new(put_bucket_acl::Reply { acl }))
+    }
+
+    async fn get_object_acl(&self, req: get_object_acl::Req) -> get_object_acl::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let object_arc = match bucket_rlock.objects.get(&body.key) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchKey),
+        };
+        let object_rlock = object_arc.read().unwrap();
+        let acl = object_rlock.acl.clone();
+        Ok(get_object_acl::Res::new(get_object_acl::Reply { acl }))
+    }
+
+    async fn put_object_acl(&self, req: put_object_acl::Req) -> put_object_acl::Ret {
+        let body = req.into_body();
+        let buf = read_document(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let object_arc = match bucket_rlock.objects.get(&body.key) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchKey),
+        };
+        let mut object_wlock = object_arc.write().unwrap();
+        let owner = object_wlock.acl.owner.clone();
+        let acl = self.make_acl(&body.acl, &buf, &owner, &bucket_rlock.info.owner)?;
+        object_wlock.acl = acl.clone();
+        Ok(put_object_acl::Res::new(put_object_acl::Reply { acl }))
+    }
+
+    async fn get_bucket_tagging(&self, req: get_bucket_tagging::Req) -> get_bucket_tagging::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let tagging = match &bucket_rlock.tagging {
+            Some(t) => t.clone(),
+            None => return Err(S3Error::NoSuchTagSet),
+        };
+        Ok(get_bucket_tagging::Res::new(get_bucket_tagging::Reply {
+            tagging,
+        }))
+    }
+
+    async fn put_bucket_tagging(&self, req: put_bucket_tagging::Req) -> put_bucket_tagging::Ret {
+        let body = req.into_body();
+        let tagging = Tagging::read(body.body, MAX_BUCKET_TAGS).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.tagging = Some(tagging);
+        Ok(put_bucket_tagging::Res::new(put_bucket_tagging::Reply {}))
+    }
+
+    async fn delete_bucket_tagging(
+        &self,
+        req: delete_bucket_tagging::Req,
+    ) -> delete_bucket_tagging::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.tagging = None;
+        Ok(delete_bucket_tagging::Res::new(
+            delete_bucket_tagging::Reply {},
+        ))
+    }
+
+    async fn get_object_tagging(&self, req: get_object_tagging::Req) -> get_object_tagging::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let object_arc = match bucket_rlock.objects.get(&body.key) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchKey),
+        };
+        let object_rlock = object_arc.read().unwrap();
+        Ok(get_object_tagging::Res::new(get_object_tagging::Reply {
+            version_id: object_rlock.object.version_id.clone(),
+            tagging: object_rlock.tags.clone(),
+        }))
+    }
+
+    async fn put_object_tagging(&self, req: put_object_tagging::Req) -> put_object_tagging::Ret {
+        let body = req.into_body();
+        let tagging = Tagging::read(body.body, MAX_OBJECT_TAGS).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let object_arc = match bucket_rlock.objects.get(&body.key) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchKey),
+        };
+        let mut object_wlock = object_arc.write().unwrap();
+        object_wlock.object.tag_count = tagging.len();
+        object_wlock.tags = tagging;
+        Ok(put_object_tagging::Res::new(put_object_tagging::Reply {
+            version_id: object_wlock.object.version_id.clone(),
+        }))
+    }
+
+    async fn delete_object_tagging(
+        &self,
+        req: delete_object_tagging::Req,
+    ) -> delete_object_tagging::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let object_arc = match bucket_rlock.objects.get(&body.key) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchKey),
+        };
+        let mut object_wlock = object_arc.write().unwrap();
+        object_wlock.object.tag_count = 0;
+        object_wlock.tags = Tagging::default();
+        Ok(delete_object_tagging::Res::new(
+            delete_object_tagging::Reply {
+                version_id: object_wlock.object.version_id.clone(),
+            },
+        ))
+    }
+
+    async fn get_bucket_lifecycle(
+        &self,
+        req: get_bucket_lifecycle::Req,
+    ) -> get_bucket_lifecycle::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let config = match &bucket_rlock.lifecycle {
+            Some(c) => c.clone(),
+            None => return Err(S3Error::NoSuchLifecycleConfiguration),
+        };
+        Ok(get_bucket_lifecycle::Res::new(
+            get_bucket_lifecycle::Reply { config },
+        ))
+    }
+
+    async fn put_bucket_lifecycle(
+        &self,
+        req: put_bucket_lifecycle::Req,
+    ) -> put_bucket_lifecycle::Ret {
+        let body = req.into_body();
+        let config = LifecycleConfiguration::read(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.lifecycle = Some(config);
+        Ok(put_bucket_lifecycle::Res::new(
+            put_bucket_lifecycle::Reply {},
+        ))
+    }
+
+    async fn delete_bucket_lifecycle(
+        &self,
+        req: delete_bucket_lifecycle::Req,
+    ) -> delete_bucket_lifecycle::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.lifecycle = None;
+        Ok(delete_bucket_lifecycle::Res::new(
+            delete_bucket_lifecycle::Reply {},
+        ))
+    }
+
+    async fn get_bucket_cors(&self, req: get_bucket_cors::Req) -> get_bucket_cors::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let config = match &bucket_rlock.cors {
+            Some(c) => c.clone(),
+            None => return Err(S3Error::NoSuchCORSConfiguration),
+        };
+        Ok(get_bucket_cors::Res::new(get_bucket_cors::Reply { config }))
+    }
+
+    async fn put_bucket_cors(&self, req: put_bucket_cors::Req) -> put_bucket_cors::Ret {
+        let body = req.into_body();
+        let config = CorsConfiguration::read(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.cors = Some(config);
+        Ok(put_bucket_cors::Res::new(put_bucket_cors::Reply {}))
+    }
+
+    async fn delete_bucket_cors(&self, req: delete_bucket_cors::Req) -> delete_bucket_cors::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.cors = None;
+        Ok(delete_bucket_cors::Res::new(delete_bucket_cors::Reply {}))
+    }
+
+    async fn get_bucket_website(&self, req: get_bucket_website::Req) -> get_bucket_website::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let config = match &bucket_rlock.website {
+            Some(c) => c.clone(),
+            None => return Err(S3Error::NoSuchWebsiteConfiguration),
+        };
+        Ok(get_bucket_website::Res::new(get_bucket_website::Reply {
+            config,
+        }))
+    }
+
+    async fn put_bucket_website(&self, req: put_bucket_website::Req) -> put_bucket_website::Ret {
+        let body = req.into_body();
+        let config = WebsiteConfiguration::read(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.website = Some(config);
+        Ok(put_bucket_website::Res::new(put_bucket_website::Reply {}))
+    }
+
+    async fn delete_bucket_website(
+        &self,
+        req: delete_bucket_website::Req,
+    ) -> delete_bucket_website::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.website = None;
+        Ok(delete_bucket_website::Res::new(
+            delete_bucket_website::Reply {},
+        ))
+    }
+
+    async fn get_bucket_encryption(
+        &self,
+        req: get_bucket_encryption::Req,
+    ) -> get_bucket_encryption::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let config = match &bucket_rlock.encryption {
+            Some(c) => c.clone(),
+            None => return Err(S3Error::ServerSideEncryptionConfigurationNotFoundError),
+        };
+        Ok(get_bucket_encryption::Res::new(
+            get_bucket_encryption::Reply { config },
+        ))
+    }
+
+    async fn put_bucket_encryption(
+        &self,
+        req: put_bucket_encryption::Req,
+    ) -> put_bucket_encryption::Ret {
+        let body = req.into_body();
+        let config = ServerSideEncryptionConfiguration::read(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.encryption = Some(config);
+        Ok(put_bucket_encryption::Res::new(
+            put_bucket_encryption::Reply {},
+        ))
+    }
+
+    async fn delete_bucket_encryption(
+        &self,
+        req: delete_bucket_encryption::Req,
+    ) -> delete_bucket_encryption::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.encryption = None;
+        Ok(delete_bucket_encryption::Res::new(
+            delete_bucket_encryption::Reply {},
+        ))
+    }
+
+    async fn get_object_lock_configuration(
+        &self,
+        req: get_object_lock_configuration::Req,
+    ) -> get_object_lock_configuration::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let config = match &bucket_rlock.object_lock {
+            Some(c) => c.clone(),
+            None => return Err(S3Error::ObjectLockConfigurationNotFoundError),
+        };
+        Ok(get_object_lock_configuration::Res::new(
+            get_object_lock_configuration::Reply { config },
+        ))
+    }
+
+    async fn put_object_lock_configuration(
+        &self,
+        req: put_object_lock_configuration::Req,
+    ) -> put_object_lock_configuration::Ret {
+        let body = req.into_body();
+        let config = ObjectLockConfiguration::read(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        if bucket_wlock.object_lock.is_none() {
+            return Err(S3Error::InvalidBucketState);
+        }
+        bucket_wlock.object_lock = Some(config);
+        Ok(put_object_lock_configuration::Res::new(
+            put_object_lock_configuration::Reply {},
+        ))
+    }
+
+    async fn get_object_retention(
+        &self,
+        req: get_object_retention::Req,
+    ) -> get_object_retention::Ret {
+        let body = &req.into_body();
+        let object_arc = self.locked_object(&body.bucket, &body.key)?;
+        let object_rlock = object_arc.read().unwrap();
+        let lock = &object_rlock.object.object_lock;
+        if lock.mode.is_none() {
+            return Err(S3Error::NoSuchObjectLockConfiguration);
+        }
+        Ok(get_object_retention::Res::new(
+            get_object_retention::Reply {
+                retention: lock.retention(),
+            },
+        ))
+    }
+
+    async fn put_object_retention(
+        &self,
+        req: put_object_retention::Req,
+    ) -> put_object_retention::Ret {
+        let body = req.into_body();
+        let retention = ObjectRetention::read(body.body).await?;
+        let now = Utc::now();
+        let object_arc = self.locked_object(&body.bucket, &body.key)?;
+        let mut object_wlock = object_arc.write().unwrap();
+        let mut lock = object_wlock.object.object_lock.clone();
+        lock.set_retention(&retention)?;
+        if lock.mode.is_some() && !lock.is_retained(now) {
+            return Err(S3Error::InvalidArgument);
+        }
+        object_wlock.object.object_lock.check_retention_change(
+            &lock,
+            body.bypass_governance,
+            now,
+        )?;
+        object_wlock.object.object_lock = lock;
+        Ok(put_object_retention::Res::new(
+            put_object_retention::Reply {},
+        ))
+    }
+
+    async fn get_object_legal_hold(
+        &self,
+        req: get_object_legal_hold::Req,
+    ) -> get_object_legal_hold::Ret {
+        let body = &req.into_body();
+        let object_arc = self.locked_object(&body.bucket, &body.key)?;
+        let object_rlock = object_arc.read().unwrap();
+        Ok(get_object_legal_hold::Res::new(
+            get_object_legal_hold::Reply {
+                legal_hold: LegalHold::new(object_rlock.object.object_lock.legal_hold),
+            },
+        ))
+    }
+
+    async fn put_object_legal_hold(
+        &self,
+        req: put_object_legal_hold::Req,
+    ) -> put_object_legal_hold::Ret {
+        let body = req.into_body();
+        let on = LegalHold::read(body.body).await?;
+        let object_arc = self.locked_object(&body.bucket, &body.key)?;
+        object_arc.write().unwrap().object.object_lock.legal_hold = on;
+        Ok(put_object_legal_hold::Res::new(
+            put_object_legal_hold::Reply {},
+        ))
+    }
+
+    async fn get_bucket_notification(
+        &self,
+        req: get_bucket_notification::Req,
+    ) -> get_bucket_notification::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let config = bucket_rlock.notification.clone().unwrap_or_default();
+        Ok(get_bucket_notification::Res::new(
+            get_bucket_notification::Reply { config },
+        ))
+    }
+
+    async fn put_bucket_notification(
+        &self,
+        req: put_bucket_notification::Req,
+    ) -> put_bucket_notification::Ret {
+        let body = req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.notification = match body.config.is_empty() {
+            true => None,
+            false => Some(body.config),
+        };
+        Ok(put_bucket_notification::Res::new(
+            put_bucket_notification::Reply {},
+        ))
+    }
+
+    async fn get_bucket_replication(
+        &self,
+        req: get_bucket_replication::Req,
+    ) -> get_bucket_replication::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        let config = match &bucket_rlock.replication {
+            Some(c) => c.clone(),
+            None => return Err(S3Error::ReplicationConfigurationNotFoundError),
+        };
+        Ok(get_bucket_replication::Res::new(
+            get_bucket_replication::Reply { config },
+        ))
+    }
+
+    async fn put_bucket_replication(
+        &self,
+        req: put_bucket_replication::Req,
+    ) -> put_bucket_replication::Ret {
+        let body = req.into_body();
+        let config = ReplicationConfiguration::read(body.body).await?;
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.replication = Some(config);
+        Ok(put_bucket_replication::Res::new(
+            put_bucket_replication::Reply {},
+        ))
+    }
+
+    async fn delete_bucket_replication(
+        &self,
+        req: delete_bucket_replication::Req,
+    ) -> delete_bucket_replication::Ret {
+        let body = &req.into_body();
+        let buckets_arc = Arc::clone(&self.buckets_arc);
+        let buckets_rlock = buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(&body.bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let mut bucket_wlock = bucket_arc.write().unwrap();
+        bucket_wlock.replication = None;
+        Ok(delete_bucket_replication::Res::new(
+            delete_bucket_replication::Reply {},
+        ))
+    }
+
+    async fn usage(&self) -> Result<Vec<BucketUsage>, S3Error> {
+        let buckets_rlock = self.buckets_arc.read().unwrap();
+        let mut usage = Vec::new();
+        for bucket_arc in buckets_rlock.values() {
+            let bucket_rlock = bucket_arc.read().unwrap();
+            let bytes = bucket_rlock
+                .objects
+                .values()
+                .map(|o| o.read().unwrap().object.size)
+                .sum();
+            usage.push(BucketUsage {
+                info: bucket_rlock.info.clone(),
+                objects: bucket_rlock.objects.len() as u64,
+                bytes,
+            });
+        }
+        usage.sort_by(|a, b| a.info.name.cmp(&b.info.name));
+        Ok(usage)
+    }
+
+    async fn stats(&self) -> Vec<LayerStat> {
+        let buckets_rlock = self.buckets_arc.read().unwrap();
+        let mut objects = 0;
+        let mut bytes = 0;
+        for bucket_arc in buckets_rlock.values() {
+            let bucket_rlock = bucket_arc.read().unwrap();
+            objects += bucket_rlock.objects.len();
+            for object_arc in bucket_rlock.objects.values() {
+                bytes += object_arc.read().unwrap().buf.len();
+            }
+        }
+        vec![
+            LayerStat::gauge(
+                "s3d_mem_buckets",
+                "Buckets in memory.",
+                buckets_rlock.len() as f64,
+            ),
+            LayerStat::gauge("s3d_mem_objects", "Objects in memory.", objects as f64),
+            LayerStat::gauge(
+                "s3d_mem_bytes",
+                "Object data bytes in memory.",
+                bytes as f64,
+            ),
+        ]
+    }
 }
 
 impl MemLayer {
+    /// ACL updates come either from an AccessControlPolicy body or from headers.
+    fn make_acl(
+        &self,
+        headers: &AclHeaders,
+        buf: &Bytes,
+        owner: &UserInfo,
+        bucket_owner: &UserInfo,
+    ) -> Result<Acl, S3Error> {
+        if buf.is_empty() {
+            return headers.to_acl(owner, bucket_owner);
+        }
+        if !headers.is_empty() {
+            return Err(S3Error::InvalidArgument);
+        }
+        let text = std::str::from_utf8(buf).map_err(|_| S3Error::MalformedACLError)?;
+        Acl::parse_xml(text, owner)
+    }
+
+    /// The object for retention and legal hold ops, which require Object Lock on the bucket.
+    fn locked_object(&self, bucket: &str, key: &str) -> Result<ObjectArc, S3Error> {
+        let buckets_rlock = self.buckets_arc.read().unwrap();
+        let bucket_arc = match buckets_rlock.get(bucket) {
+            Some(a) => Arc::clone(a),
+            None => return Err(S3Error::NoSuchBucket),
+        };
+        let bucket_rlock = bucket_arc.read().unwrap();
+        if bucket_rlock.object_lock.is_none() {
+            return Err(S3Error::InvalidRequest);
+        }
+        match bucket_rlock.objects.get(key) {
+            Some(a) => Ok(Arc::clone(a)),
+            None => Err(S3Error::NoSuchKey),
+        }
+    }
+
     fn make_bucket_info(&self, bucket: &str) -> BucketInfo {
         BucketInfo {
             name: bucket.to_string(),
             class: format!("class_{}", bucket),
-            region: format!("region_{}", bucket),
+            region: String::new(),
             owner: UserInfo {
                 id: format!("user_id_{}", bucket),
                 display_name: format!("user_name_{}", bucket),
@@ -222,6 +1072,14 @@ impl MemLayer {
                 id: format!("user_id_{}_{}", bucket, key),
                 display_name: format!("user_name_{}_{}", bucket, key),
             },
+            tag_count: 0,
+            website_redirect_location: String::new(),
+            sse_customer_key_md5: String::new(),
+            sse_algorithm: String::new(),
+            sse_kms_key_id: String::new(),
+            object_lock: ObjectLock::default(),
+            replication_status: String::new(),
+            plaintext_size: None,
         }
     }
 }
@@ -1,6 +1,6 @@
 use crate::api::*;
 use async_trait::async_trait;
-use hyper::{body::to_bytes, Body};
+use hyper::Body;
 
 #[derive(Debug, Clone)]
 pub struct MockLayer;
@@ -64,6 +64,10 @@ impl ApiLayer for MockLayer {
             marker: body.marker.to_owned(),
             max_keys: body.max_keys,
             encoding_type: body.encoding_type.to_owned(),
+            v2: body.v2,
+            start_after: body.start_after.to_owned(),
+            continuation_token: body.continuation_token.to_owned(),
+            fetch_owner: body.fetch_owner,
         }))
     }
 
@@ -72,6 +76,7 @@ impl ApiLayer for MockLayer {
         let object = self.make_object_info(body.bucket.as_str(), body.key.as_str());
         Ok(get_object::Res::new(get_object::Reply {
             object,
+            range: None,
             body: if body.head_only {
                 None
             } else {
@@ -82,7 +87,7 @@ impl ApiLayer for MockLayer {
 
     async fn put_object(&self, req: put_object::Req) -> put_object::Ret {
         let body = req.into_body();
-        let buf = to_bytes(body.body.unwrap()).await.unwrap();
+        let buf = read_body(body.body).await?;
         let mut object = self.make_object_info(body.bucket.as_str(), body.key.as_str());
         object.size = buf.len() as u64;
         Ok(put_object::Res::new(put_object::Reply { object }))
@@ -100,7 +105,7 @@ impl MockLayer {
         BucketInfo {
             name: bucket.to_string(),
             class: format!("class_{}", bucket),
-            region: format!("region_{}", bucket),
+            region: String::new(),
             owner: UserInfo {
                 id: format!("user_id_{}", bucket),
                 display_name: format!("user_name_{}", bucket),
@@ -121,6 +126,14 @@ impl MockLayer {
                 id: format!("user_id_{}_{}", bucket, key),
                 display_name: format!("user_name_{}_{}", bucket, key),
             },
+            tag_count: 0,
+            website_redirect_location: String::new(),
+            sse_customer_key_md5: String::new(),
+            sse_algorithm: String::new(),
+            sse_kms_key_id: String::new(),
+            object_lock: ObjectLock::default(),
+            replication_status: String::new(),
+            plaintext_size: None,
         }
     }
 }
@@ -1,7 +1,15 @@
+pub mod encryption_layer;
+pub mod fs_layer;
+pub mod keyring;
 pub mod mem_layer;
 pub mod mock_layer;
+pub mod replication_layer;
 pub mod s3_layer;
 
+pub use self::encryption_layer::*;
+pub use self::fs_layer::*;
+pub use self::keyring::*;
 pub use self::mem_layer::*;
 pub use self::mock_layer::*;
+pub use self::replication_layer::*;
 pub use self::s3_layer::*;
@@ -0,0 +1,1202 @@
+use crate::api::*;
+use crate::auth::*;
+use async_trait::async_trait;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{oneshot, Notify};
+
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// The journal is compacted when it has this many more lines than its state needs.
+const COMPACT_SLACK: usize = 10000;
+
+/// A named destination of a ReplicationLayer.
+pub type Destination = (String, Arc<dyn ApiLayer>);
+
+/// ReplicationLayer replays the writes and deletes of the inner layer to the
+/// destination layers, by the `?replication` rules of each bucket.
+///
+/// Every matching write adds a task per destination bucket, which the worker replays
+/// in order by reading the current object from the inner layer, so a task of an object
+/// that was deleted since has nothing to replay. Failed tasks are retried with backoff,
+/// later tasks of the same key wait for them. Objects are PENDING until their tasks
+/// are done, FAILED while a task is retried, and then COMPLETED. With a journal file
+/// the tasks and statuses are appended to it, so replication resumes after restarts.
+/// The journal is written by a thread of its own, writes wait only for their new
+/// tasks to be synced. Objects with customer-provided keys (SSE-C) are not replicated,
+/// like S3, the others are written to the destination with the same encryption.
+pub struct ReplicationLayer<Inner: ApiLayer> {
+    inner: Arc<Inner>,
+    replicator: Arc<Replicator>,
+}
+
+struct Replicator {
+    destinations: BTreeMap<String, Arc<dyn ApiLayer>>,
+    /// None keeps the tasks only in memory
+    journal: Option<PathBuf>,
+    queue: Mutex<Queue>,
+    /// one pass over the tasks at a time, see Replicator::run_pass()
+    pass: tokio::sync::Mutex<()>,
+    wake: Notify,
+    closed: AtomicBool,
+    completed: AtomicU64,
+    failures: AtomicU64,
+}
+
+#[derive(Default)]
+struct Queue {
+    tasks: BTreeMap<u64, ReplicationTask>,
+    next_id: u64,
+    /// the objects whose replication completed, by bucket and key
+    completed: BTreeSet<(String, String)>,
+    /// the journal writer, see journal_writer()
+    journal: Option<mpsc::Sender<JournalOp>>,
+    /// lines in the journal file
+    lines: usize,
+}
+
+/// JournalOp is a request to the journal writer, which runs them in order.
+enum JournalOp {
+    Append(String),
+    /// sync the appended lines and then notify
+    Sync(oneshot::Sender<()>),
+    /// replace the journal with these lines
+    Rewrite(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TaskOp {
+    Put,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReplicationTask {
+    id: u64,
+    op: TaskOp,
+    bucket: String,
+    key: String,
+    destination: String,
+    target_bucket: String,
+    #[serde(skip)]
+    attempts: u32,
+    #[serde(skip)]
+    retry_at: Option<Instant>,
+}
+
+/// JournalEntry is a line of the journal file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "lowercase")]
+enum JournalEntry {
+    Task(ReplicationTask),
+    Done { id: u64 },
+    Failed { id: u64 },
+    Completed { bucket: String, key: String },
+    Forget { bucket: String, key: String },
+}
+
+#[async_trait]
+impl<Inner: ApiLayer + 'static> ApiLayer for ReplicationLayer<Inner> {
+    /// A layer over a new inner layer without destinations, which replicates nothing.
+    fn new() -> Self {
+        ReplicationLayer {
+            inner: Arc::new(Inner::new()),
+            replicator: Arc::new(Replicator::new(BTreeMap::new(), None)),
+        }
+    }
+
+    async fn list_buckets(&self, req: list_buckets::Req) -> list_buckets::Ret {
+        self.inner.list_buckets(req).await
+    }
+
+    async fn get_bucket(&self, req: get_bucket::Req) -> get_bucket::Ret {
+        self.inner.get_bucket(req).await
+    }
+
+    async fn put_bucket(&self, req: put_bucket::Req) -> put_bucket::Ret {
+        self.inner.put_bucket(req).await
+    }
+
+    async fn delete_bucket(&self, req: delete_bucket::Req) -> delete_bucket::Ret {
+        self.inner.delete_bucket(req).await
+    }
+
+    async fn get_bucket_location(&self, req: get_bucket_location::Req) -> get_bucket_location::Ret {
+        self.inner.get_bucket_location(req).await
+    }
+
+    async fn list_objects(&self, req: list_objects::Req) -> list_objects::Ret {
+        self.inner.list_objects(req).await
+    }
+
+    async fn get_object(&self, req: get_object::Req) -> get_object::Ret {
+        let mut res = self.inner.get_object(req).await?;
+        let object = &mut res.body_mut().object;
+        if let Some(status) = self.replicator.status(&object.bucket, &object.key) {
+            object.replication_status = status.as_str().to_string();
+        }
+        Ok(res)
+    }
+
+    async fn put_object(&self, req: put_object::Req) -> put_object::Ret {
+        let bucket = req.body().bucket.to_owned();
+        let key = req.body().key.to_owned();
+        let tags = req.body().tagging.tag_set.tags.clone();
+        let res = self.inner.put_object(req).await?;
+        let tasks = match res.body().object.sse_customer_key_md5.is_empty() {
+            true => match bucket_replication(&*self.inner, &bucket).await {
+                Some(config) => self
+                    .replicator
+                    .tasks(&config, &bucket, &key, &tags, TaskOp::Put),
+                None => Vec::new(),
+            },
+            false => Vec::new(),
+        };
+        self.replicator.enqueue(&bucket, &key, tasks).await;
+        Ok(res)
+    }
+
+    async fn delete_object(&self, req: delete_object::Req) -> delete_object::Ret {
+        let bucket = req.body().bucket.to_owned();
+        let key = req.body().key.to_owned();
+        let config = bucket_replication(&*self.inner, &bucket).await;
+        let tags = match &config {
+            Some(config) if config.has_tag_filters() => {
+                object_tags(&*self.inner, &bucket, &key).await
+            }
+            _ => Vec::new(),
+        };
+        let res = self.inner.delete_object(req).await?;
+        let tasks = match &config {
+            Some(config) => self
+                .replicator
+                .tasks(config, &bucket, &key, &tags, TaskOp::Delete),
+            None => Vec::new(),
+        };
+        self.replicator.enqueue(&bucket, &key, tasks).await;
+        Ok(res)
+    }
+
+    async fn get_bucket_policy(&self, req: get_bucket_policy::Req) -> get_bucket_policy::Ret {
+        self.inner.get_bucket_policy(req).await
+    }
+
+    async fn put_bucket_policy(&self, req: put_bucket_policy::Req) -> put_bucket_policy::Ret {
+        self.inner.put_bucket_policy(req).await
+    }
+
+    async fn delete_bucket_policy(
+        &self,
+        req: delete_bucket_policy::Req,
+    ) -> delete_bucket_policy::Ret {
+        self.inner.delete_bucket_policy(req).await
+    }
+
+    async fn get_bucket_acl(&self, req: get_bucket_acl::Req) -> get_bucket_acl::Ret {
+        self.inner.get_bucket_acl(req).await
+    }
+
+    async fn put_bucket_acl(&self, req: put_bucket_acl::Req) -> put_bucket_acl::Ret {
+        self.inner.put_bucket_acl(req).await
+    }
+
+    async fn get_object_acl(&self, req: get_object_acl::Req) -> get_object_acl::Ret {
+        self.inner.get_object_acl(req).await
+    }
+
+    async fn put_object_acl(&self, req: put_object_acl::Req) -> put_object_acl::Ret {
+        self.inner.put_object_acl(req).await
+    }
+
+    async fn get_bucket_tagging(&self, req: get_bucket_tagging::Req) -> get_bucket_tagging::Ret {
+        self.inner.get_bucket_tagging(req).await
+    }
+
+    async fn put_bucket_tagging(&self, req: put_bucket_tagging::Req) -> put_bucket_tagging::Ret {
+        self.inner.put_bucket_tagging(req).await
+    }
+
+    async fn delete_bucket_tagging(
+        &self,
+        req: delete_bucket_tagging::Req,
+    ) -> delete_bucket_tagging::Ret {
+        self.inner.delete_bucket_tagging(req).await
+    }
+
+    async fn get_object_tagging(&self, req: get_object_tagging::Req) -> get_object_tagging::Ret {
+        self.inner.get_object_tagging(req).await
+    }
+
+    async fn put_object_tagging(&self, req: put_object_tagging::Req) -> put_object_tagging::Ret {
+        self.inner.put_object_tagging(req).await
+    }
+
+    async fn delete_object_tagging(
+        &self,
+        req: delete_object_tagging::Req,
+    ) -> delete_object_tagging::Ret {
+        self.inner.delete_object_tagging(req).await
+    }
+
+    async fn get_bucket_lifecycle(
+        &self,
+        req: get_bucket_lifecycle::Req,
+    ) -> get_bucket_lifecycle::Ret {
+        self.inner.get_bucket_lifecycle(req).await
+    }
+
+    async fn put_bucket_lifecycle(
+        &self,
+        req: put_bucket_lifecycle::Req,
+    ) -> put_bucket_lifecycle::Ret {
+        self.inner.put_bucket_lifecycle(req).await
+    }
+
+    async fn delete_bucket_lifecycle(
+        &self,
+        req: delete_bucket_lifecycle::Req,
+    ) -> delete_bucket_lifecycle::Ret {
+        self.inner.delete_bucket_lifecycle(req).await
+    }
+
+    async fn get_bucket_cors(&self, req: get_bucket_cors::Req) -> get_bucket_cors::Ret {
+        self.inner.get_bucket_cors(req).await
+    }
+
+    async fn put_bucket_cors(&self, req: put_bucket_cors::Req) -> put_bucket_cors::Ret {
+        self.inner.put_bucket_cors(req).await
+    }
+
+    async fn delete_bucket_cors(&self, req: delete_bucket_cors::Req) -> delete_bucket_cors::Ret {
+        self.inner.delete_bucket_cors(req).await
+    }
+
+    async fn get_bucket_website(&self, req: get_bucket_website::Req) -> get_bucket_website::Ret {
+        self.inner.get_bucket_website(req).await
+    }
+
+    async fn put_bucket_website(&self, req: put_bucket_website::Req) -> put_bucket_website::Ret {
+        self.inner.put_bucket_website(req).await
+    }
+
+    async fn delete_bucket_website(
+        &self,
+        req: delete_bucket_website::Req,
+    ) -> delete_bucket_website::Ret {
+        self.inner.delete_bucket_website(req).await
+    }
+
+    async fn get_bucket_encryption(
+        &self,
+        req: get_bucket_encryption::Req,
+    ) -> get_bucket_encryption::Ret {
+        self.inner.get_bucket_encryption(req).await
+    }
+
+    async fn put_bucket_encryption(
+        &self,
+        req: put_bucket_encryption::Req,
+    ) -> put_bucket_encryption::Ret {
+        self.inner.put_bucket_encryption(req).await
+    }
+
+    async fn delete_bucket_encryption(
+        &self,
+        req: delete_bucket_encryption::Req,
+    ) -> delete_bucket_encryption::Ret {
+        self.inner.delete_bucket_encryption(req).await
+    }
+
+    async fn get_object_lock_configuration(
+        &self,
+        req: get_object_lock_configuration::Req,
+    ) -> get_object_lock_configuration::Ret {
+        self.inner.get_object_lock_configuration(req).await
+    }
+
+    async fn put_object_lock_configuration(
+        &self,
+        req: put_object_lock_configuration::Req,
+    ) -> put_object_lock_configuration::Ret {
+        self.inner.put_object_lock_configuration(req).await
+    }
+
+    async fn get_object_retention(
+        &self,
+        req: get_object_retention::Req,
+    ) -> get_object_retention::Ret {
+        self.inner.get_object_retention(req).await
+    }
+
+    async fn put_object_retention(
+        &self,
+        req: put_object_retention::Req,
+    ) -> put_object_retention::Ret {
+        self.inner.put_object_retention(req).await
+    }
+
+    async fn get_object_legal_hold(
+        &self,
+        req: get_object_legal_hold::Req,
+    ) -> get_object_legal_hold::Ret {
+        self.inner.get_object_legal_hold(req).await
+    }
+
+    async fn put_object_legal_hold(
+        &self,
+        req: put_object_legal_hold::Req,
+    ) -> put_object_legal_hold::Ret {
+        self.inner.put_object_legal_hold(req).await
+    }
+
+    async fn get_bucket_notification(
+        &self,
+        req: get_bucket_notification::Req,
+    ) -> get_bucket_notification::Ret {
+        self.inner.get_bucket_notification(req).await
+    }
+
+    async fn put_bucket_notification(
+        &self,
+        req: put_bucket_notification::Req,
+    ) -> put_bucket_notification::Ret {
+        self.inner.put_bucket_notification(req).await
+    }
+
+    async fn get_bucket_replication(
+        &self,
+        req: get_bucket_replication::Req,
+    ) -> get_bucket_replication::Ret {
+        self.inner.get_bucket_replication(req).await
+    }
+
+    /// Every rule must replicate to a destination bucket of the layer.
+    async fn put_bucket_replication(
+        &self,
+        req: put_bucket_replication::Req,
+    ) -> put_bucket_replication::Ret {
+        let (parts, mut params) = req.into_parts();
+        let buf = read_document(params.body.take()).await?;
+        let config: ReplicationConfiguration = from_xml(&buf)?;
+        config.validate()?;
+        for rule in config.rules.iter() {
+            if self.replicator.target(&rule.destination).is_none() {
+                tracing::debug!(
+                    bucket = rule.destination.bucket.as_str(),
+                    "replication destination is not configured"
+                );
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        params.body = Some(Body::from(buf));
+        self.inner
+            .put_bucket_replication(put_bucket_replication::Req::from_parts(parts, params))
+            .await
+    }
+
+    async fn delete_bucket_replication(
+        &self,
+        req: delete_bucket_replication::Req,
+    ) -> delete_bucket_replication::Ret {
+        self.inner.delete_bucket_replication(req).await
+    }
+
+    /// Sync the journal, then flush the inner layer.
+    async fn flush(&self) {
+        self.replicator.sync().await;
+        self.inner.flush().await
+    }
+
+    fn describe(&self) -> LayerInfo {
+        let journal = match &self.replicator.journal {
+            Some(path) => path.to_string_lossy().to_string(),
+            None => "memory".to_string(),
+        };
+        let mut info = LayerInfo::new("ReplicationLayer").config("journal", &journal);
+        for (name, destination) in self.replicator.destinations.iter() {
+            let layer = destination.describe();
+            info = info.config("destination", &format!("{}={}", name, layer.name));
+        }
+        info.inner(self.inner.describe())
+    }
+
+    async fn probe(&self) -> Result<(), SyncError> {
+        self.inner.probe().await
+    }
+
+    async fn usage(&self) -> Result<Vec<BucketUsage>, S3Error> {
+        self.inner.usage().await
+    }
+
+    fn tasks(&self) -> Vec<&'static str> {
+        let mut tasks = self.inner.tasks();
+        tasks.push("replicate");
+        tasks
+    }
+
+    /// `replicate` replays the pending tasks now, also the ones waiting to be retried.
+    async fn run_task(&self, name: &str) -> Result<String, SyncError> {
+        match name {
+            "replicate" => {
+                let (done, failed) = self.replicator.run_pass(&*self.inner, true).await;
+                Ok(format!("replicated {} tasks, {} failed", done, failed))
+            }
+            _ => self.inner.run_task(name).await,
+        }
+    }
+
+    async fn stats(&self) -> Vec<LayerStat> {
+        let mut stats = self.inner.stats().await;
+        let (pending, failed) = self.replicator.counts();
+        stats.push(LayerStat::gauge(
+            "s3d_replication_pending",
+            "Replication tasks waiting to be replayed to a destination.",
+            pending as f64,
+        ));
+        stats.push(LayerStat::gauge(
+            "s3d_replication_failed",
+            "Pending replication tasks that failed and are retried.",
+            failed as f64,
+        ));
+        stats.push(LayerStat::counter(
+            "s3d_replication_completed_total",
+            "Replication tasks replayed to a destination.",
+            self.replicator.completed.load(Ordering::Relaxed) as f64,
+        ));
+        stats.push(LayerStat::counter(
+            "s3d_replication_failures_total",
+            "Failed replays of replication tasks, each is retried.",
+            self.replicator.failures.load(Ordering::Relaxed) as f64,
+        ));
+        stats
+    }
+}
+
+impl<Inner: ApiLayer + 'static> ReplicationLayer<Inner> {
+    /// Create the layer over the inner layer with named destinations, and start
+    /// the worker that replays the tasks. With a journal file the tasks left
+    /// there by a previous run are replayed again.
+    pub async fn open(
+        inner: Inner,
+        destinations: Vec<Destination>,
+        journal: Option<&Path>,
+    ) -> Result<Self, SyncError> {
+        let mut targets = BTreeMap::new();
+        for (name, destination) in destinations {
+            if !valid_target_name(&name) {
+                return Err(format!("invalid replication destination name {:?}", name).into());
+            }
+            if targets.insert(name.to_owned(), destination).is_some() {
+                return Err(format!("duplicate replication destination {}", name).into());
+            }
+        }
+        let replicator = Replicator::new(targets, journal.map(Path::to_path_buf));
+        if let Some(path) = journal {
+            let path = path.to_path_buf();
+            let queue = tokio::task::spawn_blocking(move || load_journal(&path)).await??;
+            if !queue.tasks.is_empty() {
+                tracing::info!(tasks = queue.tasks.len(), "resuming replication tasks");
+            }
+            *replicator.queue.lock().unwrap() = queue;
+        }
+        let layer = ReplicationLayer {
+            inner: Arc::new(inner),
+            replicator: Arc::new(replicator),
+        };
+        tokio::spawn(replication_worker(
+            Arc::clone(&layer.inner),
+            Arc::clone(&layer.replicator),
+        ));
+        Ok(layer)
+    }
+
+    pub fn inner(&self) -> &Inner {
+        &self.inner
+    }
+}
+
+/// Stops the worker, the journal keeps the pending tasks.
+impl<Inner: ApiLayer> Drop for ReplicationLayer<Inner> {
+    fn drop(&mut self) {
+        self.replicator.closed.store(true, Ordering::SeqCst);
+        self.replicator.wake.notify_one();
+    }
+}
+
+impl<Inner: ApiLayer> fmt::Debug for ReplicationLayer<Inner> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplicationLayer")
+            .field("destinations", &self.replicator.destinations.keys())
+            .field("journal", &self.replicator.journal)
+            .finish()
+    }
+}
+
+impl Replicator {
+    fn new(destinations: BTreeMap<String, Arc<dyn ApiLayer>>, journal: Option<PathBuf>) -> Self {
+        Replicator {
+            destinations,
+            journal,
+            queue: Mutex::new(Queue::default()),
+            pass: tokio::sync::Mutex::new(()),
+            wake: Notify::new(),
+            closed: AtomicBool::new(false),
+            completed: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    /// The destination name and bucket of a rule, the S3 form of bucket ARNs
+    /// names the only destination of the layer.
+    fn target<'a>(&'a self, destination: &'a ReplicationDestination) -> Option<(&'a str, &'a str)> {
+        let (name, bucket) = destination.target()?;
+        let name = match name {
+            Some(name) => self.destinations.get_key_value(name)?.0,
+            None if self.destinations.len() == 1 => self.destinations.keys().next()?,
+            None => return None,
+        };
+        Some((name, bucket))
+    }
+
+    /// A task per destination bucket of the rules that replicate the operation on the key.
+    fn tasks(
+        &self,
+        config: &ReplicationConfiguration,
+        bucket: &str,
+        key: &str,
+        tags: &[Tag],
+        op: TaskOp,
+    ) -> Vec<ReplicationTask> {
+        let mut tasks = Vec::new();
+        for rule in config.rules_for(key, tags) {
+            if op == TaskOp::Delete && !rule.replicates_deletes() {
+                continue;
+            }
+            let (destination, target_bucket) = match self.target(&rule.destination) {
+                Some(target) => target,
+                None => {
+                    tracing::warn!(
+                        bucket,
+                        arn = rule.destination.bucket.as_str(),
+                        "replication destination is not configured"
+                    );
+                    continue;
+                }
+            };
+            tasks.push(ReplicationTask {
+                id: 0,
+                op,
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                destination: destination.to_string(),
+                target_bucket: target_bucket.to_string(),
+                attempts: 0,
+                retry_at: None,
+            });
+        }
+        tasks
+    }
+
+    /// Add the tasks of the key to the journal and wake the worker, once they are synced.
+    /// Without tasks the key has no replication status anymore.
+    async fn enqueue(&self, bucket: &str, key: &str, tasks: Vec<ReplicationTask>) {
+        let synced = {
+            let mut queue = self.queue.lock().unwrap();
+            let object = (bucket.to_string(), key.to_string());
+            if tasks.is_empty() {
+                if queue.completed.remove(&object) {
+                    queue.append(JournalEntry::Forget {
+                        bucket: object.0,
+                        key: object.1,
+                    });
+                }
+                return;
+            }
+            queue.completed.remove(&object);
+            for mut task in tasks {
+                task.id = queue.next_id;
+                queue.next_id += 1;
+                queue.tasks.insert(task.id, task.clone());
+                queue.append(JournalEntry::Task(task));
+            }
+            queue.sync()
+        };
+        if let Some(synced) = synced {
+            let _ = synced.await;
+        }
+        self.wake.notify_one();
+    }
+
+    /// Wait until the journal has synced what was appended so far.
+    async fn sync(&self) {
+        let synced = self.queue.lock().unwrap().sync();
+        if let Some(synced) = synced {
+            let _ = synced.await;
+        }
+    }
+
+    /// The replication status of the object, None for objects that are not replicated.
+    fn status(&self, bucket: &str, key: &str) -> Option<ReplicationStatus> {
+        let queue = self.queue.lock().unwrap();
+        let mut status = None;
+        for task in queue.tasks.values() {
+            if task.op == TaskOp::Put && task.bucket == bucket && task.key == key {
+                if task.attempts > 0 {
+                    return Some(ReplicationStatus::Failed);
+                }
+                status = Some(ReplicationStatus::Pending);
+            }
+        }
+        let object = (bucket.to_string(), key.to_string());
+        status.or_else(|| match queue.completed.contains(&object) {
+            true => Some(ReplicationStatus::Completed),
+            false => None,
+        })
+    }
+
+    /// The pending tasks, and those of them that failed.
+    fn counts(&self) -> (usize, usize) {
+        let queue = self.queue.lock().unwrap();
+        let failed = queue.tasks.values().filter(|t| t.attempts > 0).count();
+        (queue.tasks.len(), failed)
+    }
+
+    /// Replay the pending tasks in order, skipping the ones waiting to be retried
+    /// unless forced, and the later tasks of their keys. Returns the tasks done and failed.
+    async fn run_pass<API: ApiLayer + ?Sized>(&self, inner: &API, force: bool) -> (usize, usize) {
+        let _pass = self.pass.lock().await;
+        let tasks: Vec<ReplicationTask> =
+            self.queue.lock().unwrap().tasks.values().cloned().collect();
+        let now = Instant::now();
+        let mut blocked = HashSet::new();
+        let (mut done, mut failed) = (0, 0);
+        for task in tasks {
+            if self.closed.load(Ordering::SeqCst) {
+                break;
+            }
+            let target = (
+                task.destination.to_owned(),
+                task.target_bucket.to_owned(),
+                task.key.to_owned(),
+            );
+            if blocked.contains(&target) {
+                continue;
+            }
+            if !force && task.retry_at.is_some_and(|at| at > now) {
+                blocked.insert(target);
+                continue;
+            }
+            match self.replay(inner, &task).await {
+                Ok(()) => {
+                    self.done(&task);
+                    done += 1;
+                }
+                Err(err) => {
+                    let delay = self.failed(&task);
+                    tracing::warn!(
+                        bucket = task.bucket.as_str(),
+                        key = task.key.as_str(),
+                        destination = task.destination.as_str(),
+                        %err,
+                        retry_in = ?delay,
+                        "replication failed"
+                    );
+                    blocked.insert(target);
+                    failed += 1;
+                }
+            }
+        }
+        self.queue.lock().unwrap().compact(self.journal.as_deref());
+        (done, failed)
+    }
+
+    /// Replay the task to its destination, the object is read from the inner layer.
+    async fn replay<API: ApiLayer + ?Sized>(
+        &self,
+        inner: &API,
+        task: &ReplicationTask,
+    ) -> Result<(), SyncError> {
+        let destination = match self.destinations.get(&task.destination) {
+            Some(destination) => destination,
+            None => return Err(format!("unknown destination {}", task.destination).into()),
+        };
+        match task.op {
+            TaskOp::Put => {
+                let req = get_object::Req::new(get_object::Params {
+                    bucket: task.bucket.to_owned(),
+                    key: task.key.to_owned(),
+                    version_id: String::new(),
+                    head_only: false,
+                    range: ObjectRange {
+                        start: None,
+                        end: None,
+                    },
+                    sse_customer: None,
+                });
+                let reply = match inner.get_object(req).await {
+                    Ok(res) => res.into_body(),
+                    // deleted since, a later task replays the delete if the rules ask for it
+                    Err(S3Error::NoSuchKey) | Err(S3Error::NoSuchBucket) => return Ok(()),
+                    Err(err) => return Err(err.into()),
+                };
+                let tags = object_tags(inner, &task.bucket, &task.key).await;
+                // the replica is encrypted like the source, with the same KMS key
+                let sse = match reply.object.sse_algorithm.as_str() {
+                    "" => None,
+                    algorithm => Some(ServerSideEncryption {
+                        algorithm: SseAlgorithm::parse(algorithm)?,
+                        kms_key_id: reply.object.sse_kms_key_id.to_owned(),
+                    }),
+                };
+                let mut req = put_object::Req::new(put_object::Params {
+                    bucket: task.target_bucket.to_owned(),
+                    key: task.key.to_owned(),
+                    acl: AclHeaders::default(),
+                    tagging: Tagging {
+                        tag_set: TagSet { tags },
+                    },
+                    website_redirect_location: reply.object.website_redirect_location.to_owned(),
+                    sse_customer: None,
+                    sse,
+                    object_lock: ObjectLock::default(),
+                    plaintext_size: None,
+                    body: reply.body,
+                });
+                req.extensions_mut()
+                    .insert(Principal::User(reply.object.owner.to_owned()));
+                destination.put_object(req).await?;
+            }
+            TaskOp::Delete => {
+                let req = delete_object::Req::new(delete_object::Params {
+                    bucket: task.target_bucket.to_owned(),
+                    key: task.key.to_owned(),
+                    version_id: String::new(),
+                    bypass_governance: false,
+                });
+                match destination.delete_object(req).await {
+                    Ok(_) | Err(S3Error::NoSuchKey) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        tracing::debug!(
+            bucket = task.bucket.as_str(),
+            key = task.key.as_str(),
+            destination = task.destination.as_str(),
+            "replicated"
+        );
+        Ok(())
+    }
+
+    /// Remove the task, the object is COMPLETED once its last write is replicated.
+    fn done(&self, task: &ReplicationTask) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        let mut queue = self.queue.lock().unwrap();
+        if queue.tasks.remove(&task.id).is_none() {
+            return;
+        }
+        queue.append(JournalEntry::Done { id: task.id });
+        let pending = queue
+            .tasks
+            .values()
+            .any(|t| t.bucket == task.bucket && t.key == task.key);
+        if task.op == TaskOp::Put && !pending {
+            queue
+                .completed
+                .insert((task.bucket.to_owned(), task.key.to_owned()));
+            queue.append(JournalEntry::Completed {
+                bucket: task.bucket.to_owned(),
+                key: task.key.to_owned(),
+            });
+        }
+    }
+
+    /// Mark the task FAILED and return the delay until it is retried.
+    fn failed(&self, task: &ReplicationTask) -> Duration {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let mut queue = self.queue.lock().unwrap();
+        let task = match queue.tasks.get_mut(&task.id) {
+            Some(task) => task,
+            None => return MIN_RETRY_DELAY,
+        };
+        task.attempts += 1;
+        let delay =
+            (MIN_RETRY_DELAY * 2u32.saturating_pow(task.attempts.min(16) - 1)).min(MAX_RETRY_DELAY);
+        task.retry_at = Some(Instant::now() + delay);
+        if task.attempts == 1 {
+            let id = task.id;
+            queue.append(JournalEntry::Failed { id });
+        }
+        delay
+    }
+
+    /// When the first task waiting to be retried is due.
+    fn next_retry(&self) -> Option<Instant> {
+        let queue = self.queue.lock().unwrap();
+        queue.tasks.values().filter_map(|t| t.retry_at).min()
+    }
+}
+
+impl Queue {
+    /// Append the entry to the journal file, if any. The journal is only
+    /// synced for new tasks, other entries are replayed again after a crash.
+    fn append(&mut self, entry: JournalEntry) {
+        let journal = match &self.journal {
+            Some(journal) => journal,
+            None => return,
+        };
+        let mut line = serde_json::to_string(&entry).unwrap_or_default();
+        line.push('\n');
+        if journal.send(JournalOp::Append(line)).is_err() {
+            tracing::warn!("replication journal writer stopped");
+        }
+        self.lines += 1;
+    }
+
+    /// Ask the writer to sync the journal, the receiver is notified when it did.
+    fn sync(&mut self) -> Option<oneshot::Receiver<()>> {
+        let (done, synced) = oneshot::channel();
+        self.journal.as_ref()?.send(JournalOp::Sync(done)).ok()?;
+        Some(synced)
+    }
+
+    /// Apply an entry read from the journal file.
+    fn apply(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Task(task) => {
+                self.completed
+                    .remove(&(task.bucket.to_owned(), task.key.to_owned()));
+                self.next_id = self.next_id.max(task.id + 1);
+                self.tasks.insert(task.id, task);
+            }
+            JournalEntry::Done { id } => {
+                self.tasks.remove(&id);
+            }
+            JournalEntry::Failed { id } => {
+                if let Some(task) = self.tasks.get_mut(&id) {
+                    task.attempts = 1;
+                }
+            }
+            JournalEntry::Completed { bucket, key } => {
+                self.completed.insert((bucket, key));
+            }
+            JournalEntry::Forget { bucket, key } => {
+                self.completed.remove(&(bucket, key));
+            }
+        }
+    }
+
+    /// Rewrite the journal with only the entries of the current state, when it
+    /// has grown much longer. The new journal replaces the old one atomically.
+    fn compact(&mut self, path: Option<&Path>) {
+        let path = match path {
+            Some(path) => path,
+            None => return,
+        };
+        let needed = self.tasks.len() * 2 + self.completed.len();
+        if self.lines <= needed + COMPACT_SLACK {
+            return;
+        }
+        let journal = match &self.journal {
+            Some(journal) => journal,
+            None => return,
+        };
+        match self.snapshot() {
+            Ok((buf, lines)) => {
+                if journal.send(JournalOp::Rewrite(buf)).is_ok() {
+                    self.lines = lines;
+                }
+            }
+            Err(err) => tracing::warn!(?path, %err, "replication journal compaction failed"),
+        }
+    }
+
+    /// The journal lines of the current state and their count.
+    fn snapshot(&self) -> Result<(String, usize), SyncError> {
+        let mut buf = String::new();
+        let mut lines = 0;
+        let mut push = |entry: &JournalEntry| -> Result<(), SyncError> {
+            buf.push_str(&serde_json::to_string(entry)?);
+            buf.push('\n');
+            lines += 1;
+            Ok(())
+        };
+        for (bucket, key) in self.completed.iter() {
+            push(&JournalEntry::Completed {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+            })?;
+        }
+        for task in self.tasks.values() {
+            push(&JournalEntry::Task(task.clone()))?;
+            if task.attempts > 0 {
+                push(&JournalEntry::Failed { id: task.id })?;
+            }
+        }
+        Ok((buf, lines))
+    }
+}
+
+/// Load the queue from the journal file, creating it when missing, and compact it.
+/// A last line that is not valid JSON was cut short by a crash and is ignored.
+fn load_journal(path: &Path) -> Result<Queue, SyncError> {
+    let mut queue = Queue::default();
+    match File::open(path) {
+        Ok(file) => {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(entry) => queue.apply(entry),
+                    Err(err) => tracing::warn!(?path, %err, "skipping partial journal entry"),
+                }
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    let (buf, lines) = queue.snapshot()?;
+    let file = write_journal(path, &buf)?;
+    let (journal, ops) = mpsc::channel();
+    let path = path.to_path_buf();
+    std::thread::Builder::new()
+        .name(String::from("s3d-journal"))
+        .spawn(move || journal_writer(path, file, ops))?;
+    queue.journal = Some(journal);
+    queue.lines = lines;
+    Ok(queue)
+}
+
+/// Replace the journal with the lines atomically, and open it for appending.
+fn write_journal(path: &Path, buf: &str) -> std::io::Result<File> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(buf.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+/// Run the journal ops in order until the queue is dropped, off the runtime
+/// so the file I/O never blocks its threads. A failed compaction keeps the
+/// old journal, which the appends went to in the meantime.
+fn journal_writer(path: PathBuf, mut file: File, ops: mpsc::Receiver<JournalOp>) {
+    for op in ops {
+        match op {
+            JournalOp::Append(line) => {
+                if let Err(err) = file.write_all(line.as_bytes()) {
+                    tracing::warn!(%err, "replication journal write failed");
+                }
+            }
+            JournalOp::Sync(done) => {
+                if let Err(err) = file.sync_data() {
+                    tracing::warn!(%err, "replication journal sync failed");
+                }
+                let _ = done.send(());
+            }
+            JournalOp::Rewrite(buf) => match write_journal(&path, &buf) {
+                Ok(compacted) => file = compacted,
+                Err(err) => tracing::warn!(?path, %err, "replication journal compaction failed"),
+            },
+        }
+    }
+}
+
+/// Replay the tasks until the layer is dropped, waiting for new tasks
+/// or for the first failed task to be retried.
+async fn replication_worker<Inner: ApiLayer>(inner: Arc<Inner>, replicator: Arc<Replicator>) {
+    while !replicator.closed.load(Ordering::SeqCst) {
+        replicator.run_pass(&*inner, false).await;
+        match replicator.next_retry() {
+            Some(at) => {
+                tokio::select! {
+                    _ = replicator.wake.notified() => {}
+                    _ = tokio::time::sleep_until(at.into()) => {}
+                }
+            }
+            None => replicator.wake.notified().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::{EncryptionLayer, FSLayer, Keyring, MemLayer};
+
+    fn alice() -> Principal {
+        Principal::User(UserInfo {
+            id: String::from("alice"),
+            display_name: String::from("alice"),
+        })
+    }
+
+    fn get(key: &str) -> get_object::Req {
+        get_object::Req::new(get_object::Params {
+            bucket: String::from("source"),
+            key: key.to_string(),
+            version_id: String::new(),
+            head_only: false,
+            range: ObjectRange {
+                start: None,
+                end: None,
+            },
+            sse_customer: None,
+        })
+    }
+
+    fn temp_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("s3d-replica-{}", new_request_id()));
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    /// Create the source bucket, replicated to the replica bucket of the fs destination.
+    async fn replicate_source<API: ApiLayer>(layer: &API) {
+        let mut req = put_bucket::Req::new(put_bucket::Params {
+            bucket: String::from("source"),
+            class: String::new(),
+            region: String::new(),
+            acl: AclHeaders::default(),
+            object_lock_enabled: false,
+            body: None,
+        });
+        req.extensions_mut().insert(alice());
+        layer.put_bucket(req).await.unwrap();
+        let config = "<ReplicationConfiguration><Role>r</Role><Rule><Status>Enabled</Status>\
+            <Destination><Bucket>arn:s3d:replica::fs:replica</Bucket></Destination>\
+            </Rule></ReplicationConfiguration>";
+        let req = put_bucket_replication::Req::new(put_bucket_replication::Params {
+            bucket: String::from("source"),
+            body: Some(Body::from(config)),
+        });
+        layer.put_bucket_replication(req).await.unwrap();
+    }
+
+    async fn put<API: ApiLayer>(layer: &API, key: &str, data: &'static str) {
+        let mut req = put_object::Req::new(put_object::Params {
+            bucket: String::from("source"),
+            key: key.to_string(),
+            acl: AclHeaders::default(),
+            tagging: Tagging::default(),
+            website_redirect_location: String::new(),
+            sse_customer: None,
+            sse: None,
+            object_lock: ObjectLock::default(),
+            plaintext_size: None,
+            body: Some(Body::from(data)),
+        });
+        req.extensions_mut().insert(alice());
+        layer.put_object(req).await.unwrap();
+    }
+
+    async fn status<API: ApiLayer>(layer: &API, key: &str) -> String {
+        let res = layer.get_object(get(key)).await.unwrap();
+        res.into_body().object.replication_status
+    }
+
+    #[tokio::test]
+    async fn replicates_to_fs_layer() {
+        let root = temp_root();
+        std::fs::create_dir_all(root.join("replica")).unwrap();
+        let fs = Arc::new(FSLayer::with_root(&root));
+        let destination: Arc<dyn ApiLayer> = fs.clone();
+        let layer = ReplicationLayer::open(
+            MemLayer::new(),
+            vec![(String::from("fs"), destination)],
+            None,
+        )
+        .await
+        .unwrap();
+        replicate_source(&layer).await;
+        put(&layer, "key", "data").await;
+        layer.run_task("replicate").await.unwrap();
+
+        assert_eq!(status(&layer, "key").await, "COMPLETED");
+        let mut req = get("key");
+        req.body_mut().bucket = String::from("replica");
+        let replica = fs.get_object(req).await.unwrap().into_body();
+        assert_eq!(replica.object.owner.id, "alice");
+        assert_eq!(read_body(replica.body).await.unwrap(), "data");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replicas_are_encrypted_like_the_source() {
+        let root = temp_root();
+        std::fs::create_dir_all(root.join("replica")).unwrap();
+        let keyring = Arc::new(Keyring::memory());
+        let fs = Arc::new(EncryptionLayer::with_shared_keyring(
+            FSLayer::with_root(&root),
+            keyring.clone(),
+        ));
+        let destination: Arc<dyn ApiLayer> = fs.clone();
+        let source = EncryptionLayer::with_shared_keyring(MemLayer::new(), keyring);
+        let layer = ReplicationLayer::open(source, vec![(String::from("fs"), destination)], None)
+            .await
+            .unwrap();
+        replicate_source(&layer).await;
+        put(&layer, "key", "plaintext data").await;
+        layer.run_task("replicate").await.unwrap();
+        assert_eq!(status(&layer, "key").await, "COMPLETED");
+
+        let replica = || {
+            let mut req = get("key");
+            req.body_mut().bucket = String::from("replica");
+            req
+        };
+        let stored = fs.inner().get_object(replica()).await.unwrap().into_body();
+        let stored = read_body(stored.body).await.unwrap();
+        assert!(!stored.windows(9).any(|w| w == b"plaintext"));
+        let replica = fs.get_object(replica()).await.unwrap().into_body();
+        assert_eq!(replica.object.sse_algorithm, "AES256");
+        assert_eq!(read_body(replica.body).await.unwrap(), "plaintext data");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_journal() {
+        let root = temp_root();
+        let journal = root.join("journal.jsonl");
+        let destination = || -> Vec<Destination> {
+            vec![(String::from("fs"), Arc::new(FSLayer::with_root(&root)))]
+        };
+        let layer = ReplicationLayer::open(MemLayer::new(), destination(), Some(&journal))
+            .await
+            .unwrap();
+        replicate_source(&layer).await;
+        // the replica bucket is missing so the task fails
+        put(&layer, "key", "data").await;
+        assert_eq!(
+            layer.run_task("replicate").await.unwrap(),
+            "replicated 0 tasks, 1 failed"
+        );
+        assert_eq!(status(&layer, "key").await, "FAILED");
+        layer.flush().await;
+        let inner = layer.inner().clone();
+        drop(layer);
+
+        std::fs::create_dir_all(root.join("replica")).unwrap();
+        let layer = ReplicationLayer::open(inner, destination(), Some(&journal))
+            .await
+            .unwrap();
+        layer.run_task("replicate").await.unwrap();
+        assert_eq!(status(&layer, "key").await, "COMPLETED");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
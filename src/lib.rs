@@ -0,0 +1,6 @@
+pub mod api;
+pub mod auth;
+pub mod embed;
+pub mod layers;
+
+pub use crate::embed::*;
@@ -1,9 +1,13 @@
-mod api;
-mod layers;
-
-use crate::api::*;
+use s3d::api::*;
+use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 pub async fn main() -> Result<(), SyncError> {
-    Ok(serve().await?)
+    // RUST_LOG selects the log level, e.g. RUST_LOG=s3d=debug logs redacted request headers
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+    serve().await
 }
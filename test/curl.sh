@@ -28,6 +28,13 @@ CURL /
 CURL /lala -X GET
 CURL /lala/README.md -X PUT -d @README.md
 CURL /lala/README.md -X GET
+CURL "/lala?policy" -X PUT -d '{"Statement":[{"Effect":"Deny","Principal":"*","Action":"s3:GetObject","Resource":"arn:aws:s3:::lala/*"}]}'
+CURL "/lala?policy" -X GET
+CURL /lala/README.md -X GET
+CURL "/lala?policy" -X DELETE
+CURL "/lala/README.md?acl" -X PUT -H "x-amz-acl: public-read"
+CURL "/lala/README.md?acl" -X GET
+CURL "/lala?acl" -X GET
 # cleanup
 CURL /lala -X DELETE
 CURL /